@@ -0,0 +1,560 @@
+//! `#[derive(CanMessage)]` for structs whose fields are packed into the
+//! payload of a classic CAN frame, implementing `pcan_basic::CanMessage`.
+//!
+//! The struct takes the frame identifier, `extended` for a 29-bit
+//! identifier and the payload `size` (8 by default). Each field takes its
+//! `start` bit and `length` (by default the width of its type) in DBC
+//! numbering, `big_endian` for Motorola byte order, and the `scale` and
+//! `offset` giving its value as `raw * scale + offset`. Integer fields are
+//! signed if their type is, unless marked `unsigned`; float fields are
+//! stored as scaled unsigned integers unless marked `signed` or `float`
+//! (IEEE 754, 32 or 64 bits). Values pass through `f64`.
+//!
+//! ```
+//! #[macro_use]
+//! extern crate pcan_basic_derive;
+//! extern crate pcan_basic;
+//!
+//! use pcan_basic::CanMessage;
+//!
+//! #[derive(CanMessage)]
+//! #[can(id = 0x18FEEE00, extended)]
+//! struct EngineTemperature {
+//!     #[can(start = 0, length = 8, offset = -40)]
+//!     coolant: f64,
+//!     #[can(start = 16, length = 16, scale = 0.03125, offset = -273)]
+//!     oil: f64,
+//!     #[can(start = 63)]
+//!     stale: bool,
+//! }
+//!
+//! fn main() {
+//!     let frame = EngineTemperature { coolant: 90.0, oil: 100.0, stale: false }.encode().unwrap();
+//!     assert_eq!(frame.data()[0], 130);
+//!     assert_eq!(EngineTemperature::decode(&frame).unwrap().oil, 100.0);
+//! }
+//! ```
+//!
+//! Fields that overlap or do not fit in the payload are rejected at
+//! compile time:
+//!
+//! ```compile_fail
+//! # #[macro_use] extern crate pcan_basic_derive;
+//! # extern crate pcan_basic;
+//! #[derive(CanMessage)]
+//! #[can(id = 0x100, size = 2)]
+//! struct Overlapping {
+//!     #[can(start = 0, length = 12)]
+//!     a: u16,
+//!     #[can(start = 8)]
+//!     b: u8,
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! ```compile_fail
+//! # #[macro_use] extern crate pcan_basic_derive;
+//! # extern crate pcan_basic;
+//! #[derive(CanMessage)]
+//! #[can(id = 0x100, size = 2)]
+//! struct TooLong {
+//!     #[can(start = 15, big_endian)]
+//!     a: u16,
+//! }
+//! # fn main() {}
+//! ```
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+#[macro_use]
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Literal, Span, TokenStream as Tokens};
+use std::collections::HashMap;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Data, DeriveInput, Fields, Ident, Lit, Type};
+
+#[proc_macro_derive(CanMessage, attributes(can))]
+pub fn derive_can_message(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// A `key` or `key = number` entry of a `#[can(...)]` attribute.
+struct Arg {
+    name: Ident,
+    value: Option<Number>,
+}
+
+#[derive(Clone, Copy)]
+struct Number {
+    value: f64,
+    integer: Option<i128>,
+    span: Span,
+}
+
+impl Parse for Arg {
+    fn parse(input: ParseStream) -> syn::Result<Arg> {
+        let name: Ident = input.parse()?;
+        if !input.peek(Token![=]) {
+            return Ok(Arg { name, value: None });
+        }
+        input.parse::<Token![=]>()?;
+        let negative = input.parse::<Option<Token![-]>>()?.is_some();
+        let sign = if negative { -1 } else { 1 };
+        let lit: Lit = input.parse()?;
+        let span = lit.span();
+        let number = match lit {
+            Lit::Int(ref i) => {
+                let integer = i.base10_parse::<i128>()? * sign;
+                Number {
+                    value: integer as f64,
+                    integer: Some(integer),
+                    span,
+                }
+            }
+            Lit::Float(ref f) => Number {
+                value: f.base10_parse::<f64>()? * sign as f64,
+                integer: None,
+                span,
+            },
+            _ => return Err(syn::Error::new(span, "expected a number")),
+        };
+        Ok(Arg {
+            name,
+            value: Some(number),
+        })
+    }
+}
+
+/// The `#[can(...)]` arguments of an item, by name.
+struct Args {
+    span: Span,
+    args: Vec<Arg>,
+}
+
+impl Args {
+    fn from_attributes(
+        attrs: &[Attribute],
+        span: Span,
+    ) -> syn::Result<Option<Args>> {
+        let mut found: Option<Args> = None;
+        for attr in attrs.iter().filter(|a| a.path.is_ident("can")) {
+            if found.is_some() {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "duplicate #[can] attribute",
+                ));
+            }
+            let args = attr.parse_args_with(
+                Punctuated::<Arg, Token![,]>::parse_terminated,
+            )?;
+            found = Some(Args {
+                span,
+                args: args.into_iter().collect(),
+            });
+        }
+        Ok(found)
+    }
+
+    fn check(&self, allowed: &[&str]) -> syn::Result<()> {
+        for arg in &self.args {
+            if !allowed.iter().any(|&a| arg.name == a) {
+                return Err(syn::Error::new(
+                    arg.name.span(),
+                    format!("unknown argument `{}`", arg.name),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn flag(&self, name: &str) -> syn::Result<bool> {
+        match self.args.iter().find(|a| a.name == name) {
+            None => Ok(false),
+            Some(&Arg { value: None, .. }) => Ok(true),
+            Some(arg) => Err(syn::Error::new(
+                arg.name.span(),
+                format!("`{}` takes no value", name),
+            )),
+        }
+    }
+
+    fn number(&self, name: &str) -> syn::Result<Option<Number>> {
+        match self.args.iter().find(|a| a.name == name) {
+            None => Ok(None),
+            Some(&Arg {
+                value: Some(number),
+                ..
+            }) => Ok(Some(number)),
+            Some(arg) => Err(syn::Error::new(
+                arg.name.span(),
+                format!("`{}` needs a value", name),
+            )),
+        }
+    }
+
+    fn integer(&self, name: &str, max: u64) -> syn::Result<Option<u64>> {
+        match self.number(name)? {
+            None => Ok(None),
+            Some(Number {
+                integer: Some(i), ..
+            }) if i >= 0 && i <= max as i128 => Ok(Some(i as u64)),
+            Some(n) => Err(syn::Error::new(
+                n.span,
+                format!("`{}` must be an integer from 0 to {}", name, max),
+            )),
+        }
+    }
+}
+
+/// Rust type of a field.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Bool,
+    Unsigned(u16),
+    Signed(u16),
+    Float(u16),
+}
+
+impl Kind {
+    fn of(ty: &Type) -> Option<Kind> {
+        let path = match *ty {
+            Type::Path(ref path) if path.qself.is_none() => path,
+            _ => return None,
+        };
+        let ident = path.path.get_ident()?.to_string();
+        Some(match ident.as_str() {
+            "bool" => Kind::Bool,
+            "u8" => Kind::Unsigned(8),
+            "u16" => Kind::Unsigned(16),
+            "u32" => Kind::Unsigned(32),
+            "u64" => Kind::Unsigned(64),
+            "i8" => Kind::Signed(8),
+            "i16" => Kind::Signed(16),
+            "i32" => Kind::Signed(32),
+            "i64" => Kind::Signed(64),
+            "f32" => Kind::Float(32),
+            "f64" => Kind::Float(64),
+            _ => return None,
+        })
+    }
+
+    fn bits(self) -> u16 {
+        match self {
+            Kind::Bool => 1,
+            Kind::Unsigned(bits) | Kind::Signed(bits) | Kind::Float(bits) => {
+                bits
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ValueType {
+    Unsigned,
+    Signed,
+    Float,
+    Double,
+}
+
+struct Signal {
+    ident: Ident,
+    ty: Type,
+    kind: Kind,
+    start: u16,
+    length: u16,
+    big_endian: bool,
+    value_type: ValueType,
+    scale: f64,
+    offset: f64,
+}
+
+impl Signal {
+    fn parse(field: &syn::Field) -> syn::Result<Signal> {
+        let ident = field.ident.clone().expect("named field");
+        let args = Args::from_attributes(&field.attrs, ident.span())?
+            .ok_or_else(|| {
+                syn::Error::new(
+                    ident.span(),
+                    "missing #[can(start = ...)] attribute",
+                )
+            })?;
+        args.check(&[
+            "start",
+            "length",
+            "big_endian",
+            "little_endian",
+            "signed",
+            "unsigned",
+            "float",
+            "scale",
+            "offset",
+        ])?;
+        let kind = Kind::of(&field.ty).ok_or_else(|| {
+            syn::Error::new_spanned(
+                &field.ty,
+                "unsupported field type, expected bool, an integer or a float",
+            )
+        })?;
+
+        let start = args
+            .integer("start", 511)?
+            .ok_or_else(|| syn::Error::new(args.span, "missing `start`"))?
+            as u16;
+        let length = args
+            .integer("length", 64)?
+            .map(|l| l as u16)
+            .unwrap_or_else(|| kind.bits());
+        if length == 0 {
+            return Err(syn::Error::new(
+                args.span,
+                "`length` must be at least 1",
+            ));
+        }
+        if args.flag("big_endian")? && args.flag("little_endian")? {
+            return Err(syn::Error::new(
+                args.span,
+                "`big_endian` and `little_endian` are exclusive",
+            ));
+        }
+
+        let value_type = match (
+            args.flag("signed")?,
+            args.flag("unsigned")?,
+            args.flag("float")?,
+        ) {
+            (false, false, false) => match kind {
+                Kind::Signed(_) => ValueType::Signed,
+                _ => ValueType::Unsigned,
+            },
+            (true, false, false) => ValueType::Signed,
+            (false, true, false) => ValueType::Unsigned,
+            (false, false, true) => match length {
+                32 => ValueType::Float,
+                64 => ValueType::Double,
+                _ => {
+                    return Err(syn::Error::new(
+                        args.span,
+                        "`float` needs a length of 32 or 64",
+                    ))
+                }
+            },
+            _ => {
+                return Err(syn::Error::new(
+                    args.span,
+                    "`signed`, `unsigned` and `float` are exclusive",
+                ))
+            }
+        };
+
+        let scale = args.number("scale")?.map(|n| n.value).unwrap_or(1.0);
+        let offset = args.number("offset")?.map(|n| n.value).unwrap_or(0.0);
+        if scale == 0.0 {
+            return Err(syn::Error::new(args.span, "`scale` must not be 0"));
+        }
+
+        // Unscaled integers must hold every raw value
+        if scale == 1.0 && offset == 0.0 {
+            let fits = match (kind, value_type) {
+                (Kind::Bool, ValueType::Unsigned) => length == 1,
+                (Kind::Unsigned(bits), ValueType::Unsigned)
+                | (Kind::Signed(bits), ValueType::Signed) => length <= bits,
+                (Kind::Signed(bits), ValueType::Unsigned) => length < bits,
+                (Kind::Float(_), _) => true,
+                _ => false,
+            };
+            if !fits {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    format!(
+                        "type cannot hold the {} bit value of `{}`",
+                        length, ident
+                    ),
+                ));
+            }
+        }
+
+        Ok(Signal {
+            ident,
+            ty: field.ty.clone(),
+            kind,
+            start,
+            length,
+            big_endian: args.flag("big_endian")?,
+            value_type,
+            scale,
+            offset,
+        })
+    }
+
+    /// Position of the `i`th bit, counting from the least significant bit,
+    /// as in `pcan_basic::dbc::Layout`.
+    fn bit_position(&self, i: u16) -> usize {
+        if self.big_endian {
+            let start = self.start as usize;
+            let msb = start / 8 * 8 + 7 - start % 8;
+            let linear = msb + (self.length - 1 - i) as usize;
+            linear / 8 * 8 + 7 - linear % 8
+        } else {
+            self.start as usize + i as usize
+        }
+    }
+
+    fn layout(&self) -> Tokens {
+        let name = self.ident.to_string();
+        let (start, length) = (self.start, self.length);
+        let byte_order = if self.big_endian {
+            quote!(BigEndian)
+        } else {
+            quote!(LittleEndian)
+        };
+        let value_type = match self.value_type {
+            ValueType::Unsigned => quote!(Unsigned),
+            ValueType::Signed => quote!(Signed),
+            ValueType::Float => quote!(Float),
+            ValueType::Double => quote!(Double),
+        };
+        let scale = Literal::f64_unsuffixed(self.scale);
+        let offset = Literal::f64_unsuffixed(self.offset);
+        quote! {
+            ::pcan_basic::dbc::Layout {
+                name: #name,
+                start_bit: #start,
+                length: #length,
+                byte_order: ::pcan_basic::dbc::ByteOrder::#byte_order,
+                value_type: ::pcan_basic::dbc::ValueType::#value_type,
+                factor: #scale,
+                offset: #offset,
+                min: 0.0,
+                max: 0.0,
+            }
+        }
+    }
+
+    fn encode(&self) -> Tokens {
+        let ident = &self.ident;
+        let layout = self.layout();
+        let physical = match self.kind {
+            Kind::Bool => quote!(if self.#ident { 1.0 } else { 0.0 }),
+            _ => quote!(self.#ident as f64),
+        };
+        quote! {
+            {
+                let layout = #layout;
+                let raw = layout.physical_to_raw(#physical)?;
+                layout.insert(data, raw);
+            }
+        }
+    }
+
+    fn decode(&self) -> Tokens {
+        let (ident, ty) = (&self.ident, &self.ty);
+        let layout = self.layout();
+        let value = match self.kind {
+            Kind::Bool => quote!(physical != 0.0),
+            Kind::Unsigned(_) | Kind::Signed(_) => {
+                quote!(physical.round() as #ty)
+            }
+            Kind::Float(_) => quote!(physical as #ty),
+        };
+        quote! {
+            #ident: {
+                let layout = #layout;
+                let raw = layout.extract(data).unwrap_or(0);
+                let physical = layout.raw_to_physical(raw);
+                #value
+            }
+        }
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<Tokens> {
+    let name = &input.ident;
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    name.span(),
+                    "CanMessage needs a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                name.span(),
+                "CanMessage can only be derived for structs",
+            ))
+        }
+    };
+
+    let args =
+        Args::from_attributes(&input.attrs, name.span())?.ok_or_else(|| {
+            syn::Error::new(name.span(), "missing #[can(id = ...)] attribute")
+        })?;
+    args.check(&["id", "extended", "size"])?;
+    let extended = args.flag("extended")?;
+    let max_id = if extended { 0x1FFF_FFFF } else { 0x7FF };
+    let id = args
+        .integer("id", max_id)?
+        .ok_or_else(|| syn::Error::new(args.span, "missing `id`"))?
+        as u32;
+    let size = args.integer("size", 8)?.unwrap_or(8) as usize;
+
+    let signals = fields
+        .iter()
+        .map(Signal::parse)
+        .collect::<syn::Result<Vec<Signal>>>()?;
+
+    // Every bit must lie in the payload and belong to one field
+    let mut owners: HashMap<usize, &Ident> = HashMap::new();
+    for signal in &signals {
+        for i in 0..signal.length {
+            let pos = signal.bit_position(i);
+            if pos >= size * 8 {
+                let message = format!(
+                    "`{}` does not fit in a payload of {} bytes",
+                    signal.ident, size
+                );
+                return Err(syn::Error::new(signal.ident.span(), message));
+            }
+            if let Some(owner) = owners.insert(pos, &signal.ident) {
+                let message = format!(
+                    "`{}` overlaps `{}` at bit {}",
+                    signal.ident, owner, pos
+                );
+                return Err(syn::Error::new(signal.ident.span(), message));
+            }
+        }
+    }
+
+    let encode = signals.iter().map(Signal::encode);
+    let decode = signals.iter().map(Signal::decode);
+    let (impl_generics, ty_generics, where_clause) =
+        input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::pcan_basic::CanMessage for #name #ty_generics #where_clause {
+            const ID: u32 = #id;
+            const EXTENDED: bool = #extended;
+            const SIZE: usize = #size;
+
+            fn encode_payload(&self, data: &mut [u8]) -> ::pcan_basic::Result<()> {
+                #(#encode)*
+                Ok(())
+            }
+
+            fn decode_payload(data: &[u8]) -> Self {
+                #name {
+                    #(#decode,)*
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,84 @@
+extern crate pcan_basic;
+
+use pcan_basic::{CANFrame, CanMessage, ErrorKind};
+
+#[derive(CanMessage, Debug, PartialEq)]
+#[can(id = 0x123)]
+struct Mixed {
+    #[can(start = 0, length = 16, scale = 0.1)]
+    speed: f64,
+    #[can(start = 16, length = 8, scale = 0.5, offset = -10, signed)]
+    temp: f32,
+    #[can(start = 39, length = 12, big_endian)]
+    motorola: u16,
+    #[can(start = 55, length = 4, big_endian)]
+    gear: i8,
+    #[can(start = 60)]
+    flag: bool,
+}
+
+#[derive(CanMessage, Debug, PartialEq)]
+#[can(id = 0x18FEF1FE, extended, size = 4)]
+struct Ieee {
+    #[can(start = 0, length = 32, float)]
+    value: f32,
+}
+
+#[test]
+fn encode_decode_round_trip() {
+    let mixed = Mixed {
+        speed: 123.4,
+        temp: -20.0,
+        motorola: 0xABC,
+        gear: -1,
+        flag: true,
+    };
+    let frame = mixed.encode().unwrap();
+    assert_eq!(
+        (frame.id(), frame.is_extended(), frame.len()),
+        (0x123, false, 8)
+    );
+    assert_eq!(
+        &frame.data()[..8],
+        &[0xD2, 0x04, 0xEC, 0x00, 0xAB, 0xC0, 0xF0, 0x10]
+    );
+    assert_eq!(Mixed::decode(&frame).unwrap(), mixed);
+
+    let frame = Ieee { value: 1.0 }.encode().unwrap();
+    assert!(frame.is_extended());
+    assert_eq!(
+        &frame.data()[..frame.len() as usize],
+        &[0x00, 0x00, 0x80, 0x3F]
+    );
+    assert_eq!(Ieee::decode(&frame).unwrap().value, 1.0);
+}
+
+#[test]
+fn errors() {
+    let mut mixed = Mixed {
+        speed: 0.0,
+        temp: 0.0,
+        motorola: 0x1000,
+        gear: 0,
+        flag: false,
+    };
+    match mixed.encode().unwrap_err().kind() {
+        ErrorKind::SignalOutOfRange(name, _) => assert_eq!(name, "motorola"),
+        other => panic!("unexpected error {:?}", other),
+    }
+    mixed.motorola = 0;
+    mixed.gear = 8;
+    assert!(mixed.encode().is_err());
+
+    assert!(
+        Mixed::decode(&CANFrame::new(0x124, &[0; 8], false).unwrap()).is_err()
+    );
+    assert!(Mixed::decode(
+        &CANFrame::new_extended(0x123, &[0; 8], false).unwrap()
+    )
+    .is_err());
+    assert!(Ieee::decode(
+        &CANFrame::new_extended(0x18FEF1FE, &[0; 3], false).unwrap()
+    )
+    .is_err());
+}
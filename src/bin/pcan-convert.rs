@@ -0,0 +1,621 @@
+//! Convert CAN traces between the formats supported by `pcan_basic::trace`.
+//!
+//! Records are streamed from the input to the output one at a time, with
+//! optional time slicing, time offset, identifier filtering and channel
+//! remapping applied on the way.
+extern crate pcan_basic;
+
+use pcan_basic::trace::{
+    asc, blf, candump, mdf, pcap, timestamp_from_micros, trc, Record,
+};
+use pcan_basic::Result;
+use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "Usage: pcan-convert [OPTIONS] <INPUT> <OUTPUT>
+
+Convert a CAN trace between formats. The formats are guessed from the file
+extensions unless given explicitly.
+
+Formats: asc, blf, candump (.log), mf4 (.mdf), pcap, pcapng, trc
+
+Options:
+    --from <FORMAT>         Format of the input file
+    --to <FORMAT>           Format of the output file
+    --start <SECONDS>       Drop records before this time
+    --end <SECONDS>         Drop records after this time
+    --offset <SECONDS>      Shift all timestamps, may be negative
+    --ids <LIST>            Only keep these identifiers, e.g. 7E0-7EF,18DAF100
+    --exclude-ids <LIST>    Drop these identifiers
+    --channel <FROM=TO>     Rename a bus channel, may be repeated
+    -h, --help              Show this message
+
+Times are seconds from the start of the input trace and identifiers are
+hexadecimal. Error frames are dropped when --ids is given.";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Asc,
+    Blf,
+    Candump,
+    Mdf,
+    Pcap,
+    PcapNg,
+    Trc,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "asc" => Some(Format::Asc),
+            "blf" => Some(Format::Blf),
+            "candump" | "log" => Some(Format::Candump),
+            "mf4" | "mdf" => Some(Format::Mdf),
+            "pcap" => Some(Format::Pcap),
+            "pcapng" => Some(Format::PcapNg),
+            "trc" => Some(Format::Trc),
+            _ => None,
+        }
+    }
+
+    fn from_path(path: &str) -> Option<Format> {
+        Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Format::from_name)
+    }
+}
+
+/// An inclusive range of identifiers.
+type IdRange = (u32, u32);
+
+struct Options {
+    input: String,
+    output: String,
+    from: Format,
+    to: Format,
+    start: Option<u64>,
+    end: Option<u64>,
+    offset: i64,
+    ids: Vec<IdRange>,
+    exclude_ids: Vec<IdRange>,
+    channels: Vec<(u8, u8)>,
+}
+
+impl Options {
+    /// Parse the command line arguments, `None` if help was asked for.
+    fn parse<I: Iterator<Item = String>>(
+        mut args: I,
+    ) -> Result<Option<Options>> {
+        let mut paths = Vec::new();
+        let mut from = None;
+        let mut to = None;
+        let mut start = None;
+        let mut end = None;
+        let mut offset = 0;
+        let mut ids = Vec::new();
+        let mut exclude_ids = Vec::new();
+        let mut channels = Vec::new();
+
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Ok(None);
+            }
+            if !arg.starts_with("--") {
+                paths.push(arg);
+                continue;
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", arg))?;
+            match arg.as_str() {
+                "--from" => {
+                    from =
+                        Some(Format::from_name(&value).ok_or_else(|| {
+                            format!("unknown format '{}'", value)
+                        })?)
+                }
+                "--to" => {
+                    to =
+                        Some(Format::from_name(&value).ok_or_else(|| {
+                            format!("unknown format '{}'", value)
+                        })?)
+                }
+                "--start" => start = Some(parse_time(&value)?),
+                "--end" => end = Some(parse_time(&value)?),
+                "--offset" => offset = parse_offset(&value)?,
+                "--ids" => ids.extend(parse_ids(&value)?),
+                "--exclude-ids" => exclude_ids.extend(parse_ids(&value)?),
+                "--channel" => channels.push(parse_channel(&value)?),
+                _ => return Err(format!("unknown option {}", arg).into()),
+            }
+        }
+
+        if paths.len() != 2 {
+            return Err("expected an input and an output file".into());
+        }
+        let output = paths.pop().unwrap_or_default();
+        let input = paths.pop().unwrap_or_default();
+        let from = match from.or_else(|| Format::from_path(&input)) {
+            Some(format) => format,
+            None => {
+                return Err(format!(
+                    "cannot guess the format of '{}', use --from",
+                    input
+                )
+                .into())
+            }
+        };
+        let to = match to.or_else(|| Format::from_path(&output)) {
+            Some(format) => format,
+            None => {
+                return Err(format!(
+                    "cannot guess the format of '{}', use --to",
+                    output
+                )
+                .into())
+            }
+        };
+
+        Ok(Some(Options {
+            input,
+            output,
+            from,
+            to,
+            start,
+            end,
+            offset,
+            ids,
+            exclude_ids,
+            channels,
+        }))
+    }
+
+    /// Apply slicing, offset, filters and channel mapping to a record whose
+    /// timestamp is relative to the start of the input. Returns `None` if
+    /// the record is dropped.
+    fn apply(&self, mut record: Record) -> Option<Record> {
+        let micros = record.micros();
+        if self.start.is_some_and(|start| micros < start)
+            || self.end.is_some_and(|end| micros > end)
+        {
+            return None;
+        }
+
+        let shifted = micros as i64 + self.offset;
+        if shifted < 0 {
+            return None;
+        }
+        record.timestamp = timestamp_from_micros(shifted as u64);
+
+        let contains = |ranges: &[IdRange], id: u32| {
+            ranges.iter().any(|&(lo, hi)| lo <= id && id <= hi)
+        };
+        match record.id() {
+            Some(id) => {
+                if (!self.ids.is_empty() && !contains(&self.ids, id))
+                    || contains(&self.exclude_ids, id)
+                {
+                    return None;
+                }
+            }
+            None => {
+                if !self.ids.is_empty() {
+                    return None;
+                }
+            }
+        }
+
+        if let Some(&(_, to)) = self
+            .channels
+            .iter()
+            .find(|&&(from, _)| from == record.channel)
+        {
+            record.channel = to;
+        }
+
+        Some(record)
+    }
+}
+
+/// Parse non-negative decimal seconds into microseconds.
+fn parse_time(text: &str) -> Result<u64> {
+    match parse_offset(text)? {
+        micros if micros >= 0 => Ok(micros as u64),
+        _ => Err(format!("time must not be negative: '{}'", text).into()),
+    }
+}
+
+/// Parse signed decimal seconds into microseconds.
+fn parse_offset(text: &str) -> Result<i64> {
+    let invalid = || format!("invalid time '{}'", text);
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let mut parts = digits.splitn(2, '.');
+    let seconds = parts.next().unwrap_or("");
+    let fraction = parts.next().unwrap_or("");
+    let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if (seconds.is_empty() && fraction.is_empty())
+        || !digits(seconds)
+        || !digits(fraction)
+        || fraction.len() > 6
+    {
+        return Err(invalid().into());
+    }
+
+    let seconds = if seconds.is_empty() {
+        0
+    } else {
+        seconds.parse::<i64>().map_err(|_| invalid())?
+    };
+    let fraction: String =
+        fraction.chars().chain("000000".chars()).take(6).collect();
+    let micros = seconds
+        .checked_mul(1_000_000)
+        .and_then(|micros| micros.checked_add(fraction.parse::<i64>().ok()?))
+        .ok_or_else(invalid)?;
+    Ok(if negative { -micros } else { micros })
+}
+
+/// Parse a comma separated list of hexadecimal identifiers and ranges.
+fn parse_ids(text: &str) -> Result<Vec<IdRange>> {
+    let hex = |s: &str| {
+        let s = s.trim();
+        u32::from_str_radix(
+            s.strip_prefix("0x")
+                .or_else(|| s.strip_prefix("0X"))
+                .unwrap_or(s),
+            16,
+        )
+        .map_err(|_| format!("invalid identifier '{}'", s))
+    };
+
+    text.split(',')
+        .map(|item| {
+            let mut bounds = item.splitn(2, '-');
+            let lo = hex(bounds.next().unwrap_or(""))?;
+            let hi = match bounds.next() {
+                Some(hi) => hex(hi)?,
+                None => lo,
+            };
+            if hi < lo {
+                return Err(format!("empty identifier range '{}'", item).into());
+            }
+            Ok((lo, hi))
+        })
+        .collect()
+}
+
+/// Parse a `FROM=TO` channel mapping, channels counting from 1.
+fn parse_channel(text: &str) -> Result<(u8, u8)> {
+    let invalid = || {
+        format!(
+            "invalid channel mapping '{}', expected FROM=TO with channels \
+             1 to 255",
+            text
+        )
+    };
+    let channel = |s: Option<&str>| {
+        s.and_then(|s| s.trim().parse::<u8>().ok())
+            .filter(|&channel| channel > 0)
+    };
+    let mut parts = text.splitn(2, '=');
+    let from = channel(parts.next()).ok_or_else(invalid)?;
+    let to = channel(parts.next()).ok_or_else(invalid)?;
+    Ok((from, to))
+}
+
+/// An opened input trace.
+struct Input {
+    records: Box<dyn Iterator<Item = Result<Record>>>,
+    start_time: Option<SystemTime>,
+    absolute: bool, // Timestamps are measured from the Unix epoch
+}
+
+impl Input {
+    fn open(path: &str, format: Format) -> Result<Input> {
+        let file = BufReader::new(File::open(path)?);
+        let (records, start_time, absolute): (
+            Box<dyn Iterator<Item = Result<Record>>>,
+            _,
+            _,
+        ) = match format {
+            Format::Asc => (Box::new(asc::Reader::new(file)?), None, false),
+            Format::Blf => {
+                let reader = blf::Reader::new(file)?;
+                let start_time = reader.header().start_time;
+                (Box::new(reader), start_time, false)
+            }
+            Format::Candump => {
+                (Box::new(candump::Reader::new(file)), None, true)
+            }
+            Format::Mdf => {
+                let reader = mdf::Reader::new(file)?;
+                let start_time = Some(reader.start_time());
+                (Box::new(reader), start_time, false)
+            }
+            Format::Pcap | Format::PcapNg => {
+                (Box::new(pcap::Reader::new(file)?), None, true)
+            }
+            Format::Trc => {
+                let reader = trc::Reader::new(file)?;
+                let start_time = reader.start_time();
+                (Box::new(reader), start_time, false)
+            }
+        };
+
+        Ok(Input {
+            records,
+            start_time,
+            absolute,
+        })
+    }
+}
+
+/// An output trace being written.
+enum Output {
+    Asc(asc::Writer<BufWriter<File>>),
+    Blf(blf::Writer<BufWriter<File>>),
+    Candump(candump::Writer<BufWriter<File>>),
+    Mdf(mdf::Writer<BufWriter<File>>),
+    Pcap(pcap::Writer<BufWriter<File>>),
+    Trc(trc::Writer<BufWriter<File>>),
+}
+
+impl Output {
+    fn create(
+        path: &str,
+        format: Format,
+        start_time: SystemTime,
+    ) -> Result<Output> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match format {
+            Format::Asc => {
+                Output::Asc(asc::Writer::new(file, asc::Header::default())?)
+            }
+            Format::Blf => Output::Blf(blf::Writer::new(file, start_time)?),
+            Format::Candump => {
+                Output::Candump(candump::Writer::new(file, start_time))
+            }
+            Format::Mdf => Output::Mdf(mdf::Writer::new(file, start_time)?),
+            Format::Pcap => Output::Pcap(pcap::Writer::new(
+                file,
+                pcap::Format::Pcap,
+                start_time,
+            )?),
+            Format::PcapNg => Output::Pcap(pcap::Writer::new(
+                file,
+                pcap::Format::PcapNg,
+                start_time,
+            )?),
+            Format::Trc => Output::Trc(trc::Writer::new(file, start_time)?),
+        })
+    }
+
+    fn write_record(&mut self, record: &Record) -> Result<()> {
+        match *self {
+            Output::Asc(ref mut writer) => writer.write_record(record),
+            Output::Blf(ref mut writer) => writer.write_record(record),
+            Output::Candump(ref mut writer) => writer.write_record(record),
+            Output::Mdf(ref mut writer) => writer.write_record(record),
+            Output::Pcap(ref mut writer) => writer.write_record(record),
+            Output::Trc(ref mut writer) => writer.write_record(record),
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Output::Asc(writer) => writer.finish().map(drop),
+            Output::Blf(writer) => writer.finish().map(drop),
+            Output::Candump(writer) => writer.finish().map(drop),
+            Output::Mdf(writer) => writer.finish().map(drop),
+            Output::Pcap(writer) => writer.finish().map(drop),
+            Output::Trc(writer) => writer.finish().map(drop),
+        }
+    }
+}
+
+fn run() -> Result<()> {
+    let options = match Options::parse(env::args().skip(1))? {
+        Some(options) => options,
+        None => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+    let mut input = Input::open(&options.input, options.from)?;
+
+    // Absolute timestamps are made relative to the first record so that
+    // slicing works the same for every format, which means the output can
+    // only be created once that record has been read.
+    let mut base = None;
+    let mut output = None;
+    let mut count = 0u64;
+
+    for record in &mut input.records {
+        let mut record = record?;
+        if input.absolute {
+            let first = *base.get_or_insert_with(|| record.micros());
+            record.timestamp =
+                timestamp_from_micros(record.micros().saturating_sub(first));
+        }
+        if output.is_none() {
+            let start_time = match base {
+                Some(micros) => UNIX_EPOCH + Duration::from_micros(micros),
+                None => input.start_time.unwrap_or(UNIX_EPOCH),
+            };
+            output =
+                Some(Output::create(&options.output, options.to, start_time)?);
+        }
+
+        if let Some(record) = options.apply(record) {
+            if let Some(ref mut output) = output {
+                output.write_record(&record)?;
+            }
+            count += 1;
+        }
+    }
+
+    let output = match output {
+        Some(output) => output,
+        None => Output::create(
+            &options.output,
+            options.to,
+            input.start_time.unwrap_or(UNIX_EPOCH),
+        )?,
+    };
+    output.finish()?;
+    eprintln!("{} records written to {}", count, options.output);
+    Ok(())
+}
+
+fn main() {
+    if let Err(ref e) = run() {
+        eprintln!("pcan-convert: {}", e);
+        for cause in e.iter().skip(1) {
+            eprintln!("  caused by: {}", cause);
+        }
+        eprintln!("\n{}", USAGE.lines().next().unwrap_or(""));
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pcan_basic::trace::{Direction, Event};
+    use pcan_basic::CANFrame;
+
+    fn parse_args(args: &[&str]) -> Result<Option<Options>> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn record(micros: u64, id: Option<u32>, channel: u8) -> Record {
+        let event = match id {
+            Some(id) => {
+                Event::Frame(CANFrame::new(id, &[1, 2], false).unwrap())
+            }
+            None => Event::ErrorFrame,
+        };
+        Record {
+            timestamp: timestamp_from_micros(micros),
+            channel,
+            direction: Direction::Rx,
+            event,
+        }
+    }
+
+    #[test]
+    fn times_and_offsets() {
+        assert_eq!(parse_offset("1.5").unwrap(), 1_500_000);
+        assert_eq!(parse_offset(".000001").unwrap(), 1);
+        assert_eq!(parse_offset("2.").unwrap(), 2_000_000);
+        assert_eq!(parse_offset("-0.25").unwrap(), -250_000);
+        assert_eq!(parse_time("0").unwrap(), 0);
+        for text in &[
+            "",
+            ".",
+            "-",
+            "1.2345678",
+            "1,5",
+            "--5",
+            "-+5",
+            "+5",
+            "1e3",
+            "1.-5",
+            "99999999999999",
+        ] {
+            assert!(parse_offset(text).is_err(), "{}", text);
+        }
+        assert!(parse_time("-1").is_err());
+    }
+
+    #[test]
+    fn ids_and_channels() {
+        assert_eq!(
+            parse_ids("7E0-7EF, 0x18DAF100").unwrap(),
+            vec![(0x7E0, 0x7EF), (0x18DA_F100, 0x18DA_F100)]
+        );
+        assert_eq!(parse_ids("100-100").unwrap(), vec![(0x100, 0x100)]);
+        for text in
+            &["", "7EF-7E0", "7E0-", "xyz", "100,", "1-2-3", "100000000"]
+        {
+            assert!(parse_ids(text).is_err(), "{}", text);
+        }
+
+        assert_eq!(parse_channel("1=2").unwrap(), (1, 2));
+        assert_eq!(parse_channel(" 3 = 255 ").unwrap(), (3, 255));
+        for text in &[
+            "0=1", "1=0", "256=1", "1=256", "-1=2", "1", "1=", "=2", "a=b",
+        ] {
+            assert!(parse_channel(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parse_and_apply_options() {
+        let args = [
+            "in.asc",
+            "--start",
+            "1",
+            "--end",
+            "3",
+            "--offset",
+            "-1.5",
+            "--ids",
+            "100-1FF",
+            "--exclude-ids",
+            "180",
+            "--channel",
+            "1=3",
+            "--to",
+            "trc",
+            "out.log",
+        ];
+        let options = parse_args(&args).unwrap().unwrap();
+        assert_eq!((options.from, options.to), (Format::Asc, Format::Trc));
+
+        let kept = options.apply(record(2_000_000, Some(0x123), 1)).unwrap();
+        assert_eq!((kept.micros(), kept.channel), (500_000, 3));
+        assert_eq!(
+            options
+                .apply(record(2_000_000, Some(0x123), 2))
+                .unwrap()
+                .channel,
+            2
+        );
+        // Before the start, after the end, moved before zero, filtered out
+        assert!(options.apply(record(999_999, Some(0x123), 1)).is_none());
+        assert!(options.apply(record(3_000_001, Some(0x123), 1)).is_none());
+        let options = parse_args(&["--offset", "-1.5", "in.asc", "out.trc"])
+            .unwrap()
+            .unwrap();
+        assert!(options.apply(record(1_499_999, Some(0x123), 1)).is_none());
+        let options = parse_args(&args).unwrap().unwrap();
+        assert!(options.apply(record(2_000_000, Some(0x180), 1)).is_none());
+        assert!(options.apply(record(2_000_000, Some(0x200), 1)).is_none());
+        assert!(options.apply(record(2_000_000, None, 1)).is_none());
+        let options = parse_args(&["in.asc", "out.trc"]).unwrap().unwrap();
+        assert!(options.apply(record(2_000_000, None, 1)).is_some());
+
+        for args in &[
+            &["in.asc"][..],
+            &["in.asc", "out.trc", "extra.blf"],
+            &["in.xyz", "out.trc"],
+            &["in.asc", "out.trc", "--channel"],
+            &["in.asc", "out.trc", "--format", "asc"],
+            &["in.asc", "out.trc", "--to", "csv"],
+            &["in.asc", "out.trc", "--ids", "7EF-7E0"],
+        ] {
+            assert!(parse_args(args).is_err(), "{:?}", args);
+        }
+        assert!(parse_args(&["in.asc", "--help"]).unwrap().is_none());
+    }
+}
@@ -0,0 +1,192 @@
+//! Frame level access to a CAN bus, implemented by `PCANDevice` and by
+//! `VirtualBus` for tests and simulations, so that protocol layers work
+//! with either.
+use errors::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use {CANFrame, CANFrameFd, MessageType, PCANDevice};
+
+pub trait CanBus {
+    /// Next received classic frame, or `None` if no frame is pending.
+    fn receive(&self) -> Result<Option<CANFrame>>;
+
+    fn transmit(&self, frame: &CANFrame) -> Result<()>;
+
+    /// Next received frame in FD form, classic frames included. Buses
+    /// without FD support return their classic frames.
+    fn receive_fd(&self) -> Result<Option<CANFrameFd>> {
+        self.receive().map(|frame| frame.map(CANFrameFd::from))
+    }
+
+    /// Transmit a frame in FD form. Buses without FD support only take
+    /// classic frames.
+    fn transmit_fd(&self, frame: &CANFrameFd) -> Result<()> {
+        self.transmit(&to_classic(frame)?)
+    }
+}
+
+/// The classic frame held by a frame in FD form, if it is not an FD frame.
+fn to_classic(frame: &CANFrameFd) -> Result<CANFrame> {
+    if frame.is_fd() || frame.len() > 8 {
+        bail!(ErrorKind::CANFrame);
+    }
+    let rtr = frame.0.MSGTYPE & u8::from(MessageType::Rtr) != 0;
+    if frame.is_extended() {
+        CANFrame::new_extended(frame.id(), frame.data(), rtr)
+    } else {
+        CANFrame::new(frame.id(), frame.data(), rtr)
+    }
+}
+
+fn is_empty_queue(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::PCAN(PCANStatus::Queue(QueueStatus::RecieveEmpty))
+    )
+}
+
+/// Devices opened in FD mode drop received FD frames when read as classic
+/// ones.
+impl CanBus for PCANDevice {
+    fn receive(&self) -> Result<Option<CANFrame>> {
+        if !self.is_fd() {
+            return match self.read_frame() {
+                Ok((frame, _)) => Ok(Some(frame)),
+                Err(ref e) if is_empty_queue(e) => Ok(None),
+                Err(e) => Err(e),
+            };
+        }
+        while let Some(frame) = self.receive_fd()? {
+            if !frame.is_fd() {
+                return to_classic(&frame).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn transmit(&self, frame: &CANFrame) -> Result<()> {
+        if self.is_fd() {
+            self.write_frame_fd(&mut CANFrameFd::from(*frame))
+        } else {
+            self.write_frame(&mut frame.clone())
+        }
+    }
+
+    fn receive_fd(&self) -> Result<Option<CANFrameFd>> {
+        if !self.is_fd() {
+            return self.receive().map(|frame| frame.map(CANFrameFd::from));
+        }
+        match self.read_frame_fd() {
+            Ok((frame, _)) => Ok(Some(frame)),
+            Err(ref e) if is_empty_queue(e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn transmit_fd(&self, frame: &CANFrameFd) -> Result<()> {
+        if self.is_fd() {
+            self.write_frame_fd(&mut frame.clone())
+        } else {
+            self.transmit(&to_classic(frame)?)
+        }
+    }
+}
+
+impl<B: CanBus + ?Sized> CanBus for &B {
+    fn receive(&self) -> Result<Option<CANFrame>> {
+        (**self).receive()
+    }
+
+    fn transmit(&self, frame: &CANFrame) -> Result<()> {
+        (**self).transmit(frame)
+    }
+
+    fn receive_fd(&self) -> Result<Option<CANFrameFd>> {
+        (**self).receive_fd()
+    }
+
+    fn transmit_fd(&self, frame: &CANFrameFd) -> Result<()> {
+        (**self).transmit_fd(frame)
+    }
+}
+
+impl<B: CanBus + ?Sized> CanBus for Box<B> {
+    fn receive(&self) -> Result<Option<CANFrame>> {
+        (**self).receive()
+    }
+
+    fn transmit(&self, frame: &CANFrame) -> Result<()> {
+        (**self).transmit(frame)
+    }
+
+    fn receive_fd(&self) -> Result<Option<CANFrameFd>> {
+        (**self).receive_fd()
+    }
+
+    fn transmit_fd(&self, frame: &CANFrameFd) -> Result<()> {
+        (**self).transmit_fd(frame)
+    }
+}
+
+/// One endpoint of an in-memory CAN FD bus. Frames transmitted by an
+/// endpoint are received by every other endpoint connected to the same
+/// bus; FD frames are dropped when read as classic ones.
+pub struct VirtualBus {
+    queues: Arc<Mutex<Vec<VecDeque<CANFrameFd>>>>,
+    index: usize,
+}
+
+impl VirtualBus {
+    /// Create a bus with a first endpoint.
+    pub fn new() -> VirtualBus {
+        VirtualBus {
+            queues: Arc::new(Mutex::new(vec![VecDeque::new()])),
+            index: 0,
+        }
+    }
+
+    /// Connect another endpoint to the same bus.
+    pub fn connect(&self) -> VirtualBus {
+        let mut queues = self.queues.lock().unwrap();
+        queues.push(VecDeque::new());
+        VirtualBus {
+            queues: self.queues.clone(),
+            index: queues.len() - 1,
+        }
+    }
+}
+
+impl Default for VirtualBus {
+    fn default() -> VirtualBus {
+        VirtualBus::new()
+    }
+}
+
+impl CanBus for VirtualBus {
+    fn receive(&self) -> Result<Option<CANFrame>> {
+        while let Some(frame) = self.receive_fd()? {
+            if !frame.is_fd() {
+                return to_classic(&frame).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn transmit(&self, frame: &CANFrame) -> Result<()> {
+        self.transmit_fd(&CANFrameFd::from(*frame))
+    }
+
+    fn receive_fd(&self) -> Result<Option<CANFrameFd>> {
+        Ok(self.queues.lock().unwrap()[self.index].pop_front())
+    }
+
+    fn transmit_fd(&self, frame: &CANFrameFd) -> Result<()> {
+        let mut queues = self.queues.lock().unwrap();
+        for (i, queue) in queues.iter_mut().enumerate() {
+            if i != self.index {
+                queue.push_back(*frame);
+            }
+        }
+        Ok(())
+    }
+}
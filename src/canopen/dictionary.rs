@@ -0,0 +1,498 @@
+//! Object dictionary of a node and values read and written by name.
+//!
+//! Objects are keyed by index and subindex. A variable is named by its own
+//! name, and an entry of a record or array by the names of both joined with
+//! a dot, as in `Identity object.Vendor-ID`. Names are case insensitive.
+use super::Master;
+use bus::CanBus;
+use errors::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Boolean,
+    Integer(usize),  // Size in bytes, 1 to 8
+    Unsigned(usize), // Size in bytes, 1 to 8
+    Real32,
+    Real64,
+    VisibleString,
+    OctetString,
+    UnicodeString,
+    Domain,
+    Other(u16),
+}
+
+impl DataType {
+    /// Size in bytes, `None` for strings and domains.
+    pub fn size(&self) -> Option<usize> {
+        match *self {
+            DataType::Boolean => Some(1),
+            DataType::Integer(size) | DataType::Unsigned(size) => Some(size),
+            DataType::Real32 => Some(4),
+            DataType::Real64 => Some(8),
+            _ => None,
+        }
+    }
+}
+
+impl From<u16> for DataType {
+    fn from(code: u16) -> DataType {
+        match code {
+            0x01 => DataType::Boolean,
+            0x02 => DataType::Integer(1),
+            0x03 => DataType::Integer(2),
+            0x04 => DataType::Integer(4),
+            0x05 => DataType::Unsigned(1),
+            0x06 => DataType::Unsigned(2),
+            0x07 => DataType::Unsigned(4),
+            0x08 => DataType::Real32,
+            0x09 => DataType::VisibleString,
+            0x0A => DataType::OctetString,
+            0x0B => DataType::UnicodeString,
+            0x0F => DataType::Domain,
+            0x10 => DataType::Integer(3),
+            0x11 => DataType::Real64,
+            0x12..=0x15 => DataType::Integer(usize::from(code) - 0x0D),
+            0x16 => DataType::Unsigned(3),
+            0x18..=0x1B => DataType::Unsigned(usize::from(code) - 0x13),
+            code => DataType::Other(code),
+        }
+    }
+}
+
+impl From<DataType> for u16 {
+    fn from(data_type: DataType) -> u16 {
+        match data_type {
+            DataType::Boolean => 0x01,
+            DataType::Integer(1) => 0x02,
+            DataType::Integer(2) => 0x03,
+            DataType::Integer(3) => 0x10,
+            DataType::Integer(4) => 0x04,
+            DataType::Integer(size) => size as u16 + 0x0D,
+            DataType::Unsigned(1) => 0x05,
+            DataType::Unsigned(2) => 0x06,
+            DataType::Unsigned(3) => 0x16,
+            DataType::Unsigned(4) => 0x07,
+            DataType::Unsigned(size) => size as u16 + 0x13,
+            DataType::Real32 => 0x08,
+            DataType::Real64 => 0x11,
+            DataType::VisibleString => 0x09,
+            DataType::OctetString => 0x0A,
+            DataType::UnicodeString => 0x0B,
+            DataType::Domain => 0x0F,
+            DataType::Other(code) => code,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessType {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+    ReadWriteRead,  // Read write, mappable to a TPDO
+    ReadWriteWrite, // Read write, mappable to an RPDO
+    Constant,
+}
+
+impl AccessType {
+    pub fn readable(&self) -> bool {
+        *self != AccessType::WriteOnly
+    }
+
+    pub fn writable(&self) -> bool {
+        *self != AccessType::ReadOnly && *self != AccessType::Constant
+    }
+}
+
+impl FromStr for AccessType {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<AccessType> {
+        match text.trim().to_lowercase().as_str() {
+            "ro" => Ok(AccessType::ReadOnly),
+            "wo" => Ok(AccessType::WriteOnly),
+            "rw" => Ok(AccessType::ReadWrite),
+            "rwr" => Ok(AccessType::ReadWriteRead),
+            "rww" => Ok(AccessType::ReadWriteWrite),
+            "const" => Ok(AccessType::Constant),
+            _ => bail!(ErrorKind::EdsFormat(format!(
+                "invalid access type '{}'",
+                text
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Boolean(bool),
+    Integer(i64),
+    Unsigned(u64),
+    Real(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    /// Decode the data of an object of `data_type`. Data longer than the
+    /// type, as from an expedited upload without a size, is truncated.
+    pub fn decode(data_type: DataType, data: &[u8]) -> Result<Value> {
+        if let Some(size) = data_type.size() {
+            if data.len() < size {
+                bail!(ErrorKind::ObjectDictionary(format!(
+                    "{} bytes for a {:?}",
+                    data.len(),
+                    data_type
+                )));
+            }
+        }
+        let raw = |size: usize| {
+            data[..size]
+                .iter()
+                .rev()
+                .fold(0u64, |raw, &byte| raw << 8 | u64::from(byte))
+        };
+        Ok(match data_type {
+            DataType::Boolean => Value::Boolean(data[0] != 0),
+            DataType::Integer(size) => {
+                let shift = 64 - 8 * size;
+                Value::Integer((raw(size) << shift) as i64 >> shift)
+            }
+            DataType::Unsigned(size) => Value::Unsigned(raw(size)),
+            DataType::Real32 => {
+                Value::Real(f64::from(f32::from_bits(raw(4) as u32)))
+            }
+            DataType::Real64 => Value::Real(f64::from_bits(raw(8))),
+            DataType::VisibleString => {
+                let end =
+                    data.iter().position(|&b| b == 0).unwrap_or(data.len());
+                Value::Text(String::from_utf8_lossy(&data[..end]).into_owned())
+            }
+            _ => Value::Bytes(data.to_vec()),
+        })
+    }
+
+    /// Encode the value for an object of `data_type`, failing if it does not
+    /// have the type or is out of its range.
+    pub fn encode(&self, data_type: DataType) -> Result<Vec<u8>> {
+        let integer = match *self {
+            Value::Integer(value) => Some(i128::from(value)),
+            Value::Unsigned(value) => Some(i128::from(value)),
+            _ => None,
+        };
+        let data = match (data_type, self) {
+            (DataType::Boolean, &Value::Boolean(value)) => {
+                Some(vec![u8::from(value)])
+            }
+            (DataType::Integer(size), _) | (DataType::Unsigned(size), _)
+                if integer.is_some() =>
+            {
+                let value = integer.unwrap_or(0);
+                let bits = 8 * size as u32;
+                let range = match data_type {
+                    DataType::Integer(_) => {
+                        -(1i128 << (bits - 1))..=(1i128 << (bits - 1)) - 1
+                    }
+                    _ => 0..=(1i128 << bits) - 1,
+                };
+                if range.contains(&value) {
+                    Some(value.to_le_bytes()[..size].to_vec())
+                } else {
+                    None
+                }
+            }
+            (DataType::Real32, &Value::Real(value)) => {
+                Some((value as f32).to_le_bytes().to_vec())
+            }
+            (DataType::Real64, &Value::Real(value)) => {
+                Some(value.to_le_bytes().to_vec())
+            }
+            (DataType::VisibleString, Value::Text(text)) => {
+                Some(text.as_bytes().to_vec())
+            }
+            (_, Value::Bytes(data))
+                if data_type.size().is_none_or(|size| size == data.len()) =>
+            {
+                Some(data.clone())
+            }
+            _ => None,
+        };
+        data.ok_or_else(|| {
+            ErrorKind::ObjectDictionary(format!(
+                "{} is not a valid {:?}",
+                self, data_type
+            ))
+            .into()
+        })
+    }
+
+    /// Parse a value as written in an EDS file, with `$NODEID` standing
+    /// for `node`.
+    pub fn parse(data_type: DataType, text: &str, node: u8) -> Option<Value> {
+        let text = text.trim();
+        match data_type {
+            DataType::Boolean => parse_integer(text, node)
+                .map(|value| Value::Boolean(value != 0)),
+            DataType::Integer(_) => parse_integer(text, node)
+                .map(|value| Value::Integer(value as i64)),
+            DataType::Unsigned(_) => parse_integer(text, node)
+                .map(|value| Value::Unsigned(value as u64)),
+            DataType::Real32 | DataType::Real64 => {
+                text.parse().ok().map(Value::Real)
+            }
+            DataType::VisibleString => Some(Value::Text(text.to_owned())),
+            _ => (0..text.len())
+                .step_by(2)
+                .map(|i| {
+                    text.get(i..i + 2)
+                        .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                })
+                .collect::<Option<Vec<u8>>>()
+                .map(Value::Bytes),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Boolean(value) => write!(f, "{}", value),
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Unsigned(value) => write!(f, "{}", value),
+            Value::Real(value) => write!(f, "{}", value),
+            Value::Text(ref text) => write!(f, "\"{}\"", text),
+            Value::Bytes(ref data) => {
+                data.iter().try_for_each(|byte| write!(f, "{:02X}", byte))
+            }
+        }
+    }
+}
+
+/// Parse an integer of an EDS file: decimal, hexadecimal with `0x`, octal
+/// with a leading `0`, or a sum with `$NODEID`.
+pub fn parse_integer(text: &str, node: u8) -> Option<i128> {
+    text.split('+').try_fold(0i128, |sum, term| {
+        let term = term.trim();
+        let value = if term.eq_ignore_ascii_case("$NODEID") {
+            i128::from(node)
+        } else if let Some(hex) =
+            term.strip_prefix("0x").or_else(|| term.strip_prefix("0X"))
+        {
+            i128::from_str_radix(hex, 16).ok()?
+        } else if term.len() > 1 && term.starts_with('0') {
+            i128::from_str_radix(&term[1..], 8).ok()?
+        } else {
+            term.parse().ok()?
+        };
+        Some(sum + value)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub index: u16,
+    pub subindex: u8,
+    pub name: String,
+    pub parent: Option<String>, // Name of the record or array holding the entry
+    pub data_type: DataType,
+    pub access: AccessType,
+    pub default_value: String, // As written in the file
+    pub parameter_value: String, // Configured value of a DCF
+    pub pdo_mappable: bool,
+}
+
+impl Object {
+    /// Name the object is found by.
+    pub fn full_name(&self) -> String {
+        match self.parent {
+            Some(ref parent) => format!("{}.{}", parent, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Default value on `node`, if there is one.
+    pub fn default(&self, node: u8) -> Option<Value> {
+        if self.default_value.is_empty() {
+            return None;
+        }
+        Value::parse(self.data_type, &self.default_value, node)
+    }
+
+    /// Value configured in a DCF for `node`, or else the default value.
+    pub fn value(&self, node: u8) -> Option<Value> {
+        if self.parameter_value.is_empty() {
+            return self.default(node);
+        }
+        Value::parse(self.data_type, &self.parameter_value, node)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectDictionary {
+    pub node_id: Option<u8>, // Configured in a DCF
+    pub objects: BTreeMap<(u16, u8), Object>,
+}
+
+impl ObjectDictionary {
+    /// Load an EDS or DCF file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ObjectDictionary> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn get(&self, index: u16, subindex: u8) -> Option<&Object> {
+        self.objects.get(&(index, subindex))
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Object> {
+        self.objects
+            .values()
+            .find(|o| o.full_name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl<B: CanBus> Master<B> {
+    /// Read the object named `name` of `node`, using the dictionary set for
+    /// it with `set_dictionary`.
+    pub fn read(&mut self, node: u8, name: &str) -> Result<Value> {
+        let object = self.named_object(node, name)?;
+        if !object.access.readable() {
+            bail!(ErrorKind::ObjectDictionary(format!(
+                "'{}' is write only",
+                name
+            )));
+        }
+        let data = self.upload(node, object.index, object.subindex)?;
+        Value::decode(object.data_type, &data)
+    }
+
+    /// Write the object named `name` of `node`, using the dictionary set for
+    /// it with `set_dictionary`.
+    pub fn write(&mut self, node: u8, name: &str, value: &Value) -> Result<()> {
+        let object = self.named_object(node, name)?;
+        if !object.access.writable() {
+            bail!(ErrorKind::ObjectDictionary(format!(
+                "'{}' is read only",
+                name
+            )));
+        }
+        let data = value.encode(object.data_type)?;
+        self.download(node, object.index, object.subindex, &data)
+    }
+
+    fn named_object(&self, node: u8, name: &str) -> Result<Object> {
+        let dictionary = self.dictionary(node).ok_or_else(|| {
+            ErrorKind::ObjectDictionary(format!(
+                "no dictionary for node {}",
+                node
+            ))
+        })?;
+        let object = dictionary.find(name).ok_or_else(|| {
+            ErrorKind::ObjectDictionary(format!("no object '{}'", name))
+        })?;
+        Ok(object.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::VirtualBus;
+    use testing::{object, sdo_server};
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            Value::decode(DataType::Integer(2), &[0x18, 0xFC]).unwrap(),
+            Value::Integer(-1000)
+        );
+        assert_eq!(
+            Value::decode(DataType::Unsigned(3), &[1, 2, 3, 0]).unwrap(),
+            Value::Unsigned(0x030201)
+        );
+        assert_eq!(
+            Value::decode(DataType::Real32, &1.5f32.to_le_bytes()).unwrap(),
+            Value::Real(1.5)
+        );
+        assert_eq!(
+            Value::decode(DataType::VisibleString, b"PCAN\0\0").unwrap(),
+            Value::Text("PCAN".to_owned())
+        );
+        assert!(Value::decode(DataType::Unsigned(4), &[1, 2]).is_err());
+
+        assert_eq!(
+            Value::Integer(-1000).encode(DataType::Integer(2)).unwrap(),
+            vec![0x18, 0xFC]
+        );
+        assert_eq!(
+            Value::Integer(255).encode(DataType::Unsigned(1)).unwrap(),
+            vec![0xFF]
+        );
+        assert!(Value::Integer(256).encode(DataType::Unsigned(1)).is_err());
+        assert!(Value::Integer(-1).encode(DataType::Unsigned(4)).is_err());
+        assert!(Value::Text("x".to_owned())
+            .encode(DataType::Integer(4))
+            .is_err());
+
+        assert_eq!(parse_integer("$NODEID+0x180", 5), Some(0x185));
+        assert_eq!(parse_integer("010", 5), Some(8));
+        assert_eq!(DataType::from(0x1B), DataType::Unsigned(8));
+        assert_eq!(u16::from(DataType::Integer(8)), 0x15);
+    }
+
+    #[test]
+    fn read_and_write_by_name() {
+        let bus = VirtualBus::new();
+        let script = vec![
+            (
+                [0x40, 0x18, 0x10, 0x01, 0, 0, 0, 0],
+                vec![[0x43, 0x18, 0x10, 0x01, 0x0C, 0x01, 0, 0]],
+            ),
+            (
+                [0x2B, 0x17, 0x10, 0x00, 0xF4, 0x01, 0, 0],
+                vec![[0x60, 0x17, 0x10, 0x00, 0, 0, 0, 0]],
+            ),
+        ];
+        let server = sdo_server(bus.connect(), 2, script);
+
+        let mut dictionary = ObjectDictionary::default();
+        let vendor = Object {
+            parent: Some("Identity object".to_owned()),
+            ..object(
+                0x1018,
+                1,
+                "Vendor-ID",
+                DataType::Unsigned(4),
+                AccessType::ReadOnly,
+            )
+        };
+        let heartbeat = object(
+            0x1017,
+            0,
+            "Producer heartbeat time",
+            DataType::Unsigned(2),
+            AccessType::ReadWrite,
+        );
+        dictionary.objects.insert((0x1018, 1), vendor);
+        dictionary.objects.insert((0x1017, 0), heartbeat);
+        let mut master = Master::new(bus);
+        master.set_dictionary(2, dictionary);
+
+        assert_eq!(
+            master.read(2, "identity object.vendor-id").unwrap(),
+            Value::Unsigned(0x010C)
+        );
+        master
+            .write(2, "Producer heartbeat time", &Value::Unsigned(500))
+            .unwrap();
+        assert!(master
+            .write(2, "Identity object.Vendor-ID", &Value::Unsigned(1))
+            .is_err());
+        assert!(master.read(2, "Manufacturer device name").is_err());
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+//! Object dictionaries from EDS and DCF files (CiA 306).
+//!
+//! Every `[XXXX]` and `[XXXXsubY]` section describes an object: variables
+//! and domains are entered at subindex 0, records and arrays through their
+//! sub-objects. Arrays in the compact form (`CompactSubObj`) are expanded,
+//! their entries named by the `[XXXXName]` section when there is one. A DCF
+//! also gives the node id and the `ParameterValue` of the objects.
+use super::dictionary::parse_integer;
+use super::{AccessType, DataType, Object, ObjectDictionary};
+use errors::*;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+const DOMAIN: i128 = 0x2;
+const ARRAY: i128 = 0x8;
+const RECORD: i128 = 0x9;
+
+type Section = BTreeMap<String, String>;
+
+/// Sections of an INI file, names and keys in lowercase.
+fn sections(text: &str) -> Result<BTreeMap<String, Section>> {
+    let mut sections = BTreeMap::new();
+    let mut current: Option<String> = None;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = line[1..line.len() - 1].trim().to_lowercase();
+            sections.entry(name.clone()).or_insert_with(Section::new);
+            current = Some(name);
+        } else if let (Some((key, value)), Some(name)) =
+            (line.split_once('='), current.as_ref())
+        {
+            let section =
+                sections.entry(name.clone()).or_insert_with(Section::new);
+            section.insert(key.trim().to_lowercase(), value.trim().to_owned());
+        } else {
+            bail!(ErrorKind::EdsFormat(format!(
+                "line {}: expected a section or a key",
+                n + 1
+            )));
+        }
+    }
+    Ok(sections)
+}
+
+/// Index and subindex of an object section.
+fn object_section(name: &str) -> Option<(u16, Option<u8>)> {
+    let (index, subindex) = match name.split_once("sub") {
+        Some((index, subindex)) => {
+            (index, Some(u8::from_str_radix(subindex, 16).ok()?))
+        }
+        None => (name, None),
+    };
+    if index.len() != 4 {
+        return None;
+    }
+    Some((u16::from_str_radix(index, 16).ok()?, subindex))
+}
+
+fn integer(section: &Section, key: &str) -> Result<Option<i128>> {
+    match section.get(key) {
+        Some(text) if !text.is_empty() => {
+            parse_integer(text, 0).map(Some).ok_or_else(|| {
+                ErrorKind::EdsFormat(format!("invalid {} '{}'", key, text))
+                    .into()
+            })
+        }
+        _ => Ok(None),
+    }
+}
+
+fn object(
+    section: &Section,
+    index: u16,
+    subindex: u8,
+    parent: Option<&str>,
+) -> Result<Object> {
+    let error = |reason: &str| {
+        Error::from(ErrorKind::EdsFormat(format!(
+            "object 0x{:04X}sub{}: {}",
+            index, subindex, reason
+        )))
+    };
+    let data_type = match integer(section, "datatype")
+        .map_err(|e| error(&e.to_string()))?
+    {
+        Some(code) => DataType::from(code as u16),
+        None if integer(section, "objecttype")? == Some(DOMAIN) => {
+            DataType::Domain
+        }
+        None => return Err(error("no DataType")),
+    };
+    let access = section
+        .get("accesstype")
+        .ok_or_else(|| error("no AccessType"))?;
+    Ok(Object {
+        index,
+        subindex,
+        name: section.get("parametername").cloned().unwrap_or_default(),
+        parent: parent.map(str::to_owned),
+        data_type,
+        access: access.parse().map_err(|e: Error| error(&e.to_string()))?,
+        default_value: section.get("defaultvalue").cloned().unwrap_or_default(),
+        parameter_value: section
+            .get("parametervalue")
+            .cloned()
+            .unwrap_or_default(),
+        pdo_mappable: integer(section, "pdomapping")?
+            .is_some_and(|mapping| mapping != 0),
+    })
+}
+
+/// Entries of an array in the compact form: the number of entries, then
+/// `count` entries described by the array section itself.
+fn compact_array(
+    sections: &BTreeMap<String, Section>,
+    section: &Section,
+    index: u16,
+    count: u8,
+) -> Result<Vec<Object>> {
+    let parent = section
+        .get("parametername")
+        .map_or("", |name| name.as_str());
+    let names = sections.get(&format!("{:04x}name", index));
+    let values = sections.get(&format!("{:04x}value", index));
+    let mut objects = vec![Object {
+        index,
+        subindex: 0,
+        name: "Number of entries".to_owned(),
+        parent: Some(parent.to_owned()),
+        data_type: DataType::Unsigned(1),
+        access: AccessType::ReadOnly,
+        default_value: count.to_string(),
+        parameter_value: String::new(),
+        pdo_mappable: false,
+    }];
+    for subindex in 1..=count {
+        let mut entry = object(section, index, subindex, Some(parent))?;
+        let key = subindex.to_string();
+        entry.name = names
+            .and_then(|n| n.get(&key))
+            .cloned()
+            .unwrap_or_else(|| format!("{} {}", parent, subindex));
+        entry.parameter_value = values
+            .and_then(|v| v.get(&key))
+            .cloned()
+            .unwrap_or_default();
+        objects.push(entry);
+    }
+    Ok(objects)
+}
+
+impl FromStr for ObjectDictionary {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<ObjectDictionary> {
+        let sections = sections(text)?;
+        let mut dictionary = ObjectDictionary::default();
+        let commissioning = sections
+            .get("devicecomissioning")
+            .or_else(|| sections.get("devicecommissioning"));
+        if let Some(node_id) = commissioning
+            .map(|s| integer(s, "nodeid"))
+            .transpose()?
+            .flatten()
+        {
+            if !(1..=127).contains(&node_id) {
+                bail!(ErrorKind::EdsFormat(format!(
+                    "invalid node id {}",
+                    node_id
+                )));
+            }
+            dictionary.node_id = Some(node_id as u8);
+        }
+
+        for (name, section) in sections.iter() {
+            let objects = match object_section(name) {
+                Some((index, None)) => match integer(section, "objecttype")? {
+                    Some(ARRAY) | Some(RECORD) => {
+                        match integer(section, "compactsubobj")? {
+                            Some(count) if count > 0 => compact_array(
+                                &sections,
+                                section,
+                                index,
+                                count.min(254) as u8,
+                            )?,
+                            _ => continue, // Entries have their own sections
+                        }
+                    }
+                    _ => vec![object(section, index, 0, None)?],
+                },
+                Some((index, Some(subindex))) => {
+                    let parent = sections
+                        .get(&format!("{:04x}", index))
+                        .ok_or_else(|| {
+                            ErrorKind::EdsFormat(format!(
+                                "no section for object 0x{:04X}",
+                                index
+                            ))
+                        })?;
+                    let parent_name = parent
+                        .get("parametername")
+                        .map_or("", |name| name.as_str());
+                    vec![object(section, index, subindex, Some(parent_name))?]
+                }
+                None => continue,
+            };
+            for object in objects {
+                dictionary
+                    .objects
+                    .insert((object.index, object.subindex), object);
+            }
+        }
+        Ok(dictionary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canopen::Value;
+
+    const DCF: &str = "\
+[FileInfo]
+FileName=drive.dcf
+; Comments are skipped
+
+[DeviceComissioning]
+NodeID=0x05
+
+[1000]
+ParameterName=Device type
+ObjectType=0x7
+DataType=0x0007
+AccessType=ro
+DefaultValue=0x00020192
+PDOMapping=0
+
+[1003]
+ParameterName=Pre-defined error field
+ObjectType=0x8
+DataType=0x0007
+AccessType=ro
+CompactSubObj=2
+
+[1003Name]
+NrOfEntries=1
+1=Most recent error
+
+[1018]
+ParameterName=Identity object
+ObjectType=0x9
+SubNumber=2
+
+[1018sub0]
+ParameterName=Highest sub-index supported
+DataType=0x0005
+AccessType=const
+DefaultValue=1
+
+[1018sub1]
+ParameterName=Vendor-ID
+DataType=0x0007
+AccessType=ro
+DefaultValue=0x0000010C
+
+[1800sub1]
+ParameterName=COB-ID used by TPDO 1
+DataType=0x0007
+AccessType=rw
+DefaultValue=$NODEID+0x180
+
+[6040]
+ParameterName=Controlword
+DataType=0x0006
+AccessType=rww
+PDOMapping=1
+DefaultValue=0
+ParameterValue=15
+";
+
+    #[test]
+    fn parse_dcf() {
+        assert!(DCF.parse::<ObjectDictionary>().is_err()); // 0x1800 has no section
+        let dcf = DCF.replace(
+            "[1800sub1]",
+            "[1800]\nParameterName=TPDO 1\nObjectType=0x9\n\n[1800sub1]",
+        );
+        let dictionary: ObjectDictionary = dcf.parse().unwrap();
+        assert_eq!(dictionary.node_id, Some(5));
+        assert_eq!(dictionary.objects.len(), 8);
+
+        let device_type = dictionary.find("device type").unwrap();
+        assert_eq!((device_type.index, device_type.subindex), (0x1000, 0));
+        assert_eq!(
+            (device_type.data_type, device_type.access),
+            (DataType::Unsigned(4), AccessType::ReadOnly)
+        );
+        assert_eq!(device_type.default(5), Some(Value::Unsigned(0x00020192)));
+
+        let entries = dictionary
+            .find("Pre-defined error field.Number of entries")
+            .unwrap();
+        assert_eq!(entries.default(5), Some(Value::Unsigned(2)));
+        assert_eq!(
+            dictionary.get(0x1003, 1).unwrap().name,
+            "Most recent error"
+        );
+        assert_eq!(
+            dictionary.get(0x1003, 2).unwrap().full_name(),
+            "Pre-defined error field.Pre-defined error field 2"
+        );
+        assert_eq!(
+            dictionary
+                .find("Identity object.Vendor-ID")
+                .unwrap()
+                .subindex,
+            1
+        );
+        assert_eq!(
+            dictionary.get(0x1800, 1).unwrap().default(5),
+            Some(Value::Unsigned(0x185))
+        );
+
+        let controlword = dictionary.get(0x6040, 0).unwrap();
+        assert!(controlword.pdo_mappable);
+        assert_eq!(controlword.access, AccessType::ReadWriteWrite);
+        assert_eq!(controlword.value(5), Some(Value::Unsigned(15)));
+
+        assert!("[1000]\nParameterName=Device type\nAccessType=ro\n"
+            .parse::<ObjectDictionary>()
+            .is_err());
+    }
+}
@@ -0,0 +1,356 @@
+//! The master: NMT commands, heartbeat consumer, SYNC producer and frame
+//! dispatch.
+//!
+//! With a SYNC period set, `poll` sends a SYNC when the period has passed
+//! since the last one. Every wait polls the bus; call `poll` regularly
+//! while idle.
+use super::{NmtCommand, NmtState, ObjectDictionary, PdoConfig, PdoKind};
+use super::{HEARTBEAT, NMT, SDO_REQUEST, SDO_RESPONSE, SYNC};
+use bus::CanBus;
+use errors::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::thread;
+use std::time::{Duration, Instant};
+use CANFrame;
+
+/// Pause between polls of the bus while waiting.
+const POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+const DEFAULT_SDO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Segments per block asked for in block uploads.
+const DEFAULT_BLOCK_SIZE: u8 = 127;
+
+/// Frames kept for `receive` before the oldest are dropped.
+const DEFAULT_RECEIVE_CAPACITY: usize = 1024;
+
+/// Change seen by the heartbeat consumer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeartbeatEvent {
+    BootUp { node: u8 },
+    StateChanged { node: u8, state: NmtState },
+    Timeout { node: u8 }, // No heartbeat within the consumer time
+}
+
+#[derive(Default)]
+struct Heartbeat {
+    state: Option<NmtState>,
+    last: Option<Instant>,
+    timeout: Option<Duration>,
+}
+
+pub struct Master<B: CanBus> {
+    bus: B,
+    sdo_timeout: Duration,
+    block_size: u8,
+    heartbeats: BTreeMap<u8, Heartbeat>,
+    events: VecDeque<HeartbeatEvent>,
+    sdo_responses: VecDeque<(u8, [u8; 8])>,
+    received: VecDeque<CANFrame>,
+    receive_capacity: usize,
+    dictionaries: BTreeMap<u8, ObjectDictionary>,
+    pdos: BTreeMap<(u8, PdoKind, u16), PdoConfig>, // By node, kind and number
+    sync_period: Option<Duration>,
+    last_sync: Instant,
+}
+
+impl<B: CanBus> Master<B> {
+    pub fn new(bus: B) -> Master<B> {
+        Master {
+            bus,
+            sdo_timeout: DEFAULT_SDO_TIMEOUT,
+            block_size: DEFAULT_BLOCK_SIZE,
+            heartbeats: BTreeMap::new(),
+            events: VecDeque::new(),
+            sdo_responses: VecDeque::new(),
+            received: VecDeque::new(),
+            receive_capacity: DEFAULT_RECEIVE_CAPACITY,
+            dictionaries: BTreeMap::new(),
+            pdos: BTreeMap::new(),
+            sync_period: None,
+            last_sync: Instant::now(),
+        }
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn into_inner(self) -> B {
+        self.bus
+    }
+
+    pub fn sdo_timeout(&self) -> Duration {
+        self.sdo_timeout
+    }
+
+    /// Time to wait for each SDO response, 1 second by default.
+    pub fn set_sdo_timeout(&mut self, timeout: Duration) {
+        self.sdo_timeout = timeout;
+    }
+
+    pub fn block_size(&self) -> u8 {
+        self.block_size
+    }
+
+    /// Segments per block asked for in block uploads, from 1 to 127.
+    pub fn set_block_size(&mut self, block_size: u8) {
+        self.block_size = block_size.clamp(1, 127);
+    }
+
+    /// Frames kept for `receive`, 1024 by default. Once full, the oldest
+    /// frame is dropped for each new one; 0 keeps none.
+    pub fn set_receive_capacity(&mut self, capacity: usize) {
+        self.receive_capacity = capacity;
+        while self.received.len() > capacity {
+            self.received.pop_front();
+        }
+    }
+
+    /// Use `dictionary` to read and write objects of `node` by name.
+    pub fn set_dictionary(&mut self, node: u8, dictionary: ObjectDictionary) {
+        self.dictionaries.insert(node, dictionary);
+    }
+
+    pub fn dictionary(&self, node: u8) -> Option<&ObjectDictionary> {
+        self.dictionaries.get(&node)
+    }
+
+    /// Let `decode_pdo` and `encode_pdo` use PDO `number` of `node` as
+    /// configured on the node, as `configure_pdo` and `read_pdo` do.
+    pub fn set_pdo(
+        &mut self,
+        node: u8,
+        kind: PdoKind,
+        number: u16,
+        config: PdoConfig,
+    ) {
+        self.pdos.insert((node, kind, number), config);
+    }
+
+    pub fn pdo(
+        &self,
+        node: u8,
+        kind: PdoKind,
+        number: u16,
+    ) -> Option<&PdoConfig> {
+        self.pdos.get(&(node, kind, number))
+    }
+
+    pub fn pdos(&self) -> &BTreeMap<(u8, PdoKind, u16), PdoConfig> {
+        &self.pdos
+    }
+
+    pub fn set_sync_period(&mut self, period: Option<Duration>) {
+        self.sync_period = period;
+    }
+
+    /// Send a SYNC.
+    pub fn sync(&mut self) -> Result<()> {
+        self.last_sync = Instant::now();
+        self.bus.transmit(&CANFrame::new(SYNC, &[], false)?)
+    }
+
+    /// Send an NMT command to `node`, or to every node with `ALL_NODES`.
+    pub fn nmt(&self, command: NmtCommand, node: u8) -> Result<()> {
+        self.bus.transmit(&CANFrame::new(
+            NMT,
+            &[u8::from(command), node],
+            false,
+        )?)
+    }
+
+    /// Expect heartbeats from `node` at least every `timeout`.
+    pub fn add_heartbeat_consumer(&mut self, node: u8, timeout: Duration) {
+        self.heartbeats.entry(node).or_default().timeout = Some(timeout);
+    }
+
+    /// State of `node` from its last heartbeat, `None` if none was received
+    /// or the node timed out.
+    pub fn state(&self, node: u8) -> Option<NmtState> {
+        self.heartbeats.get(&node).and_then(|h| h.state)
+    }
+
+    /// Heartbeat events since the last call.
+    pub fn take_heartbeat_events(&mut self) -> Vec<HeartbeatEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// Send an SDO request frame to `node`.
+    pub fn send_sdo(&self, node: u8, data: &[u8; 8]) -> Result<()> {
+        if node == 0 || node > 127 {
+            bail!(ErrorKind::SdoProtocol(format!("invalid node id {}", node)));
+        }
+        self.bus.transmit(&CANFrame::new(
+            SDO_REQUEST + u32::from(node),
+            data,
+            false,
+        )?)
+    }
+
+    /// Drop the SDO responses from `node` received so far, such as one that
+    /// came after its transfer timed out.
+    pub fn discard_sdo(&mut self, node: u8) -> Result<()> {
+        self.poll()?;
+        self.sdo_responses.retain(|&(n, _)| n != node);
+        Ok(())
+    }
+
+    /// Wait for the next SDO response frame from `node`, up to `timeout`.
+    /// Returns `None` on timeout.
+    pub fn receive_sdo(
+        &mut self,
+        node: u8,
+        timeout: Duration,
+    ) -> Result<Option<[u8; 8]>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(i) =
+                self.sdo_responses.iter().position(|&(n, _)| n == node)
+            {
+                return Ok(self.sdo_responses.remove(i).map(|(_, data)| data));
+            }
+            self.poll()?;
+            if !self.sdo_responses.iter().any(|&(n, _)| n == node) {
+                if Instant::now() >= deadline {
+                    return Ok(None);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+
+    /// Wait for the next frame that is neither an SDO response nor a
+    /// heartbeat, up to `timeout`. Returns `None` on timeout.
+    pub fn receive(&mut self, timeout: Duration) -> Result<Option<CANFrame>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(frame) = self.received.pop_front() {
+                return Ok(Some(frame));
+            }
+            self.poll()?;
+            if self.received.is_empty() {
+                if Instant::now() >= deadline {
+                    return Ok(None);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+
+    /// Handle every frame waiting on the bus, check heartbeat times and
+    /// send a SYNC when due.
+    pub fn poll(&mut self) -> Result<()> {
+        while let Some(frame) = self.bus.receive()? {
+            if !frame.is_rtr() {
+                self.handle_frame(frame);
+            }
+        }
+        if let Some(period) = self.sync_period {
+            let elapsed = self.last_sync.elapsed();
+            if elapsed >= period {
+                // Keep the cadence unless a whole period was missed
+                let last = self.last_sync;
+                self.sync()?;
+                if elapsed < period * 2 {
+                    self.last_sync = last + period;
+                }
+            }
+        }
+        let now = Instant::now();
+        for (&node, heartbeat) in self.heartbeats.iter_mut() {
+            let expired = match (heartbeat.last, heartbeat.timeout) {
+                (Some(last), Some(timeout)) => now > last + timeout,
+                _ => false,
+            };
+            if expired && heartbeat.state.is_some() {
+                heartbeat.state = None;
+                self.events.push_back(HeartbeatEvent::Timeout { node });
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_frame(&mut self, frame: CANFrame) {
+        let id = frame.id();
+        let data = &frame.data()[..frame.len() as usize];
+        match id {
+            _ if frame.is_extended() => self.keep(frame),
+            0x581..=0x5FF => {
+                let mut response = [0u8; 8];
+                response[..data.len()].copy_from_slice(data);
+                self.sdo_responses
+                    .push_back(((id - SDO_RESPONSE) as u8, response));
+            }
+            0x701..=0x77F if !data.is_empty() => {
+                let node = (id - HEARTBEAT) as u8;
+                let state = NmtState::from(data[0]);
+                let heartbeat = self.heartbeats.entry(node).or_default();
+                heartbeat.last = Some(Instant::now());
+                if state == NmtState::BootUp {
+                    self.events.push_back(HeartbeatEvent::BootUp { node });
+                } else if heartbeat.state != Some(state) {
+                    self.events.push_back(HeartbeatEvent::StateChanged {
+                        node,
+                        state,
+                    });
+                }
+                heartbeat.state = Some(state);
+            }
+            _ => self.keep(frame),
+        }
+    }
+
+    fn keep(&mut self, frame: CANFrame) {
+        if self.receive_capacity == 0 {
+            return;
+        }
+        if self.received.len() == self.receive_capacity {
+            self.received.pop_front();
+        }
+        self.received.push_back(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::VirtualBus;
+
+    #[test]
+    fn sync_producer() {
+        let bus = VirtualBus::new();
+        let device = bus.connect();
+        let mut master = Master::new(bus);
+        let period = Duration::from_millis(10);
+        master.set_sync_period(Some(period));
+        let syncs = |device: &VirtualBus| {
+            let mut syncs = 0;
+            while let Some(frame) = device.receive().unwrap() {
+                assert_eq!((frame.id(), frame.len()), (SYNC, 0));
+                syncs += 1;
+            }
+            syncs
+        };
+
+        master.poll().unwrap();
+        assert_eq!(syncs(&device), 0);
+
+        // A SYNC a little late keeps the cadence
+        let due = Instant::now() - period - period / 2;
+        master.last_sync = due;
+        master.poll().unwrap();
+        assert_eq!((syncs(&device), master.last_sync), (1, due + period));
+
+        // After a missed period the next SYNC follows a period after this one
+        master.last_sync = Instant::now() - period * 3;
+        master.poll().unwrap();
+        assert_eq!(syncs(&device), 1);
+        assert!(master.last_sync.elapsed() < period);
+
+        master.set_sync_period(None);
+        master.last_sync = Instant::now() - period * 3;
+        master.poll().unwrap();
+        assert_eq!(syncs(&device), 0);
+    }
+}
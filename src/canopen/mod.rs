@@ -0,0 +1,145 @@
+//! CANopen (CiA 301) master: NMT, SDO, PDO, SYNC producer, heartbeat
+//! consumer and object dictionaries from EDS and DCF files (CiA 306).
+//!
+//! A `Master` handles every frame it reads: SDO responses go to the
+//! transfer in progress, heartbeats update the state of the nodes it
+//! consumes them from, and any other frame is kept for `receive`, up to a
+//! capacity past which the oldest frames are dropped.
+use std::fmt;
+
+mod dictionary;
+mod eds;
+mod master;
+mod pdo;
+mod sdo;
+
+pub use self::dictionary::{
+    AccessType, DataType, Object, ObjectDictionary, Value,
+};
+pub use self::master::{HeartbeatEvent, Master};
+pub use self::pdo::{DecodedPdo, PdoConfig, PdoKind, PdoMapping};
+pub use self::sdo::crc16;
+
+pub const NMT: u32 = 0x000;
+pub const SYNC: u32 = 0x080;
+pub const EMERGENCY: u32 = 0x080; // Plus the node id
+pub const SDO_RESPONSE: u32 = 0x580; // Plus the node id
+pub const SDO_REQUEST: u32 = 0x600; // Plus the node id
+pub const HEARTBEAT: u32 = 0x700; // Plus the node id
+
+/// Node id addressing every node in NMT commands.
+pub const ALL_NODES: u8 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmtCommand {
+    Start,
+    Stop,
+    EnterPreOperational,
+    ResetNode,
+    ResetCommunication,
+}
+
+impl From<NmtCommand> for u8 {
+    fn from(command: NmtCommand) -> u8 {
+        match command {
+            NmtCommand::Start => 0x01,
+            NmtCommand::Stop => 0x02,
+            NmtCommand::EnterPreOperational => 0x80,
+            NmtCommand::ResetNode => 0x81,
+            NmtCommand::ResetCommunication => 0x82,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmtState {
+    BootUp,
+    Stopped,
+    Operational,
+    PreOperational,
+    Other(u8),
+}
+
+impl From<u8> for NmtState {
+    fn from(state: u8) -> NmtState {
+        match state & 0x7F {
+            0x00 => NmtState::BootUp,
+            0x04 => NmtState::Stopped,
+            0x05 => NmtState::Operational,
+            0x7F => NmtState::PreOperational,
+            state => NmtState::Other(state),
+        }
+    }
+}
+
+impl From<NmtState> for u8 {
+    fn from(state: NmtState) -> u8 {
+        match state {
+            NmtState::BootUp => 0x00,
+            NmtState::Stopped => 0x04,
+            NmtState::Operational => 0x05,
+            NmtState::PreOperational => 0x7F,
+            NmtState::Other(state) => state,
+        }
+    }
+}
+
+/// Reason given by an SDO abort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbortCode(pub u32);
+
+impl AbortCode {
+    pub const TOGGLE_BIT: AbortCode = AbortCode(0x0503_0000);
+    pub const TIMEOUT: AbortCode = AbortCode(0x0504_0000);
+    pub const INVALID_COMMAND: AbortCode = AbortCode(0x0504_0001);
+    pub const INVALID_BLOCK_SIZE: AbortCode = AbortCode(0x0504_0002);
+    pub const INVALID_SEQUENCE: AbortCode = AbortCode(0x0504_0003);
+    pub const CRC_ERROR: AbortCode = AbortCode(0x0504_0004);
+    pub const OBJECT_DOES_NOT_EXIST: AbortCode = AbortCode(0x0602_0000);
+    pub const SUBINDEX_DOES_NOT_EXIST: AbortCode = AbortCode(0x0609_0011);
+    pub const LENGTH_MISMATCH: AbortCode = AbortCode(0x0607_0010);
+    pub const GENERAL_ERROR: AbortCode = AbortCode(0x0800_0000);
+
+    pub fn description(&self) -> &'static str {
+        match self.0 {
+            0x0503_0000 => "toggle bit not alternated",
+            0x0504_0000 => "SDO protocol timed out",
+            0x0504_0001 => "command specifier not valid or unknown",
+            0x0504_0002 => "invalid block size",
+            0x0504_0003 => "invalid sequence number",
+            0x0504_0004 => "CRC error",
+            0x0504_0005 => "out of memory",
+            0x0601_0000 => "unsupported access to an object",
+            0x0601_0001 => "attempt to read a write only object",
+            0x0601_0002 => "attempt to write a read only object",
+            0x0602_0000 => "object does not exist in the object dictionary",
+            0x0604_0041 => "object cannot be mapped to the PDO",
+            0x0604_0042 => "mapped objects would exceed the PDO length",
+            0x0604_0043 => "general parameter incompatibility",
+            0x0604_0047 => "general internal incompatibility in the device",
+            0x0606_0000 => "access failed due to a hardware error",
+            0x0607_0010 => "data type does not match, length of service parameter does not match",
+            0x0607_0012 => "data type does not match, length of service parameter too high",
+            0x0607_0013 => "data type does not match, length of service parameter too low",
+            0x0609_0011 => "sub-index does not exist",
+            0x0609_0030 => "invalid value for parameter",
+            0x0609_0031 => "value of parameter written too high",
+            0x0609_0032 => "value of parameter written too low",
+            0x0609_0036 => "maximum value is less than minimum value",
+            0x060A_0023 => "resource not available: SDO connection",
+            0x0800_0000 => "general error",
+            0x0800_0020 => "data cannot be transferred or stored to the application",
+            0x0800_0021 => "data cannot be transferred or stored because of local control",
+            0x0800_0022 => "data cannot be transferred or stored because of the present device state",
+            0x0800_0023 => "no object dictionary is present",
+            0x0800_0024 => "no data available",
+            _ => "unknown abort code",
+        }
+    }
+}
+
+impl fmt::Display for AbortCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (0x{:08X})", self.description(), self.0)
+    }
+}
@@ -0,0 +1,644 @@
+//! PDO configuration over SDO and PDO encoding and decoding.
+//!
+//! Mapped objects are packed from the least significant bit of the first
+//! byte, at most 64 bits per PDO. Their data type comes from the dictionary
+//! of the node when it has one; objects it lacks are read as unsigned
+//! integers of the mapped length. A COB-ID above 0x7FF is sent in an
+//! extended frame.
+use super::{AbortCode, DataType, Master, Value};
+use bus::CanBus;
+use errors::*;
+use CANFrame;
+
+/// Set in a COB-ID to disable the PDO.
+const COB_ID_INVALID: u32 = 0x8000_0000;
+/// Set in a COB-ID for a 29-bit identifier.
+const COB_ID_EXTENDED: u32 = 0x2000_0000;
+
+/// A PDO, seen from the node: it receives RPDOs and transmits TPDOs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PdoKind {
+    Receive,
+    Transmit,
+}
+
+impl PdoKind {
+    /// Index of the communication parameters of PDO `number`, counting from
+    /// 1. The mapping parameters are 0x200 above.
+    pub fn parameter_index(&self, number: u16) -> u16 {
+        let base = match *self {
+            PdoKind::Receive => 0x1400,
+            PdoKind::Transmit => 0x1800,
+        };
+        base + number - 1
+    }
+
+    /// COB-ID of the predefined connection set for PDOs 1 to 4.
+    pub fn default_cob_id(&self, number: u16, node: u8) -> Option<u32> {
+        let base = match *self {
+            PdoKind::Receive => 0x200,
+            PdoKind::Transmit => 0x180,
+        };
+        match number {
+            1..=4 => {
+                Some(base + 0x100 * u32::from(number - 1) + u32::from(node))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An object mapped into a PDO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PdoMapping {
+    pub index: u16,
+    pub subindex: u8,
+    pub bits: u8,
+}
+
+impl From<u32> for PdoMapping {
+    fn from(entry: u32) -> PdoMapping {
+        PdoMapping {
+            index: (entry >> 16) as u16,
+            subindex: (entry >> 8) as u8,
+            bits: entry as u8,
+        }
+    }
+}
+
+impl From<PdoMapping> for u32 {
+    fn from(mapping: PdoMapping) -> u32 {
+        u32::from(mapping.index) << 16
+            | u32::from(mapping.subindex) << 8
+            | u32::from(mapping.bits)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdoConfig {
+    pub cob_id: u32,
+    pub enabled: bool,
+    pub transmission_type: u8, // 0 to 240 synchronous, 254 and 255 event driven
+    pub inhibit_time: u16,     // In 100 µs, TPDOs only
+    pub event_timer: u16,      // In ms
+    pub mapping: Vec<PdoMapping>,
+}
+
+impl PdoConfig {
+    /// An enabled PDO without inhibit time or event timer.
+    pub fn new(
+        cob_id: u32,
+        transmission_type: u8,
+        mapping: Vec<PdoMapping>,
+    ) -> PdoConfig {
+        PdoConfig {
+            cob_id,
+            enabled: true,
+            transmission_type,
+            inhibit_time: 0,
+            event_timer: 0,
+            mapping,
+        }
+    }
+
+    /// Length of the mapped data in bytes.
+    pub fn len(&self) -> usize {
+        self.bits().div_ceil(8)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mapping.is_empty()
+    }
+
+    fn bits(&self) -> usize {
+        self.mapping.iter().map(|m| usize::from(m.bits)).sum()
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.bits() > 64 || self.mapping.len() > 64 {
+            bail!(ErrorKind::PdoMapping(format!(
+                "{} bits mapped in {} objects",
+                self.bits(),
+                self.mapping.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedPdo {
+    pub node: u8,
+    pub number: u16,
+    pub values: Vec<(PdoMapping, Value)>,
+}
+
+impl DecodedPdo {
+    pub fn get(&self, index: u16, subindex: u8) -> Option<&Value> {
+        self.values
+            .iter()
+            .find(|(m, _)| m.index == index && m.subindex == subindex)
+            .map(|(_, value)| value)
+    }
+}
+
+fn check_number(number: u16) -> Result<()> {
+    if number == 0 || number > 512 {
+        bail!(ErrorKind::PdoMapping(format!(
+            "invalid PDO number {}",
+            number
+        )));
+    }
+    Ok(())
+}
+
+fn mask(bits: usize) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+/// Read the low `bits` of `value` as a two's complement integer.
+fn sign_extend(value: u64, bits: usize) -> i64 {
+    let shift = 64 - bits.min(64) as u32;
+    (value.checked_shl(shift).unwrap_or(0) as i64)
+        .checked_shr(shift)
+        .unwrap_or(0)
+}
+
+impl<B: CanBus> Master<B> {
+    /// Configure PDO `number` of `node`: the PDO is disabled while its
+    /// parameters and mapping are written, then enabled as asked.
+    pub fn configure_pdo(
+        &mut self,
+        node: u8,
+        kind: PdoKind,
+        number: u16,
+        config: PdoConfig,
+    ) -> Result<()> {
+        check_number(number)?;
+        config.check()?;
+        let index = kind.parameter_index(number);
+        let cob_id = if config.cob_id > 0x7FF {
+            config.cob_id | COB_ID_EXTENDED
+        } else {
+            config.cob_id
+        };
+        self.download(
+            node,
+            index,
+            1,
+            &(cob_id | COB_ID_INVALID).to_le_bytes(),
+        )?;
+        self.download(node, index, 2, &[config.transmission_type])?;
+        if kind == PdoKind::Transmit {
+            self.write_optional(node, index, 3, config.inhibit_time)?;
+        }
+        self.write_optional(node, index, 5, config.event_timer)?;
+
+        let mapping_index = index + 0x200;
+        self.download(node, mapping_index, 0, &[0])?;
+        for (subindex, mapping) in (1..).zip(config.mapping.iter()) {
+            self.download(
+                node,
+                mapping_index,
+                subindex,
+                &u32::from(*mapping).to_le_bytes(),
+            )?;
+        }
+        self.download(node, mapping_index, 0, &[config.mapping.len() as u8])?;
+        if config.enabled {
+            self.download(node, index, 1, &cob_id.to_le_bytes())?;
+        }
+        self.set_pdo(node, kind, number, config);
+        Ok(())
+    }
+
+    /// Read the configuration of PDO `number` of `node`, and use it for
+    /// `decode_pdo` and `encode_pdo`.
+    pub fn read_pdo(
+        &mut self,
+        node: u8,
+        kind: PdoKind,
+        number: u16,
+    ) -> Result<PdoConfig> {
+        check_number(number)?;
+        let index = kind.parameter_index(number);
+        let cob_id = self.read_unsigned(node, index, 1)? as u32;
+        let transmission_type = self.read_unsigned(node, index, 2)? as u8;
+        let inhibit_time = match kind {
+            PdoKind::Transmit => self.read_optional(node, index, 3)?,
+            PdoKind::Receive => 0,
+        };
+        let event_timer = self.read_optional(node, index, 5)?;
+        let mapping_index = index + 0x200;
+        let count = self.read_unsigned(node, mapping_index, 0)? as u8;
+        let mapping = (1..=count)
+            .map(|subindex| {
+                Ok(PdoMapping::from(self.read_unsigned(
+                    node,
+                    mapping_index,
+                    subindex,
+                )? as u32))
+            })
+            .collect::<Result<Vec<PdoMapping>>>()?;
+        let config = PdoConfig {
+            cob_id: cob_id
+                & if cob_id & COB_ID_EXTENDED != 0 {
+                    0x1FFF_FFFF
+                } else {
+                    0x7FF
+                },
+            enabled: cob_id & COB_ID_INVALID == 0,
+            transmission_type,
+            inhibit_time,
+            event_timer,
+            mapping,
+        };
+        self.set_pdo(node, kind, number, config.clone());
+        Ok(config)
+    }
+
+    /// Decode a frame of an enabled TPDO, returning `None` for any other
+    /// frame.
+    pub fn decode_pdo(&self, frame: &CANFrame) -> Result<Option<DecodedPdo>> {
+        let pdo = self.pdos().iter().find(|(&(_, kind, _), config)| {
+            kind == PdoKind::Transmit
+                && config.enabled
+                && config.cob_id == frame.id()
+                && (config.cob_id > 0x7FF) == frame.is_extended()
+        });
+        let (&(node, _, number), config) = match pdo {
+            Some(pdo) => pdo,
+            None => return Ok(None),
+        };
+        config.check()?;
+        let data = &frame.data()[..frame.len() as usize];
+        if data.len() < config.len() {
+            bail!(ErrorKind::PdoMapping(format!(
+                "TPDO {} of node {} has {} bytes",
+                number,
+                node,
+                data.len()
+            )));
+        }
+        let mut padded = [0u8; 8];
+        padded[..data.len()].copy_from_slice(data);
+        let raw = u64::from_le_bytes(padded);
+
+        let mut values = Vec::with_capacity(config.mapping.len());
+        let mut offset = 0;
+        for mapping in config.mapping.iter().filter(|m| m.bits > 0) {
+            let bits = usize::from(mapping.bits);
+            let data_type = self.mapped_type(node, mapping);
+            let field = raw >> offset & mask(bits);
+            let value = match data_type {
+                // Signed objects may be mapped with fewer bits than their type
+                DataType::Integer(_) => {
+                    Value::Integer(sign_extend(field, bits))
+                }
+                _ => {
+                    let len = data_type
+                        .size()
+                        .unwrap_or_else(|| bits.div_ceil(8))
+                        .min(8);
+                    Value::decode(data_type, &field.to_le_bytes()[..len])?
+                }
+            };
+            values.push((*mapping, value));
+            offset += bits;
+        }
+        Ok(Some(DecodedPdo {
+            node,
+            number,
+            values,
+        }))
+    }
+
+    /// Encode `values` for RPDO `number` of `node`, one for each mapped
+    /// object.
+    pub fn encode_pdo(
+        &self,
+        node: u8,
+        number: u16,
+        values: &[Value],
+    ) -> Result<CANFrame> {
+        let config =
+            self.pdo(node, PdoKind::Receive, number).ok_or_else(|| {
+                ErrorKind::PdoMapping(format!(
+                    "RPDO {} of node {} is not configured",
+                    number, node
+                ))
+            })?;
+        config.check()?;
+        let mapping: Vec<&PdoMapping> =
+            config.mapping.iter().filter(|m| m.bits > 0).collect();
+        if values.len() != mapping.len() {
+            bail!(ErrorKind::PdoMapping(format!(
+                "{} values for {} mapped objects",
+                values.len(),
+                mapping.len()
+            )));
+        }
+        let mut raw = 0u64;
+        let mut offset = 0;
+        for (mapping, value) in mapping.into_iter().zip(values) {
+            let bits = usize::from(mapping.bits);
+            let data_type = self.mapped_type(node, mapping);
+            let data = value.encode(data_type)?;
+            let mut padded = [0u8; 8];
+            padded[..data.len().min(8)]
+                .copy_from_slice(&data[..data.len().min(8)]);
+            let field = u64::from_le_bytes(padded);
+            let fits = match data_type {
+                DataType::Integer(size) => {
+                    let value = sign_extend(field, 8 * size);
+                    sign_extend(value as u64, bits) == value
+                }
+                _ => field & !mask(bits) == 0,
+            };
+            if data.len() > 8 || !fits {
+                bail!(ErrorKind::PdoMapping(format!(
+                    "{} does not fit in {} bits",
+                    value, bits
+                )));
+            }
+            raw |= (field & mask(bits)) << offset;
+            offset += bits;
+        }
+        let data = &raw.to_le_bytes()[..config.len()];
+        if config.cob_id > 0x7FF {
+            CANFrame::new_extended(config.cob_id, data, false)
+        } else {
+            CANFrame::new(config.cob_id, data, false)
+        }
+    }
+
+    /// Encode and send RPDO `number` of `node`.
+    pub fn send_pdo(
+        &mut self,
+        node: u8,
+        number: u16,
+        values: &[Value],
+    ) -> Result<()> {
+        let frame = self.encode_pdo(node, number, values)?;
+        self.bus().transmit(&frame)
+    }
+
+    fn mapped_type(&self, node: u8, mapping: &PdoMapping) -> DataType {
+        let object = self
+            .dictionary(node)
+            .and_then(|d| d.get(mapping.index, mapping.subindex));
+        match object {
+            Some(object) => object.data_type,
+            None if mapping.bits == 1 => DataType::Boolean,
+            None => {
+                DataType::Unsigned(usize::from(mapping.bits).div_ceil(8).min(8))
+            }
+        }
+    }
+
+    fn read_unsigned(
+        &mut self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+    ) -> Result<u64> {
+        let data = self.upload(node, index, subindex)?;
+        Ok(data
+            .iter()
+            .take(8)
+            .rev()
+            .fold(0u64, |value, &byte| value << 8 | u64::from(byte)))
+    }
+
+    /// Read a parameter nodes may lack, as 0 if they do.
+    fn read_optional(
+        &mut self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+    ) -> Result<u16> {
+        match self.read_unsigned(node, index, subindex) {
+            Err(Error(
+                ErrorKind::SdoAbort(_, _, AbortCode::SUBINDEX_DOES_NOT_EXIST),
+                _,
+            )) => Ok(0),
+            result => result.map(|value| value as u16),
+        }
+    }
+
+    /// Write a parameter nodes may lack, unless they do and it is 0.
+    fn write_optional(
+        &mut self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+        value: u16,
+    ) -> Result<()> {
+        match self.download(node, index, subindex, &value.to_le_bytes()) {
+            Err(Error(
+                ErrorKind::SdoAbort(_, _, AbortCode::SUBINDEX_DOES_NOT_EXIST),
+                _,
+            )) if value == 0 => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::VirtualBus;
+    use canopen::{AccessType, ObjectDictionary};
+    use std::time::Duration;
+    use testing::{object, sdo_server};
+
+    #[test]
+    fn configure_and_decode_tpdo() {
+        let bus = VirtualBus::new();
+        let device = bus.connect();
+        let ack = |request: [u8; 8]| {
+            (
+                request,
+                vec![[0x60, request[1], request[2], request[3], 0, 0, 0, 0]],
+            )
+        };
+        let script = vec![
+            ack([0x23, 0x00, 0x18, 0x01, 0x84, 0x01, 0x00, 0x80]),
+            ack([0x2F, 0x00, 0x18, 0x02, 0x01, 0, 0, 0]),
+            // No inhibit time on this node
+            (
+                [0x2B, 0x00, 0x18, 0x03, 0, 0, 0, 0],
+                vec![[0x80, 0x00, 0x18, 0x03, 0x11, 0x00, 0x09, 0x06]],
+            ),
+            ack([0x2B, 0x00, 0x18, 0x05, 0x64, 0, 0, 0]),
+            ack([0x2F, 0x00, 0x1A, 0x00, 0, 0, 0, 0]),
+            ack([0x23, 0x00, 0x1A, 0x01, 0x10, 0x00, 0x41, 0x60]),
+            ack([0x23, 0x00, 0x1A, 0x02, 0x20, 0x00, 0x64, 0x60]),
+            ack([0x2F, 0x00, 0x1A, 0x00, 2, 0, 0, 0]),
+            ack([0x23, 0x00, 0x18, 0x01, 0x84, 0x01, 0x00, 0x00]),
+        ];
+        let server = sdo_server(device.connect(), 4, script);
+
+        let mut dictionary = ObjectDictionary::default();
+        dictionary.objects.insert(
+            (0x6064, 0),
+            object(
+                0x6064,
+                0,
+                "Position actual value",
+                DataType::Integer(4),
+                AccessType::ReadWriteRead,
+            ),
+        );
+        let mut master = Master::new(bus);
+        master.set_dictionary(4, dictionary);
+        let mapping = vec![
+            PdoMapping {
+                index: 0x6041,
+                subindex: 0,
+                bits: 16,
+            },
+            PdoMapping {
+                index: 0x6064,
+                subindex: 0,
+                bits: 32,
+            },
+        ];
+        let mut config = PdoConfig::new(
+            PdoKind::Transmit.default_cob_id(1, 4).unwrap(),
+            1,
+            mapping,
+        );
+        config.event_timer = 100;
+        master
+            .configure_pdo(4, PdoKind::Transmit, 1, config)
+            .unwrap();
+        server.join().unwrap();
+        device
+            .transmit(
+                &CANFrame::new(
+                    0x184,
+                    &[0x37, 0x06, 0xF0, 0xD8, 0xFF, 0xFF],
+                    false,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+
+        let frame = master.receive(Duration::from_secs(1)).unwrap().unwrap();
+        let pdo = master.decode_pdo(&frame).unwrap().unwrap();
+        assert_eq!((pdo.node, pdo.number), (4, 1));
+        assert_eq!(pdo.get(0x6041, 0), Some(&Value::Unsigned(0x0637)));
+        assert_eq!(pdo.get(0x6064, 0), Some(&Value::Integer(-10000)));
+        let other = CANFrame::new(0x284, &[0; 8], false).unwrap();
+        assert_eq!(master.decode_pdo(&other).unwrap(), None);
+        let short = CANFrame::new(0x184, &[0x37, 0x06], false).unwrap();
+        assert!(master.decode_pdo(&short).is_err());
+    }
+
+    #[test]
+    fn encode_rpdo_and_decode_narrow_values() {
+        let bus = VirtualBus::new();
+        let device = bus.connect();
+        let mut master = Master::new(bus);
+        let mapping = vec![
+            PdoMapping {
+                index: 0x6040,
+                subindex: 0,
+                bits: 16,
+            },
+            PdoMapping {
+                index: 0x2000,
+                subindex: 1,
+                bits: 1,
+            },
+            PdoMapping {
+                index: 0x2000,
+                subindex: 2,
+                bits: 7,
+            },
+        ];
+        assert_eq!(u32::from(mapping[0]), 0x6040_0010);
+        assert_eq!(
+            PdoMapping::from(0x2000_0201),
+            PdoMapping {
+                index: 0x2000,
+                subindex: 2,
+                bits: 1
+            }
+        );
+        master.set_pdo(
+            4,
+            PdoKind::Receive,
+            1,
+            PdoConfig::new(0x204, 255, mapping),
+        );
+
+        let values = [
+            Value::Unsigned(0x000F),
+            Value::Boolean(true),
+            Value::Unsigned(0x40),
+        ];
+        master.send_pdo(4, 1, &values).unwrap();
+        let frame = device.receive().unwrap().unwrap();
+        assert_eq!(
+            (frame.id(), &frame.data()[..frame.len() as usize]),
+            (0x204, &[0x0F, 0x00, 0x81][..])
+        );
+        assert!(master
+            .encode_pdo(
+                4,
+                1,
+                &[
+                    Value::Unsigned(1),
+                    Value::Boolean(false),
+                    Value::Unsigned(0x80)
+                ]
+            )
+            .is_err());
+        assert!(master.encode_pdo(4, 1, &values[..2]).is_err());
+        assert!(master.encode_pdo(4, 2, &values).is_err());
+
+        // A 32-bit signed object mapped into 16 bits both ways
+        let mut dictionary = ObjectDictionary::default();
+        dictionary.objects.insert(
+            (0x6064, 0),
+            object(
+                0x6064,
+                0,
+                "Position actual value",
+                DataType::Integer(4),
+                AccessType::ReadWrite,
+            ),
+        );
+        master.set_dictionary(4, dictionary);
+        let position = vec![PdoMapping {
+            index: 0x6064,
+            subindex: 0,
+            bits: 16,
+        }];
+        master.set_pdo(
+            4,
+            PdoKind::Receive,
+            2,
+            PdoConfig::new(0x304, 255, position.clone()),
+        );
+        master.set_pdo(
+            4,
+            PdoKind::Transmit,
+            2,
+            PdoConfig::new(0x284, 255, position),
+        );
+
+        let frame = master.encode_pdo(4, 2, &[Value::Integer(-1000)]).unwrap();
+        assert_eq!(&frame.data()[..frame.len() as usize], &[0x18, 0xFC]);
+        assert!(master.encode_pdo(4, 2, &[Value::Integer(40000)]).is_err());
+        let frame = CANFrame::new(0x284, &[0x18, 0xFC], false).unwrap();
+        let pdo = master.decode_pdo(&frame).unwrap().unwrap();
+        assert_eq!(pdo.get(0x6064, 0), Some(&Value::Integer(-1000)));
+    }
+}
@@ -0,0 +1,773 @@
+//! SDO client transfers: expedited, segmented and block.
+//!
+//! `upload` and `download` use expedited transfers for up to 4 bytes and
+//! segmented ones beyond; the server picks for uploads. Block transfers
+//! check the CRC when the server supports it. A missing response aborts
+//! the transfer with "SDO protocol timed out", and an unexpected one with
+//! the matching abort code.
+use super::{AbortCode, Master};
+use bus::CanBus;
+use errors::*;
+use std::time::Duration;
+
+/// CRC of block transfers: CRC-16-CCITT with polynomial 0x1021 and a zero
+/// initial value.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// First 4 bytes of an initiate or abort frame, followed by `data`.
+fn initiate(command: u8, index: u16, subindex: u8, data: [u8; 4]) -> [u8; 8] {
+    let index = index.to_le_bytes();
+    [
+        command, index[0], index[1], subindex, data[0], data[1], data[2],
+        data[3],
+    ]
+}
+
+/// A segment frame: a command byte and up to 7 bytes of data.
+fn segment(command: u8, data: &[u8]) -> [u8; 8] {
+    let mut frame = [0u8; 8];
+    frame[0] = command;
+    frame[1..=data.len()].copy_from_slice(data);
+    frame
+}
+
+fn le_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[4], data[5], data[6], data[7]])
+}
+
+impl<B: CanBus> Master<B> {
+    /// Read an object of `node`.
+    pub fn upload(
+        &mut self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+    ) -> Result<Vec<u8>> {
+        let response = self.initiate_exchange(
+            node,
+            index,
+            subindex,
+            &initiate(0x40, index, subindex, [0; 4]),
+        )?;
+        self.check(
+            node,
+            index,
+            subindex,
+            &response,
+            0xE0,
+            0x40,
+            AbortCode::INVALID_COMMAND,
+        )?;
+        if response[0] & 0x02 != 0 {
+            let len = if response[0] & 0x01 != 0 {
+                4 - usize::from((response[0] >> 2) & 0x03)
+            } else {
+                4
+            };
+            return Ok(response[4..4 + len].to_vec());
+        }
+        let size = if response[0] & 0x01 != 0 {
+            Some(le_u32(&response) as usize)
+        } else {
+            None
+        };
+
+        let mut data = Vec::with_capacity(size.unwrap_or(0));
+        let mut toggle = 0u8;
+        loop {
+            let response = self.sdo_exchange(
+                node,
+                index,
+                subindex,
+                &segment(0x60 | toggle, &[]),
+            )?;
+            self.check(
+                node,
+                index,
+                subindex,
+                &response,
+                0xE0,
+                0x00,
+                AbortCode::INVALID_COMMAND,
+            )?;
+            self.check(
+                node,
+                index,
+                subindex,
+                &response,
+                0x10,
+                toggle,
+                AbortCode::TOGGLE_BIT,
+            )?;
+            let unused = usize::from((response[0] >> 1) & 0x07);
+            data.extend_from_slice(&response[1..8 - unused]);
+            if response[0] & 0x01 != 0 {
+                break;
+            }
+            toggle ^= 0x10;
+        }
+        self.check_size(node, index, subindex, size, data.len())?;
+        Ok(data)
+    }
+
+    /// Write an object of `node`.
+    pub fn download(
+        &mut self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+        data: &[u8],
+    ) -> Result<()> {
+        if !data.is_empty() && data.len() <= 4 {
+            let mut bytes = [0u8; 4];
+            bytes[..data.len()].copy_from_slice(data);
+            let command = 0x23 | ((4 - data.len()) as u8) << 2;
+            let response = self.initiate_exchange(
+                node,
+                index,
+                subindex,
+                &initiate(command, index, subindex, bytes),
+            )?;
+            return self.check(
+                node,
+                index,
+                subindex,
+                &response,
+                0xE0,
+                0x60,
+                AbortCode::INVALID_COMMAND,
+            );
+        }
+
+        let size = (data.len() as u32).to_le_bytes();
+        let response = self.initiate_exchange(
+            node,
+            index,
+            subindex,
+            &initiate(0x21, index, subindex, size),
+        )?;
+        self.check(
+            node,
+            index,
+            subindex,
+            &response,
+            0xE0,
+            0x60,
+            AbortCode::INVALID_COMMAND,
+        )?;
+        // Empty data, which an expedited transfer cannot express, takes a
+        // single segment without data
+        let segments: Vec<&[u8]> = if data.is_empty() {
+            vec![&[]]
+        } else {
+            data.chunks(7).collect()
+        };
+        let mut toggle = 0u8;
+        for (i, chunk) in segments.iter().enumerate() {
+            let last = if i + 1 == segments.len() { 0x01 } else { 0x00 };
+            let command = toggle | ((7 - chunk.len()) as u8) << 1 | last;
+            let response = self.sdo_exchange(
+                node,
+                index,
+                subindex,
+                &segment(command, chunk),
+            )?;
+            self.check(
+                node,
+                index,
+                subindex,
+                &response,
+                0xE0,
+                0x20,
+                AbortCode::INVALID_COMMAND,
+            )?;
+            self.check(
+                node,
+                index,
+                subindex,
+                &response,
+                0x10,
+                toggle,
+                AbortCode::TOGGLE_BIT,
+            )?;
+            toggle ^= 0x10;
+        }
+        Ok(())
+    }
+
+    /// Read an object of `node` with a block transfer, asking for
+    /// `block_size` segments per block.
+    pub fn block_upload(
+        &mut self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+    ) -> Result<Vec<u8>> {
+        let block_size = self.block_size();
+        let request = initiate(0xA4, index, subindex, [block_size, 0, 0, 0]);
+        let response =
+            self.initiate_exchange(node, index, subindex, &request)?;
+        self.check(
+            node,
+            index,
+            subindex,
+            &response,
+            0xE1,
+            0xC0,
+            AbortCode::INVALID_COMMAND,
+        )?;
+        let crc = response[0] & 0x04 != 0;
+        let size = if response[0] & 0x02 != 0 {
+            Some(le_u32(&response) as usize)
+        } else {
+            None
+        };
+        self.send_sdo(node, &segment(0xA3, &[]))?;
+
+        let mut data = Vec::with_capacity(size.unwrap_or(0));
+        loop {
+            // Segments are acknowledged up to the first one missing, and the
+            // server sends the others again in the next block
+            let mut acknowledged = 0u8;
+            let mut last = false;
+            loop {
+                let frame = self.sdo_response(node, index, subindex)?;
+                let sequence = frame[0] & 0x7F;
+                let accepted = sequence == acknowledged + 1;
+                if accepted {
+                    data.extend_from_slice(&frame[1..]);
+                    acknowledged = sequence;
+                }
+                if frame[0] & 0x80 != 0 {
+                    last = accepted;
+                    break;
+                }
+                if sequence >= block_size {
+                    break;
+                }
+            }
+            self.send_sdo(node, &segment(0xA2, &[acknowledged, block_size]))?;
+            if last {
+                break;
+            }
+        }
+
+        let end = self.sdo_response(node, index, subindex)?;
+        self.check(
+            node,
+            index,
+            subindex,
+            &end,
+            0xE3,
+            0xC1,
+            AbortCode::INVALID_COMMAND,
+        )?;
+        let unused = usize::from((end[0] >> 2) & 0x07);
+        data.truncate(data.len().saturating_sub(unused));
+        if crc && u16::from_le_bytes([end[1], end[2]]) != crc16(&data) {
+            self.abort(node, index, subindex, AbortCode::CRC_ERROR)?;
+            bail!(ErrorKind::SdoProtocol("CRC mismatch".to_owned()));
+        }
+        self.send_sdo(node, &segment(0xA1, &[]))?;
+        self.check_size(node, index, subindex, size, data.len())?;
+        Ok(data)
+    }
+
+    /// Write an object of `node` with a block transfer.
+    pub fn block_download(
+        &mut self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+        data: &[u8],
+    ) -> Result<()> {
+        let size = (data.len() as u32).to_le_bytes();
+        let response = self.initiate_exchange(
+            node,
+            index,
+            subindex,
+            &initiate(0xC6, index, subindex, size),
+        )?;
+        self.check(
+            node,
+            index,
+            subindex,
+            &response,
+            0xE3,
+            0xA0,
+            AbortCode::INVALID_COMMAND,
+        )?;
+        let crc = response[0] & 0x04 != 0;
+        let mut block_size = response[4];
+
+        let segments: Vec<&[u8]> = if data.is_empty() {
+            vec![&[]]
+        } else {
+            data.chunks(7).collect()
+        };
+        let mut sent = 0;
+        while sent < segments.len() {
+            if block_size == 0 || block_size > 127 {
+                self.abort(
+                    node,
+                    index,
+                    subindex,
+                    AbortCode::INVALID_BLOCK_SIZE,
+                )?;
+                bail!(ErrorKind::SdoProtocol(format!(
+                    "invalid block size {}",
+                    block_size
+                )));
+            }
+            let block = &segments
+                [sent..segments.len().min(sent + usize::from(block_size))];
+            for (i, chunk) in block.iter().enumerate() {
+                let last = if sent + i + 1 == segments.len() {
+                    0x80
+                } else {
+                    0x00
+                };
+                self.send_sdo(node, &segment(last | (i + 1) as u8, chunk))?;
+            }
+            let response = self.sdo_response(node, index, subindex)?;
+            self.check(
+                node,
+                index,
+                subindex,
+                &response,
+                0xE3,
+                0xA2,
+                AbortCode::INVALID_COMMAND,
+            )?;
+            // Segments after the last acknowledged one are sent again
+            sent += usize::from(response[1]).min(block.len());
+            block_size = response[2];
+        }
+
+        let unused = (7 - segments[segments.len() - 1].len()) as u8;
+        let crc = if crc { crc16(data) } else { 0 }.to_le_bytes();
+        let response = self.sdo_exchange(
+            node,
+            index,
+            subindex,
+            &segment(0xC1 | unused << 2, &crc),
+        )?;
+        self.check(
+            node,
+            index,
+            subindex,
+            &response,
+            0xE3,
+            0xA1,
+            AbortCode::INVALID_COMMAND,
+        )
+    }
+
+    /// Abort a transfer with `node`.
+    pub fn abort(
+        &self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+        code: AbortCode,
+    ) -> Result<()> {
+        self.send_sdo(
+            node,
+            &initiate(0x80, index, subindex, code.0.to_le_bytes()),
+        )
+    }
+
+    fn sdo_exchange(
+        &mut self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+        request: &[u8; 8],
+    ) -> Result<[u8; 8]> {
+        self.send_sdo(node, request)?;
+        self.sdo_response(node, index, subindex)
+    }
+
+    /// Start a transfer, ignoring responses left over from an earlier one.
+    fn initiate_exchange(
+        &mut self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+        request: &[u8; 8],
+    ) -> Result<[u8; 8]> {
+        self.discard_sdo(node)?;
+        let response = self.sdo_exchange(node, index, subindex, request)?;
+        self.check_multiplexer(node, index, subindex, &response)?;
+        Ok(response)
+    }
+
+    /// Abort unless an initiate response or an abort is for the object of
+    /// the transfer.
+    fn check_multiplexer(
+        &self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+        response: &[u8; 8],
+    ) -> Result<()> {
+        let multiplexer = initiate(0, index, subindex, [0; 4]);
+        if response[1..4] == multiplexer[1..4] {
+            return Ok(());
+        }
+        self.abort(node, index, subindex, AbortCode::GENERAL_ERROR)?;
+        let other = u16::from_le_bytes([response[1], response[2]]);
+        bail!(ErrorKind::SdoProtocol(format!(
+            "response for 0x{:04X}:{}",
+            other, response[3]
+        )))
+    }
+
+    /// Next response of a transfer, failing on abort and timeout.
+    fn sdo_response(
+        &mut self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+    ) -> Result<[u8; 8]> {
+        let timeout: Duration = self.sdo_timeout();
+        match self.receive_sdo(node, timeout)? {
+            Some(response) if response[0] == 0x80 => {
+                self.check_multiplexer(node, index, subindex, &response)?;
+                bail!(ErrorKind::SdoAbort(
+                    index,
+                    subindex,
+                    AbortCode(le_u32(&response))
+                ))
+            }
+            Some(response) => Ok(response),
+            None => {
+                self.abort(node, index, subindex, AbortCode::TIMEOUT)?;
+                bail!(ErrorKind::SdoTimeout(index, subindex))
+            }
+        }
+    }
+
+    /// Abort with `code` unless the bits of `mask` in the command byte of
+    /// `response` equal `expected`.
+    #[allow(clippy::too_many_arguments)]
+    fn check(
+        &self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+        response: &[u8; 8],
+        mask: u8,
+        expected: u8,
+        code: AbortCode,
+    ) -> Result<()> {
+        if response[0] & mask == expected {
+            return Ok(());
+        }
+        self.abort(node, index, subindex, code)?;
+        bail!(ErrorKind::SdoProtocol(format!(
+            "unexpected command byte 0x{:02X}",
+            response[0]
+        )))
+    }
+
+    fn check_size(
+        &self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+        size: Option<usize>,
+        received: usize,
+    ) -> Result<()> {
+        match size {
+            Some(size) if size != received => {
+                self.abort(node, index, subindex, AbortCode::LENGTH_MISMATCH)?;
+                bail!(ErrorKind::SdoProtocol(format!(
+                    "expected {} bytes, received {}",
+                    size, received
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::{CanBus, VirtualBus};
+    use canopen::{HeartbeatEvent, NmtCommand, NmtState};
+    use std::thread;
+    use std::time::Duration;
+    use testing::sdo_server as server;
+    use CANFrame;
+
+    #[test]
+    fn expedited_segmented_and_heartbeats() {
+        let bus = VirtualBus::new();
+        let device = bus.connect();
+        let script = vec![
+            (
+                [0x40, 0x08, 0x10, 0x00, 0, 0, 0, 0],
+                vec![[0x41, 0x08, 0x10, 0x00, 10, 0, 0, 0]],
+            ),
+            (
+                [0x60, 0, 0, 0, 0, 0, 0, 0],
+                vec![[0x00, b'P', b'C', b'A', b'N', b'-', b'D', b'r']],
+            ),
+            (
+                [0x70, 0, 0, 0, 0, 0, 0, 0],
+                vec![[0x19, b'i', b'v', b'e', 0, 0, 0, 0]],
+            ),
+            (
+                [0x40, 0x00, 0x10, 0x00, 0, 0, 0, 0],
+                vec![[0x43, 0x00, 0x10, 0x00, 0x92, 0x01, 0x02, 0x00]],
+            ),
+            (
+                [0x2B, 0x40, 0x60, 0x00, 0x0F, 0x00, 0, 0],
+                vec![[0x60, 0x40, 0x60, 0x00, 0, 0, 0, 0]],
+            ),
+            (
+                [0x21, 0x00, 0x20, 0x01, 9, 0, 0, 0],
+                vec![[0x60, 0x00, 0x20, 0x01, 0, 0, 0, 0]],
+            ),
+            (
+                [0x00, 1, 2, 3, 4, 5, 6, 7],
+                vec![[0x20, 0, 0, 0, 0, 0, 0, 0]],
+            ),
+            (
+                [0x1B, 8, 9, 0, 0, 0, 0, 0],
+                vec![[0x30, 0, 0, 0, 0, 0, 0, 0]],
+            ),
+            // Nothing to write takes a segment without data
+            (
+                [0x21, 0x00, 0x20, 0x02, 0, 0, 0, 0],
+                vec![[0x60, 0x00, 0x20, 0x02, 0, 0, 0, 0]],
+            ),
+            (
+                [0x0F, 0, 0, 0, 0, 0, 0, 0],
+                vec![[0x20, 0, 0, 0, 0, 0, 0, 0]],
+            ),
+            (
+                [0x40, 0x18, 0x10, 0x05, 0, 0, 0, 0],
+                vec![[0x80, 0x18, 0x10, 0x05, 0x11, 0x00, 0x09, 0x06]],
+            ),
+        ];
+        let server = server(device.connect(), 5, script);
+        let mut master = Master::new(bus);
+
+        assert_eq!(
+            master.upload(5, 0x1008, 0).unwrap(),
+            b"PCAN-Drive".to_vec()
+        );
+        assert_eq!(
+            master.upload(5, 0x1000, 0).unwrap(),
+            vec![0x92, 0x01, 0x02, 0x00]
+        );
+        master.download(5, 0x6040, 0, &[0x0F, 0x00]).unwrap();
+        master
+            .download(5, 0x2000, 1, &[1, 2, 3, 4, 5, 6, 7, 8, 9])
+            .unwrap();
+        master.download(5, 0x2000, 2, &[]).unwrap();
+        match master.upload(5, 0x1018, 5) {
+            Err(Error(ErrorKind::SdoAbort(0x1018, 5, code), _)) => {
+                assert_eq!(code, AbortCode::SUBINDEX_DOES_NOT_EXIST)
+            }
+            result => panic!("expected an abort, got {:?}", result),
+        }
+        server.join().unwrap();
+
+        let device = device.connect();
+        master.nmt(NmtCommand::Start, 5).unwrap();
+        let frame = device.receive().unwrap().unwrap();
+        assert_eq!(
+            (frame.id(), &frame.data()[..frame.len() as usize]),
+            (0x000, &[0x01, 0x05][..])
+        );
+        master.add_heartbeat_consumer(5, Duration::from_millis(50));
+        device
+            .transmit(&CANFrame::new(0x705, &[0x00], false).unwrap())
+            .unwrap();
+        device
+            .transmit(&CANFrame::new(0x705, &[0x05], false).unwrap())
+            .unwrap();
+        device
+            .transmit(&CANFrame::new(0x185, &[0x12, 0x34], false).unwrap())
+            .unwrap();
+        assert_eq!(
+            master
+                .receive(Duration::from_millis(10))
+                .unwrap()
+                .unwrap()
+                .id(),
+            0x185
+        );
+        // Past the capacity the oldest frames are dropped
+        master.set_receive_capacity(2);
+        for id in 0x181..0x184 {
+            device
+                .transmit(&CANFrame::new(id, &[], false).unwrap())
+                .unwrap();
+        }
+        master.poll().unwrap();
+        assert_eq!(
+            master
+                .receive(Duration::from_millis(0))
+                .unwrap()
+                .unwrap()
+                .id(),
+            0x182
+        );
+        assert_eq!(
+            master
+                .receive(Duration::from_millis(0))
+                .unwrap()
+                .unwrap()
+                .id(),
+            0x183
+        );
+        assert!(master.receive(Duration::from_millis(0)).unwrap().is_none());
+        assert_eq!(master.state(5), Some(NmtState::Operational));
+        thread::sleep(Duration::from_millis(80));
+        master.poll().unwrap();
+        assert_eq!(master.state(5), None);
+        assert_eq!(
+            master.take_heartbeat_events(),
+            vec![
+                HeartbeatEvent::BootUp { node: 5 },
+                HeartbeatEvent::StateChanged {
+                    node: 5,
+                    state: NmtState::Operational
+                },
+                HeartbeatEvent::Timeout { node: 5 },
+            ]
+        );
+    }
+
+    #[test]
+    fn block_transfers() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        let written: Vec<u8> = (0..20u8).collect();
+        let read: Vec<u8> = (0..10u8).collect();
+        let r: Vec<u8> = (100..117u8).collect();
+        let (write_crc, read_crc) =
+            (crc16(&written).to_le_bytes(), crc16(&read).to_le_bytes());
+        let resent_crc = crc16(&r).to_le_bytes();
+        let w = &written;
+        let script = vec![
+            // Download in blocks of 2 segments, then 1
+            (
+                [0xC6, 0x50, 0x1F, 0x01, 20, 0, 0, 0],
+                vec![[0xA4, 0x50, 0x1F, 0x01, 2, 0, 0, 0]],
+            ),
+            ([0x01, w[0], w[1], w[2], w[3], w[4], w[5], w[6]], vec![]),
+            (
+                [0x02, w[7], w[8], w[9], w[10], w[11], w[12], w[13]],
+                vec![[0xA2, 2, 2, 0, 0, 0, 0, 0]],
+            ),
+            (
+                [0x81, w[14], w[15], w[16], w[17], w[18], w[19], 0],
+                vec![[0xA2, 1, 2, 0, 0, 0, 0, 0]],
+            ),
+            (
+                [0xC5, write_crc[0], write_crc[1], 0, 0, 0, 0, 0],
+                vec![[0xA1, 0, 0, 0, 0, 0, 0, 0]],
+            ),
+            // Upload in a single block of 2 segments, 4 bytes of the last unused
+            (
+                [0xA4, 0x50, 0x1F, 0x01, 4, 0, 0, 0],
+                vec![[0xC6, 0x50, 0x1F, 0x01, 10, 0, 0, 0]],
+            ),
+            (
+                [0xA3, 0, 0, 0, 0, 0, 0, 0],
+                vec![[0x01, 0, 1, 2, 3, 4, 5, 6], [0x82, 7, 8, 9, 0, 0, 0, 0]],
+            ),
+            (
+                [0xA2, 2, 4, 0, 0, 0, 0, 0],
+                vec![[0xD1, read_crc[0], read_crc[1], 0, 0, 0, 0, 0]],
+            ),
+            ([0xA1, 0, 0, 0, 0, 0, 0, 0], vec![]),
+            // The second of 3 segments is lost, the last two are sent again
+            (
+                [0xA4, 0x50, 0x1F, 0x02, 4, 0, 0, 0],
+                vec![[0xC6, 0x50, 0x1F, 0x02, 17, 0, 0, 0]],
+            ),
+            (
+                [0xA3, 0, 0, 0, 0, 0, 0, 0],
+                vec![
+                    [0x01, r[0], r[1], r[2], r[3], r[4], r[5], r[6]],
+                    [0x83, r[14], r[15], r[16], 0, 0, 0, 0],
+                ],
+            ),
+            (
+                [0xA2, 1, 4, 0, 0, 0, 0, 0],
+                vec![
+                    [0x01, r[7], r[8], r[9], r[10], r[11], r[12], r[13]],
+                    [0x82, r[14], r[15], r[16], 0, 0, 0, 0],
+                ],
+            ),
+            (
+                [0xA2, 2, 4, 0, 0, 0, 0, 0],
+                vec![[0xD1, resent_crc[0], resent_crc[1], 0, 0, 0, 0, 0]],
+            ),
+            ([0xA1, 0, 0, 0, 0, 0, 0, 0], vec![]),
+        ];
+        let bus = VirtualBus::new();
+        let server = server(bus.connect(), 3, script);
+        let mut master = Master::new(bus);
+        master.set_block_size(4);
+
+        master.block_download(3, 0x1F50, 1, &written).unwrap();
+        assert_eq!(master.block_upload(3, 0x1F50, 1).unwrap(), read);
+        assert_eq!(master.block_upload(3, 0x1F50, 2).unwrap(), r);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn late_and_mismatched_responses() {
+        let script = vec![
+            // No response aborts the transfer, and the response comes late
+            ([0x40, 0x00, 0x10, 0x00, 0, 0, 0, 0], vec![]),
+            (
+                [0x80, 0x00, 0x10, 0x00, 0x00, 0x00, 0x04, 0x05],
+                vec![[0x43, 0x00, 0x10, 0x00, 0x92, 0x01, 0x02, 0x00]],
+            ),
+            (
+                [0x40, 0x01, 0x10, 0x00, 0, 0, 0, 0],
+                vec![[0x4F, 0x01, 0x10, 0x00, 0x05, 0, 0, 0]],
+            ),
+            // A response for another object aborts the transfer
+            (
+                [0x40, 0x02, 0x10, 0x00, 0, 0, 0, 0],
+                vec![[0x4F, 0x01, 0x10, 0x00, 0x05, 0, 0, 0]],
+            ),
+            ([0x80, 0x02, 0x10, 0x00, 0x00, 0x00, 0x00, 0x08], vec![]),
+        ];
+        let bus = VirtualBus::new();
+        let server = server(bus.connect(), 7, script);
+        let mut master = Master::new(bus);
+        master.set_sdo_timeout(Duration::from_millis(20));
+
+        match master.upload(7, 0x1000, 0) {
+            Err(Error(ErrorKind::SdoTimeout(0x1000, 0), _)) => {}
+            result => panic!("expected a timeout, got {:?}", result),
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(master.upload(7, 0x1001, 0).unwrap(), vec![0x05]);
+        match master.upload(7, 0x1002, 0) {
+            Err(Error(ErrorKind::SdoProtocol(_), _)) => {}
+            result => panic!("expected a protocol error, got {:?}", result),
+        }
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,1014 @@
+//! Generation of typed Rust code from a DBC, meant to be run from a build
+//! script:
+//!
+//! ```no_run
+//! // build.rs
+//! extern crate pcan_basic;
+//!
+//! fn main() {
+//!     let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("network.rs");
+//!     pcan_basic::dbc::codegen::generate("network.dbc", out).unwrap();
+//! }
+//! ```
+//!
+//! and included with `include!(concat!(env!("OUT_DIR"), "/network.rs"));`.
+//!
+//! Each message becomes a struct wrapping its payload, with a getter and a
+//! setter per signal, in physical units, plus `_raw` variants. Signals with
+//! value descriptions get an enum. Multiplexed signals read as `None`
+//! unless selected, and setting them selects them. Messages of up to 8
+//! bytes convert from `&CANFrame` with `TryFrom` and into `CANFrame` with
+//! `From`; longer messages use `CANFrameFd`.
+use dbc::{ByteOrder, Database, Message, Multiplexing, Signal, ValueType};
+use errors::*;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const",
+    "continue", "crate", "do", "dyn", "else", "enum", "extern", "false",
+    "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "macro",
+    "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Constants and methods every message struct has.
+const RESERVED_ITEMS: &[&str] =
+    &["ID", "EXTENDED", "SIZE", "new", "from_payload", "payload"];
+
+/// Read `dbc` and write the generated code to `out`, asking Cargo to rerun
+/// the build script when the DBC changes.
+pub fn generate<P: AsRef<Path>, Q: AsRef<Path>>(dbc: P, out: Q) -> Result<()> {
+    let db = Database::from_file(&dbc)?;
+    let code = Generator::new(&db).generate()?;
+    fs::write(out, code)?;
+    println!("cargo:rerun-if-changed={}", dbc.as_ref().display());
+    Ok(())
+}
+
+/// Code generator for the messages of a database.
+pub struct Generator<'a> {
+    db: &'a Database,
+    krate: String,
+}
+
+/// Names and types chosen for one signal.
+struct SignalCode<'a> {
+    signal: &'a Signal,
+    getter: String,
+    constant: String,
+    raw_type: &'static str,
+    enum_name: Option<String>,
+    physical_type: String,
+    selection: Option<Selection>,
+}
+
+/// Multiplexor switch selecting a signal.
+struct Selection {
+    getter: String,
+    constant: String,
+    ranges: Vec<(u64, u64)>,
+    nested: bool, // The switch is itself multiplexed
+}
+
+impl<'a> Generator<'a> {
+    pub fn new(db: &'a Database) -> Generator<'a> {
+        Generator {
+            db,
+            krate: "::pcan_basic".to_owned(),
+        }
+    }
+
+    /// Path of this crate in the generated code, `::pcan_basic` by
+    /// default.
+    pub fn set_crate_path(&mut self, path: &str) {
+        self.krate = path.to_owned();
+    }
+
+    pub fn generate(&self) -> Result<String> {
+        let mut out = String::new();
+        out.push_str(
+            "// Generated by pcan-basic from a DBC file. Do not edit.\n",
+        );
+        // Type names of the messages and of the signal value enums
+        let mut types = HashSet::new();
+        for message in self.db.messages.values() {
+            let name = unique(camel_case(&message.name, "M"), "", &mut types);
+            self.message(&mut out, message, &name, &mut types)?;
+        }
+        Ok(out)
+    }
+
+    fn message(
+        &self,
+        out: &mut String,
+        message: &Message,
+        name: &str,
+        types: &mut HashSet<String>,
+    ) -> Result<()> {
+        let k = &self.krate;
+        let size = message.size as usize;
+        let signals = self.signals(message, name, types)?;
+
+        for code in &signals {
+            if let Some(ref enum_name) = code.enum_name {
+                self.value_enum(out, code.signal, enum_name, code.raw_type);
+            }
+        }
+
+        // Struct
+        w(out, "");
+        if let Some(ref comment) = message.comment {
+            doc(out, "", comment);
+        }
+        w(out, &format!("/// `{}` (0x{:X})", message.name, message.id));
+        w(out, "#[derive(Debug, Clone, Copy, PartialEq)]");
+        w(out, &format!("pub struct {} {{", name));
+        w(out, &format!("    data: [u8; {}],", size));
+        w(out, "}");
+        w(out, "");
+        w(out, &format!("impl {} {{", name));
+        w(out, &format!("    pub const ID: u32 = 0x{:X};", message.id));
+        w(
+            out,
+            &format!("    pub const EXTENDED: bool = {};", message.extended),
+        );
+        w(out, &format!("    pub const SIZE: usize = {};", size));
+        for code in &signals {
+            self.layout_constant(out, code);
+        }
+
+        // Constructors and payload access
+        w(out, "");
+        w(
+            out,
+            "    /// A message with every signal at its start value.",
+        );
+        w(out, &format!("    pub fn new() -> {} {{", name));
+        let start_values: Vec<(&SignalCode, u64)> = signals
+            .iter()
+            .filter(|c| c.selection.is_none())
+            .map(|c| (c, self.db.start_value(c.signal)))
+            .filter(|&(_, value)| value != 0)
+            .collect();
+        if start_values.is_empty() {
+            w(out, &format!("        {} {{ data: [0; {}] }}", name, size));
+        } else {
+            w(out, &format!("        let mut data = [0; {}];", size));
+            for (code, value) in start_values {
+                w(
+                    out,
+                    &format!(
+                        "        Self::{}.insert(&mut data, {});",
+                        code.constant, value
+                    ),
+                );
+            }
+            w(out, &format!("        {} {{ data }}", name));
+        }
+        w(out, "    }");
+        w(out, "");
+        w(out, "    /// Wrap a payload of at least `SIZE` bytes.");
+        w(
+            out,
+            &format!(
+                "    pub fn from_payload(payload: &[u8]) -> Option<{}> {{",
+                name
+            ),
+        );
+        w(out, "        if payload.len() < Self::SIZE {");
+        w(out, "            return None;");
+        w(out, "        }");
+        w(out, &format!("        let mut data = [0; {}];", size));
+        w(out, "        data.copy_from_slice(&payload[..Self::SIZE]);");
+        w(out, &format!("        Some({} {{ data }})", name));
+        w(out, "    }");
+        w(out, "");
+        w(out, "    pub fn payload(&self) -> &[u8] {");
+        w(out, "        &self.data");
+        w(out, "    }");
+
+        for code in &signals {
+            self.accessors(out, code);
+        }
+        w(out, "}");
+
+        // Conversions
+        w(out, "");
+        w(out, &format!("impl Default for {} {{", name));
+        w(out, &format!("    fn default() -> {} {{", name));
+        w(out, &format!("        {}::new()", name));
+        w(out, "    }");
+        w(out, "}");
+
+        let (frame, constructor) = if size <= 8 {
+            let constructor = if message.extended {
+                format!(
+                    "{}::CANFrame::new_extended({}::ID, &message.data, false)",
+                    k, name
+                )
+            } else {
+                format!(
+                    "{}::CANFrame::new({}::ID, &message.data, false)",
+                    k, name
+                )
+            };
+            (format!("{}::CANFrame", k), constructor)
+        } else {
+            let constructor = format!(
+                "{}::CANFrameFd::new({}::ID, &message.data, {}::EXTENDED, false)",
+                k, name, name
+            );
+            (format!("{}::CANFrameFd", k), constructor)
+        };
+        let (rtr_check, payload) = if size <= 8 {
+            (
+                " || frame.is_rtr()",
+                "&frame.data()[..frame.len() as usize]",
+            )
+        } else {
+            ("", "frame.data()")
+        };
+
+        w(out, "");
+        w(
+            out,
+            &format!(
+                "impl<'a> ::std::convert::TryFrom<&'a {}> for {} {{",
+                frame, name
+            ),
+        );
+        w(out, &format!("    type Error = {}::Error;", k));
+        w(out, "");
+        w(
+            out,
+            &format!(
+                "    fn try_from(frame: &'a {}) -> {}::Result<{}> {{",
+                frame, k, name
+            ),
+        );
+        w(
+            out,
+            &format!(
+                "        if frame.id() != {n}::ID \
+                 || frame.is_extended() != {n}::EXTENDED{} {{",
+                rtr_check,
+                n = name
+            ),
+        );
+        let unexpected =
+            format!("{}::ErrorKind::UnexpectedFrame(frame.id()).into()", k);
+        w(out, &format!("            return Err({});", unexpected));
+        w(out, "        }");
+        w(out, &format!("        {}::from_payload({})", name, payload));
+        w(out, &format!("            .ok_or_else(|| {})", unexpected));
+        w(out, "    }");
+        w(out, "}");
+        w(out, "");
+        w(out, &format!("impl From<{}> for {} {{", name, frame));
+        w(
+            out,
+            &format!("    fn from(message: {}) -> {} {{", name, frame),
+        );
+        w(
+            out,
+            &format!(
+                "        {}.expect(\"payload fits the frame\")",
+                constructor
+            ),
+        );
+        w(out, "    }");
+        w(out, "}");
+
+        Ok(())
+    }
+
+    fn signals<'s>(
+        &self,
+        message: &'s Message,
+        message_name: &str,
+        types: &mut HashSet<String>,
+    ) -> Result<Vec<SignalCode<'s>>> {
+        // Constants and methods of the struct, whatever the signals
+        let mut names: HashSet<String> =
+            RESERVED_ITEMS.iter().map(|&item| item.to_owned()).collect();
+        let mut codes: Vec<SignalCode> = Vec::new();
+
+        for signal in &message.signals {
+            if !signal.layout().fits(message.size as usize) {
+                bail!(ErrorKind::DbcFormat(format!(
+                    "signal {} does not fit in message {}",
+                    signal.name, message.name
+                )));
+            }
+
+            let getter = unique_with(
+                snake_case(&signal.name),
+                "_",
+                &mut names,
+                signal_items,
+            );
+            let constant = constant_name(&getter);
+            let raw_type = raw_type(signal);
+            let integer = signal.value_type == ValueType::Unsigned
+                || signal.value_type == ValueType::Signed;
+            let enum_name = if integer && !signal.value_descriptions.is_empty()
+            {
+                let name = format!(
+                    "{}{}",
+                    message_name,
+                    camel_case(&signal.name, "S")
+                );
+                Some(unique(name, "", types))
+            } else {
+                None
+            };
+            let physical_type = match enum_name {
+                Some(ref name) => name.clone(),
+                None if integer
+                    && signal.factor == 1.0
+                    && signal.offset == 0.0 =>
+                {
+                    raw_type.to_owned()
+                }
+                None => "f64".to_owned(),
+            };
+
+            codes.push(SignalCode {
+                signal,
+                getter,
+                constant,
+                raw_type,
+                enum_name,
+                physical_type,
+                selection: None,
+            });
+        }
+
+        // Resolve multiplexor switches once every constant name is known
+        for i in 0..codes.len() {
+            let selection = message.multiplexor_of(codes[i].signal).and_then(
+                |(switch, ranges)| {
+                    codes.iter().find(|c| c.signal.name == switch.name).map(
+                        |switch_code| {
+                            let nested = switch_code.signal.multiplexing
+                                != Multiplexing::Multiplexor
+                                && message
+                                    .multiplexor_of(switch_code.signal)
+                                    .is_some();
+                            Selection {
+                                getter: switch_code.getter.clone(),
+                                constant: switch_code.constant.clone(),
+                                ranges,
+                                nested,
+                            }
+                        },
+                    )
+                },
+            );
+            codes[i].selection = selection;
+        }
+
+        Ok(codes)
+    }
+
+    fn layout_constant(&self, out: &mut String, code: &SignalCode) {
+        let k = &self.krate;
+        let s = code.signal;
+        let byte_order = match s.byte_order {
+            ByteOrder::LittleEndian => "LittleEndian",
+            ByteOrder::BigEndian => "BigEndian",
+        };
+        let value_type = match s.value_type {
+            ValueType::Unsigned => "Unsigned",
+            ValueType::Signed => "Signed",
+            ValueType::Float => "Float",
+            ValueType::Double => "Double",
+        };
+
+        w(
+            out,
+            &format!(
+                "    const {}: {}::dbc::Layout<'static> = {}::dbc::Layout {{",
+                code.constant, k, k
+            ),
+        );
+        w(out, &format!("        name: {:?},", s.name));
+        w(out, &format!("        start_bit: {},", s.start_bit));
+        w(out, &format!("        length: {},", s.length));
+        w(
+            out,
+            &format!(
+                "        byte_order: {}::dbc::ByteOrder::{},",
+                k, byte_order
+            ),
+        );
+        w(
+            out,
+            &format!(
+                "        value_type: {}::dbc::ValueType::{},",
+                k, value_type
+            ),
+        );
+        w(out, &format!("        factor: {:?},", s.factor));
+        w(out, &format!("        offset: {:?},", s.offset));
+        w(out, &format!("        min: {:?},", s.min));
+        w(out, &format!("        max: {:?},", s.max));
+        w(out, "    };");
+    }
+
+    fn accessors(&self, out: &mut String, code: &SignalCode) {
+        let k = &self.krate;
+        let s = code.signal;
+        let c = &code.constant;
+        let g = &code.getter;
+        let multiplexed = code.selection.is_some();
+        let wrap = |t: &str| {
+            if multiplexed {
+                format!("Option<{}>", t)
+            } else {
+                t.to_owned()
+            }
+        };
+        let bits = format!("Self::{}.extract(&self.data).unwrap_or(0)", c);
+
+        let raw = match s.value_type {
+            ValueType::Unsigned if code.raw_type == "u64" => bits.clone(),
+            ValueType::Unsigned => format!("{} as {}", bits, code.raw_type),
+            ValueType::Signed if code.raw_type == "i64" => {
+                format!("Self::{}.sign_extend({})", c, bits)
+            }
+            ValueType::Signed => format!(
+                "Self::{}.sign_extend({}) as {}",
+                c, bits, code.raw_type
+            ),
+            ValueType::Float => format!("f32::from_bits({} as u32)", bits),
+            ValueType::Double => format!("f64::from_bits({})", bits),
+        };
+        let raw_bits = match s.value_type {
+            ValueType::Unsigned if code.raw_type == "u64" => "raw".to_owned(),
+            ValueType::Unsigned | ValueType::Signed => "raw as u64".to_owned(),
+            ValueType::Float => "raw.to_bits() as u64".to_owned(),
+            ValueType::Double => "raw.to_bits()".to_owned(),
+        };
+        let physical = if code.enum_name.is_some() {
+            format!("{}::from({})", code.physical_type, raw)
+        } else if code.physical_type == "f64" {
+            format!("Self::{}.raw_to_physical({})", c, bits)
+        } else {
+            raw.clone()
+        };
+        let some = |e: &str| {
+            if multiplexed {
+                format!(
+                    "if self.selected_{}() {{ Some({}) }} else {{ None }}",
+                    g, e
+                )
+            } else {
+                e.to_owned()
+            }
+        };
+        let select = if multiplexed {
+            format!("        self.select_{}();\n", g)
+        } else {
+            String::new()
+        };
+
+        // Getters
+        w(out, "");
+        if let Some(ref comment) = s.comment {
+            doc(out, "    ", comment);
+        }
+        let unit = if s.unit.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", s.unit)
+        };
+        if s.min == 0.0 && s.max == 0.0 {
+            w(out, &format!("    /// `{}`{}", s.name, unit));
+        } else {
+            w(
+                out,
+                &format!(
+                    "    /// `{}`{}, from {} to {}",
+                    s.name, unit, s.min, s.max
+                ),
+            );
+        }
+        w(
+            out,
+            &format!(
+                "    pub fn {}(&self) -> {} {{",
+                g,
+                wrap(&code.physical_type)
+            ),
+        );
+        w(out, &format!("        {}", some(&physical)));
+        w(out, "    }");
+        w(out, "");
+        w(
+            out,
+            &format!(
+                "    pub fn {}_raw(&self) -> {} {{",
+                g,
+                wrap(code.raw_type)
+            ),
+        );
+        w(out, &format!("        {}", some(&raw)));
+        w(out, "    }");
+
+        // Setters
+        w(out, "");
+        if code.enum_name.is_some() {
+            w(
+                out,
+                &format!(
+                    "    pub fn set_{}(&mut self, value: {}) {{",
+                    g, code.physical_type
+                ),
+            );
+            w(out, &format!("        self.set_{}_raw(value.into());", g));
+            w(out, "    }");
+        } else {
+            let value = if code.physical_type == "f64" {
+                "value".to_owned()
+            } else {
+                "value as f64".to_owned()
+            };
+            w(
+                out,
+                &format!(
+                    "    pub fn set_{}(&mut self, value: {}) \
+                     -> {}::Result<()> {{",
+                    g, code.physical_type, k
+                ),
+            );
+            w(
+                out,
+                &format!(
+                    "        let raw = Self::{}.physical_to_raw({})?;",
+                    c, value
+                ),
+            );
+            w(
+                out,
+                &format!("        Self::{}.insert(&mut self.data, raw);", c),
+            );
+            out.push_str(&select);
+            w(out, "        Ok(())");
+            w(out, "    }");
+        }
+        w(out, "");
+        w(
+            out,
+            &format!(
+                "    pub fn set_{}_raw(&mut self, raw: {}) {{",
+                g, code.raw_type
+            ),
+        );
+        w(
+            out,
+            &format!(
+                "        Self::{}.insert(&mut self.data, {});",
+                c, raw_bits
+            ),
+        );
+        out.push_str(&select);
+        w(out, "    }");
+
+        // Multiplexor selection
+        if let Some(ref selection) = code.selection {
+            let (switch, switch_constant, ranges, nested) = (
+                &selection.getter,
+                &selection.constant,
+                &selection.ranges,
+                selection.nested,
+            );
+            let condition: Vec<String> = ranges
+                .iter()
+                .map(|&(lo, hi)| {
+                    if lo == hi {
+                        format!("value == {}", lo)
+                    } else {
+                        format!("({}..={}).contains(&value)", lo, hi)
+                    }
+                })
+                .collect();
+            let condition = if condition.is_empty() {
+                "false".to_owned()
+            } else {
+                condition.join(" || ")
+            };
+            let first = ranges.first().map(|r| r.0).unwrap_or(0);
+
+            let extract = format!(
+                "        let value = Self::{}.extract(&self.data).unwrap_or(0);",
+                switch_constant
+            );
+
+            w(out, "");
+            w(out, &format!("    fn selected_{}(&self) -> bool {{", g));
+            w(out, &extract);
+            if nested {
+                w(
+                    out,
+                    &format!(
+                        "        self.selected_{}() && ({})",
+                        switch, condition
+                    ),
+                );
+            } else {
+                w(out, &format!("        {}", condition));
+            }
+            w(out, "    }");
+            w(out, "");
+            w(out, &format!("    fn select_{}(&mut self) {{", g));
+            if nested {
+                w(out, &format!("        self.select_{}();", switch));
+            }
+            w(out, &extract);
+            w(out, &format!("        let selected = {};", condition));
+            w(out, "        if !selected {");
+            w(
+                out,
+                &format!(
+                    "            Self::{}.insert(&mut self.data, {});",
+                    switch_constant, first
+                ),
+            );
+            w(out, "        }");
+            w(out, "    }");
+        }
+    }
+
+    fn value_enum(
+        &self,
+        out: &mut String,
+        signal: &Signal,
+        name: &str,
+        raw_type: &str,
+    ) {
+        let mut variants = HashSet::new();
+        variants.insert("Other".to_owned());
+        let entries: Vec<(String, i64)> = signal
+            .value_descriptions
+            .iter()
+            .filter(|&(&raw, _)| fits_type(raw, raw_type))
+            .map(|(&raw, label)| {
+                let variant = camel_case(label, "V");
+                let variant = if variants.contains(&variant) {
+                    format!("{}{}", variant, raw.unsigned_abs())
+                } else {
+                    variant
+                };
+                (unique(variant, "", &mut variants), raw)
+            })
+            .collect();
+
+        w(out, "");
+        w(out, &format!("/// Values of `{}`", signal.name));
+        w(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq)]");
+        w(out, &format!("pub enum {} {{", name));
+        for (variant, raw) in &entries {
+            w(out, &format!("    {}, // {}", variant, raw));
+        }
+        w(out, &format!("    Other({}),", raw_type));
+        w(out, "}");
+        w(out, "");
+        w(out, &format!("impl From<{}> for {} {{", raw_type, name));
+        w(
+            out,
+            &format!("    fn from(raw: {}) -> {} {{", raw_type, name),
+        );
+        w(out, "        match raw {");
+        for (variant, raw) in &entries {
+            w(
+                out,
+                &format!("            {} => {}::{},", raw, name, variant),
+            );
+        }
+        w(
+            out,
+            &format!("            other => {}::Other(other),", name),
+        );
+        w(out, "        }");
+        w(out, "    }");
+        w(out, "}");
+        w(out, "");
+        w(out, &format!("impl From<{}> for {} {{", name, raw_type));
+        w(
+            out,
+            &format!("    fn from(value: {}) -> {} {{", name, raw_type),
+        );
+        w(out, "        match value {");
+        for (variant, raw) in &entries {
+            w(
+                out,
+                &format!("            {}::{} => {},", name, variant, raw),
+            );
+        }
+        w(out, &format!("            {}::Other(raw) => raw,", name));
+        w(out, "        }");
+        w(out, "    }");
+        w(out, "}");
+    }
+}
+
+fn w(out: &mut String, line: &str) {
+    writeln!(out, "{}", line).expect("writing to a String");
+}
+
+fn doc(out: &mut String, indent: &str, text: &str) {
+    for line in text.lines() {
+        w(out, format!("{}/// {}", indent, line).trim_end());
+    }
+    w(out, &format!("{}///", indent));
+}
+
+/// `name`, numbered from 2 if taken. Numbers are joined with `separator`,
+/// `_` in snake case names and nothing in camel case ones.
+fn unique(
+    name: String,
+    separator: &str,
+    taken: &mut HashSet<String>,
+) -> String {
+    unique_with(name, separator, taken, |name| vec![name.to_owned()])
+}
+
+/// `name`, numbered from 2 until none of the items generated from it is
+/// taken. Those items are then taken.
+fn unique_with<F: Fn(&str) -> Vec<String>>(
+    name: String,
+    separator: &str,
+    taken: &mut HashSet<String>,
+    items: F,
+) -> String {
+    let mut candidate = name.clone();
+    let mut n = 2;
+    while items(&candidate).iter().any(|item| taken.contains(item)) {
+        candidate = format!("{}{}{}", name, separator, n);
+        n += 1;
+    }
+    taken.extend(items(&candidate));
+    candidate
+}
+
+/// Layout constant of the signal with getter `getter`.
+fn constant_name(getter: &str) -> String {
+    getter.trim_end_matches('_').to_uppercase()
+}
+
+/// Constant and methods generated for the signal with getter `getter`.
+fn signal_items(getter: &str) -> Vec<String> {
+    vec![
+        constant_name(getter),
+        getter.to_owned(),
+        format!("{}_raw", getter),
+        format!("set_{}", getter),
+        format!("set_{}_raw", getter),
+        format!("selected_{}", getter),
+        format!("select_{}", getter),
+    ]
+}
+
+/// Split a DBC name into lower case words at `_`, other punctuation and
+/// lower to upper case transitions.
+fn words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let chars: Vec<char> = name.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_ascii_alphanumeric() {
+            if !current.is_empty() {
+                words.push(current.clone());
+                current.clear();
+            }
+            continue;
+        }
+        let boundary = c.is_ascii_uppercase()
+            && i > 0
+            && (chars[i - 1].is_ascii_lowercase()
+                || (chars[i - 1].is_ascii_uppercase()
+                    && chars
+                        .get(i + 1)
+                        .is_some_and(|n| n.is_ascii_lowercase())));
+        if boundary && !current.is_empty() {
+            words.push(current.clone());
+            current.clear();
+        }
+        current.push(c.to_ascii_lowercase());
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = words(name).join("_");
+    if snake.is_empty() || snake.starts_with(|c: char| c.is_ascii_digit()) {
+        snake = format!("_{}", snake);
+    }
+    if KEYWORDS.contains(&snake.as_str()) {
+        snake.push('_');
+    }
+    snake
+}
+
+fn camel_case(name: &str, prefix: &str) -> String {
+    let camel: String = words(name)
+        .iter()
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => {
+                    first.to_ascii_uppercase().to_string() + chars.as_str()
+                }
+                None => String::new(),
+            }
+        })
+        .collect();
+    if camel.is_empty()
+        || camel.starts_with(|c: char| c.is_ascii_digit())
+        || camel == "Self"
+    {
+        format!("{}{}", prefix, camel)
+    } else {
+        camel
+    }
+}
+
+fn raw_type(signal: &Signal) -> &'static str {
+    let width = match signal.length {
+        0..=8 => 8,
+        9..=16 => 16,
+        17..=32 => 32,
+        _ => 64,
+    };
+    match (signal.value_type, width) {
+        (ValueType::Float, _) => "f32",
+        (ValueType::Double, _) => "f64",
+        (ValueType::Unsigned, 8) => "u8",
+        (ValueType::Unsigned, 16) => "u16",
+        (ValueType::Unsigned, 32) => "u32",
+        (ValueType::Unsigned, _) => "u64",
+        (ValueType::Signed, 8) => "i8",
+        (ValueType::Signed, 16) => "i16",
+        (ValueType::Signed, 32) => "i32",
+        (ValueType::Signed, _) => "i64",
+    }
+}
+
+fn fits_type(raw: i64, raw_type: &str) -> bool {
+    match raw_type {
+        "u8" => raw >= 0 && raw <= u8::MAX as i64,
+        "u16" => raw >= 0 && raw <= u16::MAX as i64,
+        "u32" => raw >= 0 && raw <= u32::MAX as i64,
+        "u64" => raw >= 0,
+        "i8" => raw >= i8::MIN as i64 && raw <= i8::MAX as i64,
+        "i16" => raw >= i16::MIN as i64 && raw <= i16::MAX as i64,
+        "i32" => raw >= i32::MIN as i64 && raw <= i32::MAX as i64,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbc::Database;
+    use std::convert::TryFrom;
+    use CANFrame;
+
+    mod generated {
+        include!("testdata/example.rs");
+    }
+    use self::generated::*;
+
+    // Signals named like the items every message struct has
+    mod clashes {
+        include!("testdata/clashes.rs");
+    }
+
+    fn database() -> Database {
+        include_str!("testdata/example.dbc").parse().unwrap()
+    }
+
+    fn generate(dbc: &str) -> String {
+        let db: Database = dbc.parse().unwrap();
+        let mut generator = Generator::new(&db);
+        generator.set_crate_path("crate");
+        generator.generate().unwrap()
+    }
+
+    #[test]
+    fn generated_code_is_up_to_date() {
+        let code = generate(include_str!("testdata/example.dbc"));
+        assert!(
+            code == include_str!("testdata/example.rs"),
+            "regenerate src/dbc/testdata/example.rs:\n{}",
+            code
+        );
+        let code = generate(include_str!("testdata/clashes.dbc"));
+        assert!(
+            code == include_str!("testdata/clashes.rs"),
+            "regenerate src/dbc/testdata/clashes.rs:\n{}",
+            code
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(snake_case("EngineSpeed"), "engine_speed");
+        assert_eq!(snake_case("ABS_Active"), "abs_active");
+        assert_eq!(snake_case("HTTPServer2"), "http_server2");
+        assert_eq!(snake_case("type"), "type_");
+        assert_eq!(camel_case("ENGINE_DATA", "M"), "EngineData");
+        assert_eq!(camel_case("2nd gear", "V"), "V2ndGear");
+
+        // Clashing type names stay camel case, getters snake case
+        let mut taken = HashSet::new();
+        assert_eq!(
+            unique("EngineData".to_owned(), "", &mut taken),
+            "EngineData"
+        );
+        assert_eq!(
+            unique("EngineData".to_owned(), "", &mut taken),
+            "EngineData2"
+        );
+        assert_eq!(
+            unique("EngineData".to_owned(), "", &mut taken),
+            "EngineData3"
+        );
+        assert_eq!(unique("speed".to_owned(), "_", &mut taken), "speed");
+        assert_eq!(unique("speed".to_owned(), "_", &mut taken), "speed_2");
+    }
+
+    #[test]
+    fn clashing_signal_names() {
+        use self::clashes::{Names, NamesMode, NamesMode2};
+
+        let mut names = Names::new();
+        names.set_id_2(1).unwrap();
+        names.set_size_2(2).unwrap();
+        names.set_payload_2(3).unwrap();
+        names.set_speed(4).unwrap();
+        names.set_speed_raw_2(5).unwrap();
+        names.set_mode(NamesMode::On);
+        assert_eq!((Names::ID, Names::SIZE), (0x200, 8));
+        assert_eq!(names.payload(), &[1, 2, 3, 4, 5, 1, 0, 0]);
+        assert_eq!((names.id_2(), names.size_2()), (1, 2));
+        assert_eq!(
+            (names.speed(), names.speed_raw(), names.speed_raw_2()),
+            (4, 4, 5)
+        );
+        assert_eq!(NamesMode2::ID, 0x201);
+    }
+
+    #[test]
+    fn typed_access() {
+        let mut engine = EngineData::new();
+        assert_eq!(engine.status(), EngineDataStatus::Ok);
+        engine.set_engine_speed(1000.25).unwrap();
+        engine.set_coolant_temp(-20).unwrap();
+        engine.set_status(EngineDataStatus::Fault);
+        assert!(engine.set_engine_speed(-1.0).is_err());
+
+        let frame = CANFrame::from(engine);
+        assert_eq!(frame.id(), EngineData::ID);
+        let db = database();
+        let decoded = db.decode(&frame).unwrap();
+        assert_eq!(decoded.physical("EngineSpeed"), Some(1000.25));
+        assert_eq!(decoded.physical("CoolantTemp"), Some(-20.0));
+        assert_eq!(decoded.signal("Status").unwrap().label, Some("Fault"));
+
+        let back = EngineData::try_from(&frame).unwrap();
+        assert_eq!(back, engine);
+        assert_eq!(back.coolant_temp_raw(), -20);
+        assert!(EngineData::try_from(
+            &CANFrame::new(0x101, &[0; 8], false).unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn multiplexed_access() {
+        let mut diag = Diagnostics::new();
+        assert_eq!(diag.value(), None);
+        diag.set_value(12.5).unwrap();
+        assert_eq!(diag.mode(), 2);
+        assert_eq!(diag.value(), Some(12.5));
+        assert_eq!(diag.counter(), None);
+
+        let mut diag = Diagnostics::new();
+        diag.set_deep_raw(7.0);
+        assert_eq!((diag.mode(), diag.sub()), (3, Some(16)));
+        assert_eq!(diag.deep_raw(), Some(7.0));
+
+        let frame = CANFrame::from(diag);
+        assert!(frame.is_extended());
+        assert_eq!(Diagnostics::try_from(&frame).unwrap().deep(), Some(7.0));
+    }
+}
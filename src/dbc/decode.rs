@@ -0,0 +1,248 @@
+//! Decoding of frame payloads into signal values.
+use dbc::{
+    Database, Message, Multiplexing, Signal, ValueType, MAX_MULTIPLEXING_DEPTH,
+};
+use {CANFrame, CANFrameFd};
+
+/// A signal decoded from a payload.
+#[derive(Debug, Clone)]
+pub struct SignalValue<'a> {
+    pub signal: &'a Signal,
+    pub raw: u64, // Bits of the signal as stored in the payload
+    pub physical: f64,
+    pub label: Option<&'a str>, // Value description of the raw value
+}
+
+/// The active signals of a decoded message.
+#[derive(Debug, Clone)]
+pub struct DecodedMessage<'a> {
+    pub message: &'a Message,
+    pub signals: Vec<SignalValue<'a>>,
+}
+
+impl<'a> DecodedMessage<'a> {
+    pub fn signal(&self, name: &str) -> Option<&SignalValue<'a>> {
+        self.signals.iter().find(|s| s.signal.name == name)
+    }
+
+    /// Physical value of a signal, if it was present in the payload.
+    pub fn physical(&self, name: &str) -> Option<f64> {
+        self.signal(name).map(|s| s.physical)
+    }
+}
+
+impl Signal {
+    /// Interpret raw bits as a number: sign extended for signed signals and
+    /// reinterpreted for IEEE floats.
+    pub fn raw_to_number(&self, raw: u64) -> f64 {
+        self.layout().raw_to_number(raw)
+    }
+
+    /// Scale raw bits to the physical value, `number * factor + offset`.
+    pub fn raw_to_physical(&self, raw: u64) -> f64 {
+        self.layout().raw_to_physical(raw)
+    }
+
+    /// Value description of raw bits, if any.
+    pub fn label(&self, raw: u64) -> Option<&str> {
+        let key = match self.value_type {
+            ValueType::Signed => self.layout().sign_extend(raw),
+            _ => raw as i64,
+        };
+        self.value_description(key)
+    }
+
+    /// Read the raw bits of the signal, or `None` if the payload is too
+    /// short to hold it.
+    pub fn extract(&self, data: &[u8]) -> Option<u64> {
+        self.layout().extract(data)
+    }
+
+    pub fn decode<'a>(&'a self, data: &[u8]) -> Option<SignalValue<'a>> {
+        self.extract(data).map(|raw| SignalValue {
+            signal: self,
+            raw,
+            physical: self.raw_to_physical(raw),
+            label: self.label(raw),
+        })
+    }
+}
+
+impl Message {
+    /// Decode every signal that is active for the multiplexor values of the
+    /// payload. Signals that do not fit in a short payload are left out.
+    pub fn decode<'a>(&'a self, data: &[u8]) -> DecodedMessage<'a> {
+        let signals = self
+            .signals
+            .iter()
+            .filter(|signal| self.is_active(signal, data, 0))
+            .filter_map(|signal| signal.decode(data))
+            .collect();
+
+        DecodedMessage {
+            message: self,
+            signals,
+        }
+    }
+
+    /// Multiplexor switch and selecting values of a multiplexed signal,
+    /// preferring the `SG_MUL_VAL_` definition when present.
+    pub fn multiplexor_of<'a>(
+        &'a self,
+        signal: &'a Signal,
+    ) -> Option<(&'a Signal, Vec<(u64, u64)>)> {
+        let value = match signal.multiplexing {
+            Multiplexing::Multiplexed(value)
+            | Multiplexing::MultiplexedMultiplexor(value) => value,
+            _ => return None,
+        };
+
+        match signal.extended_multiplexing {
+            Some(ref extended) => self
+                .signal(&extended.switch)
+                .map(|switch| (switch, extended.ranges.clone())),
+            None => self
+                .multiplexor()
+                .map(|switch| (switch, vec![(value, value)])),
+        }
+    }
+
+    fn is_active(&self, signal: &Signal, data: &[u8], depth: usize) -> bool {
+        if depth > MAX_MULTIPLEXING_DEPTH {
+            return false;
+        }
+
+        match self.multiplexor_of(signal) {
+            None => true,
+            Some((switch, ranges)) => {
+                self.is_active(switch, data, depth + 1)
+                    && switch.extract(data).is_some_and(|value| {
+                        ranges
+                            .iter()
+                            .any(|&(lo, hi)| lo <= value && value <= hi)
+                    })
+            }
+        }
+    }
+}
+
+impl Database {
+    /// Decode a received frame, if the database defines its identifier.
+    pub fn decode<'a>(
+        &'a self,
+        frame: &CANFrame,
+    ) -> Option<DecodedMessage<'a>> {
+        let message = self.message(frame.id(), frame.is_extended())?;
+        if frame.is_rtr() {
+            return None;
+        }
+        Some(message.decode(&frame.data()[..frame.len().min(8) as usize]))
+    }
+
+    /// Decode a received CAN FD frame, if the database defines its
+    /// identifier.
+    pub fn decode_fd<'a>(
+        &'a self,
+        frame: &CANFrameFd,
+    ) -> Option<DecodedMessage<'a>> {
+        let message = self.message(frame.id(), frame.is_extended())?;
+        Some(message.decode(frame.data()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbc::Database;
+    use CANFrame;
+
+    const DBC: &str = r#"VERSION ""
+BU_: Ecu
+BO_ 291 Mixed: 8 Ecu
+ SG_ Speed : 0|16@1+ (0.1,0) [0|6553.5] "km/h" Vector__XXX
+ SG_ Temp : 16|8@1- (0.5,-10) [-74|53.5] "degC" Vector__XXX
+ SG_ Motorola : 39|12@0+ (1,0) [0|4095] "" Vector__XXX
+ SG_ Gear : 55|4@0- (1,0) [-8|7] "" Vector__XXX
+ SG_ Level : 60|4@1+ (1,0) [0|15] "" Vector__XXX
+BO_ 2147484160 Muxed: 8 Ecu
+ SG_ Mux M : 0|8@1+ (1,0) [0|255] "" Vector__XXX
+ SG_ A m1 : 8|16@1+ (1,0) [0|65535] "" Vector__XXX
+ SG_ B m2 : 8|8@1+ (1,0) [0|255] "" Vector__XXX
+ SG_ Sub m3M : 8|8@1+ (1,0) [0|255] "" Vector__XXX
+ SG_ C m0 : 16|32@1+ (1,0) [0|0] "" Vector__XXX
+ SG_ D m0 : 16|8@1+ (1,0) [0|255] "" Vector__XXX
+VAL_ 291 Level 0 "Empty" 15 "Full" ;
+VAL_ 291 Gear -1 "Reverse" 0 "Neutral" ;
+SIG_VALTYPE_ 2147484160 C : 1;
+SG_MUL_VAL_ 2147484160 C Sub 16-31;
+SG_MUL_VAL_ 2147484160 D Sub 0-15;
+"#;
+
+    #[test]
+    fn decode_byte_orders_and_signs() {
+        let db: Database = DBC.parse().unwrap();
+        // Speed 1234 (123.4 km/h), Temp -20 (-20 degC), Motorola 0xABC at
+        // bytes 4-5, Gear -1 in the high nibble of byte 6, Level 15
+        let frame = CANFrame::new(
+            0x123,
+            &[0xD2, 0x04, 0xEC, 0x00, 0xAB, 0xC0, 0xF0, 0xF0],
+            false,
+        )
+        .unwrap();
+        let decoded = db.decode(&frame).unwrap();
+        assert_eq!(decoded.message.name, "Mixed");
+        assert!((decoded.physical("Speed").unwrap() - 123.4).abs() < 1e-9);
+        assert_eq!(decoded.physical("Temp"), Some(-20.0));
+        assert_eq!(decoded.signal("Motorola").unwrap().raw, 0xABC);
+        let gear = decoded.signal("Gear").unwrap();
+        assert_eq!(
+            (gear.raw, gear.physical, gear.label),
+            (0xF, -1.0, Some("Reverse"))
+        );
+        assert_eq!(decoded.signal("Level").unwrap().label, Some("Full"));
+
+        // A short frame only yields the signals it holds
+        let short = CANFrame::new(0x123, &[0x01, 0x00, 0x14], false).unwrap();
+        let names: Vec<&str> = db
+            .decode(&short)
+            .unwrap()
+            .signals
+            .iter()
+            .map(|s| s.signal.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Speed", "Temp"]);
+        assert!(db
+            .decode(&CANFrame::new_extended(0x123, &[0; 8], false).unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn decode_multiplexed_signals() {
+        let db: Database = DBC.parse().unwrap();
+        let names = |data: &[u8]| -> Vec<String> {
+            let frame = CANFrame::new_extended(0x200, data, false).unwrap();
+            db.decode(&frame)
+                .unwrap()
+                .signals
+                .iter()
+                .map(|s| s.signal.name.clone())
+                .collect()
+        };
+
+        assert_eq!(names(&[1, 0x34, 0x12, 0, 0, 0, 0, 0]), vec!["Mux", "A"]);
+        assert_eq!(names(&[2, 0, 0, 0, 0, 0, 0, 0]), vec!["Mux", "B"]);
+        assert_eq!(names(&[3, 5, 7, 0, 0, 0, 0, 0]), vec!["Mux", "Sub", "D"]);
+        assert_eq!(
+            names(&[3, 20, 0, 0, 0x80, 0x3F, 0, 0]),
+            vec!["Mux", "Sub", "C"]
+        );
+        assert_eq!(names(&[4, 20, 0, 0, 0, 0, 0, 0]), vec!["Mux"]);
+
+        let frame = CANFrame::new_extended(
+            0x200,
+            &[3, 20, 0x00, 0x00, 0x80, 0x3F, 0, 0],
+            false,
+        )
+        .unwrap();
+        assert_eq!(db.decode(&frame).unwrap().physical("C"), Some(1.0));
+    }
+}
@@ -0,0 +1,267 @@
+//! Encoding of physical signal values into frame payloads.
+use dbc::{
+    AttributeValue, Database, Message, Multiplexing, Signal,
+    MAX_MULTIPLEXING_DEPTH, START_VALUE_ATTRIBUTE,
+};
+use errors::*;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use {CANFrame, CANFrameFd};
+
+impl Signal {
+    /// Convert a physical value to the raw bits of the signal, checking it
+    /// against the `[min|max]` range (unless both are 0) and the bit length.
+    pub fn physical_to_raw(&self, physical: f64) -> Result<u64> {
+        self.layout().physical_to_raw(physical)
+    }
+}
+
+impl Database {
+    /// Raw value of a signal that is not set explicitly: its
+    /// `GenSigStartValue` attribute, or 0.
+    pub fn start_value(&self, signal: &Signal) -> u64 {
+        let mask = if signal.length >= 64 {
+            u64::MAX
+        } else {
+            (1u64 << signal.length) - 1
+        };
+        match self.signal_attribute(signal, START_VALUE_ATTRIBUTE) {
+            Some(&AttributeValue::Integer(value)) => value as u64 & mask,
+            Some(&AttributeValue::Float(value)) => (value as i64) as u64 & mask,
+            _ => 0,
+        }
+    }
+
+    /// Build the payload of a message from physical signal values.
+    ///
+    /// Signals that are not given take their start value. Multiplexor
+    /// signals that are not given are set to select the multiplexed
+    /// signals that are; only the signals selected by the resulting
+    /// multiplexor values are written.
+    pub fn encode_payload<K>(
+        &self,
+        message: &Message,
+        values: &HashMap<K, f64>,
+    ) -> Result<Vec<u8>>
+    where
+        K: Borrow<str> + Hash + Eq,
+    {
+        for name in values.keys() {
+            if message.signal(name.borrow()).is_none() {
+                bail!(ErrorKind::UnknownSignal(name.borrow().to_owned()));
+            }
+        }
+
+        // Raw values of the signals that are given or selected
+        let mut raw: HashMap<&str, u64> = HashMap::new();
+        for signal in &message.signals {
+            if let Some(&physical) = values.get(signal.name.as_str()) {
+                raw.insert(&signal.name, signal.physical_to_raw(physical)?);
+            }
+        }
+
+        // Derive the multiplexor values implied by the given signals,
+        // following nested multiplexors outwards
+        let mut pending: Vec<(&Signal, &Signal)> = message
+            .signals
+            .iter()
+            .filter(|s| raw.contains_key(s.name.as_str()))
+            .map(|s| (s, s))
+            .collect();
+        while let Some((signal, given)) = pending.pop() {
+            let (switch, ranges) = match message.multiplexor_of(signal) {
+                Some(selection) => selection,
+                None => continue,
+            };
+            match raw.get(switch.name.as_str()) {
+                Some(&value) => {
+                    if !ranges
+                        .iter()
+                        .any(|&(lo, hi)| lo <= value && value <= hi)
+                    {
+                        bail!(ErrorKind::InactiveSignal(given.name.clone()));
+                    }
+                }
+                None => {
+                    raw.insert(
+                        &switch.name,
+                        ranges.first().map(|r| r.0).unwrap_or(0),
+                    );
+                    pending.push((switch, given));
+                }
+            }
+        }
+
+        let mut data = vec![0u8; message.size as usize];
+        for signal in &message.signals {
+            if !is_selected(message, signal, &raw, self, 0) {
+                continue;
+            }
+            let layout = signal.layout();
+            if !layout.fits(data.len()) {
+                bail!(ErrorKind::DbcFormat(format!(
+                    "signal {} does not fit in message {}",
+                    signal.name, message.name
+                )));
+            }
+            let value = raw
+                .get(signal.name.as_str())
+                .cloned()
+                .unwrap_or_else(|| self.start_value(signal));
+            layout.insert(&mut data, value);
+        }
+
+        Ok(data)
+    }
+
+    /// Build a frame for the named message, ready for
+    /// `PCANDevice::write_frame`.
+    pub fn encode<K>(
+        &self,
+        message: &str,
+        values: &HashMap<K, f64>,
+    ) -> Result<CANFrame>
+    where
+        K: Borrow<str> + Hash + Eq,
+    {
+        let message = self
+            .message_by_name(message)
+            .ok_or_else(|| ErrorKind::UnknownMessage(message.to_owned()))?;
+        let data = self.encode_payload(message, values)?;
+        if message.extended {
+            CANFrame::new_extended(message.id, &data, false)
+        } else {
+            CANFrame::new(message.id, &data, false)
+        }
+    }
+
+    /// Build a CAN FD frame for the named message.
+    pub fn encode_fd<K>(
+        &self,
+        message: &str,
+        values: &HashMap<K, f64>,
+        brs: bool,
+    ) -> Result<CANFrameFd>
+    where
+        K: Borrow<str> + Hash + Eq,
+    {
+        let message = self
+            .message_by_name(message)
+            .ok_or_else(|| ErrorKind::UnknownMessage(message.to_owned()))?;
+        let data = self.encode_payload(message, values)?;
+        CANFrameFd::new(message.id, &data, message.extended, brs)
+    }
+}
+
+/// Whether a signal is selected by the multiplexor values in `raw`, with
+/// unset multiplexors taking their start value.
+fn is_selected(
+    message: &Message,
+    signal: &Signal,
+    raw: &HashMap<&str, u64>,
+    db: &Database,
+    depth: usize,
+) -> bool {
+    if depth > MAX_MULTIPLEXING_DEPTH {
+        return false;
+    }
+    if let Multiplexing::None | Multiplexing::Multiplexor = signal.multiplexing
+    {
+        return true;
+    }
+
+    match message.multiplexor_of(signal) {
+        None => true,
+        Some((switch, ranges)) => {
+            let value = raw
+                .get(switch.name.as_str())
+                .cloned()
+                .unwrap_or_else(|| db.start_value(switch));
+            is_selected(message, switch, raw, db, depth + 1)
+                && ranges.iter().any(|&(lo, hi)| lo <= value && value <= hi)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbc::Database;
+    use errors::*;
+    use std::collections::HashMap;
+
+    const DBC: &str = r#"VERSION ""
+BU_: Ecu
+BO_ 291 Mixed: 8 Ecu
+ SG_ Speed : 0|16@1+ (0.1,0) [0|6553.5] "km/h" Vector__XXX
+ SG_ Temp : 16|8@1- (0.5,-10) [-74|53.5] "degC" Vector__XXX
+ SG_ Motorola : 39|12@0+ (1,0) [0|4095] "" Vector__XXX
+ SG_ Gear : 55|4@0- (1,0) [-8|7] "" Vector__XXX
+BO_ 2147484160 Muxed: 8 Ecu
+ SG_ Mux M : 0|8@1+ (1,0) [0|255] "" Vector__XXX
+ SG_ A m1 : 8|16@1+ (1,0) [0|65535] "" Vector__XXX
+ SG_ Sub m3M : 8|8@1+ (1,0) [0|255] "" Vector__XXX
+ SG_ C m0 : 16|32@1+ (1,0) [0|0] "" Vector__XXX
+BA_DEF_ SG_ "GenSigStartValue" INT 0 100000;
+BA_ "GenSigStartValue" SG_ 291 Motorola 291;
+SIG_VALTYPE_ 2147484160 C : 1;
+SG_MUL_VAL_ 2147484160 C Sub 16-31;
+"#;
+
+    #[test]
+    fn encode_round_trip() {
+        let db: Database = DBC.parse().unwrap();
+        let mut values = HashMap::new();
+        values.insert("Speed", 123.4);
+        values.insert("Temp", -20.0);
+        values.insert("Gear", -1.0);
+        let frame = db.encode("Mixed", &values).unwrap();
+        assert_eq!(frame.id(), 0x123);
+        assert!(!frame.is_extended());
+        // Motorola takes its start value 0x123
+        assert_eq!(
+            &frame.data()[..8],
+            &[0xD2, 0x04, 0xEC, 0x00, 0x12, 0x30, 0xF0, 0x00]
+        );
+
+        let decoded = db.decode(&frame).unwrap();
+        assert!((decoded.physical("Speed").unwrap() - 123.4).abs() < 1e-9);
+        assert_eq!(decoded.physical("Temp"), Some(-20.0));
+        assert_eq!(decoded.physical("Gear"), Some(-1.0));
+    }
+
+    #[test]
+    fn encode_selects_multiplexors() {
+        let db: Database = DBC.parse().unwrap();
+        let mut values = HashMap::new();
+        values.insert("C".to_owned(), 1.0);
+        let frame = db.encode("Muxed", &values).unwrap();
+        assert!(frame.is_extended());
+        assert_eq!(&frame.data()[..8], &[3, 16, 0x00, 0x00, 0x80, 0x3F, 0, 0]);
+
+        values.insert("Mux".to_owned(), 1.0);
+        match db.encode("Muxed", &values).unwrap_err().kind() {
+            ErrorKind::InactiveSignal(name) => assert_eq!(name, "C"),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encode_checks_ranges() {
+        let db: Database = DBC.parse().unwrap();
+        let encode = |name: &str, value: f64| {
+            let mut values = HashMap::new();
+            values.insert(name, value);
+            db.encode("Mixed", &values)
+        };
+
+        assert!(encode("Speed", 6553.5).is_ok());
+        assert!(encode("Speed", 6553.6).is_err());
+        assert!(encode("Temp", -75.0).is_err());
+        assert!(encode("Gear", 8.0).is_err());
+        match encode("Nope", 1.0).unwrap_err().kind() {
+            ErrorKind::UnknownSignal(_) => {}
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+}
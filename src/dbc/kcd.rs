@@ -0,0 +1,408 @@
+//! Parser for Kayak network definitions (`.kcd`).
+//!
+//! The messages of every `Bus` are merged into one database. Signal
+//! offsets count bits from the start of the payload; big endian offsets
+//! give the most significant bit counting most significant bit first, and
+//! are converted to DBC numbering. Label groups only label their first
+//! value.
+use dbc::{
+    big_endian_start, AttributeValue, ByteOrder, Database, Message,
+    Multiplexing, Node, Signal, ValueTable, ValueType, CYCLE_TIME_ATTRIBUTE,
+};
+use errors::*;
+use roxmltree::{Document, Node as XmlNode};
+use std::collections::HashMap;
+
+pub fn parse(text: &str) -> Result<Database> {
+    let doc = Document::parse(text).map_err(|e| error(&e.to_string()))?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "NetworkDefinition" {
+        return Err(error("missing NetworkDefinition element"));
+    }
+
+    let mut db = Database::default();
+    let mut node_names = HashMap::new();
+    for element in elements(root) {
+        match element.tag_name().name() {
+            "Document" => {
+                db.version =
+                    element.attribute("version").unwrap_or("").to_owned();
+                db.comment = text_of(element);
+            }
+            "Node" => {
+                let name = required(element, "name")?.to_owned();
+                node_names
+                    .insert(required(element, "id")?.to_owned(), name.clone());
+                db.nodes.push(Node {
+                    name,
+                    ..Node::default()
+                });
+            }
+            _ => {}
+        }
+    }
+
+    for bus in elements(root).filter(|e| e.tag_name().name() == "Bus") {
+        for element in
+            elements(bus).filter(|e| e.tag_name().name() == "Message")
+        {
+            let message = message(element, &node_names)?;
+            db.messages.insert((message.id, message.extended), message);
+        }
+    }
+    Ok(db)
+}
+
+fn error(reason: &str) -> Error {
+    ErrorKind::KcdFormat(reason.to_owned()).into()
+}
+
+fn elements<'a, 'input>(
+    node: XmlNode<'a, 'input>,
+) -> impl Iterator<Item = XmlNode<'a, 'input>> {
+    node.children().filter(|n| n.is_element())
+}
+
+fn child<'a, 'input>(
+    node: XmlNode<'a, 'input>,
+    name: &str,
+) -> Option<XmlNode<'a, 'input>> {
+    elements(node).find(|n| n.tag_name().name() == name)
+}
+
+fn text_of(node: XmlNode) -> Option<String> {
+    node.text()
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_owned())
+}
+
+fn required<'a>(node: XmlNode<'a, '_>, name: &str) -> Result<&'a str> {
+    node.attribute(name).ok_or_else(|| {
+        error(&format!(
+            "{} element without {} attribute",
+            node.tag_name().name(),
+            name
+        ))
+    })
+}
+
+/// Decimal or `0x` prefixed hexadecimal integer.
+fn integer(node: XmlNode, name: &str) -> Result<Option<u64>> {
+    let text = match node.attribute(name) {
+        Some(text) => text.trim(),
+        None => return Ok(None),
+    };
+    let value =
+        match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => text.parse(),
+        };
+    value.map(Some).map_err(|_| {
+        error(&format!(
+            "invalid {} '{}' in {} element",
+            name,
+            text,
+            node.tag_name().name()
+        ))
+    })
+}
+
+fn float(node: XmlNode, name: &str, default: f64) -> Result<f64> {
+    match node.attribute(name) {
+        Some(text) => text.trim().parse().map_err(|_| {
+            error(&format!(
+                "invalid {} '{}' in {} element",
+                name,
+                text,
+                node.tag_name().name()
+            ))
+        }),
+        None => Ok(default),
+    }
+}
+
+fn node_refs(
+    node: Option<XmlNode>,
+    node_names: &HashMap<String, String>,
+) -> Vec<String> {
+    node.map(|n| {
+        elements(n)
+            .filter(|e| e.tag_name().name() == "NodeRef")
+            .filter_map(|e| {
+                e.attribute("id").and_then(|id| node_names.get(id)).cloned()
+            })
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+fn message(
+    element: XmlNode,
+    node_names: &HashMap<String, String>,
+) -> Result<Message> {
+    let name = required(element, "name")?.to_owned();
+    let id = integer(element, "id")?
+        .ok_or_else(|| error(&format!("message {} without id", name)))?;
+    let extended = element.attribute("format") == Some("extended");
+    if id > if extended { 0x1FFF_FFFF } else { 0x7FF } {
+        return Err(error(&format!(
+            "invalid id 0x{:X} for message {}",
+            id, name
+        )));
+    }
+
+    let mut signals = Vec::new();
+    for child in elements(element) {
+        match child.tag_name().name() {
+            "Signal" => {
+                signals.push(signal(child, Multiplexing::None, node_names)?)
+            }
+            "Multiplex" => {
+                signals.push(signal(
+                    child,
+                    Multiplexing::Multiplexor,
+                    node_names,
+                )?);
+                for group in elements(child)
+                    .filter(|e| e.tag_name().name() == "MuxGroup")
+                {
+                    let count = integer(group, "count")?.ok_or_else(|| {
+                        error("MuxGroup element without count")
+                    })?;
+                    for grouped in elements(group)
+                        .filter(|e| e.tag_name().name() == "Signal")
+                    {
+                        signals.push(signal(
+                            grouped,
+                            Multiplexing::Multiplexed(count),
+                            node_names,
+                        )?);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // `auto` (the default) sizes the message to its signals
+    let size = match element.attribute("length") {
+        None | Some("auto") => signals
+            .iter()
+            .map(|s| (0..=64).find(|&len| s.layout().fits(len)).unwrap_or(65))
+            .max()
+            .unwrap_or(0),
+        Some(_) => integer(element, "length")?.unwrap_or(0) as usize,
+    };
+    if size > 64 {
+        return Err(error(&format!(
+            "message {} is longer than 64 bytes",
+            name
+        )));
+    }
+
+    let mut attributes = HashMap::new();
+    if let Some(interval) = integer(element, "interval")? {
+        attributes.insert(
+            CYCLE_TIME_ATTRIBUTE.to_owned(),
+            AttributeValue::Integer(interval as i64),
+        );
+    }
+    let mut transmitters =
+        node_refs(child(element, "Producer"), node_names).into_iter();
+
+    Ok(Message {
+        id: id as u32,
+        extended,
+        name,
+        size: size as u8,
+        transmitter: transmitters.next(),
+        extra_transmitters: transmitters.collect(),
+        signals,
+        comment: child(element, "Notes").and_then(text_of),
+        attributes,
+    })
+}
+
+fn signal(
+    element: XmlNode,
+    multiplexing: Multiplexing,
+    node_names: &HashMap<String, String>,
+) -> Result<Signal> {
+    let name = required(element, "name")?.to_owned();
+    let offset = integer(element, "offset")?
+        .ok_or_else(|| error(&format!("signal {} without offset", name)))?;
+    let length = integer(element, "length")?.unwrap_or(1);
+    if offset > 511 || length == 0 || length > 64 {
+        return Err(error(&format!("invalid layout for signal {}", name)));
+    }
+    let byte_order = match element.attribute("endianess").unwrap_or("little") {
+        "little" => ByteOrder::LittleEndian,
+        "big" => ByteOrder::BigEndian,
+        other => {
+            return Err(error(&format!(
+                "invalid endianess '{}' for signal {}",
+                other, name
+            )))
+        }
+    };
+    let start_bit = match byte_order {
+        ByteOrder::LittleEndian => offset as u16,
+        ByteOrder::BigEndian => big_endian_start(offset as u16),
+    };
+
+    let value = child(element, "Value");
+    let value_type = match value
+        .and_then(|v| v.attribute("type"))
+        .unwrap_or("unsigned")
+    {
+        "unsigned" => ValueType::Unsigned,
+        "signed" => ValueType::Signed,
+        "single" => ValueType::Float,
+        "double" => ValueType::Double,
+        other => {
+            return Err(error(&format!(
+                "invalid value type '{}' for signal {}",
+                other, name
+            )))
+        }
+    };
+    let (factor, offset, min, max) = match value {
+        Some(value) => (
+            float(value, "slope", 1.0)?,
+            float(value, "intercept", 0.0)?,
+            float(value, "min", 0.0)?,
+            float(value, "max", 0.0)?,
+        ),
+        None => (1.0, 0.0, 0.0, 0.0),
+    };
+
+    let mut value_descriptions = ValueTable::new();
+    if let Some(labels) = child(element, "LabelSet") {
+        for label in elements(labels) {
+            let key = match label.tag_name().name() {
+                "Label" => "value",
+                "LabelGroup" => "from",
+                _ => continue,
+            };
+            let raw = integer(label, key)?.ok_or_else(|| {
+                error(&format!("label without {} in signal {}", key, name))
+            })?;
+            value_descriptions
+                .insert(raw as i64, required(label, "name")?.to_owned());
+        }
+    }
+
+    Ok(Signal {
+        name,
+        multiplexing,
+        extended_multiplexing: None,
+        start_bit,
+        length: length as u16,
+        byte_order,
+        value_type,
+        factor,
+        offset,
+        min,
+        max,
+        unit: value
+            .and_then(|v| v.attribute("unit"))
+            .unwrap_or("")
+            .to_owned(),
+        receivers: node_refs(child(element, "Consumer"), node_names),
+        comment: child(element, "Notes").and_then(text_of),
+        value_descriptions,
+        attributes: HashMap::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use dbc::{AttributeValue, ByteOrder, Database, Multiplexing, ValueType};
+    use CANFrame;
+
+    const SAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<NetworkDefinition xmlns="http://kayak.2codeornot2code.org/1.0">
+  <Document name="Demo" version="1.2">Demo network</Document>
+  <Node id="1" name="Engine"/>
+  <Node id="2" name="Tester"/>
+  <Bus name="Powertrain" baudrate="500000">
+    <Message id="0x100" name="EngineData" length="8" interval="10">
+      <Notes>Cyclic engine data</Notes>
+      <Producer><NodeRef id="1"/></Producer>
+      <Signal name="EngineSpeed" offset="0" length="16">
+        <Consumer><NodeRef id="2"/></Consumer>
+        <Value slope="0.25" unit="rpm" min="0" max="16383.75"/>
+      </Signal>
+      <Signal name="Status" offset="24" length="4" endianess="big">
+        <LabelSet>
+          <Label name="Ok" value="0"/>
+          <Label name="Fault" value="2"/>
+        </LabelSet>
+      </Signal>
+    </Message>
+    <Message id="0x18FEF1FE" name="Diagnostics" format="extended">
+      <Multiplex name="Mode" offset="0" length="8">
+        <MuxGroup count="1">
+          <Signal name="Counter" offset="8" length="8"/>
+        </MuxGroup>
+        <MuxGroup count="2">
+          <Signal name="Value" offset="8" length="32"><Value type="single"/></Signal>
+        </MuxGroup>
+      </Multiplex>
+    </Message>
+  </Bus>
+</NetworkDefinition>
+"#;
+
+    #[test]
+    fn parse_sample() {
+        let db = Database::from_kcd(SAMPLE).unwrap();
+        assert_eq!(
+            (db.version.as_str(), db.comment.as_deref()),
+            ("1.2", Some("Demo network"))
+        );
+        assert_eq!(db.nodes.len(), 2);
+
+        let engine = db.message(0x100, false).unwrap();
+        assert_eq!(
+            (engine.size, engine.transmitter.as_deref()),
+            (8, Some("Engine"))
+        );
+        assert_eq!(engine.comment.as_deref(), Some("Cyclic engine data"));
+        assert_eq!(
+            engine.attributes["GenMsgCycleTime"],
+            AttributeValue::Integer(10)
+        );
+        let speed = engine.signal("EngineSpeed").unwrap();
+        assert_eq!(
+            (speed.factor, speed.max, speed.unit.as_str()),
+            (0.25, 16383.75, "rpm")
+        );
+        assert_eq!(speed.receivers, vec!["Tester".to_owned()]);
+        let status = engine.signal("Status").unwrap();
+        assert_eq!(
+            (status.byte_order, status.start_bit),
+            (ByteOrder::BigEndian, 31)
+        );
+        assert_eq!(status.value_description(2), Some("Fault"));
+
+        let diag = db.message(0x18FE_F1FE, true).unwrap();
+        assert!(diag.extended);
+        assert_eq!(diag.size, 5);
+        assert_eq!(diag.multiplexor().unwrap().name, "Mode");
+        let value = diag.signal("Value").unwrap();
+        assert_eq!(
+            (value.multiplexing, value.value_type),
+            (Multiplexing::Multiplexed(2), ValueType::Float)
+        );
+
+        // Decodes the same as the equivalent DBC
+        let frame =
+            CANFrame::new(0x100, &[0xA1, 0x0F, 0x00, 0x20, 0, 0, 0, 0], false)
+                .unwrap();
+        let decoded = db.decode(&frame).unwrap();
+        assert_eq!(decoded.physical("EngineSpeed"), Some(1000.25));
+        assert_eq!(decoded.signal("Status").unwrap().label, Some("Fault"));
+    }
+}
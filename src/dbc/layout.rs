@@ -0,0 +1,165 @@
+//! Placement and scaling of a signal in a payload.
+//!
+//! Bits use the DBC numbering: bit `n` is bit `n % 8` of byte `n / 8`.
+//! Little endian signals start at their least significant bit and big
+//! endian signals at their most significant bit, continuing in the next
+//! byte after bit 0.
+use dbc::{ByteOrder, Signal, ValueType};
+use errors::*;
+
+/// Tolerance for range checks, so that values printed with limited
+/// precision (such as the limits themselves) are accepted.
+const RANGE_EPSILON: f64 = 1e-9;
+
+/// Everything needed to convert between payload bits and physical values.
+/// Code generated by `dbc::codegen` holds these as constants.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout<'a> {
+    pub name: &'a str,
+    pub start_bit: u16,
+    pub length: u16,
+    pub byte_order: ByteOrder,
+    pub value_type: ValueType,
+    pub factor: f64,
+    pub offset: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl<'a> Layout<'a> {
+    /// Position of the `i`th bit, counting from the least significant bit.
+    fn bit_position(&self, i: u16) -> usize {
+        match self.byte_order {
+            ByteOrder::LittleEndian => self.start_bit as usize + i as usize,
+            ByteOrder::BigEndian => {
+                // Count bits most significant first across the payload, where
+                // the signal occupies a contiguous range
+                let start = self.start_bit as usize;
+                let msb = start / 8 * 8 + 7 - start % 8;
+                let linear = msb + (self.length - 1 - i) as usize;
+                linear / 8 * 8 + 7 - linear % 8
+            }
+        }
+    }
+
+    fn mask(&self) -> u64 {
+        if self.length >= 64 {
+            u64::MAX
+        } else {
+            (1u64 << self.length) - 1
+        }
+    }
+
+    /// Whether every bit lies within a payload of `len` bytes.
+    pub fn fits(&self, len: usize) -> bool {
+        let first = self.bit_position(0);
+        let last = self.bit_position(self.length - 1);
+        first.max(last) < len * 8
+    }
+
+    /// Read the raw bits, or `None` if the payload is too short.
+    pub fn extract(&self, data: &[u8]) -> Option<u64> {
+        if !self.fits(data.len()) {
+            return None;
+        }
+        Some((0..self.length).fold(0u64, |value, i| {
+            let pos = self.bit_position(i);
+            value | (((data[pos / 8] >> (pos % 8)) & 1) as u64) << i
+        }))
+    }
+
+    /// Store the low bits of `raw`. Bits beyond the end of `data` are
+    /// dropped.
+    pub fn insert(&self, data: &mut [u8], raw: u64) {
+        for i in 0..self.length {
+            let pos = self.bit_position(i);
+            if pos / 8 >= data.len() {
+                continue;
+            }
+            let mask = 1u8 << (pos % 8);
+            if (raw >> i) & 1 == 1 {
+                data[pos / 8] |= mask;
+            } else {
+                data[pos / 8] &= !mask;
+            }
+        }
+    }
+
+    /// Raw bits as a two's complement number of `length` bits.
+    pub fn sign_extend(&self, raw: u64) -> i64 {
+        let shift = 64 - self.length.min(64) as u32;
+        ((raw << shift) as i64) >> shift
+    }
+
+    /// Interpret raw bits as a number: sign extended for signed signals and
+    /// reinterpreted for IEEE floats.
+    pub fn raw_to_number(&self, raw: u64) -> f64 {
+        match self.value_type {
+            ValueType::Unsigned => raw as f64,
+            ValueType::Signed => self.sign_extend(raw) as f64,
+            ValueType::Float => f32::from_bits(raw as u32) as f64,
+            ValueType::Double => f64::from_bits(raw),
+        }
+    }
+
+    /// Scale raw bits to the physical value, `number * factor + offset`.
+    pub fn raw_to_physical(&self, raw: u64) -> f64 {
+        self.raw_to_number(raw) * self.factor + self.offset
+    }
+
+    /// Convert a physical value to raw bits, checking it against the
+    /// `[min|max]` range (unless both are 0) and the bit length.
+    pub fn physical_to_raw(&self, physical: f64) -> Result<u64> {
+        let out_of_range = || -> Error {
+            ErrorKind::SignalOutOfRange(self.name.to_owned(), physical).into()
+        };
+
+        let unrestricted = self.min == 0.0 && self.max == 0.0;
+        let tolerance =
+            RANGE_EPSILON * (1.0 + self.min.abs().max(self.max.abs()));
+        if !physical.is_finite()
+            || (!unrestricted
+                && (physical < self.min - tolerance
+                    || physical > self.max + tolerance))
+        {
+            return Err(out_of_range());
+        }
+
+        let number = (physical - self.offset) / self.factor;
+        match self.value_type {
+            ValueType::Float => Ok((number as f32).to_bits() as u64),
+            ValueType::Double => Ok(number.to_bits()),
+            ValueType::Unsigned => {
+                let number = number.round();
+                if number < 0.0 || number > self.mask() as f64 {
+                    return Err(out_of_range());
+                }
+                Ok(number as u64)
+            }
+            ValueType::Signed => {
+                let number = number.round();
+                let limit = (1u64 << (self.length.min(64) - 1)) as f64;
+                if number < -limit || number > limit - 1.0 {
+                    return Err(out_of_range());
+                }
+                Ok((number as i64) as u64 & self.mask())
+            }
+        }
+    }
+}
+
+impl Signal {
+    pub fn layout(&self) -> Layout<'_> {
+        Layout {
+            name: &self.name,
+            start_bit: self.start_bit,
+            length: self.length,
+            byte_order: self.byte_order,
+            value_type: self.value_type,
+            factor: self.factor,
+            offset: self.offset,
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Vector CANdb++ (`.dbc`) databases.
+//!
+//! A `Database` holds the nodes, messages and signals of a network together
+//! with their comments, attributes and value descriptions. Messages are
+//! keyed by their frame identifier so a received `CANFrame` can be matched
+//! and decoded directly, and frames can be built from physical signal values.
+//!
+//! Kayak (`.kcd`) and PCAN Symbol Editor (`.sym`) files load into the same
+//! `Database`, with cycle times and default values stored as the DBC
+//! attributes `GenMsgCycleTime` and `GenSigStartValue`.
+use errors::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use text;
+
+pub mod codegen;
+mod decode;
+mod encode;
+mod kcd;
+mod layout;
+mod parser;
+mod sym;
+
+pub use self::decode::{DecodedMessage, SignalValue};
+pub use self::layout::Layout;
+
+/// Guards against multiplexor chains that refer to each other.
+const MAX_MULTIPLEXING_DEPTH: usize = 16;
+
+/// Attribute holding the cycle time of a message in milliseconds.
+const CYCLE_TIME_ATTRIBUTE: &str = "GenMsgCycleTime";
+
+/// Attribute holding the raw value a signal takes when not set.
+const START_VALUE_ATTRIBUTE: &str = "GenSigStartValue";
+
+/// DBC start bit of a big endian signal whose most significant bit is
+/// `msb`, counting bits most significant first as KCD and SYM files do.
+fn big_endian_start(msb: u16) -> u16 {
+    msb / 8 * 8 + 7 - msb % 8
+}
+
+/// Bit numbering of a signal in the payload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteOrder {
+    LittleEndian, // Intel, `@1`
+    BigEndian,    // Motorola, `@0`
+}
+
+/// Encoding of the raw value of a signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueType {
+    Unsigned,
+    Signed,
+    Float,  // IEEE 754 single precision, `SIG_VALTYPE_ ... : 1`
+    Double, // IEEE 754 double precision, `SIG_VALTYPE_ ... : 2`
+}
+
+/// Role of a signal in a multiplexed message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Multiplexing {
+    None,
+    Multiplexor,                 // `M`
+    Multiplexed(u64),            // `m<value>`
+    MultiplexedMultiplexor(u64), // `m<value>M`, extended multiplexing
+}
+
+/// Multiplexor values selecting a signal, from `SG_MUL_VAL_`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedMultiplexing {
+    pub switch: String,
+    pub ranges: Vec<(u64, u64)>,
+}
+
+/// Value of an attribute or an attribute default.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+/// Object kind an attribute definition applies to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttributeObject {
+    Network,
+    Node,
+    Message,
+    Signal,
+    EnvironmentVariable,
+}
+
+/// Type and range of an attribute.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeKind {
+    Integer(i64, i64),
+    Hex(i64, i64),
+    Float(f64, f64),
+    String,
+    Enum(Vec<String>),
+}
+
+/// A `BA_DEF_` attribute definition with its `BA_DEF_DEF_` default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeDefinition {
+    pub name: String,
+    pub object: AttributeObject,
+    pub kind: AttributeKind,
+    pub default: Option<AttributeValue>,
+}
+
+/// Labels for raw values, either shared (`VAL_TABLE_`) or per signal
+/// (`VAL_`).
+pub type ValueTable = BTreeMap<i64, String>;
+
+/// A network node (ECU) from `BU_`.
+#[derive(Debug, Clone, Default)]
+pub struct Node {
+    pub name: String,
+    pub comment: Option<String>,
+    pub attributes: HashMap<String, AttributeValue>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Signal {
+    pub name: String,
+    pub multiplexing: Multiplexing,
+    pub extended_multiplexing: Option<ExtendedMultiplexing>,
+    pub start_bit: u16,
+    pub length: u16,
+    pub byte_order: ByteOrder,
+    pub value_type: ValueType,
+    pub factor: f64,
+    pub offset: f64,
+    pub min: f64,
+    pub max: f64,
+    pub unit: String,
+    pub receivers: Vec<String>,
+    pub comment: Option<String>,
+    pub value_descriptions: ValueTable,
+    pub attributes: HashMap<String, AttributeValue>,
+}
+
+impl Signal {
+    pub fn is_signed(&self) -> bool {
+        self.value_type == ValueType::Signed
+    }
+
+    /// Label of a raw value, if the signal has a value description for it.
+    pub fn value_description(&self, raw: i64) -> Option<&str> {
+        self.value_descriptions.get(&raw).map(|s| s.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub id: u32,
+    pub extended: bool,
+    pub name: String,
+    pub size: u8,
+    pub transmitter: Option<String>, // `None` for `Vector__XXX`
+    pub extra_transmitters: Vec<String>, // From `BO_TX_BU_`
+    pub signals: Vec<Signal>,
+    pub comment: Option<String>,
+    pub attributes: HashMap<String, AttributeValue>,
+}
+
+impl Message {
+    pub fn signal(&self, name: &str) -> Option<&Signal> {
+        self.signals.iter().find(|s| s.name == name)
+    }
+
+    /// The top level multiplexor of the message, if any.
+    pub fn multiplexor(&self) -> Option<&Signal> {
+        self.signals
+            .iter()
+            .find(|s| s.multiplexing == Multiplexing::Multiplexor)
+    }
+}
+
+/// A parsed DBC file.
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    pub version: String,
+    pub comment: Option<String>,
+    pub nodes: Vec<Node>,
+    pub messages: BTreeMap<(u32, bool), Message>, // By identifier and extended flag
+    pub value_tables: HashMap<String, ValueTable>,
+    pub attribute_definitions: Vec<AttributeDefinition>,
+    pub attributes: HashMap<String, AttributeValue>, // Network attributes
+}
+
+impl Database {
+    /// Load a database, as a KCD or SYM file if the extension says so and
+    /// as a DBC file otherwise. Files that are not valid UTF-8 are read as
+    /// Windows-1252, the encoding CANdb++ uses.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Database> {
+        let path = path.as_ref();
+        let text = text::decode(fs::read(path)?);
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("kcd") => Database::from_kcd(&text),
+            Some("sym") => Database::from_sym(&text),
+            _ => text.parse(),
+        }
+    }
+
+    /// Parse a Kayak network definition.
+    pub fn from_kcd(text: &str) -> Result<Database> {
+        kcd::parse(text)
+    }
+
+    /// Parse a PCAN Symbol Editor file.
+    pub fn from_sym(text: &str) -> Result<Database> {
+        sym::parse(text)
+    }
+
+    /// Message sent with the given frame identifier, standard or extended.
+    pub fn message(&self, id: u32, extended: bool) -> Option<&Message> {
+        self.messages.get(&(id, extended))
+    }
+
+    pub fn message_by_name(&self, name: &str) -> Option<&Message> {
+        self.messages.values().find(|m| m.name == name)
+    }
+
+    pub fn node(&self, name: &str) -> Option<&Node> {
+        self.nodes.iter().find(|n| n.name == name)
+    }
+
+    pub fn attribute_definition(
+        &self,
+        name: &str,
+    ) -> Option<&AttributeDefinition> {
+        self.attribute_definitions.iter().find(|d| d.name == name)
+    }
+
+    /// Value of a message attribute, falling back to the attribute default.
+    pub fn message_attribute<'a>(
+        &'a self,
+        message: &'a Message,
+        name: &str,
+    ) -> Option<&'a AttributeValue> {
+        message.attributes.get(name).or_else(|| {
+            self.attribute_definition(name)
+                .and_then(|d| d.default.as_ref())
+        })
+    }
+
+    /// Value of a signal attribute, falling back to the attribute default.
+    pub fn signal_attribute<'a>(
+        &'a self,
+        signal: &'a Signal,
+        name: &str,
+    ) -> Option<&'a AttributeValue> {
+        signal.attributes.get(name).or_else(|| {
+            self.attribute_definition(name)
+                .and_then(|d| d.default.as_ref())
+        })
+    }
+}
+
+impl FromStr for Database {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Database> {
+        parser::parse(text)
+    }
+}
@@ -0,0 +1,957 @@
+//! Tokenizer and recursive descent parser for the DBC grammar.
+//!
+//! Statements that carry no information for this crate (environment
+//! variables, signal groups, signal types, relation attributes...) are
+//! skipped up to their terminating `;`.
+use dbc::{
+    AttributeDefinition, AttributeKind, AttributeObject, AttributeValue,
+    ByteOrder, Database, ExtendedMultiplexing, Message, Multiplexing, Node,
+    Signal, ValueTable, ValueType,
+};
+use errors::*;
+use std::collections::HashMap;
+
+const EXTENDED_ID_FLAG: u64 = 0x8000_0000;
+const INDEPENDENT_SIGNALS: &str = "VECTOR__INDEPENDENT_SIG_MSG";
+const NO_NODE: &str = "Vector__XXX";
+
+/// Statement keywords, used to find the end of statements that are not
+/// terminated by `;`.
+const KEYWORDS: &[&str] = &[
+    "VERSION",
+    "NS_",
+    "BS_",
+    "BU_",
+    "VAL_TABLE_",
+    "BO_",
+    "SG_",
+    "BO_TX_BU_",
+    "EV_",
+    "ENVVAR_DATA_",
+    "SGTYPE_",
+    "SGTYPE_VAL_",
+    "SIGTYPE_VALTYPE_",
+    "CM_",
+    "BA_DEF_",
+    "BA_DEF_REL_",
+    "BA_DEF_SGTYPE_",
+    "BA_DEF_DEF_",
+    "BA_DEF_DEF_REL_",
+    "BA_",
+    "BA_REL_",
+    "BA_SGTYPE_",
+    "VAL_",
+    "SIG_VALTYPE_",
+    "SIG_GROUP_",
+    "SIG_TYPE_REF_",
+    "SG_MUL_VAL_",
+    "CAT_DEF_",
+    "CAT_",
+    "FILTER",
+    "BU_SG_REL_",
+    "BU_EV_REL_",
+    "BU_BO_REL_",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Str(String),
+    Punct(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '"' {
+            let start_line = line;
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => bail!(ErrorKind::DbcFormat(format!(
+                        "line {}: unterminated string",
+                        start_line
+                    ))),
+                    Some(&'"') => break,
+                    Some(&'\\') if i + 1 < chars.len() => {
+                        value.push(chars[i + 1]);
+                        i += 1;
+                    }
+                    Some(&ch) => {
+                        if ch == '\n' {
+                            line += 1;
+                        }
+                        value.push(ch);
+                    }
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push((Token::Str(value), start_line));
+        } else if c.is_ascii_digit()
+            || ((c == '-' || c == '.') && starts_number(&chars, i))
+        {
+            let start = i;
+            i += 1;
+            while i < chars.len() {
+                let ch = chars[i];
+                let exponent_sign = (ch == '-' || ch == '+')
+                    && (chars[i - 1] == 'e' || chars[i - 1] == 'E');
+                if ch.is_ascii_alphanumeric() || ch == '.' || exponent_sign {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            tokens
+                .push((Token::Number(chars[start..i].iter().collect()), line));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_')
+            {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), line));
+        } else {
+            tokens.push((Token::Punct(c), line));
+            i += 1;
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// A `-` or `.` starts a number when followed by a digit and not directly
+/// preceded by one, so that `3-5` in `SG_MUL_VAL_` and `@1-` stay
+/// separate tokens.
+fn starts_number(chars: &[char], i: usize) -> bool {
+    let next_is_digit = chars
+        .get(i + 1)
+        .is_some_and(|c| c.is_ascii_digit() || *c == '.');
+    let after_value =
+        i > 0 && (chars[i - 1].is_alphanumeric() || chars[i - 1] == '_');
+    next_is_digit && !after_value
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    db: Database,
+}
+
+pub fn parse(text: &str) -> Result<Database> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+        db: Database::default(),
+    };
+    parser.statements()?;
+    Ok(parser.db)
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.0)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|t| t.0.clone());
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map(|t| t.1)
+            .unwrap_or(0)
+    }
+
+    fn error(&self, reason: &str) -> Error {
+        ErrorKind::DbcFormat(format!("line {}: {}", self.line(), reason)).into()
+    }
+
+    fn at_keyword(&self) -> bool {
+        match self.peek() {
+            Some(Token::Ident(s)) => KEYWORDS.contains(&s.as_str()),
+            None => true,
+            _ => false,
+        }
+    }
+
+    fn is_punct(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punct(c))
+    }
+
+    fn eat_punct(&mut self, c: char) -> bool {
+        if self.is_punct(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, c: char) -> Result<()> {
+        if self.eat_punct(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(&Token::Ident(_)) => {}
+            _ => return Err(self.error("expected an identifier")),
+        }
+        match self.next() {
+            Some(Token::Ident(s)) => Ok(s),
+            _ => unreachable!(),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(&Token::Str(_)) => {}
+            _ => return Err(self.error("expected a string")),
+        }
+        match self.next() {
+            Some(Token::Str(s)) => Ok(s),
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_text(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(&Token::Number(_)) => {}
+            _ => return Err(self.error("expected a number")),
+        }
+        match self.next() {
+            Some(Token::Number(s)) => Ok(s),
+            _ => unreachable!(),
+        }
+    }
+
+    fn float(&mut self) -> Result<f64> {
+        let text = self.number_text()?;
+        text.parse()
+            .map_err(|_| self.error(&format!("invalid number '{}'", text)))
+    }
+
+    fn integer(&mut self) -> Result<i64> {
+        let text = self.number_text()?;
+        text.parse()
+            .map_err(|_| self.error(&format!("invalid integer '{}'", text)))
+    }
+
+    fn unsigned(&mut self) -> Result<u64> {
+        let text = self.number_text()?;
+        text.parse().map_err(|_| {
+            self.error(&format!("invalid unsigned integer '{}'", text))
+        })
+    }
+
+    /// Skip to the end of the current statement.
+    fn skip_statement(&mut self) {
+        while let Some(token) = self.next() {
+            if token == Token::Punct(';') {
+                break;
+            }
+        }
+    }
+
+    fn statements(&mut self) -> Result<()> {
+        while let Some(token) = self.next() {
+            let keyword = match token {
+                Token::Ident(keyword) => keyword,
+                _ => return Err(self.error("expected a statement keyword")),
+            };
+
+            match keyword.as_str() {
+                "VERSION" => self.db.version = self.string()?,
+                "NS_" => {
+                    while self.peek().is_some()
+                        && self.peek() != Some(&Token::Ident("BS_".to_owned()))
+                    {
+                        self.pos += 1;
+                    }
+                }
+                "BS_" => {
+                    self.expect_punct(':')?;
+                    while !self.at_keyword() {
+                        self.pos += 1;
+                    }
+                }
+                "BU_" => self.nodes()?,
+                "VAL_TABLE_" => {
+                    let name = self.ident()?;
+                    let table = self.value_descriptions()?;
+                    self.db.value_tables.insert(name, table);
+                }
+                "BO_" => self.message()?,
+                "BO_TX_BU_" => self.message_transmitters()?,
+                "CM_" => self.comment()?,
+                "BA_DEF_" => self.attribute_definition()?,
+                "BA_DEF_DEF_" => self.attribute_default()?,
+                "BA_" => self.attribute()?,
+                "VAL_" => self.signal_value_descriptions()?,
+                "SIG_VALTYPE_" => self.signal_value_type()?,
+                "SG_MUL_VAL_" => self.extended_multiplexing()?,
+                _ => self.skip_statement(),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn nodes(&mut self) -> Result<()> {
+        self.expect_punct(':')?;
+        while !self.at_keyword() {
+            let name = self.ident()?;
+            self.db.nodes.push(Node {
+                name,
+                ..Node::default()
+            });
+        }
+        Ok(())
+    }
+
+    /// `value "label"` pairs up to the terminating `;`.
+    fn value_descriptions(&mut self) -> Result<ValueTable> {
+        let mut table = ValueTable::new();
+        while !self.eat_punct(';') {
+            let value = self.integer()?;
+            let label = self.string()?;
+            table.insert(value, label);
+        }
+        Ok(table)
+    }
+
+    /// `BO_ id name : size transmitter` followed by its `SG_` lines.
+    fn message(&mut self) -> Result<()> {
+        let raw_id = self.unsigned()?;
+        let name = self.ident()?;
+        self.expect_punct(':')?;
+        let size = self.unsigned()?;
+        let transmitter = self.ident()?;
+
+        let mut signals = Vec::new();
+        while self.peek() == Some(&Token::Ident("SG_".to_owned())) {
+            self.pos += 1;
+            signals.push(self.signal()?);
+        }
+
+        if name == INDEPENDENT_SIGNALS {
+            return Ok(());
+        }
+        if size > 64 {
+            return Err(self
+                .error(&format!("message {} is longer than 64 bytes", name)));
+        }
+
+        let (id, extended) = split_id(raw_id);
+        self.db.messages.insert(
+            (id, extended),
+            Message {
+                id,
+                extended,
+                name,
+                size: size as u8,
+                transmitter: if transmitter == NO_NODE {
+                    None
+                } else {
+                    Some(transmitter)
+                },
+                extra_transmitters: Vec::new(),
+                signals,
+                comment: None,
+                attributes: HashMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// `SG_ name [mux] : start|length@order sign (factor,offset) [min|max] "unit" receivers`
+    fn signal(&mut self) -> Result<Signal> {
+        let name = self.ident()?;
+        let multiplexing = if self.is_punct(':') {
+            Multiplexing::None
+        } else {
+            let indicator = self.ident()?;
+            parse_multiplexing(&indicator).ok_or_else(|| {
+                self.error(&format!(
+                    "invalid multiplexer indicator '{}'",
+                    indicator
+                ))
+            })?
+        };
+        self.expect_punct(':')?;
+
+        let start_bit = self.unsigned()?;
+        self.expect_punct('|')?;
+        let length = self.unsigned()?;
+        self.expect_punct('@')?;
+        let byte_order = match self.unsigned()? {
+            0 => ByteOrder::BigEndian,
+            1 => ByteOrder::LittleEndian,
+            _ => return Err(self.error("invalid byte order")),
+        };
+        let value_type = if self.eat_punct('-') {
+            ValueType::Signed
+        } else {
+            self.expect_punct('+')?;
+            ValueType::Unsigned
+        };
+        if start_bit > 511 || length == 0 || length > 64 {
+            return Err(
+                self.error(&format!("invalid layout for signal {}", name))
+            );
+        }
+
+        self.expect_punct('(')?;
+        let factor = self.float()?;
+        self.expect_punct(',')?;
+        let offset = self.float()?;
+        self.expect_punct(')')?;
+        self.expect_punct('[')?;
+        let min = self.float()?;
+        self.expect_punct('|')?;
+        let max = self.float()?;
+        self.expect_punct(']')?;
+        let unit = self.string()?;
+
+        let mut receivers = Vec::new();
+        while !self.at_keyword() {
+            let receiver = self.ident()?;
+            if receiver != NO_NODE {
+                receivers.push(receiver);
+            }
+            self.eat_punct(',');
+        }
+
+        Ok(Signal {
+            name,
+            multiplexing,
+            extended_multiplexing: None,
+            start_bit: start_bit as u16,
+            length: length as u16,
+            byte_order,
+            value_type,
+            factor,
+            offset,
+            min,
+            max,
+            unit,
+            receivers,
+            comment: None,
+            value_descriptions: ValueTable::new(),
+            attributes: HashMap::new(),
+        })
+    }
+
+    fn message_transmitters(&mut self) -> Result<()> {
+        let key = split_id(self.unsigned()?);
+        self.expect_punct(':')?;
+        let mut transmitters = Vec::new();
+        while !self.eat_punct(';') {
+            transmitters.push(self.ident()?);
+            self.eat_punct(',');
+        }
+        if let Some(message) = self.db.messages.get_mut(&key) {
+            message.extra_transmitters = transmitters;
+        }
+        Ok(())
+    }
+
+    fn comment(&mut self) -> Result<()> {
+        let object = match self.peek() {
+            Some(Token::Ident(keyword)) => Some(keyword.clone()),
+            _ => None,
+        };
+
+        match object.as_deref() {
+            None => self.db.comment = Some(self.string()?),
+            Some("BU_") => {
+                self.pos += 1;
+                let name = self.ident()?;
+                let text = self.string()?;
+                if let Some(node) =
+                    self.db.nodes.iter_mut().find(|n| n.name == name)
+                {
+                    node.comment = Some(text);
+                }
+            }
+            Some("BO_") => {
+                self.pos += 1;
+                let key = split_id(self.unsigned()?);
+                let text = self.string()?;
+                if let Some(message) = self.db.messages.get_mut(&key) {
+                    message.comment = Some(text);
+                }
+            }
+            Some("SG_") => {
+                self.pos += 1;
+                let key = split_id(self.unsigned()?);
+                let name = self.ident()?;
+                let text = self.string()?;
+                if let Some(signal) = self.signal_mut(key, &name) {
+                    signal.comment = Some(text);
+                }
+            }
+            Some(_) => {
+                self.skip_statement();
+                return Ok(());
+            }
+        }
+
+        self.expect_punct(';')
+    }
+
+    fn attribute_definition(&mut self) -> Result<()> {
+        let object = match self.peek() {
+            Some(Token::Ident(keyword)) => match keyword.as_str() {
+                "BU_" => AttributeObject::Node,
+                "BO_" => AttributeObject::Message,
+                "SG_" => AttributeObject::Signal,
+                "EV_" => AttributeObject::EnvironmentVariable,
+                _ => return Err(self.error("invalid attribute object type")),
+            },
+            _ => AttributeObject::Network,
+        };
+        if object != AttributeObject::Network {
+            self.pos += 1;
+        }
+
+        let name = self.string()?;
+        let kind = match self.ident()?.as_str() {
+            "INT" => AttributeKind::Integer(self.integer()?, self.integer()?),
+            "HEX" => AttributeKind::Hex(self.integer()?, self.integer()?),
+            "FLOAT" => AttributeKind::Float(self.float()?, self.float()?),
+            "STRING" => AttributeKind::String,
+            "ENUM" => {
+                let mut labels = Vec::new();
+                while !self.is_punct(';') {
+                    labels.push(self.string()?);
+                    self.eat_punct(',');
+                }
+                AttributeKind::Enum(labels)
+            }
+            other => {
+                return Err(
+                    self.error(&format!("unknown attribute type '{}'", other))
+                )
+            }
+        };
+        self.expect_punct(';')?;
+
+        self.db.attribute_definitions.push(AttributeDefinition {
+            name,
+            object,
+            kind,
+            default: None,
+        });
+        Ok(())
+    }
+
+    fn attribute_default(&mut self) -> Result<()> {
+        let name = self.string()?;
+        let value = self.attribute_value(&name)?;
+        self.expect_punct(';')?;
+        if let Some(definition) = self
+            .db
+            .attribute_definitions
+            .iter_mut()
+            .find(|d| d.name == name)
+        {
+            definition.default = Some(value);
+        }
+        Ok(())
+    }
+
+    fn attribute(&mut self) -> Result<()> {
+        let name = self.string()?;
+        let object = match self.peek() {
+            Some(Token::Ident(keyword)) => Some(keyword.clone()),
+            _ => None,
+        };
+
+        match object.as_deref() {
+            None => {
+                let value = self.attribute_value(&name)?;
+                self.db.attributes.insert(name, value);
+            }
+            Some("BU_") => {
+                self.pos += 1;
+                let node_name = self.ident()?;
+                let value = self.attribute_value(&name)?;
+                if let Some(node) =
+                    self.db.nodes.iter_mut().find(|n| n.name == node_name)
+                {
+                    node.attributes.insert(name, value);
+                }
+            }
+            Some("BO_") => {
+                self.pos += 1;
+                let key = split_id(self.unsigned()?);
+                let value = self.attribute_value(&name)?;
+                if let Some(message) = self.db.messages.get_mut(&key) {
+                    message.attributes.insert(name, value);
+                }
+            }
+            Some("SG_") => {
+                self.pos += 1;
+                let key = split_id(self.unsigned()?);
+                let signal_name = self.ident()?;
+                let value = self.attribute_value(&name)?;
+                if let Some(signal) = self.signal_mut(key, &signal_name) {
+                    signal.attributes.insert(name, value);
+                }
+            }
+            Some(_) => {
+                self.skip_statement();
+                return Ok(());
+            }
+        }
+
+        self.expect_punct(';')
+    }
+
+    /// Parse an attribute value, converting it to the type of its
+    /// definition. Enumeration indices are replaced by their label.
+    fn attribute_value(&mut self, name: &str) -> Result<AttributeValue> {
+        let value = match self.peek() {
+            Some(&Token::Str(_)) => AttributeValue::String(self.string()?),
+            Some(&Token::Number(_)) => {
+                let text = self.number_text()?;
+                match text.parse::<i64>() {
+                    Ok(value) => AttributeValue::Integer(value),
+                    Err(_) => {
+                        AttributeValue::Float(text.parse().map_err(|_| {
+                            self.error(&format!("invalid number '{}'", text))
+                        })?)
+                    }
+                }
+            }
+            _ => return Err(self.error("expected an attribute value")),
+        };
+
+        let kind = self
+            .db
+            .attribute_definitions
+            .iter()
+            .find(|d| d.name == name)
+            .map(|d| &d.kind);
+        Ok(match (kind, value) {
+            (
+                Some(AttributeKind::Enum(labels)),
+                AttributeValue::Integer(index),
+            ) => match labels.get(index as usize) {
+                Some(label) if index >= 0 => {
+                    AttributeValue::String(label.clone())
+                }
+                _ => AttributeValue::Integer(index),
+            },
+            (
+                Some(AttributeKind::Float(..)),
+                AttributeValue::Integer(value),
+            ) => AttributeValue::Float(value as f64),
+            (_, value) => value,
+        })
+    }
+
+    fn signal_value_descriptions(&mut self) -> Result<()> {
+        // Value descriptions of environment variables have no message id
+        if !matches!(self.peek(), Some(&Token::Number(_))) {
+            self.skip_statement();
+            return Ok(());
+        }
+
+        let key = split_id(self.unsigned()?);
+        let name = self.ident()?;
+        let table = self.value_descriptions()?;
+        if let Some(signal) = self.signal_mut(key, &name) {
+            signal.value_descriptions = table;
+        }
+        Ok(())
+    }
+
+    fn signal_value_type(&mut self) -> Result<()> {
+        let key = split_id(self.unsigned()?);
+        let name = self.ident()?;
+        self.eat_punct(':');
+        let value_type = match self.unsigned()? {
+            1 => Some(ValueType::Float),
+            2 => Some(ValueType::Double),
+            _ => None,
+        };
+        self.expect_punct(';')?;
+
+        if let (Some(value_type), Some(signal)) =
+            (value_type, self.signal_mut(key, &name))
+        {
+            signal.value_type = value_type;
+        }
+        Ok(())
+    }
+
+    /// `SG_MUL_VAL_ id signal switch lo-hi, lo-hi ;`
+    fn extended_multiplexing(&mut self) -> Result<()> {
+        let key = split_id(self.unsigned()?);
+        let name = self.ident()?;
+        let switch = self.ident()?;
+        let mut ranges = Vec::new();
+        while !self.eat_punct(';') {
+            let lo = self.unsigned()?;
+            self.expect_punct('-')?;
+            let hi = self.unsigned()?;
+            ranges.push((lo, hi));
+            self.eat_punct(',');
+        }
+
+        if let Some(signal) = self.signal_mut(key, &name) {
+            match signal.extended_multiplexing {
+                Some(ref mut existing) if existing.switch == switch => {
+                    existing.ranges.extend(ranges)
+                }
+                _ => {
+                    signal.extended_multiplexing =
+                        Some(ExtendedMultiplexing { switch, ranges })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn signal_mut(
+        &mut self,
+        key: (u32, bool),
+        name: &str,
+    ) -> Option<&mut Signal> {
+        self.db
+            .messages
+            .get_mut(&key)
+            .and_then(|m| m.signals.iter_mut().find(|s| s.name == name))
+    }
+}
+
+/// Split a DBC message id into the frame identifier and the extended flag.
+fn split_id(raw: u64) -> (u32, bool) {
+    ((raw & 0x1FFF_FFFF) as u32, raw & EXTENDED_ID_FLAG != 0)
+}
+
+fn parse_multiplexing(indicator: &str) -> Option<Multiplexing> {
+    if indicator == "M" {
+        return Some(Multiplexing::Multiplexor);
+    }
+    let value = indicator.strip_prefix('m')?;
+    match value.strip_suffix('M') {
+        Some(value) => {
+            value.parse().ok().map(Multiplexing::MultiplexedMultiplexor)
+        }
+        None => value.parse().ok().map(Multiplexing::Multiplexed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbc::*;
+    use CANFrame;
+
+    const SAMPLE: &str = r#"VERSION "1.0"
+
+NS_ :
+    NS_DESC_
+    CM_
+    BA_DEF_
+    BA_
+    VAL_
+
+BS_:
+
+BU_: Engine Gateway Tester
+
+VAL_TABLE_ OnOff 1 "On" 0 "Off" ;
+
+BO_ 256 EngineData: 8 Engine
+ SG_ EngineSpeed : 0|16@1+ (0.25,0) [0|16383.75] "rpm" Gateway,Tester
+ SG_ CoolantTemp : 16|8@1- (1,-40) [-40|215] "degC" Gateway
+ SG_ Status : 31|4@0+ (1,0) [0|15] "" Vector__XXX
+
+BO_ 2566844926 Diagnostics: 8 Gateway
+ SG_ Mode M : 0|8@1+ (1,0) [0|255] "" Tester
+ SG_ Sub m1M : 8|8@1+ (1,0) [0|255] "" Tester
+ SG_ Value m2 : 16|16@1+ (0.1,0) [0|6553.5] "V" Tester
+ SG_ Deep m3 : 24|32@1+ (1,0) [0|4294967295] "" Tester
+
+BO_TX_BU_ 256 : Engine,Tester;
+
+CM_ "Demo network";
+CM_ BU_ Engine "Engine control unit";
+CM_ BO_ 256 "Cyclic engine data";
+CM_ SG_ 256 EngineSpeed "Crankshaft speed
+over two lines";
+
+BA_DEF_ BO_ "GenMsgCycleTime" INT 0 65535;
+BA_DEF_ SG_ "GenSigStartValue" FLOAT 0 1e+9;
+BA_DEF_ "BusType" STRING ;
+BA_DEF_ BO_ "VFrameFormat" ENUM "StandardCAN","ExtendedCAN","J1939PG";
+BA_DEF_DEF_ "GenMsgCycleTime" 100;
+BA_DEF_DEF_ "VFrameFormat" "StandardCAN";
+BA_ "BusType" "CAN";
+BA_ "GenMsgCycleTime" BO_ 256 10;
+BA_ "VFrameFormat" BO_ 2566844926 2;
+BA_ "GenSigStartValue" SG_ 256 CoolantTemp 40;
+
+VAL_ 256 Status 0 "Ok" 1 "Warning" 2 "Fault" ;
+SIG_VALTYPE_ 2566844926 Deep : 1;
+SG_MUL_VAL_ 2566844926 Deep Sub 3-5, 8-8;
+"#;
+
+    #[test]
+    fn parse_sample() {
+        let db: Database = SAMPLE.parse().unwrap();
+        assert_eq!(db.version, "1.0");
+        assert_eq!(db.comment.as_deref(), Some("Demo network"));
+        assert_eq!(db.nodes.len(), 3);
+        assert_eq!(
+            db.node("Engine").unwrap().comment.as_ref().unwrap(),
+            "Engine control unit"
+        );
+        assert_eq!(db.value_tables["OnOff"][&1], "On");
+        assert_eq!(
+            db.attributes["BusType"],
+            AttributeValue::String("CAN".to_owned())
+        );
+
+        let engine = db.message(0x100, false).unwrap();
+        assert!(!engine.extended);
+        assert_eq!(engine.transmitter.as_ref().unwrap(), "Engine");
+        assert_eq!(
+            engine.extra_transmitters,
+            vec!["Engine".to_owned(), "Tester".to_owned()]
+        );
+        assert_eq!(
+            engine.attributes["GenMsgCycleTime"],
+            AttributeValue::Integer(10)
+        );
+
+        let speed = engine.signal("EngineSpeed").unwrap();
+        assert_eq!(
+            (speed.start_bit, speed.length, speed.factor),
+            (0, 16, 0.25)
+        );
+        assert_eq!(
+            speed.receivers,
+            vec!["Gateway".to_owned(), "Tester".to_owned()]
+        );
+        assert_eq!(
+            speed.comment.as_ref().unwrap(),
+            "Crankshaft speed\nover two lines"
+        );
+
+        let coolant = engine.signal("CoolantTemp").unwrap();
+        assert!(coolant.is_signed());
+        assert_eq!(
+            (coolant.offset, coolant.min, coolant.max),
+            (-40.0, -40.0, 215.0)
+        );
+        assert_eq!(
+            db.signal_attribute(coolant, "GenSigStartValue"),
+            Some(&AttributeValue::Float(40.0))
+        );
+
+        let status = engine.signal("Status").unwrap();
+        assert_eq!(status.byte_order, ByteOrder::BigEndian);
+        assert!(status.receivers.is_empty());
+        assert_eq!(status.value_description(2), Some("Fault"));
+
+        let diag = db.message_by_name("Diagnostics").unwrap();
+        assert!(diag.extended);
+        assert_eq!(diag.id, 0x18FE_F1FE);
+        assert_eq!(
+            db.message_attribute(diag, "GenMsgCycleTime"),
+            Some(&AttributeValue::Integer(100))
+        );
+        assert_eq!(
+            db.message_attribute(diag, "VFrameFormat"),
+            Some(&AttributeValue::String("J1939PG".to_owned()))
+        );
+        assert_eq!(diag.multiplexor().unwrap().name, "Mode");
+        assert_eq!(
+            diag.signal("Sub").unwrap().multiplexing,
+            Multiplexing::MultiplexedMultiplexor(1)
+        );
+        let deep = diag.signal("Deep").unwrap();
+        assert_eq!(deep.value_type, ValueType::Float);
+        assert_eq!(
+            deep.extended_multiplexing,
+            Some(ExtendedMultiplexing {
+                switch: "Sub".to_owned(),
+                ranges: vec![(3, 5), (8, 8)],
+            })
+        );
+    }
+
+    #[test]
+    fn standard_and_extended_ids() {
+        let text = "BO_ 256 Standard: 1 Engine\n SG_ A : 0|8@1+ (1,0) [0|255] \"\" Gateway\n\n\
+                    BO_ 2147483904 Extended: 2 Engine\n SG_ B : 0|16@1+ (1,0) [0|65535] \"\" Gateway\n\n\
+                    CM_ BO_ 2147483904 \"Extended 0x100\";\n\
+                    VAL_ 256 A 1 \"One\" ;\n";
+        let db: Database = text.parse().unwrap();
+        assert_eq!(db.messages.len(), 2);
+        let standard = db.message(0x100, false).unwrap();
+        assert_eq!(
+            (standard.name.as_str(), standard.comment.as_deref()),
+            ("Standard", None)
+        );
+        assert_eq!(
+            standard.signal("A").unwrap().value_description(1),
+            Some("One")
+        );
+        let extended = db.message(0x100, true).unwrap();
+        assert_eq!(
+            (extended.name.as_str(), extended.comment.as_deref()),
+            ("Extended", Some("Extended 0x100"))
+        );
+        assert!(extended.signal("B").unwrap().value_descriptions.is_empty());
+
+        let frame =
+            CANFrame::new_extended(0x100, &[0x34, 0x12], false).unwrap();
+        assert_eq!(db.decode(&frame).unwrap().message.name, "Extended");
+        let frame = CANFrame::new(0x100, &[0x34], false).unwrap();
+        assert_eq!(db.decode(&frame).unwrap().message.name, "Standard");
+    }
+
+    #[test]
+    fn report_line_of_error() {
+        let err = "VERSION \"\"\n\nBO_ 1 Broken: 8 Node\n SG_ A : 0|8@2+ (1,0) [0|1] \"\" Node\n"
+            .parse::<Database>()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Malformed DBC file: line 4: invalid byte order"
+        );
+    }
+}
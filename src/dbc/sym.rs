@@ -0,0 +1,637 @@
+//! Parser for PCAN Symbol Editor files (`.sym`, format version 6).
+//!
+//! Messages are `[Name]` sections holding `ID=`, `Type=`, `DLC=`,
+//! `CycleTime=` and one `Var=` or `Sig=` line per signal. A message
+//! repeated with a different `Mux=` line adds the signals selected by
+//! another multiplexor value. Big endian (`-m`) start bits count the most
+//! significant bit first and are converted to DBC numbering. Enums become
+//! shared value tables.
+use dbc::{
+    big_endian_start, AttributeValue, ByteOrder, Database, Message,
+    Multiplexing, Signal, ValueTable, ValueType, CYCLE_TIME_ATTRIBUTE,
+    START_VALUE_ATTRIBUTE,
+};
+use errors::*;
+use std::collections::HashMap;
+
+/// A `Sig=` definition from the `{SIGNALS}` section, placed by a message.
+struct SignalDefinition {
+    words: Vec<String>, // Type, length and options
+    comment: Option<String>,
+}
+
+/// A message section being read.
+struct Section {
+    line: usize,
+    name: String,
+    id: Option<(u32, bool)>,
+    extended: bool,
+    size: Option<u8>,
+    cycle_time: Option<i64>,
+    mux: Option<(Signal, u64)>,
+    signals: Vec<Signal>,
+}
+
+struct Parser {
+    db: Database,
+    definitions: HashMap<String, SignalDefinition>,
+    group: String, // Current `{...}` group
+    section: Option<Section>,
+    line: usize,
+}
+
+pub fn parse(text: &str) -> Result<Database> {
+    let mut parser = Parser {
+        db: Database::default(),
+        definitions: HashMap::new(),
+        group: String::new(),
+        section: None,
+        line: 0,
+    };
+
+    let mut lines = text.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        parser.line = index + 1;
+        let (mut content, comment) = split_comment(line);
+        // Enums may continue over several lines until their closing parenthesis
+        if content.starts_with("enum") {
+            while content.matches('(').count() > content.matches(')').count() {
+                match lines.next() {
+                    Some((_, next)) => {
+                        content =
+                            format!("{} {}", content, split_comment(next).0)
+                    }
+                    None => break,
+                }
+            }
+        }
+        parser.line(&content, comment)?;
+    }
+    parser.finish_section()?;
+    Ok(parser.db)
+}
+
+/// Split a line into its content and trailing `//` comment, ignoring
+/// slashes inside quotes.
+fn split_comment(line: &str) -> (String, Option<String>) {
+    let mut quoted = false;
+    let chars: Vec<char> = line.chars().collect();
+    for i in 0..chars.len() {
+        match chars[i] {
+            '"' => quoted = !quoted,
+            '/' if !quoted && chars.get(i + 1) == Some(&'/') => {
+                let content: String = chars[..i].iter().collect();
+                let comment: String = chars[i + 2..].iter().collect();
+                let comment = comment.trim();
+                return (
+                    content.trim().to_owned(),
+                    if comment.is_empty() {
+                        None
+                    } else {
+                        Some(comment.to_owned())
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+    (line.trim().to_owned(), None)
+}
+
+/// Split on whitespace, keeping quoted text (without its quotes) together.
+fn words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut started = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if started {
+                    words.push(word.clone());
+                    word.clear();
+                    started = false;
+                }
+            }
+            c => {
+                word.push(c);
+                started = true;
+            }
+        }
+    }
+    if started {
+        words.push(word);
+    }
+    words
+}
+
+/// Decimal or `h` suffixed hexadecimal integer.
+fn integer(text: &str) -> Option<u64> {
+    match text.strip_suffix('h').or_else(|| text.strip_suffix('H')) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+impl Parser {
+    fn error(&self, reason: &str) -> Error {
+        ErrorKind::SymFormat(format!("line {}: {}", self.line, reason)).into()
+    }
+
+    fn line(&mut self, content: &str, comment: Option<String>) -> Result<()> {
+        if content.is_empty() {
+            return Ok(());
+        }
+        if content.starts_with('{') && content.ends_with('}') {
+            self.finish_section()?;
+            self.group = content[1..content.len() - 1].to_uppercase();
+            return Ok(());
+        }
+        if content.starts_with('[') && content.ends_with(']') {
+            self.finish_section()?;
+            self.section = Some(Section {
+                line: self.line,
+                name: content[1..content.len() - 1].to_owned(),
+                id: None,
+                extended: false,
+                size: None,
+                cycle_time: None,
+                mux: None,
+                signals: Vec::new(),
+            });
+            return Ok(());
+        }
+        if self.group == "ENUMS" {
+            return self.enumeration(content);
+        }
+
+        let (key, value) = match content.find('=') {
+            Some(i) => (content[..i].trim(), content[i + 1..].trim()),
+            None => {
+                return Err(self.error(&format!("unexpected '{}'", content)))
+            }
+        };
+        if self.section.is_none() {
+            // File header (`FormatVersion`, `Title`) and `{SIGNALS}` definitions
+            match key {
+                "FormatVersion" => self.db.version = value.to_owned(),
+                "Title" => {
+                    self.db.comment = Some(value.trim_matches('"').to_owned())
+                }
+                "Sig" if self.group == "SIGNALS" => {
+                    let mut words = words(value);
+                    if words.len() < 3 {
+                        return Err(self.error("incomplete signal definition"));
+                    }
+                    let name = words.remove(0);
+                    self.definitions
+                        .insert(name, SignalDefinition { words, comment });
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        match key {
+            "ID" => {
+                // Ranges (`ID=100h-10Fh`) keep their first identifier
+                let text = value.split('-').next().unwrap_or("");
+                let id = integer(text).ok_or_else(|| {
+                    self.error(&format!("invalid ID '{}'", value))
+                })?;
+                if id > 0x1FFF_FFFF {
+                    return Err(self.error(&format!("invalid ID '{}'", value)));
+                }
+                self.section_mut().id = Some((id as u32, id > 0x7FF));
+            }
+            "Type" => {
+                self.section_mut().extended =
+                    value.eq_ignore_ascii_case("extended")
+            }
+            "DLC" | "Len" => {
+                let size =
+                    integer(value).filter(|&s| s <= 64).ok_or_else(|| {
+                        self.error(&format!("invalid length '{}'", value))
+                    })?;
+                self.section_mut().size = Some(size as u8);
+            }
+            "CycleTime" => {
+                let time = integer(value).ok_or_else(|| {
+                    self.error(&format!("invalid cycle time '{}'", value))
+                })?;
+                self.section_mut().cycle_time = Some(time as i64);
+            }
+            "Var" => {
+                let words = words(value);
+                if words.len() < 3 {
+                    return Err(self.error("incomplete Var line"));
+                }
+                let (start, length) = self.position(&words[2])?;
+                let signal = self.signal(
+                    &words[0],
+                    &words[1],
+                    start,
+                    length,
+                    &words[3..],
+                    comment,
+                )?;
+                self.section_mut().signals.push(signal);
+            }
+            "Sig" => {
+                let words = words(value);
+                if words.len() < 2 {
+                    return Err(self.error("incomplete Sig line"));
+                }
+                let start = integer(&words[1]).ok_or_else(|| {
+                    self.error(&format!("invalid start bit '{}'", words[1]))
+                })?;
+                let signal = {
+                    let definition =
+                        self.definitions.get(&words[0]).ok_or_else(|| {
+                            self.error(&format!(
+                                "undefined signal {}",
+                                words[0]
+                            ))
+                        })?;
+                    let length =
+                        integer(&definition.words[1]).ok_or_else(|| {
+                            self.error(&format!(
+                                "invalid length for signal {}",
+                                words[0]
+                            ))
+                        })?;
+                    let options: Vec<String> = definition.words[2..]
+                        .iter()
+                        .chain(&words[2..])
+                        .cloned()
+                        .collect();
+                    let comment =
+                        comment.or_else(|| definition.comment.clone());
+                    self.signal(
+                        &words[0],
+                        &definition.words[0],
+                        start,
+                        length,
+                        &options,
+                        comment,
+                    )?
+                };
+                self.section_mut().signals.push(signal);
+            }
+            "Mux" => {
+                let words = words(value);
+                if words.len() < 3 {
+                    return Err(self.error("incomplete Mux line"));
+                }
+                let (start, length) = self.position(&words[1])?;
+                let selector = integer(&words[2]).ok_or_else(|| {
+                    self.error(&format!(
+                        "invalid multiplexor value '{}'",
+                        words[2]
+                    ))
+                })?;
+                let mut signal = self.signal(
+                    &words[0],
+                    "unsigned",
+                    start,
+                    length,
+                    &words[3..],
+                    comment,
+                )?;
+                signal.multiplexing = Multiplexing::Multiplexor;
+                self.section_mut().mux = Some((signal, selector));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn section_mut(&mut self) -> &mut Section {
+        self.section.as_mut().expect("inside a message section")
+    }
+
+    /// `enum Name(0="Label", 1="Other")`
+    fn enumeration(&mut self, content: &str) -> Result<()> {
+        let rest = content
+            .strip_prefix("enum")
+            .ok_or_else(|| self.error(&format!("unexpected '{}'", content)))?;
+        let (open, close) = match (rest.find('('), rest.rfind(')')) {
+            (Some(open), Some(close)) if open < close => (open, close),
+            _ => return Err(self.error("unterminated enum")),
+        };
+        let name = rest[..open].trim().to_owned();
+        let mut table = ValueTable::new();
+        let mut entries = &rest[open + 1..close];
+        while !entries.trim().is_empty() {
+            let eq = entries.find('=').ok_or_else(|| {
+                self.error(&format!("invalid entry in enum {}", name))
+            })?;
+            let value = entries[..eq].trim().trim_start_matches(',').trim();
+            let value = integer(value)
+                .map(|v| v as i64)
+                .or_else(|| value.parse().ok())
+                .ok_or_else(|| {
+                    self.error(&format!(
+                        "invalid value '{}' in enum {}",
+                        value, name
+                    ))
+                })?;
+            let label = entries[eq + 1..].trim_start();
+            let label = label.strip_prefix('"').ok_or_else(|| {
+                self.error(&format!("unquoted label in enum {}", name))
+            })?;
+            let end = label.find('"').ok_or_else(|| {
+                self.error(&format!("unterminated label in enum {}", name))
+            })?;
+            table.insert(value, label[..end].to_owned());
+            entries = &label[end + 1..];
+        }
+        self.db.value_tables.insert(name, table);
+        Ok(())
+    }
+
+    /// `start,length`
+    fn position(&self, text: &str) -> Result<(u64, u64)> {
+        let mut parts = text.split(',');
+        match (
+            parts.next().and_then(integer),
+            parts.next().and_then(integer),
+            parts.next(),
+        ) {
+            (Some(start), Some(length), None) => Ok((start, length)),
+            _ => Err(self.error(&format!("invalid position '{}'", text))),
+        }
+    }
+
+    fn signal(
+        &self,
+        name: &str,
+        kind: &str,
+        start: u64,
+        length: u64,
+        options: &[String],
+        comment: Option<String>,
+    ) -> Result<Signal> {
+        let value_type = match kind {
+            "unsigned" | "bit" | "char" | "string" | "raw" => {
+                ValueType::Unsigned
+            }
+            "signed" => ValueType::Signed,
+            "float" => ValueType::Float,
+            "double" => ValueType::Double,
+            other => {
+                return Err(self.error(&format!(
+                    "unknown type '{}' for signal {}",
+                    other, name
+                )))
+            }
+        };
+        if start > 511 || length == 0 || length > 64 {
+            return Err(
+                self.error(&format!("invalid layout for signal {}", name))
+            );
+        }
+
+        let mut signal = Signal {
+            name: name.to_owned(),
+            multiplexing: Multiplexing::None,
+            extended_multiplexing: None,
+            start_bit: start as u16,
+            length: length as u16,
+            byte_order: ByteOrder::LittleEndian,
+            value_type,
+            factor: 1.0,
+            offset: 0.0,
+            min: 0.0,
+            max: 0.0,
+            unit: String::new(),
+            receivers: Vec::new(),
+            comment,
+            value_descriptions: ValueTable::new(),
+            attributes: HashMap::new(),
+        };
+        let mut default = None;
+        for option in options {
+            let number = |text: &str| -> Result<f64> {
+                text.parse().map_err(|_| {
+                    self.error(&format!(
+                        "invalid option '{}' for signal {}",
+                        option, name
+                    ))
+                })
+            };
+            if option == "-m" {
+                signal.byte_order = ByteOrder::BigEndian;
+                signal.start_bit = big_endian_start(start as u16);
+            } else if let Some(unit) = option.strip_prefix("/u:") {
+                signal.unit = unit.to_owned();
+            } else if let Some(factor) = option.strip_prefix("/f:") {
+                signal.factor = number(factor)?;
+            } else if let Some(offset) = option.strip_prefix("/o:") {
+                signal.offset = number(offset)?;
+            } else if let Some(min) = option.strip_prefix("/min:") {
+                signal.min = number(min)?;
+            } else if let Some(max) = option.strip_prefix("/max:") {
+                signal.max = number(max)?;
+            } else if let Some(value) = option.strip_prefix("/d:") {
+                default = Some(number(value)?);
+            } else if let Some(table) = option.strip_prefix("/e:") {
+                signal.value_descriptions =
+                    self.db.value_tables.get(table).cloned().ok_or_else(
+                        || self.error(&format!("undefined enum {}", table)),
+                    )?;
+            }
+        }
+        if signal.factor == 0.0 {
+            return Err(self.error(&format!("zero factor for signal {}", name)));
+        }
+        // Defaults are physical values, start values raw ones
+        if let Some(default) = default {
+            let raw = ((default - signal.offset) / signal.factor).round();
+            signal.attributes.insert(
+                START_VALUE_ATTRIBUTE.to_owned(),
+                AttributeValue::Float(raw),
+            );
+        }
+        Ok(signal)
+    }
+
+    /// Add the signals of the finished message section to its message.
+    fn finish_section(&mut self) -> Result<()> {
+        let section = match self.section.take() {
+            Some(section) => section,
+            None => return Ok(()),
+        };
+        let existing = self
+            .db
+            .messages
+            .values()
+            .find(|m| m.name == section.name)
+            .map(|m| (m.id, m.extended));
+        let id = match (section.id, existing) {
+            (Some((id, _)), _) | (None, Some((id, _))) => id,
+            (None, None) => {
+                self.line = section.line;
+                return Err(
+                    self.error(&format!("message {} without ID", section.name))
+                );
+            }
+        };
+        let extended = section.extended
+            || section.id.is_some_and(|(_, extended)| extended)
+            || existing == Some((id, true));
+
+        let message =
+            self.db
+                .messages
+                .entry((id, extended))
+                .or_insert_with(|| Message {
+                    id,
+                    extended,
+                    name: section.name.clone(),
+                    size: 0,
+                    transmitter: None,
+                    extra_transmitters: Vec::new(),
+                    signals: Vec::new(),
+                    comment: None,
+                    attributes: HashMap::new(),
+                });
+        if let Some(size) = section.size {
+            message.size = message.size.max(size);
+        }
+        if let Some(time) = section.cycle_time {
+            message.attributes.insert(
+                CYCLE_TIME_ATTRIBUTE.to_owned(),
+                AttributeValue::Integer(time),
+            );
+        }
+
+        let selector = section.mux.as_ref().map(|m| m.1);
+        if let Some((mux, _)) = section.mux {
+            if message.multiplexor().is_none() {
+                message.signals.push(mux);
+            }
+        }
+        for mut signal in section.signals {
+            if let Some(value) = selector {
+                signal.multiplexing = Multiplexing::Multiplexed(value);
+            } else if message.signal(&signal.name).is_some() {
+                // Signals outside the multiplexed part repeat in every section
+                continue;
+            }
+            message.signals.push(signal);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbc::{AttributeValue, ByteOrder, Database, Multiplexing, ValueType};
+    use CANFrame;
+
+    const SAMPLE: &str = r#"FormatVersion=6.0 // Do not edit this line!
+Title="Demo network"
+
+{ENUMS}
+enum Status(0="Ok", 1="Warning",
+  2="Fault")
+
+{SIGNALS}
+Sig=Counter unsigned 8 /max:255 // Rolling counter
+
+{SENDRECEIVE}
+
+[EngineData]
+ID=100h
+DLC=8
+CycleTime=10
+Var=EngineSpeed unsigned 0,16 /u:rpm /f:0.25 /max:16383.75 // Crankshaft speed
+Var=CoolantTemp signed 16,8 /u:"deg C" /o:-40 /d:20
+Var=Status unsigned 24,4 -m /e:Status
+
+[Diagnostics]
+ID=18FEF1FEh
+Type=Extended
+DLC=8
+Mux=Mode 0,8 1
+Sig=Counter 8
+
+[Diagnostics]
+DLC=8
+Mux=Mode 0,8 2
+Var=Value float 8,32
+"#;
+
+    #[test]
+    fn parse_sample() {
+        let db = Database::from_sym(SAMPLE).unwrap();
+        assert_eq!(
+            (db.version.as_str(), db.comment.as_deref()),
+            ("6.0", Some("Demo network"))
+        );
+        assert_eq!(db.value_tables["Status"][&2], "Fault");
+
+        let engine = db.message(0x100, false).unwrap();
+        assert_eq!(
+            (engine.name.as_str(), engine.size, engine.extended),
+            ("EngineData", 8, false)
+        );
+        assert_eq!(
+            engine.attributes["GenMsgCycleTime"],
+            AttributeValue::Integer(10)
+        );
+        let speed = engine.signal("EngineSpeed").unwrap();
+        assert_eq!((speed.factor, speed.unit.as_str()), (0.25, "rpm"));
+        assert_eq!(speed.comment.as_deref(), Some("Crankshaft speed"));
+        let coolant = engine.signal("CoolantTemp").unwrap();
+        assert_eq!(
+            (coolant.value_type, coolant.unit.as_str()),
+            (ValueType::Signed, "deg C")
+        );
+        assert_eq!(db.start_value(coolant), 60);
+        let status = engine.signal("Status").unwrap();
+        assert_eq!(
+            (status.byte_order, status.start_bit),
+            (ByteOrder::BigEndian, 31)
+        );
+
+        let diag = db.message(0x18FE_F1FE, true).unwrap();
+        assert!(diag.extended);
+        let names: Vec<&str> =
+            diag.signals.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Mode", "Counter", "Value"]);
+        assert_eq!(diag.multiplexor().unwrap().name, "Mode");
+        let counter = diag.signal("Counter").unwrap();
+        assert_eq!(
+            (counter.multiplexing, counter.length),
+            (Multiplexing::Multiplexed(1), 8)
+        );
+        assert_eq!(counter.comment.as_deref(), Some("Rolling counter"));
+        assert_eq!(diag.signal("Value").unwrap().value_type, ValueType::Float);
+
+        let frame =
+            CANFrame::new(0x100, &[0xA1, 0x0F, 0x3C, 0x20, 0, 0, 0, 0], false)
+                .unwrap();
+        let decoded = db.decode(&frame).unwrap();
+        assert_eq!(decoded.physical("EngineSpeed"), Some(1000.25));
+        assert_eq!(decoded.physical("CoolantTemp"), Some(20.0));
+        assert_eq!(decoded.signal("Status").unwrap().label, Some("Fault"));
+    }
+
+    #[test]
+    fn report_line_of_error() {
+        let err = Database::from_sym(
+            "FormatVersion=6.0\n\n[Broken]\nID=100h\nVar=A unsigned 0;8\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Malformed SYM file: line 5: invalid position '0;8'"
+        );
+    }
+}
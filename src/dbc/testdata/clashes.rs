@@ -0,0 +1,347 @@
+// Generated by pcan-basic from a DBC file. Do not edit.
+
+/// Values of `Mode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamesMode {
+    Off, // 0
+    On, // 1
+    Other(u8),
+}
+
+impl From<u8> for NamesMode {
+    fn from(raw: u8) -> NamesMode {
+        match raw {
+            0 => NamesMode::Off,
+            1 => NamesMode::On,
+            other => NamesMode::Other(other),
+        }
+    }
+}
+
+impl From<NamesMode> for u8 {
+    fn from(value: NamesMode) -> u8 {
+        match value {
+            NamesMode::Off => 0,
+            NamesMode::On => 1,
+            NamesMode::Other(raw) => raw,
+        }
+    }
+}
+
+/// `Names` (0x200)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Names {
+    data: [u8; 8],
+}
+
+impl Names {
+    pub const ID: u32 = 0x200;
+    pub const EXTENDED: bool = false;
+    pub const SIZE: usize = 8;
+    const ID_2: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Id",
+        start_bit: 0,
+        length: 8,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 255.0,
+    };
+    const SIZE_2: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Size",
+        start_bit: 8,
+        length: 8,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 255.0,
+    };
+    const PAYLOAD_2: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Payload",
+        start_bit: 16,
+        length: 8,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 255.0,
+    };
+    const SPEED: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Speed",
+        start_bit: 24,
+        length: 8,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 255.0,
+    };
+    const SPEED_RAW_2: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "SpeedRaw",
+        start_bit: 32,
+        length: 8,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 255.0,
+    };
+    const MODE: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Mode",
+        start_bit: 40,
+        length: 8,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 255.0,
+    };
+
+    /// A message with every signal at its start value.
+    pub fn new() -> Names {
+        Names { data: [0; 8] }
+    }
+
+    /// Wrap a payload of at least `SIZE` bytes.
+    pub fn from_payload(payload: &[u8]) -> Option<Names> {
+        if payload.len() < Self::SIZE {
+            return None;
+        }
+        let mut data = [0; 8];
+        data.copy_from_slice(&payload[..Self::SIZE]);
+        Some(Names { data })
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// `Id`, from 0 to 255
+    pub fn id_2(&self) -> u8 {
+        Self::ID_2.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn id_2_raw(&self) -> u8 {
+        Self::ID_2.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn set_id_2(&mut self, value: u8) -> crate::Result<()> {
+        let raw = Self::ID_2.physical_to_raw(value as f64)?;
+        Self::ID_2.insert(&mut self.data, raw);
+        Ok(())
+    }
+
+    pub fn set_id_2_raw(&mut self, raw: u8) {
+        Self::ID_2.insert(&mut self.data, raw as u64);
+    }
+
+    /// `Size`, from 0 to 255
+    pub fn size_2(&self) -> u8 {
+        Self::SIZE_2.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn size_2_raw(&self) -> u8 {
+        Self::SIZE_2.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn set_size_2(&mut self, value: u8) -> crate::Result<()> {
+        let raw = Self::SIZE_2.physical_to_raw(value as f64)?;
+        Self::SIZE_2.insert(&mut self.data, raw);
+        Ok(())
+    }
+
+    pub fn set_size_2_raw(&mut self, raw: u8) {
+        Self::SIZE_2.insert(&mut self.data, raw as u64);
+    }
+
+    /// `Payload`, from 0 to 255
+    pub fn payload_2(&self) -> u8 {
+        Self::PAYLOAD_2.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn payload_2_raw(&self) -> u8 {
+        Self::PAYLOAD_2.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn set_payload_2(&mut self, value: u8) -> crate::Result<()> {
+        let raw = Self::PAYLOAD_2.physical_to_raw(value as f64)?;
+        Self::PAYLOAD_2.insert(&mut self.data, raw);
+        Ok(())
+    }
+
+    pub fn set_payload_2_raw(&mut self, raw: u8) {
+        Self::PAYLOAD_2.insert(&mut self.data, raw as u64);
+    }
+
+    /// `Speed`, from 0 to 255
+    pub fn speed(&self) -> u8 {
+        Self::SPEED.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn speed_raw(&self) -> u8 {
+        Self::SPEED.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn set_speed(&mut self, value: u8) -> crate::Result<()> {
+        let raw = Self::SPEED.physical_to_raw(value as f64)?;
+        Self::SPEED.insert(&mut self.data, raw);
+        Ok(())
+    }
+
+    pub fn set_speed_raw(&mut self, raw: u8) {
+        Self::SPEED.insert(&mut self.data, raw as u64);
+    }
+
+    /// `SpeedRaw`, from 0 to 255
+    pub fn speed_raw_2(&self) -> u8 {
+        Self::SPEED_RAW_2.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn speed_raw_2_raw(&self) -> u8 {
+        Self::SPEED_RAW_2.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn set_speed_raw_2(&mut self, value: u8) -> crate::Result<()> {
+        let raw = Self::SPEED_RAW_2.physical_to_raw(value as f64)?;
+        Self::SPEED_RAW_2.insert(&mut self.data, raw);
+        Ok(())
+    }
+
+    pub fn set_speed_raw_2_raw(&mut self, raw: u8) {
+        Self::SPEED_RAW_2.insert(&mut self.data, raw as u64);
+    }
+
+    /// `Mode`, from 0 to 255
+    pub fn mode(&self) -> NamesMode {
+        NamesMode::from(Self::MODE.extract(&self.data).unwrap_or(0) as u8)
+    }
+
+    pub fn mode_raw(&self) -> u8 {
+        Self::MODE.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn set_mode(&mut self, value: NamesMode) {
+        self.set_mode_raw(value.into());
+    }
+
+    pub fn set_mode_raw(&mut self, raw: u8) {
+        Self::MODE.insert(&mut self.data, raw as u64);
+    }
+}
+
+impl Default for Names {
+    fn default() -> Names {
+        Names::new()
+    }
+}
+
+impl<'a> ::std::convert::TryFrom<&'a crate::CANFrame> for Names {
+    type Error = crate::Error;
+
+    fn try_from(frame: &'a crate::CANFrame) -> crate::Result<Names> {
+        if frame.id() != Names::ID || frame.is_extended() != Names::EXTENDED || frame.is_rtr() {
+            return Err(crate::ErrorKind::UnexpectedFrame(frame.id()).into());
+        }
+        Names::from_payload(&frame.data()[..frame.len() as usize])
+            .ok_or_else(|| crate::ErrorKind::UnexpectedFrame(frame.id()).into())
+    }
+}
+
+impl From<Names> for crate::CANFrame {
+    fn from(message: Names) -> crate::CANFrame {
+        crate::CANFrame::new(Names::ID, &message.data, false).expect("payload fits the frame")
+    }
+}
+
+/// `NamesMode` (0x201)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NamesMode2 {
+    data: [u8; 1],
+}
+
+impl NamesMode2 {
+    pub const ID: u32 = 0x201;
+    pub const EXTENDED: bool = false;
+    pub const SIZE: usize = 1;
+    const VALUE: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Value",
+        start_bit: 0,
+        length: 8,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 255.0,
+    };
+
+    /// A message with every signal at its start value.
+    pub fn new() -> NamesMode2 {
+        NamesMode2 { data: [0; 1] }
+    }
+
+    /// Wrap a payload of at least `SIZE` bytes.
+    pub fn from_payload(payload: &[u8]) -> Option<NamesMode2> {
+        if payload.len() < Self::SIZE {
+            return None;
+        }
+        let mut data = [0; 1];
+        data.copy_from_slice(&payload[..Self::SIZE]);
+        Some(NamesMode2 { data })
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// `Value`, from 0 to 255
+    pub fn value(&self) -> u8 {
+        Self::VALUE.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn value_raw(&self) -> u8 {
+        Self::VALUE.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn set_value(&mut self, value: u8) -> crate::Result<()> {
+        let raw = Self::VALUE.physical_to_raw(value as f64)?;
+        Self::VALUE.insert(&mut self.data, raw);
+        Ok(())
+    }
+
+    pub fn set_value_raw(&mut self, raw: u8) {
+        Self::VALUE.insert(&mut self.data, raw as u64);
+    }
+}
+
+impl Default for NamesMode2 {
+    fn default() -> NamesMode2 {
+        NamesMode2::new()
+    }
+}
+
+impl<'a> ::std::convert::TryFrom<&'a crate::CANFrame> for NamesMode2 {
+    type Error = crate::Error;
+
+    fn try_from(frame: &'a crate::CANFrame) -> crate::Result<NamesMode2> {
+        if frame.id() != NamesMode2::ID || frame.is_extended() != NamesMode2::EXTENDED || frame.is_rtr() {
+            return Err(crate::ErrorKind::UnexpectedFrame(frame.id()).into());
+        }
+        NamesMode2::from_payload(&frame.data()[..frame.len() as usize])
+            .ok_or_else(|| crate::ErrorKind::UnexpectedFrame(frame.id()).into())
+    }
+}
+
+impl From<NamesMode2> for crate::CANFrame {
+    fn from(message: NamesMode2) -> crate::CANFrame {
+        crate::CANFrame::new(NamesMode2::ID, &message.data, false).expect("payload fits the frame")
+    }
+}
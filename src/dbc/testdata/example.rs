@@ -0,0 +1,445 @@
+// Generated by pcan-basic from a DBC file. Do not edit.
+
+/// Values of `Status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineDataStatus {
+    Ok, // 0
+    Warning, // 1
+    Fault, // 2
+    Other(u8),
+}
+
+impl From<u8> for EngineDataStatus {
+    fn from(raw: u8) -> EngineDataStatus {
+        match raw {
+            0 => EngineDataStatus::Ok,
+            1 => EngineDataStatus::Warning,
+            2 => EngineDataStatus::Fault,
+            other => EngineDataStatus::Other(other),
+        }
+    }
+}
+
+impl From<EngineDataStatus> for u8 {
+    fn from(value: EngineDataStatus) -> u8 {
+        match value {
+            EngineDataStatus::Ok => 0,
+            EngineDataStatus::Warning => 1,
+            EngineDataStatus::Fault => 2,
+            EngineDataStatus::Other(raw) => raw,
+        }
+    }
+}
+
+/// Cyclic engine data
+///
+/// `EngineData` (0x100)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EngineData {
+    data: [u8; 8],
+}
+
+impl EngineData {
+    pub const ID: u32 = 0x100;
+    pub const EXTENDED: bool = false;
+    pub const SIZE: usize = 8;
+    const ENGINE_SPEED: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "EngineSpeed",
+        start_bit: 0,
+        length: 16,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 0.25,
+        offset: 0.0,
+        min: 0.0,
+        max: 16383.75,
+    };
+    const COOLANT_TEMP: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "CoolantTemp",
+        start_bit: 16,
+        length: 8,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Signed,
+        factor: 1.0,
+        offset: 0.0,
+        min: -40.0,
+        max: 127.0,
+    };
+    const STATUS: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Status",
+        start_bit: 31,
+        length: 4,
+        byte_order: crate::dbc::ByteOrder::BigEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 15.0,
+    };
+
+    /// A message with every signal at its start value.
+    pub fn new() -> EngineData {
+        EngineData { data: [0; 8] }
+    }
+
+    /// Wrap a payload of at least `SIZE` bytes.
+    pub fn from_payload(payload: &[u8]) -> Option<EngineData> {
+        if payload.len() < Self::SIZE {
+            return None;
+        }
+        let mut data = [0; 8];
+        data.copy_from_slice(&payload[..Self::SIZE]);
+        Some(EngineData { data })
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Crankshaft speed
+    ///
+    /// `EngineSpeed` [rpm], from 0 to 16383.75
+    pub fn engine_speed(&self) -> f64 {
+        Self::ENGINE_SPEED.raw_to_physical(Self::ENGINE_SPEED.extract(&self.data).unwrap_or(0))
+    }
+
+    pub fn engine_speed_raw(&self) -> u16 {
+        Self::ENGINE_SPEED.extract(&self.data).unwrap_or(0) as u16
+    }
+
+    pub fn set_engine_speed(&mut self, value: f64) -> crate::Result<()> {
+        let raw = Self::ENGINE_SPEED.physical_to_raw(value)?;
+        Self::ENGINE_SPEED.insert(&mut self.data, raw);
+        Ok(())
+    }
+
+    pub fn set_engine_speed_raw(&mut self, raw: u16) {
+        Self::ENGINE_SPEED.insert(&mut self.data, raw as u64);
+    }
+
+    /// `CoolantTemp` [degC], from -40 to 127
+    pub fn coolant_temp(&self) -> i8 {
+        Self::COOLANT_TEMP.sign_extend(Self::COOLANT_TEMP.extract(&self.data).unwrap_or(0)) as i8
+    }
+
+    pub fn coolant_temp_raw(&self) -> i8 {
+        Self::COOLANT_TEMP.sign_extend(Self::COOLANT_TEMP.extract(&self.data).unwrap_or(0)) as i8
+    }
+
+    pub fn set_coolant_temp(&mut self, value: i8) -> crate::Result<()> {
+        let raw = Self::COOLANT_TEMP.physical_to_raw(value as f64)?;
+        Self::COOLANT_TEMP.insert(&mut self.data, raw);
+        Ok(())
+    }
+
+    pub fn set_coolant_temp_raw(&mut self, raw: i8) {
+        Self::COOLANT_TEMP.insert(&mut self.data, raw as u64);
+    }
+
+    /// `Status`, from 0 to 15
+    pub fn status(&self) -> EngineDataStatus {
+        EngineDataStatus::from(Self::STATUS.extract(&self.data).unwrap_or(0) as u8)
+    }
+
+    pub fn status_raw(&self) -> u8 {
+        Self::STATUS.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn set_status(&mut self, value: EngineDataStatus) {
+        self.set_status_raw(value.into());
+    }
+
+    pub fn set_status_raw(&mut self, raw: u8) {
+        Self::STATUS.insert(&mut self.data, raw as u64);
+    }
+}
+
+impl Default for EngineData {
+    fn default() -> EngineData {
+        EngineData::new()
+    }
+}
+
+impl<'a> ::std::convert::TryFrom<&'a crate::CANFrame> for EngineData {
+    type Error = crate::Error;
+
+    fn try_from(frame: &'a crate::CANFrame) -> crate::Result<EngineData> {
+        if frame.id() != EngineData::ID || frame.is_extended() != EngineData::EXTENDED || frame.is_rtr() {
+            return Err(crate::ErrorKind::UnexpectedFrame(frame.id()).into());
+        }
+        EngineData::from_payload(&frame.data()[..frame.len() as usize])
+            .ok_or_else(|| crate::ErrorKind::UnexpectedFrame(frame.id()).into())
+    }
+}
+
+impl From<EngineData> for crate::CANFrame {
+    fn from(message: EngineData) -> crate::CANFrame {
+        crate::CANFrame::new(EngineData::ID, &message.data, false).expect("payload fits the frame")
+    }
+}
+
+/// `Diagnostics` (0x18FEF1FE)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Diagnostics {
+    data: [u8; 8],
+}
+
+impl Diagnostics {
+    pub const ID: u32 = 0x18FEF1FE;
+    pub const EXTENDED: bool = true;
+    pub const SIZE: usize = 8;
+    const MODE: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Mode",
+        start_bit: 0,
+        length: 8,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 255.0,
+    };
+    const VALUE: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Value",
+        start_bit: 8,
+        length: 16,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 0.5,
+        offset: 0.0,
+        min: 0.0,
+        max: 32767.5,
+    };
+    const COUNTER: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Counter",
+        start_bit: 8,
+        length: 8,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 255.0,
+    };
+    const SUB: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Sub",
+        start_bit: 8,
+        length: 8,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Unsigned,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 255.0,
+    };
+    const DEEP: crate::dbc::Layout<'static> = crate::dbc::Layout {
+        name: "Deep",
+        start_bit: 16,
+        length: 32,
+        byte_order: crate::dbc::ByteOrder::LittleEndian,
+        value_type: crate::dbc::ValueType::Float,
+        factor: 1.0,
+        offset: 0.0,
+        min: 0.0,
+        max: 0.0,
+    };
+
+    /// A message with every signal at its start value.
+    pub fn new() -> Diagnostics {
+        Diagnostics { data: [0; 8] }
+    }
+
+    /// Wrap a payload of at least `SIZE` bytes.
+    pub fn from_payload(payload: &[u8]) -> Option<Diagnostics> {
+        if payload.len() < Self::SIZE {
+            return None;
+        }
+        let mut data = [0; 8];
+        data.copy_from_slice(&payload[..Self::SIZE]);
+        Some(Diagnostics { data })
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// `Mode`, from 0 to 255
+    pub fn mode(&self) -> u8 {
+        Self::MODE.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn mode_raw(&self) -> u8 {
+        Self::MODE.extract(&self.data).unwrap_or(0) as u8
+    }
+
+    pub fn set_mode(&mut self, value: u8) -> crate::Result<()> {
+        let raw = Self::MODE.physical_to_raw(value as f64)?;
+        Self::MODE.insert(&mut self.data, raw);
+        Ok(())
+    }
+
+    pub fn set_mode_raw(&mut self, raw: u8) {
+        Self::MODE.insert(&mut self.data, raw as u64);
+    }
+
+    /// `Value` [V], from 0 to 32767.5
+    pub fn value(&self) -> Option<f64> {
+        if self.selected_value() { Some(Self::VALUE.raw_to_physical(Self::VALUE.extract(&self.data).unwrap_or(0))) } else { None }
+    }
+
+    pub fn value_raw(&self) -> Option<u16> {
+        if self.selected_value() { Some(Self::VALUE.extract(&self.data).unwrap_or(0) as u16) } else { None }
+    }
+
+    pub fn set_value(&mut self, value: f64) -> crate::Result<()> {
+        let raw = Self::VALUE.physical_to_raw(value)?;
+        Self::VALUE.insert(&mut self.data, raw);
+        self.select_value();
+        Ok(())
+    }
+
+    pub fn set_value_raw(&mut self, raw: u16) {
+        Self::VALUE.insert(&mut self.data, raw as u64);
+        self.select_value();
+    }
+
+    fn selected_value(&self) -> bool {
+        let value = Self::MODE.extract(&self.data).unwrap_or(0);
+        value == 2
+    }
+
+    fn select_value(&mut self) {
+        let value = Self::MODE.extract(&self.data).unwrap_or(0);
+        let selected = value == 2;
+        if !selected {
+            Self::MODE.insert(&mut self.data, 2);
+        }
+    }
+
+    /// `Counter`, from 0 to 255
+    pub fn counter(&self) -> Option<u8> {
+        if self.selected_counter() { Some(Self::COUNTER.extract(&self.data).unwrap_or(0) as u8) } else { None }
+    }
+
+    pub fn counter_raw(&self) -> Option<u8> {
+        if self.selected_counter() { Some(Self::COUNTER.extract(&self.data).unwrap_or(0) as u8) } else { None }
+    }
+
+    pub fn set_counter(&mut self, value: u8) -> crate::Result<()> {
+        let raw = Self::COUNTER.physical_to_raw(value as f64)?;
+        Self::COUNTER.insert(&mut self.data, raw);
+        self.select_counter();
+        Ok(())
+    }
+
+    pub fn set_counter_raw(&mut self, raw: u8) {
+        Self::COUNTER.insert(&mut self.data, raw as u64);
+        self.select_counter();
+    }
+
+    fn selected_counter(&self) -> bool {
+        let value = Self::MODE.extract(&self.data).unwrap_or(0);
+        value == 1
+    }
+
+    fn select_counter(&mut self) {
+        let value = Self::MODE.extract(&self.data).unwrap_or(0);
+        let selected = value == 1;
+        if !selected {
+            Self::MODE.insert(&mut self.data, 1);
+        }
+    }
+
+    /// `Sub`, from 0 to 255
+    pub fn sub(&self) -> Option<u8> {
+        if self.selected_sub() { Some(Self::SUB.extract(&self.data).unwrap_or(0) as u8) } else { None }
+    }
+
+    pub fn sub_raw(&self) -> Option<u8> {
+        if self.selected_sub() { Some(Self::SUB.extract(&self.data).unwrap_or(0) as u8) } else { None }
+    }
+
+    pub fn set_sub(&mut self, value: u8) -> crate::Result<()> {
+        let raw = Self::SUB.physical_to_raw(value as f64)?;
+        Self::SUB.insert(&mut self.data, raw);
+        self.select_sub();
+        Ok(())
+    }
+
+    pub fn set_sub_raw(&mut self, raw: u8) {
+        Self::SUB.insert(&mut self.data, raw as u64);
+        self.select_sub();
+    }
+
+    fn selected_sub(&self) -> bool {
+        let value = Self::MODE.extract(&self.data).unwrap_or(0);
+        value == 3
+    }
+
+    fn select_sub(&mut self) {
+        let value = Self::MODE.extract(&self.data).unwrap_or(0);
+        let selected = value == 3;
+        if !selected {
+            Self::MODE.insert(&mut self.data, 3);
+        }
+    }
+
+    /// `Deep`
+    pub fn deep(&self) -> Option<f64> {
+        if self.selected_deep() { Some(Self::DEEP.raw_to_physical(Self::DEEP.extract(&self.data).unwrap_or(0))) } else { None }
+    }
+
+    pub fn deep_raw(&self) -> Option<f32> {
+        if self.selected_deep() { Some(f32::from_bits(Self::DEEP.extract(&self.data).unwrap_or(0) as u32)) } else { None }
+    }
+
+    pub fn set_deep(&mut self, value: f64) -> crate::Result<()> {
+        let raw = Self::DEEP.physical_to_raw(value)?;
+        Self::DEEP.insert(&mut self.data, raw);
+        self.select_deep();
+        Ok(())
+    }
+
+    pub fn set_deep_raw(&mut self, raw: f32) {
+        Self::DEEP.insert(&mut self.data, raw.to_bits() as u64);
+        self.select_deep();
+    }
+
+    fn selected_deep(&self) -> bool {
+        let value = Self::SUB.extract(&self.data).unwrap_or(0);
+        self.selected_sub() && ((16..=31).contains(&value))
+    }
+
+    fn select_deep(&mut self) {
+        self.select_sub();
+        let value = Self::SUB.extract(&self.data).unwrap_or(0);
+        let selected = (16..=31).contains(&value);
+        if !selected {
+            Self::SUB.insert(&mut self.data, 16);
+        }
+    }
+}
+
+impl Default for Diagnostics {
+    fn default() -> Diagnostics {
+        Diagnostics::new()
+    }
+}
+
+impl<'a> ::std::convert::TryFrom<&'a crate::CANFrame> for Diagnostics {
+    type Error = crate::Error;
+
+    fn try_from(frame: &'a crate::CANFrame) -> crate::Result<Diagnostics> {
+        if frame.id() != Diagnostics::ID || frame.is_extended() != Diagnostics::EXTENDED || frame.is_rtr() {
+            return Err(crate::ErrorKind::UnexpectedFrame(frame.id()).into());
+        }
+        Diagnostics::from_payload(&frame.data()[..frame.len() as usize])
+            .ok_or_else(|| crate::ErrorKind::UnexpectedFrame(frame.id()).into())
+    }
+}
+
+impl From<Diagnostics> for crate::CANFrame {
+    fn from(message: Diagnostics) -> crate::CANFrame {
+        crate::CANFrame::new_extended(Diagnostics::ID, &message.data, false).expect("payload fits the frame")
+    }
+}
@@ -1,6 +1,8 @@
 use pcan_basic_sys as pcan;
 use std::convert::From;
 use std::io;
+use uds::NegativeResponseCode;
+use canopen::AbortCode;
 
 
 #[derive(Debug, Clone, Copy)]
@@ -194,6 +196,141 @@ error_chain! {
             description("Invalid can frame")
             display("Could not make can frame")
         }
+
+        TraceFormat(reason: String) {
+            description("Malformed trace file")
+            display("Malformed trace file: {}", reason)
+        }
+
+        DbcFormat(reason: String) {
+            description("Malformed DBC file")
+            display("Malformed DBC file: {}", reason)
+        }
+
+        KcdFormat(reason: String) {
+            description("Malformed KCD file")
+            display("Malformed KCD file: {}", reason)
+        }
+
+        SymFormat(reason: String) {
+            description("Malformed SYM file")
+            display("Malformed SYM file: {}", reason)
+        }
+
+        UnknownMessage(name: String) {
+            description("Unknown message")
+            display("Unknown message '{}'", name)
+        }
+
+        UnknownSignal(name: String) {
+            description("Unknown signal")
+            display("Unknown signal '{}'", name)
+        }
+
+        SignalOutOfRange(name: String, value: f64) {
+            description("Signal value out of range")
+            display("Value {} is out of range for signal '{}'", value, name)
+        }
+
+        InactiveSignal(name: String) {
+            description("Signal is not selected by its multiplexor")
+            display("Signal '{}' is not selected by its multiplexor", name)
+        }
+
+        UnexpectedFrame(id: u32) {
+            description("Frame does not match the expected message")
+            display("Frame 0x{:X} does not match the expected message", id)
+        }
+
+        IsoTpTimeout(timer: String) {
+            description("ISO-TP timeout")
+            display("ISO-TP {} timeout", timer)
+        }
+
+        IsoTpProtocol(reason: String) {
+            description("ISO-TP protocol error")
+            display("ISO-TP protocol error: {}", reason)
+        }
+
+        NegativeResponse(service: u8, code: NegativeResponseCode) {
+            description("Negative diagnostic response")
+            display("Service 0x{:02X} rejected: {}", service, code)
+        }
+
+        DiagnosticTimeout(service: u8) {
+            description("No diagnostic response")
+            display("No response to service 0x{:02X}", service)
+        }
+
+        InvalidResponse(reason: String) {
+            description("Malformed diagnostic response")
+            display("Malformed diagnostic response: {}", reason)
+        }
+
+        InvalidRequest(reason: String) {
+            description("Invalid diagnostic request")
+            display("Invalid diagnostic request: {}", reason)
+        }
+
+        DescriptionFormat(reason: String) {
+            description("Malformed DTC description file")
+            display("Malformed DTC description file: {}", reason)
+        }
+
+        KeyGeneration(reason: String) {
+            description("Could not compute a security access key")
+            display("Could not compute a security access key: {}", reason)
+        }
+
+        ImageFormat(reason: String) {
+            description("Malformed firmware image")
+            display("Malformed firmware image: {}", reason)
+        }
+
+        AnnexFormat(reason: String) {
+            description("Malformed J1939 digital annex")
+            display("Malformed J1939 digital annex: {}", reason)
+        }
+
+        J1939Timeout(timer: String) {
+            description("J1939 transport protocol timeout")
+            display("J1939 transport protocol {} timeout", timer)
+        }
+
+        J1939Protocol(reason: String) {
+            description("J1939 protocol error")
+            display("J1939 protocol error: {}", reason)
+        }
+
+        SdoAbort(index: u16, subindex: u8, code: AbortCode) {
+            description("SDO transfer aborted")
+            display("SDO transfer of 0x{:04X}:{} aborted: {}", index, subindex, code)
+        }
+
+        SdoTimeout(index: u16, subindex: u8) {
+            description("SDO transfer timed out")
+            display("SDO transfer of 0x{:04X}:{} timed out", index, subindex)
+        }
+
+        SdoProtocol(reason: String) {
+            description("SDO protocol error")
+            display("SDO protocol error: {}", reason)
+        }
+
+        EdsFormat(reason: String) {
+            description("Malformed EDS file")
+            display("Malformed EDS file: {}", reason)
+        }
+
+        ObjectDictionary(reason: String) {
+            description("Object dictionary error")
+            display("Object dictionary error: {}", reason)
+        }
+
+        PdoMapping(reason: String) {
+            description("Invalid PDO mapping")
+            display("Invalid PDO mapping: {}", reason)
+        }
     }
 }
 
@@ -0,0 +1,761 @@
+//! ISO-TP (ISO 15765-2) transport of messages longer than a frame.
+//!
+//! An `IsoTp` channel sends on one identifier and receives on another.
+//! Frames for the receiving side are handled while a message is being sent,
+//! so both directions can be active at once. Messages longer than 4095
+//! bytes use the 32-bit first frame length of ISO 15765-2:2016.
+//!
+//! With a TX_DL above 8 the channel uses CAN FD frames of up to TX_DL
+//! bytes. Single frames longer than a classic frame then carry their length
+//! in a second byte, and frames longer than 8 bytes are padded up to the
+//! next valid FD length. Received frames of any length up to 64 bytes are
+//! accepted.
+use bus::CanBus;
+use errors::*;
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+use {fd_dlc_to_len, fd_len_to_dlc, CANFrame, CANFrameFd, MessageType};
+
+/// Pause between polls of the bus while waiting.
+const POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+const CLASSIC_FRAME_LEN: usize = 8;
+
+/// Padding of FD frames when `Config::padding` is not set, as ISO
+/// 15765-2 recommends.
+const DEFAULT_FD_PADDING: u8 = 0xCC;
+
+/// Longest message whose length fits the 12 bits of a first frame.
+const SHORT_MESSAGE_MAX: usize = 4095;
+
+const SINGLE_FRAME: u8 = 0x0;
+const FIRST_FRAME: u8 = 0x1;
+const CONSECUTIVE_FRAME: u8 = 0x2;
+const FLOW_CONTROL: u8 = 0x3;
+
+/// Where the N_PCI starts in the frame payload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Addressing {
+    Normal,
+    Extended { tx: u8, rx: u8 }, // Target address written on sent frames and expected on received ones
+    Mixed(u8),                   // Address extension, in both directions
+}
+
+impl Addressing {
+    fn tx_prefix(&self) -> Option<u8> {
+        match *self {
+            Addressing::Normal => None,
+            Addressing::Extended { tx, .. } => Some(tx),
+            Addressing::Mixed(extension) => Some(extension),
+        }
+    }
+
+    fn rx_prefix(&self) -> Option<u8> {
+        match *self {
+            Addressing::Normal => None,
+            Addressing::Extended { rx, .. } => Some(rx),
+            Addressing::Mixed(extension) => Some(extension),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlowStatus {
+    ContinueToSend,
+    Wait,
+    Overflow,
+}
+
+/// Settings of a channel. `Config::new` gives 1 second timeouts, no block
+/// size limit, no separation time, no padding and up to 10 FC.WAIT in a row.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub tx_id: u32,
+    pub rx_id: u32,
+    pub extended_id: bool, // 29-bit identifiers
+    pub addressing: Addressing,
+    pub block_size: u8, // Consecutive frames the peer may send per flow control, 0 for all
+    pub st_min: Duration, // Separation time the peer must leave between consecutive frames
+    pub padding: Option<u8>, // Fill byte for frames shorter than 8 bytes
+    pub tx_dl: usize, // Longest frame sent: 8 for classic CAN, up to 64 for CAN FD
+    pub brs: bool,    // Send FD frames with bit rate switching
+    pub max_length: usize, // Longest message accepted, longer ones are refused with an overflow
+    pub n_as: Duration,    // Time allowed to hand a frame to the bus
+    pub n_bs: Duration,    // Time allowed for the peer to send a flow control
+    pub n_cr: Duration, // Time allowed for the peer to send the next consecutive frame
+    pub wft_max: u8, // N_WFTmax: FC.WAIT accepted in a row before giving up on a message
+}
+
+impl Config {
+    pub fn new(tx_id: u32, rx_id: u32) -> Config {
+        Config {
+            tx_id,
+            rx_id,
+            extended_id: tx_id > 0x7FF || rx_id > 0x7FF,
+            addressing: Addressing::Normal,
+            block_size: 0,
+            st_min: Duration::from_millis(0),
+            padding: None,
+            tx_dl: CLASSIC_FRAME_LEN,
+            brs: false,
+            max_length: SHORT_MESSAGE_MAX,
+            n_as: Duration::from_millis(1000),
+            n_bs: Duration::from_millis(1000),
+            n_cr: Duration::from_millis(1000),
+            wft_max: 10,
+        }
+    }
+}
+
+/// Encode a separation time as an STmin byte, rounding up.
+pub fn st_min_to_byte(st_min: Duration) -> u8 {
+    let micros = st_min.as_micros();
+    match micros {
+        0 => 0,
+        1..=900 => 0xF0 + micros.div_ceil(100) as u8,
+        _ => micros.div_ceil(1000).min(0x7F) as u8,
+    }
+}
+
+/// Decode an STmin byte. Reserved values mean the longest time, 127 ms.
+pub fn st_min_from_byte(byte: u8) -> Duration {
+    match byte {
+        0x00..=0x7F => Duration::from_millis(byte as u64),
+        0xF1..=0xF9 => Duration::from_micros((byte - 0xF0) as u64 * 100),
+        _ => Duration::from_millis(0x7F),
+    }
+}
+
+/// A message being received.
+struct Reception {
+    data: Vec<u8>,
+    length: usize,
+    sequence: u8,   // Expected sequence number
+    block_left: u8, // Consecutive frames until the next flow control, 0 for no limit
+    deadline: Instant,
+}
+
+pub struct IsoTp<B: CanBus> {
+    bus: B,
+    config: Config,
+    reception: Option<Reception>,
+    received: VecDeque<Vec<u8>>,
+    flow_controls: VecDeque<(FlowStatus, u8, Duration)>, // From the peer, not handled yet
+}
+
+impl<B: CanBus> IsoTp<B> {
+    pub fn new(bus: B, config: Config) -> IsoTp<B> {
+        IsoTp {
+            bus,
+            config,
+            reception: None,
+            received: VecDeque::new(),
+            flow_controls: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn into_inner(self) -> B {
+        self.bus
+    }
+
+    fn is_fd(&self) -> bool {
+        self.config.tx_dl > CLASSIC_FRAME_LEN
+    }
+
+    fn prefix_len(&self) -> usize {
+        self.config.addressing.tx_prefix().map_or(0, |_| 1)
+    }
+
+    /// Send a message, blocking until its last frame is handed to the bus.
+    pub fn send(&mut self, data: &[u8]) -> Result<()> {
+        let tx_dl = self.config.tx_dl;
+        if tx_dl < CLASSIC_FRAME_LEN
+            || fd_dlc_to_len(fd_len_to_dlc(tx_dl).unwrap_or(0)) != tx_dl
+        {
+            bail!(ErrorKind::IsoTpProtocol(format!("invalid TX_DL {}", tx_dl)));
+        }
+        if data.len() > u32::MAX as usize {
+            bail!(ErrorKind::IsoTpProtocol(format!(
+                "message of {} bytes is too long",
+                data.len()
+            )));
+        }
+
+        // Payload bytes available after the addressing byte
+        let capacity = tx_dl - self.prefix_len();
+        if data.len() < CLASSIC_FRAME_LEN - self.prefix_len() {
+            let mut payload = vec![(SINGLE_FRAME << 4) | data.len() as u8];
+            payload.extend_from_slice(data);
+            return self.transmit(&payload);
+        }
+        if self.is_fd() && data.len() <= capacity - 2 {
+            let mut payload = vec![SINGLE_FRAME << 4, data.len() as u8];
+            payload.extend_from_slice(data);
+            return self.transmit(&payload);
+        }
+
+        let mut payload = if data.len() <= SHORT_MESSAGE_MAX {
+            vec![
+                (FIRST_FRAME << 4) | (data.len() >> 8) as u8,
+                data.len() as u8,
+            ]
+        } else {
+            let mut header = vec![FIRST_FRAME << 4, 0];
+            header.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            header
+        };
+        let first = capacity - payload.len();
+        payload.extend_from_slice(&data[..first]);
+        self.flow_controls.clear();
+        self.transmit(&payload)?;
+
+        let mut chunks = data[first..].chunks(capacity - 1);
+        let mut sequence = 1u8;
+        let mut remaining = chunks.len();
+        while remaining > 0 {
+            let (block_size, st_min) = self.wait_for_flow_control()?;
+            let block = if block_size == 0 {
+                remaining
+            } else {
+                remaining.min(block_size as usize)
+            };
+            for i in 0..block {
+                if i > 0 && st_min > Duration::from_millis(0) {
+                    self.pause(st_min)?;
+                }
+                let chunk = chunks.next().expect("remaining chunk");
+                let mut payload = vec![(CONSECUTIVE_FRAME << 4) | sequence];
+                payload.extend_from_slice(chunk);
+                self.transmit(&payload)?;
+                sequence = (sequence + 1) & 0x0F;
+                self.poll()?;
+            }
+            remaining -= block;
+        }
+        Ok(())
+    }
+
+    /// Wait for the next complete message, up to `timeout` unless a message
+    /// is already being received. Returns `None` on timeout.
+    pub fn receive(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(message) = self.received.pop_front() {
+                return Ok(Some(message));
+            }
+            self.poll()?;
+            if self.received.is_empty() {
+                let now = Instant::now();
+                if self.reception.as_ref().is_some_and(|r| now > r.deadline) {
+                    self.reception = None;
+                    bail!(ErrorKind::IsoTpTimeout("N_Cr".to_owned()));
+                }
+                if self.reception.is_none() && now >= deadline {
+                    return Ok(None);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+
+    /// Handle every frame waiting on the bus.
+    pub fn poll(&mut self) -> Result<()> {
+        if self.is_fd() {
+            while let Some(frame) = self.bus.receive_fd()? {
+                self.feed(&frame)?;
+            }
+        } else {
+            while let Some(frame) = self.bus.receive()? {
+                if !frame.is_rtr() {
+                    self.handle_frame(
+                        frame.id(),
+                        frame.is_extended(),
+                        &frame.data()[..frame.len() as usize],
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle a frame read from the bus by the caller, so that one reader
+    /// can serve several channels sharing a bus. Frames for other
+    /// identifiers are ignored.
+    pub fn feed(&mut self, frame: &CANFrameFd) -> Result<()> {
+        if frame.0.MSGTYPE & u8::from(MessageType::Rtr) != 0 {
+            return Ok(());
+        }
+        self.handle_frame(frame.id(), frame.is_extended(), frame.data())
+    }
+
+    /// Next message completed by `feed`, without polling the bus.
+    pub fn take_received(&mut self) -> Option<Vec<u8>> {
+        self.received.pop_front()
+    }
+
+    /// Whether a multi-frame message is being received.
+    pub fn is_receiving(&self) -> bool {
+        self.reception.is_some()
+    }
+
+    /// Keep handling received frames for `duration`.
+    fn pause(&mut self, duration: Duration) -> Result<()> {
+        let end = Instant::now() + duration;
+        loop {
+            self.poll()?;
+            let now = Instant::now();
+            if now >= end {
+                return Ok(());
+            }
+            thread::sleep(POLL_INTERVAL.min(end - now));
+        }
+    }
+
+    fn wait_for_flow_control(&mut self) -> Result<(u8, Duration)> {
+        let mut deadline = Instant::now() + self.config.n_bs;
+        let mut waits = 0;
+        loop {
+            self.poll()?;
+            match self.flow_controls.pop_front() {
+                Some((FlowStatus::ContinueToSend, block_size, st_min)) => {
+                    return Ok((block_size, st_min))
+                }
+                Some((FlowStatus::Wait, _, _))
+                    if waits >= self.config.wft_max =>
+                {
+                    bail!(ErrorKind::IsoTpProtocol(format!(
+                        "more than {} FC.WAIT in a row",
+                        waits
+                    )))
+                }
+                Some((FlowStatus::Wait, _, _)) => {
+                    waits += 1;
+                    deadline = Instant::now() + self.config.n_bs;
+                }
+                Some((FlowStatus::Overflow, _, _)) => {
+                    bail!(ErrorKind::IsoTpProtocol(
+                        "receiver cannot hold the message".to_owned()
+                    ))
+                }
+                None if Instant::now() > deadline => {
+                    bail!(ErrorKind::IsoTpTimeout("N_Bs".to_owned()))
+                }
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+
+    /// Hand a frame with the given N_PCI and data to the bus, retrying while
+    /// the transmit queue is full.
+    fn transmit(&self, payload: &[u8]) -> Result<()> {
+        let mut data = Vec::with_capacity(self.config.tx_dl);
+        data.extend(self.config.addressing.tx_prefix());
+        data.extend_from_slice(payload);
+        if data.len() > CLASSIC_FRAME_LEN {
+            let len = fd_len_to_dlc(data.len())
+                .map(fd_dlc_to_len)
+                .unwrap_or(data.len());
+            data.resize(len, self.config.padding.unwrap_or(DEFAULT_FD_PADDING));
+        } else if let Some(padding) = self.config.padding {
+            data.resize(CLASSIC_FRAME_LEN, padding);
+        }
+
+        let (id, extended) = (self.config.tx_id, self.config.extended_id);
+        let deadline = Instant::now() + self.config.n_as;
+        loop {
+            let result = if self.is_fd() {
+                self.bus.transmit_fd(&CANFrameFd::new(
+                    id,
+                    &data,
+                    extended,
+                    self.config.brs,
+                )?)
+            } else if extended {
+                self.bus
+                    .transmit(&CANFrame::new_extended(id, &data, false)?)
+            } else {
+                self.bus.transmit(&CANFrame::new(id, &data, false)?)
+            };
+            match result {
+                Err(Error(ErrorKind::PCAN(PCANStatus::XmtFull), _))
+                | Err(Error(
+                    ErrorKind::PCAN(PCANStatus::Queue(
+                        QueueStatus::TransmitFull,
+                    )),
+                    _,
+                )) => {
+                    if Instant::now() > deadline {
+                        bail!(ErrorKind::IsoTpTimeout("N_As".to_owned()));
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn send_flow_control(&self, status: FlowStatus) -> Result<()> {
+        let status = match status {
+            FlowStatus::ContinueToSend => 0,
+            FlowStatus::Wait => 1,
+            FlowStatus::Overflow => 2,
+        };
+        self.transmit(&[
+            (FLOW_CONTROL << 4) | status,
+            self.config.block_size,
+            st_min_to_byte(self.config.st_min),
+        ])
+    }
+
+    fn handle_frame(
+        &mut self,
+        id: u32,
+        extended: bool,
+        data: &[u8],
+    ) -> Result<()> {
+        if id != self.config.rx_id || extended != self.config.extended_id {
+            return Ok(());
+        }
+        let frame_len = data.len();
+        let data = match self.config.addressing.rx_prefix() {
+            None => data,
+            Some(prefix) if data.first() == Some(&prefix) => &data[1..],
+            Some(_) => return Ok(()),
+        };
+        let pci = match data.first() {
+            Some(&pci) => pci,
+            None => return Ok(()),
+        };
+
+        match pci >> 4 {
+            SINGLE_FRAME => {
+                // Lengths above 7 follow in a second byte, in FD frames only
+                let (length, header) = match pci & 0x0F {
+                    0 if frame_len > CLASSIC_FRAME_LEN => {
+                        (data.get(1).cloned().unwrap_or(0) as usize, 2)
+                    }
+                    length => (length as usize, 1),
+                };
+                if length == 0 || header + length > data.len() {
+                    return Ok(());
+                }
+                // A new message aborts the one being received
+                self.reception = None;
+                self.received
+                    .push_back(data[header..header + length].to_vec());
+            }
+            FIRST_FRAME => {
+                let (length, header) = match (((pci & 0x0F) as usize) << 8)
+                    | data.get(1).cloned().unwrap_or(0) as usize
+                {
+                    0 if data.len() >= 6 => (
+                        u32::from_be_bytes([data[2], data[3], data[4], data[5]])
+                            as usize,
+                        6,
+                    ),
+                    0 => return Ok(()),
+                    length => (length, 2),
+                };
+                // Messages a single frame could carry are not segmented, and
+                // the escape sequence only starts above 4095 bytes
+                let single_frame_max = if frame_len > CLASSIC_FRAME_LEN {
+                    data.len().saturating_sub(2)
+                } else {
+                    data.len().saturating_sub(1)
+                };
+                if length <= single_frame_max || (header == 6 && length < 4096)
+                {
+                    return Ok(());
+                }
+                self.reception = None;
+                if length > self.config.max_length {
+                    return self.send_flow_control(FlowStatus::Overflow);
+                }
+                let mut message = Vec::with_capacity(length);
+                message.extend_from_slice(&data[header.min(data.len())..]);
+                self.reception = Some(Reception {
+                    data: message,
+                    length,
+                    sequence: 1,
+                    block_left: self.config.block_size,
+                    deadline: Instant::now() + self.config.n_cr,
+                });
+                self.send_flow_control(FlowStatus::ContinueToSend)?;
+            }
+            CONSECUTIVE_FRAME => {
+                let mut reception = match self.reception.take() {
+                    Some(reception) => reception,
+                    None => return Ok(()),
+                };
+                if pci & 0x0F != reception.sequence {
+                    bail!(ErrorKind::IsoTpProtocol(format!(
+                        "expected consecutive frame {}, got {}",
+                        reception.sequence,
+                        pci & 0x0F
+                    )));
+                }
+                let take = (reception.length - reception.data.len())
+                    .min(data.len() - 1);
+                reception.data.extend_from_slice(&data[1..=take]);
+                if reception.data.len() == reception.length {
+                    self.received.push_back(reception.data);
+                    return Ok(());
+                }
+
+                reception.sequence = (reception.sequence + 1) & 0x0F;
+                reception.deadline = Instant::now() + self.config.n_cr;
+                let block_done = reception.block_left == 1;
+                if reception.block_left > 0 {
+                    reception.block_left -= 1;
+                }
+                if block_done {
+                    reception.block_left = self.config.block_size;
+                }
+                self.reception = Some(reception);
+                if block_done {
+                    self.send_flow_control(FlowStatus::ContinueToSend)?;
+                }
+            }
+            FLOW_CONTROL => {
+                let status = match pci & 0x0F {
+                    0 => FlowStatus::ContinueToSend,
+                    1 => FlowStatus::Wait,
+                    2 => FlowStatus::Overflow,
+                    _ => return Ok(()),
+                };
+                let block_size = data.get(1).cloned().unwrap_or(0);
+                let st_min =
+                    st_min_from_byte(data.get(2).cloned().unwrap_or(0));
+                self.flow_controls.push_back((status, block_size, st_min));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::{CanBus, VirtualBus};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn single_frame_with_padding_and_extended_addressing() {
+        let bus = VirtualBus::new();
+        let monitor = bus.connect();
+        let mut config = Config::new(0x7E0, 0x7E8);
+        config.addressing = Addressing::Extended { tx: 0x10, rx: 0xF1 };
+        config.padding = Some(0xCC);
+        let mut tester = IsoTp::new(bus, config);
+
+        let mut config = Config::new(0x7E8, 0x7E0);
+        config.addressing = Addressing::Extended { tx: 0xF1, rx: 0x10 };
+        let mut ecu = IsoTp::new(monitor.connect(), config);
+
+        tester.send(&[0x22, 0xF1, 0x90]).unwrap();
+        let frame = monitor.receive().unwrap().unwrap();
+        assert_eq!(
+            frame.data(),
+            &[0x10, 0x03, 0x22, 0xF1, 0x90, 0xCC, 0xCC, 0xCC]
+        );
+        assert_eq!(
+            ecu.receive(Duration::from_millis(100)).unwrap(),
+            Some(vec![0x22, 0xF1, 0x90])
+        );
+        assert_eq!(ecu.receive(Duration::from_millis(10)).unwrap(), None);
+    }
+
+    #[test]
+    fn multi_frame_with_block_size() {
+        let bus = VirtualBus::new();
+        let monitor = bus.connect();
+        let ecu_bus = bus.connect();
+        let message: Vec<u8> = (0..100u8).collect();
+
+        let receiver = thread::spawn(move || {
+            let mut config = Config::new(0x7E8, 0x7E0);
+            config.block_size = 4;
+            config.st_min = Duration::from_micros(500);
+            let mut ecu = IsoTp::new(ecu_bus, config);
+            ecu.receive(Duration::from_secs(5)).unwrap()
+        });
+        let mut tester = IsoTp::new(bus, Config::new(0x7E0, 0x7E8));
+        tester.send(&message).unwrap();
+        assert_eq!(receiver.join().unwrap(), Some(message));
+
+        let mut frames = Vec::new();
+        while let Some(frame) = monitor.receive().unwrap() {
+            frames.push(frame);
+        }
+        // First frame, 14 consecutive frames and a flow control every 4
+        assert_eq!(&frames[0].data()[..3], &[0x10, 100, 0]);
+        let flow_controls: Vec<&CANFrame> =
+            frames.iter().filter(|f| f.id() == 0x7E8).collect();
+        assert_eq!(flow_controls.len(), 4);
+        assert_eq!(&flow_controls[0].data()[..3], &[0x30, 4, 0xF5]);
+        let sequence: Vec<u8> = frames
+            .iter()
+            .filter(|f| f.data()[0] >> 4 == 2)
+            .map(|f| f.data()[0] & 0x0F)
+            .collect();
+        assert_eq!(
+            sequence,
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14]
+        );
+    }
+
+    #[test]
+    fn fd_frames_with_escape_sequences() {
+        let bus = VirtualBus::new();
+        let monitor = bus.connect();
+        let ecu_bus = bus.connect();
+        let fd_config = |tx_id, rx_id| {
+            let mut config = Config::new(tx_id, rx_id);
+            config.tx_dl = 64;
+            config.brs = true;
+            config.max_length = 8192;
+            config
+        };
+
+        let mut tester = IsoTp::new(bus, fd_config(0x7E0, 0x7E8));
+        let mut ecu = IsoTp::new(ecu_bus, fd_config(0x7E8, 0x7E0));
+        let short: Vec<u8> = (0..20u8).collect();
+        tester.send(&short).unwrap();
+        let frame = monitor.receive_fd().unwrap().unwrap();
+        assert!(frame.is_fd() && frame.is_brs());
+        assert_eq!(frame.len(), 24);
+        assert_eq!(&frame.data()[..3], &[0x00, 20, 0]);
+        assert_eq!(&frame.data()[22..], &[0xCC, 0xCC]);
+        assert_eq!(
+            ecu.receive(Duration::from_millis(100)).unwrap(),
+            Some(short)
+        );
+        // Remote frames carry no message
+        monitor
+            .transmit(
+                &CANFrame::new(0x7E0, &[0x03, 0x22, 0xF1, 0x90], true).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(ecu.receive(Duration::from_millis(10)).unwrap(), None);
+
+        let message: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let expected = message.clone();
+        let receiver =
+            thread::spawn(move || ecu.receive(Duration::from_secs(5)).unwrap());
+        tester.send(&message).unwrap();
+        assert_eq!(receiver.join().unwrap(), Some(expected));
+
+        let mut frames = Vec::new();
+        while let Some(frame) = monitor.receive_fd().unwrap() {
+            if frame.id() == 0x7E0 {
+                frames.push(frame);
+            }
+        }
+        // 58 bytes in the first frame, then 79 consecutive frames of up to 63
+        assert_eq!(
+            &frames[0].data()[..6],
+            &[0x10, 0x00, 0x00, 0x00, 0x13, 0x88]
+        );
+        assert_eq!(frames.len(), 80);
+        assert!(frames[1..79].iter().all(|f| f.len() == 64));
+        assert_eq!(frames[79].len(), 32);
+        assert_eq!(frames[79].data()[29], 0xCC);
+    }
+
+    #[test]
+    fn timeouts_and_overflow() {
+        let bus = VirtualBus::new();
+        let mut config = Config::new(0x7E0, 0x7E8);
+        config.n_bs = Duration::from_millis(20);
+        let mut tester = IsoTp::new(bus.connect(), config);
+        match tester.send(&[0; 20]).unwrap_err().kind() {
+            ErrorKind::IsoTpTimeout(timer) => assert_eq!(timer, "N_Bs"),
+            other => panic!("unexpected error {:?}", other),
+        }
+
+        // A receiver refusing long messages
+        bus.transmit(&CANFrame::new(0x7E8, &[0x32, 0, 0], false).unwrap())
+            .unwrap();
+        match tester.send(&[0; 20]).unwrap_err().kind() {
+            ErrorKind::IsoTpProtocol(_) => {}
+            other => panic!("unexpected error {:?}", other),
+        }
+
+        // A receiver asking to wait once too often, then one more time
+        tester.config.wft_max = 2;
+        let wait = CANFrame::new(0x7E8, &[0x31, 0, 0], false).unwrap();
+        for _ in 0..3 {
+            bus.transmit(&wait).unwrap();
+        }
+        match tester.send(&[0; 20]).unwrap_err().kind() {
+            ErrorKind::IsoTpProtocol(reason) => {
+                assert_eq!(reason, "more than 2 FC.WAIT in a row")
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+        bus.transmit(&wait).unwrap();
+        bus.transmit(&wait).unwrap();
+        bus.transmit(&CANFrame::new(0x7E8, &[0x30, 0, 0], false).unwrap())
+            .unwrap();
+        tester.send(&[0; 20]).unwrap();
+
+        // A first frame never followed by consecutive frames
+        let mut config = Config::new(0x7E8, 0x7E0);
+        config.n_cr = Duration::from_millis(20);
+        let mut ecu = IsoTp::new(bus.connect(), config);
+        bus.transmit(
+            &CANFrame::new(0x7E0, &[0x10, 20, 1, 2, 3, 4, 5, 6], false)
+                .unwrap(),
+        )
+        .unwrap();
+        match ecu.receive(Duration::from_millis(1)).unwrap_err().kind() {
+            ErrorKind::IsoTpTimeout(timer) => assert_eq!(timer, "N_Cr"),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_first_frames() {
+        let bus = VirtualBus::new();
+        let mut ecu = IsoTp::new(bus.connect(), Config::new(0x7E8, 0x7E0));
+        let frames: [&[u8]; 3] = [
+            // Shorter than the data it carries
+            &[0x10, 3, 1, 2, 3, 4, 5, 6],
+            // Short enough for a single frame
+            &[0x10, 7, 1, 2, 3, 4, 5, 6],
+            // Escape sequence for a 12 bit length
+            &[0x10, 0, 0, 0, 0x00, 20, 1, 2],
+        ];
+        for data in frames.iter() {
+            bus.transmit(&CANFrame::new(0x7E0, data, false).unwrap())
+                .unwrap();
+            // Followed by a consecutive frame a reception would accept
+            bus.transmit(
+                &CANFrame::new(0x7E0, &[0x21, 7, 8, 9, 10, 11, 12, 13], false)
+                    .unwrap(),
+            )
+            .unwrap();
+        }
+        assert_eq!(ecu.receive(Duration::from_millis(10)).unwrap(), None);
+    }
+
+    #[test]
+    fn st_min_encoding() {
+        assert_eq!(st_min_to_byte(Duration::from_micros(300)), 0xF3);
+        assert_eq!(st_min_to_byte(Duration::from_millis(20)), 20);
+        assert_eq!(st_min_to_byte(Duration::from_secs(1)), 0x7F);
+        assert_eq!(st_min_from_byte(0xF9), Duration::from_micros(900));
+        assert_eq!(st_min_from_byte(0x80), Duration::from_millis(127));
+    }
+}
@@ -0,0 +1,419 @@
+//! PGN and SPN definitions from a CSV export of the J1939 Digital Annex.
+//!
+//! Columns are found by their header, so exports of different editions
+//! load as long as they have the PGN, SPN, SPN position and SPN length
+//! columns. The PGN label, acronym, SPN name, resolution, offset and units
+//! columns are used when present. Positions read as `byte.bit`, counting
+//! from 1 (`4-5` for two whole bytes). SPNs with a variable length, or
+//! longer than 64 bits, are text or structures and are skipped, as are SPNs
+//! with a position that does not read this way.
+//!
+//! Values follow J1939-71: for a parameter of whole bytes, a most
+//! significant byte of 0xFE is an error and 0xFF "not available"; for a
+//! bit field, all ones is "not available" and the value below it an error.
+use super::Message;
+use errors::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::str::FromStr;
+use text;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpnDefinition {
+    pub spn: u32,
+    pub name: String,
+    pub start_bit: usize, // From the least significant bit of the first byte
+    pub length: usize,    // In bits
+    pub resolution: f64,
+    pub offset: f64,
+    pub unit: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnDefinition {
+    pub pgn: u32,
+    pub name: String,
+    pub acronym: String,
+    pub spns: Vec<SpnDefinition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpnValue {
+    Valid(f64),
+    Error,
+    NotAvailable,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodedSpn<'a> {
+    pub definition: &'a SpnDefinition,
+    pub raw: u64,
+    pub value: SpnValue,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodedPgn<'a> {
+    pub definition: &'a PgnDefinition,
+    pub source: u8,
+    pub spns: Vec<DecodedSpn<'a>>, // SPNs the data was long enough to hold
+}
+
+impl<'a> DecodedPgn<'a> {
+    pub fn spn(&self, spn: u32) -> Option<&DecodedSpn<'a>> {
+        self.spns.iter().find(|s| s.definition.spn == spn)
+    }
+
+    /// Value of an SPN, if it was present and valid.
+    pub fn physical(&self, spn: u32) -> Option<f64> {
+        match self.spn(spn)?.value {
+            SpnValue::Valid(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl SpnDefinition {
+    /// Read the raw bits of the SPN, or `None` if the data is too short.
+    pub fn extract(&self, data: &[u8]) -> Option<u64> {
+        if self.start_bit + self.length > data.len() * 8 {
+            return None;
+        }
+        Some((0..self.length).fold(0u64, |raw, i| {
+            let bit = self.start_bit + i;
+            raw | u64::from(data[bit / 8] >> (bit % 8) & 1) << i
+        }))
+    }
+
+    pub fn raw_to_value(&self, raw: u64) -> SpnValue {
+        let (error, not_available) =
+            if self.length >= 8 && self.length.is_multiple_of(8) {
+                let top = raw >> (self.length - 8);
+                (top == 0xFE, top == 0xFF)
+            } else {
+                let ones = u64::MAX >> (64 - self.length);
+                (
+                    self.length > 1 && raw == ones - 1,
+                    self.length > 1 && raw == ones,
+                )
+            };
+        if not_available {
+            SpnValue::NotAvailable
+        } else if error {
+            SpnValue::Error
+        } else {
+            SpnValue::Valid(raw as f64 * self.resolution + self.offset)
+        }
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Option<DecodedSpn<'_>> {
+        self.extract(data).map(|raw| DecodedSpn {
+            definition: self,
+            raw,
+            value: self.raw_to_value(raw),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DigitalAnnex {
+    pub pgns: BTreeMap<u32, PgnDefinition>,
+}
+
+impl DigitalAnnex {
+    /// Load a CSV export, in UTF-8 or else Windows-1252 as Excel saves it.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<DigitalAnnex> {
+        text::decode(fs::read(path)?).parse()
+    }
+
+    pub fn pgn(&self, pgn: u32) -> Option<&PgnDefinition> {
+        self.pgns.get(&pgn)
+    }
+
+    /// Find an SPN in any PGN.
+    pub fn spn(&self, spn: u32) -> Option<(&PgnDefinition, &SpnDefinition)> {
+        self.pgns
+            .values()
+            .flat_map(|pgn| pgn.spns.iter().map(move |s| (pgn, s)))
+            .find(|(_, s)| s.spn == spn)
+    }
+
+    /// Decode a message received by a `Node` or reassembled by an
+    /// `Assembler`, if its PGN is defined.
+    pub fn decode(&self, message: &Message) -> Option<DecodedPgn<'_>> {
+        let definition = self.pgns.get(&message.id.pgn)?;
+        Some(DecodedPgn {
+            definition,
+            source: message.id.source,
+            spns: definition
+                .spns
+                .iter()
+                .filter_map(|s| s.decode(&message.data))
+                .collect(),
+        })
+    }
+}
+
+/// Split CSV text into records, handling quoted fields with commas,
+/// doubled quotes and line breaks.
+fn csv_records(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(mem::take(&mut field));
+                records.push(mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+/// Leading number of a text such as `0.125 rpm/bit`, `1/128 km/h per bit`
+/// or `-40 deg C`.
+fn leading_number(text: &str) -> Option<f64> {
+    let token = text.split_whitespace().next()?;
+    match token.split_once('/') {
+        Some((numerator, denominator)) => Some(
+            numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?,
+        ),
+        None => token.parse().ok(),
+    }
+}
+
+/// First bit of a position such as `1`, `1.5` or `4-5`.
+fn start_bit(position: &str) -> Option<usize> {
+    let start = position.split('-').next()?.trim();
+    let (byte, bit) = match start.split_once('.') {
+        Some((byte, bit)) => (
+            byte.trim().parse::<usize>().ok()?,
+            bit.trim().parse::<usize>().ok()?,
+        ),
+        None => (start.parse::<usize>().ok()?, 1),
+    };
+    if byte == 0 || bit == 0 || bit > 8 {
+        return None;
+    }
+    Some((byte - 1) * 8 + bit - 1)
+}
+
+/// Length in bits of `2 bytes`, `4 bits` and the like.
+fn length_bits(length: &str) -> Option<usize> {
+    let mut words = length.split_whitespace();
+    let count: usize = words.next()?.parse().ok()?;
+    match words.next()?.to_lowercase().as_str() {
+        "byte" | "bytes" => Some(count * 8),
+        "bit" | "bits" => Some(count),
+        _ => None,
+    }
+}
+
+impl FromStr for DigitalAnnex {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<DigitalAnnex> {
+        let records = csv_records(text);
+        let error =
+            |reason: String| Error::from(ErrorKind::AnnexFormat(reason));
+        let (header_line, header) = records
+            .iter()
+            .enumerate()
+            .find(|(_, r)| {
+                r.iter().any(|f| f.trim().eq_ignore_ascii_case("pgn"))
+            })
+            .ok_or_else(|| error("no header with a PGN column".to_owned()))?;
+        let column = |names: &[&str]| {
+            header.iter().position(|f| {
+                names.iter().any(|n| f.trim().eq_ignore_ascii_case(n))
+            })
+        };
+        let required = |names: &[&str]| {
+            column(names)
+                .ok_or_else(|| error(format!("no {} column", names[0])))
+        };
+        let pgn_column = required(&["PGN"])?;
+        let spn_column = required(&["SPN", "SP Number", "SPN Number"])?;
+        let position_column = required(&[
+            "SPN Position in PGN",
+            "SP Position in PG",
+            "Position",
+        ])?;
+        let length_column = required(&["SPN Length", "SP Length"])?;
+        let label_column = column(&[
+            "Parameter Group Label",
+            "PG Label",
+            "PGN Label",
+            "PGN Name",
+        ]);
+        let acronym_column = column(&["Acronym", "PG Acronym"]);
+        let name_column =
+            column(&["SPN Name", "SP Label", "SPN Label", "Name"]);
+        let resolution_column =
+            column(&["Resolution", "Scaling", "SP Resolution"]);
+        let offset_column = column(&["Offset", "SP Offset"]);
+        let unit_column = column(&["Units", "Unit", "SP Units"]);
+
+        let mut annex = DigitalAnnex::default();
+        for (n, record) in records.iter().enumerate().skip(header_line + 1) {
+            let field = |column: Option<usize>| {
+                column.and_then(|c| record.get(c)).map_or("", |f| f.trim())
+            };
+            let pgn_text = field(Some(pgn_column));
+            if pgn_text.is_empty() {
+                continue;
+            }
+            let pgn: u32 = pgn_text.parse().map_err(|_| {
+                error(format!("row {}: invalid PGN '{}'", n + 1, pgn_text))
+            })?;
+            let definition =
+                annex.pgns.entry(pgn).or_insert_with(|| PgnDefinition {
+                    pgn,
+                    name: field(label_column).to_owned(),
+                    acronym: field(acronym_column).to_owned(),
+                    spns: Vec::new(),
+                });
+
+            let spn_text = field(Some(spn_column));
+            if spn_text.is_empty() {
+                continue;
+            }
+            let spn = spn_text.parse().map_err(|_| {
+                error(format!("row {}: invalid SPN '{}'", n + 1, spn_text))
+            })?;
+            let length = match length_bits(field(Some(length_column))) {
+                Some(length) if length > 0 && length <= 64 => length,
+                _ => continue,
+            };
+            let start_bit = match start_bit(field(Some(position_column))) {
+                Some(start_bit) => start_bit,
+                None => continue,
+            };
+            // Discrete parameters give their states rather than a scale
+            let resolution = match field(resolution_column) {
+                text if text.contains("states") => 1.0,
+                text => leading_number(text).unwrap_or(1.0),
+            };
+            definition.spns.push(SpnDefinition {
+                spn,
+                name: field(name_column).to_owned(),
+                start_bit,
+                length,
+                resolution,
+                offset: leading_number(field(offset_column)).unwrap_or(0.0),
+                unit: field(unit_column).to_owned(),
+            });
+        }
+        Ok(annex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use j1939::{Id, GLOBAL};
+
+    const ANNEX: &str = "\
+PGN,Parameter Group Label,Acronym,SPN Position in PGN,SPN,SPN Name,SPN Description,SPN Length,Resolution,Offset,Units
+61444,Electronic Engine Controller 1,EEC1,1.1,899,Engine Torque Mode,\"State of the engine, as
+selected by the torque mode\",4 bits,16 states/4 bit,0,bit
+61444,Electronic Engine Controller 1,EEC1,2,512,Driver's Demand Engine - Percent Torque,,1 byte,1 %/bit,-125 %,%
+61444,Electronic Engine Controller 1,EEC1,4-5,190,Engine Speed,,2 bytes,0.125 rpm/bit,0,rpm
+65262,Engine Temperature 1,ET1,1,110,Engine Coolant Temperature,,1 byte,1 deg C/bit,-40 deg C,deg C
+65262,Engine Temperature 1,ET1,3-4,175,Engine Oil Temperature 1,,2 bytes,0.03125 deg C/bit,-273 deg C,deg C
+65262,Engine Temperature 1,ET1,see notes,52,Engine Intercooler Temperature,,1 byte,1 deg C/bit,-40 deg C,deg C
+65260,Vehicle Identification,VI,a,237,Vehicle Identification Number,,Variable,ASCII,0,ASCII
+";
+
+    fn message(pgn: u32, data: &[u8]) -> Message {
+        Message {
+            id: Id {
+                priority: 6,
+                pgn,
+                source: 0x00,
+                destination: GLOBAL,
+            },
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn parse_export() {
+        let annex: DigitalAnnex = ANNEX.parse().unwrap();
+        assert_eq!(annex.pgns.len(), 3);
+        let eec1 = annex.pgn(61444).unwrap();
+        assert_eq!(
+            (eec1.name.as_str(), eec1.acronym.as_str()),
+            ("Electronic Engine Controller 1", "EEC1")
+        );
+        assert_eq!(eec1.spns.len(), 3);
+        assert_eq!(
+            (
+                eec1.spns[0].start_bit,
+                eec1.spns[0].length,
+                eec1.spns[0].resolution
+            ),
+            (0, 4, 1.0)
+        );
+        assert_eq!(
+            (eec1.spns[1].offset, eec1.spns[1].unit.as_str()),
+            (-125.0, "%")
+        );
+        assert_eq!(
+            (eec1.spns[2].start_bit, eec1.spns[2].resolution),
+            (24, 0.125)
+        );
+        assert!(annex.pgn(65260).unwrap().spns.is_empty());
+        assert_eq!(annex.spn(175).unwrap().0.acronym, "ET1");
+        // Unknown position
+        assert_eq!(annex.pgn(65262).unwrap().spns.len(), 2);
+        assert!(annex.spn(52).is_none());
+
+        assert!("SPN,Name\n190,Engine Speed\n"
+            .parse::<DigitalAnnex>()
+            .is_err());
+    }
+
+    #[test]
+    fn decode_with_indicators() {
+        let annex: DigitalAnnex = ANNEX.parse().unwrap();
+        let eec1 = annex
+            .decode(&message(
+                61444,
+                &[0xF3, 0xAF, 0xFF, 0x40, 0x1F, 0xFF, 0xFF, 0xFF],
+            ))
+            .unwrap();
+        assert_eq!(eec1.physical(899), Some(3.0));
+        assert_eq!(eec1.physical(512), Some(50.0));
+        assert_eq!(eec1.physical(190), Some(1000.0));
+
+        let et1 = annex
+            .decode(&message(65262, &[0xFE, 0xFF, 0xFF, 0xFF]))
+            .unwrap();
+        assert_eq!(et1.spn(110).unwrap().value, SpnValue::Error);
+        assert_eq!(et1.spn(175).unwrap().value, SpnValue::NotAvailable);
+        assert_eq!(et1.physical(110), None);
+
+        // Too short for the oil temperature
+        let et1 = annex.decode(&message(65262, &[0x5A, 0xFF, 0x20])).unwrap();
+        assert_eq!(et1.physical(110), Some(50.0));
+        assert!(et1.spn(175).is_none());
+        assert!(annex.decode(&message(0xFEF1, &[0; 8])).is_none());
+    }
+}
@@ -0,0 +1,192 @@
+//! Passive reassembly of J1939 messages from frames seen on a bus or read
+//! from a trace.
+//!
+//! Unlike a `Node`, an `Assembler` never transmits: it follows the BAM and
+//! RTS/CTS sessions between any nodes and yields every message, whatever
+//! its destination. Sessions are not timed out, so a trace with gaps only
+//! costs the messages that straddle them.
+use super::{Id, Message, GLOBAL, MAX_MESSAGE_LEN, TP_CM, TP_DT};
+use std::collections::BTreeMap;
+use trace::{Event, Record};
+use CANFrame;
+
+const RTS: u8 = 16;
+const BAM: u8 = 32;
+const ABORT: u8 = 255;
+
+/// A message being reassembled.
+struct Session {
+    id: Id,
+    data: Vec<u8>,
+    size: usize,
+    next: u8, // Expected sequence number
+}
+
+#[derive(Default)]
+pub struct Assembler {
+    sessions: BTreeMap<(u8, u8), Session>, // By source and destination
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler::default()
+    }
+
+    /// Handle a frame, returning the message it completes. Frames with
+    /// 11-bit identifiers are ignored.
+    pub fn feed(&mut self, frame: &CANFrame) -> Option<Message> {
+        if !frame.is_extended() || frame.is_rtr() {
+            return None;
+        }
+        let id = Id::from(frame.id());
+        let data = &frame.data()[..frame.len() as usize];
+        match id.pgn {
+            TP_CM => {
+                self.handle_control(id, data);
+                None
+            }
+            TP_DT => self.handle_packet(id, data),
+            _ => Some(Message {
+                id,
+                data: data.to_vec(),
+            }),
+        }
+    }
+
+    /// Handle the frame of a trace record, if it holds a classic frame.
+    pub fn feed_record(&mut self, record: &Record) -> Option<Message> {
+        match record.event {
+            Event::Frame(ref frame) => self.feed(frame),
+            _ => None,
+        }
+    }
+
+    fn handle_control(&mut self, id: Id, data: &[u8]) {
+        if data.len() < 8 {
+            return;
+        }
+        let key = (id.source, id.destination);
+        match data[0] {
+            BAM | RTS if (data[0] == BAM) == (id.destination == GLOBAL) => {
+                let size = usize::from(u16::from_le_bytes([data[1], data[2]]));
+                if size > MAX_MESSAGE_LEN {
+                    return;
+                }
+                let pgn = u32::from(data[5])
+                    | u32::from(data[6]) << 8
+                    | u32::from(data[7]) << 16;
+                let session = Session {
+                    id: Id {
+                        priority: id.priority,
+                        pgn,
+                        source: id.source,
+                        destination: id.destination,
+                    },
+                    data: Vec::with_capacity(size),
+                    size,
+                    next: 1,
+                };
+                self.sessions.insert(key, session);
+            }
+            ABORT => {
+                // Either side may abort a connection
+                self.sessions.remove(&key);
+                self.sessions.remove(&(id.destination, id.source));
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_packet(&mut self, id: Id, data: &[u8]) -> Option<Message> {
+        let key = (id.source, id.destination);
+        let mut session = self.sessions.remove(&key)?;
+        if data.first() != Some(&session.next) {
+            // A retransmission after a CTS may resend a packet already held
+            if data.first().is_some_and(|&sequence| {
+                sequence > 0 && sequence < session.next
+            }) {
+                let keep = (usize::from(data[0]) - 1) * 7;
+                session.data.truncate(keep);
+                session.next = data[0];
+            } else {
+                return None;
+            }
+        }
+        let take = (session.size - session.data.len()).min(data.len() - 1);
+        session.data.extend_from_slice(&data[1..=take]);
+        if session.data.len() == session.size {
+            return Some(Message {
+                id: session.id,
+                data: session.data,
+            });
+        }
+        session.next = session.next.wrapping_add(1);
+        self.sessions.insert(key, session);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(id: u32, data: &[u8]) -> CANFrame {
+        CANFrame::new_extended(id, data, false).unwrap()
+    }
+
+    #[test]
+    fn bam_and_rts_cts_sessions() {
+        let mut assembler = Assembler::new();
+        let frames = [
+            // DM1 of 10 bytes from 0x00 with BAM, interleaved with a single frame
+            frame(0x1CEC_FF00, &[32, 10, 0, 2, 0xFF, 0xCA, 0xFE, 0x00]),
+            frame(0x1CEB_FF00, &[1, 0x04, 0xFF, 0x64, 0x00, 0x01, 0x05, 0x6E]),
+            frame(
+                0x0CF0_0400,
+                &[0xF0, 0x7D, 0x7D, 0x00, 0x1A, 0xFF, 0xFF, 0xFF],
+            ),
+            frame(0x1CEB_FF00, &[2, 0x00, 0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFF]),
+            // 9 bytes from 0xF9 to 0x00 with RTS/CTS, the first packet sent again
+            frame(0x1CEC_00F9, &[16, 9, 0, 2, 0xFF, 0x00, 0xEF, 0x00]),
+            frame(0x1CEC_F900, &[17, 2, 1, 0xFF, 0xFF, 0x00, 0xEF, 0x00]),
+            frame(0x1CEB_00F9, &[1, 0xEE, 0xEE, 0xEE, 0xEE, 0xEE, 0xEE, 0xEE]),
+            frame(0x1CEC_F900, &[17, 2, 1, 0xFF, 0xFF, 0x00, 0xEF, 0x00]),
+            frame(0x1CEB_00F9, &[1, 1, 2, 3, 4, 5, 6, 7]),
+            frame(0x1CEB_00F9, &[2, 8, 9, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+        ];
+        let messages: Vec<Message> =
+            frames.iter().filter_map(|f| assembler.feed(f)).collect();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].id.pgn, 0xF004);
+        assert_eq!(
+            messages[1].id,
+            Id {
+                priority: 7,
+                pgn: 0xFECA,
+                source: 0x00,
+                destination: GLOBAL
+            }
+        );
+        assert_eq!(
+            messages[1].data,
+            vec![0x04, 0xFF, 0x64, 0x00, 0x01, 0x05, 0x6E, 0x00, 0x00, 0x01]
+        );
+        assert_eq!(
+            (messages[2].id.pgn, messages[2].id.destination),
+            (0xEF00, 0x00)
+        );
+        assert_eq!(messages[2].data, vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+        // A packet out of sequence drops the session
+        assembler
+            .feed(&frame(0x1CEC_FF00, &[32, 10, 0, 2, 0xFF, 0xCA, 0xFE, 0x00]));
+        assert_eq!(
+            assembler.feed(&frame(0x1CEB_FF00, &[2, 0, 0, 0, 0, 0, 0, 0])),
+            None
+        );
+        assert_eq!(
+            assembler.feed(&frame(0x1CEB_FF00, &[1, 0, 0, 0, 0, 0, 0, 0])),
+            None
+        );
+    }
+}
@@ -0,0 +1,652 @@
+//! J1939-73 diagnostic messages: active (DM1) and previously active (DM2)
+//! DTCs with lamp status, and clearing them with DM11 and DM3.
+//!
+//! DM1 is broadcast about once a second, in several packets when more than
+//! one DTC is active. `DtcMonitor` follows the DM1s received by a `Node`
+//! and reports DTCs as they appear and clear.
+use super::{Message, Node, ACKNOWLEDGEMENT, GLOBAL};
+use bus::CanBus;
+use errors::*;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+pub const DM1: u32 = 0xFECA;
+pub const DM2: u32 = 0xFECB;
+pub const DM3: u32 = 0xFECC;
+pub const DM11: u32 = 0xFED3;
+
+const PRIORITY: u8 = 6;
+
+/// Occurrence count when the sender does not count occurrences.
+pub const OCCURRENCE_COUNT_NOT_AVAILABLE: u8 = 0x7F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LampState {
+    Off,
+    On,
+    Reserved,
+    NotAvailable,
+}
+
+impl From<u8> for LampState {
+    fn from(bits: u8) -> LampState {
+        match bits & 0x03 {
+            0 => LampState::Off,
+            1 => LampState::On,
+            2 => LampState::Reserved,
+            _ => LampState::NotAvailable,
+        }
+    }
+}
+
+impl From<LampState> for u8 {
+    fn from(state: LampState) -> u8 {
+        match state {
+            LampState::Off => 0,
+            LampState::On => 1,
+            LampState::Reserved => 2,
+            LampState::NotAvailable => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LampFlash {
+    Slow, // 1 Hz
+    Fast, // 2 Hz
+    Reserved,
+    Off, // Steady when on
+}
+
+impl From<u8> for LampFlash {
+    fn from(bits: u8) -> LampFlash {
+        match bits & 0x03 {
+            0 => LampFlash::Slow,
+            1 => LampFlash::Fast,
+            2 => LampFlash::Reserved,
+            _ => LampFlash::Off,
+        }
+    }
+}
+
+impl From<LampFlash> for u8 {
+    fn from(flash: LampFlash) -> u8 {
+        match flash {
+            LampFlash::Slow => 0,
+            LampFlash::Fast => 1,
+            LampFlash::Reserved => 2,
+            LampFlash::Off => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lamp {
+    pub state: LampState,
+    pub flash: LampFlash,
+}
+
+impl Lamp {
+    fn decode(status: u8, flash: u8, shift: u8) -> Lamp {
+        Lamp {
+            state: LampState::from(status >> shift),
+            flash: LampFlash::from(flash >> shift),
+        }
+    }
+}
+
+impl Default for Lamp {
+    fn default() -> Lamp {
+        Lamp {
+            state: LampState::Off,
+            flash: LampFlash::Off,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LampStatus {
+    pub malfunction_indicator: Lamp,
+    pub red_stop: Lamp,
+    pub amber_warning: Lamp,
+    pub protect: Lamp,
+}
+
+impl LampStatus {
+    fn decode(status: u8, flash: u8) -> LampStatus {
+        LampStatus {
+            malfunction_indicator: Lamp::decode(status, flash, 6),
+            red_stop: Lamp::decode(status, flash, 4),
+            amber_warning: Lamp::decode(status, flash, 2),
+            protect: Lamp::decode(status, flash, 0),
+        }
+    }
+
+    fn encode(&self) -> [u8; 2] {
+        let lamps = [
+            self.malfunction_indicator,
+            self.red_stop,
+            self.amber_warning,
+            self.protect,
+        ];
+        let mut bytes = [0u8; 2];
+        for (lamp, shift) in lamps.iter().zip([6, 4, 2, 0].iter()) {
+            bytes[0] |= u8::from(lamp.state) << shift;
+            bytes[1] |= u8::from(lamp.flash) << shift;
+        }
+        bytes
+    }
+}
+
+/// A diagnostic trouble code: suspect parameter and failure mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dtc {
+    pub spn: u32,
+    pub fmi: u8,
+    pub occurrence_count: u8,
+    pub conversion_method: bool, // Set by senders using the SPN bit order of J1939-73 version 1
+}
+
+impl Dtc {
+    pub fn new(spn: u32, fmi: u8) -> Dtc {
+        Dtc {
+            spn,
+            fmi,
+            occurrence_count: OCCURRENCE_COUNT_NOT_AVAILABLE,
+            conversion_method: false,
+        }
+    }
+
+    /// Decode the 4 bytes of a DTC. With the conversion method bit set the
+    /// SPN is read most significant bits first.
+    pub fn decode(data: &[u8]) -> Dtc {
+        let conversion_method = data[3] & 0x80 != 0;
+        let high = u32::from(data[2] >> 5);
+        let spn = if conversion_method {
+            u32::from(data[0]) << 11 | u32::from(data[1]) << 3 | high
+        } else {
+            high << 16 | u32::from(data[1]) << 8 | u32::from(data[0])
+        };
+        Dtc {
+            spn,
+            fmi: data[2] & 0x1F,
+            occurrence_count: data[3] & 0x7F,
+            conversion_method,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; 4] {
+        let (first, second, high) = if self.conversion_method {
+            (
+                (self.spn >> 11) as u8,
+                (self.spn >> 3) as u8,
+                (self.spn & 0x07) as u8,
+            )
+        } else {
+            (
+                self.spn as u8,
+                (self.spn >> 8) as u8,
+                ((self.spn >> 16) & 0x07) as u8,
+            )
+        };
+        [
+            first,
+            second,
+            high << 5 | (self.fmi & 0x1F),
+            u8::from(self.conversion_method) << 7
+                | (self.occurrence_count & 0x7F),
+        ]
+    }
+
+    /// Whether two DTCs are the same fault, whatever their occurrence counts.
+    pub fn same_fault(&self, other: &Dtc) -> bool {
+        self.spn == other.spn && self.fmi == other.fmi
+    }
+}
+
+/// Content of a DM1 or DM2: lamp status and DTCs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DtcReport {
+    pub lamps: LampStatus,
+    pub dtcs: Vec<Dtc>,
+}
+
+impl DtcReport {
+    pub fn decode(data: &[u8]) -> Result<DtcReport> {
+        if data.len() < 6 {
+            bail!(ErrorKind::J1939Protocol(format!(
+                "DTC report of {} bytes is too short",
+                data.len()
+            )));
+        }
+        // "No DTC" is sent as an all zero DTC, and short reports are padded with 0xFF
+        let dtcs = data[2..]
+            .chunks(4)
+            .filter(|d| d.len() == 4 && d != &[0, 0, 0, 0] && d != &[0xFF; 4])
+            .map(Dtc::decode)
+            .collect();
+        Ok(DtcReport {
+            lamps: LampStatus::decode(data[0], data[1]),
+            dtcs,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = self.lamps.encode().to_vec();
+        if self.dtcs.is_empty() {
+            data.extend_from_slice(&[0, 0, 0, 0]);
+        }
+        for dtc in &self.dtcs {
+            data.extend_from_slice(&dtc.encode());
+        }
+        data.resize(data.len().max(8), 0xFF);
+        data
+    }
+}
+
+/// Change reported by `DtcMonitor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DtcEvent {
+    Appeared { source: u8, dtc: Dtc },
+    Cleared { source: u8, dtc: Dtc },
+    LampsChanged { source: u8, lamps: LampStatus },
+    Silent { source: u8 }, // No DM1 for the monitor's timeout, its DTCs are forgotten
+}
+
+/// Follows the DM1s of every source.
+pub struct DtcMonitor {
+    timeout: Duration,
+    sources: BTreeMap<u8, (DtcReport, Instant)>,
+}
+
+impl DtcMonitor {
+    /// A monitor forgetting sources silent for `timeout`. DM1 is sent every
+    /// second, so a few seconds suit most networks.
+    pub fn new(timeout: Duration) -> DtcMonitor {
+        DtcMonitor {
+            timeout,
+            sources: BTreeMap::new(),
+        }
+    }
+
+    /// Last DM1 of each source.
+    pub fn active(&self) -> BTreeMap<u8, &DtcReport> {
+        self.sources
+            .iter()
+            .map(|(&source, (report, _))| (source, report))
+            .collect()
+    }
+
+    /// Handle a received message. Messages other than DM1 are ignored.
+    pub fn update(&mut self, message: &Message) -> Result<Vec<DtcEvent>> {
+        if message.id.pgn != DM1 {
+            return Ok(Vec::new());
+        }
+        let source = message.id.source;
+        let report = DtcReport::decode(&message.data)?;
+        let previous = self
+            .sources
+            .remove(&source)
+            .map(|(report, _)| report)
+            .unwrap_or_default();
+
+        let mut events: Vec<DtcEvent> = report
+            .dtcs
+            .iter()
+            .filter(|dtc| !previous.dtcs.iter().any(|p| p.same_fault(dtc)))
+            .map(|&dtc| DtcEvent::Appeared { source, dtc })
+            .collect();
+        events.extend(
+            previous
+                .dtcs
+                .iter()
+                .filter(|dtc| !report.dtcs.iter().any(|d| d.same_fault(dtc)))
+                .map(|&dtc| DtcEvent::Cleared { source, dtc }),
+        );
+        if report.lamps != previous.lamps {
+            events.push(DtcEvent::LampsChanged {
+                source,
+                lamps: report.lamps,
+            });
+        }
+        self.sources.insert(source, (report, Instant::now()));
+        Ok(events)
+    }
+
+    /// Forget sources that stopped sending DM1.
+    pub fn check(&mut self) -> Vec<DtcEvent> {
+        let timeout = self.timeout;
+        let silent: Vec<u8> = self
+            .sources
+            .iter()
+            .filter(|(_, (_, seen))| seen.elapsed() > timeout)
+            .map(|(&s, _)| s)
+            .collect();
+        silent
+            .into_iter()
+            .map(|source| {
+                self.sources.remove(&source);
+                DtcEvent::Silent { source }
+            })
+            .collect()
+    }
+}
+
+impl<B: CanBus> Node<B> {
+    /// Broadcast a DM1, with BAM if more than one DTC is active.
+    pub fn send_active_dtcs(&mut self, report: &DtcReport) -> Result<()> {
+        self.send(DM1, GLOBAL, PRIORITY, &report.encode())
+    }
+
+    /// Request DM1 from `destination`, or from every node with `GLOBAL`,
+    /// and collect the answers received within `timeout` by source. Other
+    /// messages received meanwhile, and answers too short to decode, are
+    /// dropped.
+    pub fn read_active_dtcs(
+        &mut self,
+        destination: u8,
+        timeout: Duration,
+    ) -> Result<BTreeMap<u8, DtcReport>> {
+        self.read_dtc_report(DM1, destination, timeout)
+    }
+
+    /// Request DM2, as `read_active_dtcs` does DM1.
+    pub fn read_previously_active_dtcs(
+        &mut self,
+        destination: u8,
+        timeout: Duration,
+    ) -> Result<BTreeMap<u8, DtcReport>> {
+        self.read_dtc_report(DM2, destination, timeout)
+    }
+
+    fn read_dtc_report(
+        &mut self,
+        pgn: u32,
+        destination: u8,
+        timeout: Duration,
+    ) -> Result<BTreeMap<u8, DtcReport>> {
+        self.request(pgn, destination)?;
+        let mut reports = BTreeMap::new();
+        for message in self.collect(timeout)? {
+            if message.id.pgn != pgn
+                || (destination != GLOBAL && message.id.source != destination)
+            {
+                continue;
+            }
+            // One node's malformed report must not hide the others
+            if let Ok(report) = DtcReport::decode(&message.data) {
+                reports.insert(message.id.source, report);
+            }
+        }
+        Ok(reports)
+    }
+
+    /// Clear active DTCs with DM11. Returns the acknowledgements received
+    /// within `timeout` by source, `true` for a positive one.
+    pub fn clear_active_dtcs(
+        &mut self,
+        destination: u8,
+        timeout: Duration,
+    ) -> Result<BTreeMap<u8, bool>> {
+        self.clear_dtcs(DM11, destination, timeout)
+    }
+
+    /// Clear previously active DTCs with DM3, as `clear_active_dtcs` does.
+    pub fn clear_previously_active_dtcs(
+        &mut self,
+        destination: u8,
+        timeout: Duration,
+    ) -> Result<BTreeMap<u8, bool>> {
+        self.clear_dtcs(DM3, destination, timeout)
+    }
+
+    fn clear_dtcs(
+        &mut self,
+        pgn: u32,
+        destination: u8,
+        timeout: Duration,
+    ) -> Result<BTreeMap<u8, bool>> {
+        self.request(pgn, destination)?;
+        let pgn_bytes = [pgn as u8, (pgn >> 8) as u8, (pgn >> 16) as u8];
+        Ok(self
+            .collect(timeout)?
+            .into_iter()
+            .filter(|m| {
+                m.id.pgn == ACKNOWLEDGEMENT
+                    && m.data.len() >= 8
+                    && m.data[5..8] == pgn_bytes
+            })
+            .map(|m| (m.id.source, m.data[0] == 0))
+            .collect())
+    }
+
+    /// Messages received until `timeout`.
+    fn collect(&mut self, timeout: Duration) -> Result<Vec<Message>> {
+        let deadline = Instant::now() + timeout;
+        let mut messages = Vec::new();
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(messages);
+            }
+            messages.extend(self.receive(deadline - now)?);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::{CanBus, VirtualBus};
+    use j1939::{Config, Id, Name, Node, REQUEST};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use CANFrame;
+
+    #[test]
+    fn decode_and_encode() {
+        // Amber warning lamp on, SPN 100 FMI 1 seen 5 times
+        let data = [0x04, 0xFF, 0x64, 0x00, 0x01, 0x05, 0xFF, 0xFF];
+        let report = DtcReport::decode(&data).unwrap();
+        assert_eq!(
+            report.lamps.amber_warning,
+            Lamp {
+                state: LampState::On,
+                flash: LampFlash::Off
+            }
+        );
+        assert_eq!(report.lamps.malfunction_indicator.state, LampState::Off);
+        assert_eq!(
+            report.dtcs,
+            vec![Dtc {
+                spn: 100,
+                fmi: 1,
+                occurrence_count: 5,
+                conversion_method: false
+            }]
+        );
+        assert_eq!(report.encode(), data.to_vec());
+
+        // SPN 520192, beyond 16 bits, in both bit orders
+        for &conversion_method in &[false, true] {
+            let dtc = Dtc {
+                spn: 520_192,
+                fmi: 31,
+                occurrence_count: 1,
+                conversion_method,
+            };
+            assert_eq!(Dtc::decode(&dtc.encode()), dtc);
+        }
+        assert_eq!(Dtc::decode(&[0x00, 0x0C, 0xE3, 0x81]).spn, 0x67);
+
+        let empty =
+            DtcReport::decode(&[0x00, 0xFF, 0, 0, 0, 0, 0xFF, 0xFF]).unwrap();
+        assert!(empty.dtcs.is_empty());
+        assert_eq!(empty.encode(), vec![0x00, 0xFF, 0, 0, 0, 0, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn monitor_multi_packet_dm1() {
+        let bus = VirtualBus::new();
+        let name = |identity_number| Name {
+            identity_number,
+            ..Name::default()
+        };
+        let mut config = Config::new(name(1), 0x00);
+        config.bam_interval = Duration::from_millis(1);
+        let mut engine = Node::new(bus.connect(), config);
+        let mut tool = Node::new(bus.connect(), Config::new(name(2), 0xF9));
+
+        let lamps = LampStatus {
+            amber_warning: Lamp {
+                state: LampState::On,
+                flash: LampFlash::Off,
+            },
+            ..LampStatus::default()
+        };
+        let report = DtcReport {
+            lamps,
+            dtcs: vec![Dtc::new(100, 1), Dtc::new(110, 0), Dtc::new(190, 2)],
+        };
+        let later = DtcReport {
+            lamps,
+            dtcs: vec![Dtc::new(110, 0), Dtc::new(190, 2), Dtc::new(91, 3)],
+        };
+        let sent = (report.clone(), later.clone());
+        let engine = thread::spawn(move || {
+            engine.claim_address().unwrap();
+            engine.add_response(
+                DM2,
+                DtcReport {
+                    lamps,
+                    dtcs: vec![Dtc::new(84, 2)],
+                }
+                .encode(),
+            );
+            thread::sleep(Duration::from_millis(300));
+            engine.send_active_dtcs(&sent.0).unwrap();
+            engine.send_active_dtcs(&sent.1).unwrap();
+            engine.receive(Duration::from_millis(500)).unwrap();
+        });
+        tool.claim_address().unwrap();
+
+        let mut monitor = DtcMonitor::new(Duration::from_millis(200));
+        let mut events = Vec::new();
+        while let Some(message) = tool.receive(Duration::from_secs(1)).unwrap()
+        {
+            events.extend(monitor.update(&message).unwrap());
+            if monitor.active().get(&0x00) == Some(&&later) {
+                break;
+            }
+        }
+        let appeared = |spn, fmi| DtcEvent::Appeared {
+            source: 0x00,
+            dtc: Dtc::new(spn, fmi),
+        };
+        assert_eq!(events.len(), 6);
+        assert_eq!(
+            &events[..4],
+            &[
+                appeared(100, 1),
+                appeared(110, 0),
+                appeared(190, 2),
+                DtcEvent::LampsChanged {
+                    source: 0x00,
+                    lamps
+                },
+            ]
+        );
+        assert_eq!(
+            &events[4..],
+            &[
+                appeared(91, 3),
+                DtcEvent::Cleared {
+                    source: 0x00,
+                    dtc: Dtc::new(100, 1)
+                }
+            ]
+        );
+
+        let previous = tool
+            .read_previously_active_dtcs(0x00, Duration::from_millis(300))
+            .unwrap();
+        assert_eq!(previous[&0x00].dtcs, vec![Dtc::new(84, 2)]);
+        engine.join().unwrap();
+        assert_eq!(monitor.check(), vec![DtcEvent::Silent { source: 0x00 }]);
+    }
+
+    #[test]
+    fn clear_dtcs_and_skip_malformed_reports() {
+        let bus = VirtualBus::new();
+        let name = |identity_number| Name {
+            identity_number,
+            ..Name::default()
+        };
+        let end = Instant::now() + Duration::from_millis(1500);
+
+        // Answers DM1 requests, refuses DM11 and DM3 with a NACK
+        let mut engine = Node::new(bus.connect(), Config::new(name(1), 0x00));
+        let report = DtcReport {
+            lamps: LampStatus::default(),
+            dtcs: vec![Dtc::new(100, 1)],
+        };
+        engine.add_response(DM1, report.encode());
+        let engine = thread::spawn(move || {
+            engine.claim_address().unwrap();
+            while Instant::now() < end {
+                engine.receive(Duration::from_millis(10)).unwrap();
+            }
+        });
+        // Answers DM1 requests with a truncated report, acknowledges DM3
+        let gateway = bus.connect();
+        let frame = |pgn, data: &[u8]| {
+            let id = Id {
+                priority: 6,
+                pgn,
+                source: 0x01,
+                destination: GLOBAL,
+            };
+            CANFrame::new_extended(id.into(), data, false).unwrap()
+        };
+        let gateway = thread::spawn(move || {
+            while Instant::now() < end {
+                let request = match gateway.receive().unwrap() {
+                    Some(frame) => frame,
+                    None => {
+                        thread::sleep(Duration::from_millis(1));
+                        continue;
+                    }
+                };
+                if Id::from(request.id()).pgn != REQUEST {
+                    continue;
+                }
+                let answer = match request.data().get(..3) {
+                    Some([0xCA, 0xFE, 0x00]) => frame(DM1, &[0x00, 0xFF, 0x00]),
+                    Some([0xCC, 0xFE, 0x00]) => frame(
+                        ACKNOWLEDGEMENT,
+                        &[0x00, 0xFF, 0xFF, 0xFF, 0xF9, 0xCC, 0xFE, 0x00],
+                    ),
+                    _ => continue,
+                };
+                gateway.transmit(&answer).unwrap();
+            }
+        });
+
+        let mut tool = Node::new(bus.connect(), Config::new(name(2), 0xF9));
+        tool.claim_address().unwrap();
+        let timeout = Duration::from_millis(200);
+        let active = tool.read_active_dtcs(GLOBAL, timeout).unwrap();
+        assert_eq!(active.keys().collect::<Vec<_>>(), vec![&0x00]);
+        assert_eq!(active[&0x00], report);
+        let cleared = tool.clear_active_dtcs(0x00, timeout).unwrap();
+        assert_eq!(
+            cleared.into_iter().collect::<Vec<_>>(),
+            vec![(0x00, false)]
+        );
+        let cleared =
+            tool.clear_previously_active_dtcs(GLOBAL, timeout).unwrap();
+        assert_eq!(cleared.into_iter().collect::<Vec<_>>(), vec![(0x01, true)]);
+        engine.join().unwrap();
+        gateway.join().unwrap();
+    }
+}
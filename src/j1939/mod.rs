@@ -0,0 +1,189 @@
+//! SAE J1939 over extended-ID frames.
+//!
+//! `Id` splits a 29-bit identifier into priority, PGN, source and
+//! destination. A `Node` claims an address by NAME, answers requests, and
+//! sends and receives messages of up to 1785 bytes with the BAM and RTS/CTS
+//! transport protocols of J1939-21. Diagnostic messages of J1939-73 are
+//! decoded on top of it.
+//!
+//! An `Assembler` reassembles messages from frames without taking part in
+//! the traffic, for bus monitoring and recorded traces, and a
+//! `DigitalAnnex` decodes messages into SPN values.
+
+mod annex;
+mod assembler;
+mod diagnostics;
+mod node;
+
+pub use self::annex::{
+    DecodedPgn, DecodedSpn, DigitalAnnex, PgnDefinition, SpnDefinition,
+    SpnValue,
+};
+pub use self::assembler::Assembler;
+pub use self::diagnostics::{
+    Dtc, DtcEvent, DtcMonitor, DtcReport, Lamp, LampFlash, LampState,
+    LampStatus, DM1, DM11, DM2, DM3, OCCURRENCE_COUNT_NOT_AVAILABLE,
+};
+pub use self::node::{Config, Node};
+
+/// Destination of broadcasts.
+pub const GLOBAL: u8 = 0xFF;
+
+/// Source of "cannot claim address".
+pub const NULL: u8 = 0xFE;
+
+pub const ACKNOWLEDGEMENT: u32 = 0xE800;
+pub const REQUEST: u32 = 0xEA00;
+pub const TP_DT: u32 = 0xEB00;
+pub const TP_CM: u32 = 0xEC00;
+pub const ADDRESS_CLAIMED: u32 = 0xEE00;
+
+/// Longest message of the transport protocols: 255 packets of 7 bytes.
+pub const MAX_MESSAGE_LEN: usize = 1785;
+
+/// Whether a PGN is sent to a specific destination (PDU1 format).
+pub fn is_pdu1(pgn: u32) -> bool {
+    (pgn >> 8) & 0xFF < 240
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Id {
+    pub priority: u8,
+    pub pgn: u32,
+    pub source: u8,
+    pub destination: u8, // GLOBAL for PDU2 PGNs
+}
+
+impl From<u32> for Id {
+    fn from(id: u32) -> Id {
+        let format = (id >> 16) & 0xFF;
+        let specific = (id >> 8) & 0xFF;
+        // Extended data page, data page and PDU format
+        let pgn = (id >> 8) & 0x3FF00;
+        let (pgn, destination) = if format < 240 {
+            (pgn, specific as u8)
+        } else {
+            (pgn | specific, GLOBAL)
+        };
+        Id {
+            priority: ((id >> 26) & 0x07) as u8,
+            pgn,
+            source: id as u8,
+            destination,
+        }
+    }
+}
+
+impl From<Id> for u32 {
+    fn from(id: Id) -> u32 {
+        let specific = if is_pdu1(id.pgn) {
+            u32::from(id.destination)
+        } else {
+            id.pgn & 0xFF
+        };
+        u32::from(id.priority & 0x07) << 26
+            | (id.pgn & 0x3FF00) << 8
+            | specific << 8
+            | u32::from(id.source)
+    }
+}
+
+/// The 64-bit NAME identifying a node. A lower NAME wins address claim
+/// contention.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Name {
+    pub arbitrary_address_capable: bool, // May claim another address when it loses its own
+    pub industry_group: u8,
+    pub vehicle_system_instance: u8,
+    pub vehicle_system: u8,
+    pub function: u8,
+    pub function_instance: u8,
+    pub ecu_instance: u8,
+    pub manufacturer_code: u16,
+    pub identity_number: u32,
+}
+
+impl From<u64> for Name {
+    fn from(name: u64) -> Name {
+        Name {
+            arbitrary_address_capable: name >> 63 != 0,
+            industry_group: ((name >> 60) & 0x07) as u8,
+            vehicle_system_instance: ((name >> 56) & 0x0F) as u8,
+            vehicle_system: ((name >> 49) & 0x7F) as u8,
+            function: (name >> 40) as u8,
+            function_instance: ((name >> 35) & 0x1F) as u8,
+            ecu_instance: ((name >> 32) & 0x07) as u8,
+            manufacturer_code: ((name >> 21) & 0x7FF) as u16,
+            identity_number: (name & 0x1F_FFFF) as u32,
+        }
+    }
+}
+
+impl From<Name> for u64 {
+    fn from(name: Name) -> u64 {
+        u64::from(name.arbitrary_address_capable) << 63
+            | u64::from(name.industry_group & 0x07) << 60
+            | u64::from(name.vehicle_system_instance & 0x0F) << 56
+            | u64::from(name.vehicle_system & 0x7F) << 49
+            | u64::from(name.function) << 40
+            | u64::from(name.function_instance & 0x1F) << 35
+            | u64::from(name.ecu_instance & 0x07) << 32
+            | u64::from(name.manufacturer_code & 0x7FF) << 21
+            | u64::from(name.identity_number & 0x1F_FFFF)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub id: Id,
+    pub data: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifiers_and_names() {
+        // EEC1 from the engine, a PDU2 PGN
+        let id = Id::from(0x0CF0_0400);
+        assert_eq!(
+            id,
+            Id {
+                priority: 3,
+                pgn: 0xF004,
+                source: 0x00,
+                destination: GLOBAL
+            }
+        );
+        assert_eq!(u32::from(id), 0x0CF0_0400);
+        // Request from 0xF9 to 0x00, a PDU1 PGN
+        let id = Id::from(0x18EA_00F9);
+        assert_eq!(
+            id,
+            Id {
+                priority: 6,
+                pgn: REQUEST,
+                source: 0xF9,
+                destination: 0x00
+            }
+        );
+        assert_eq!(u32::from(id), 0x18EA_00F9);
+        assert!(is_pdu1(TP_CM) && !is_pdu1(0xFECA));
+
+        let name = Name {
+            arbitrary_address_capable: true,
+            industry_group: 1,
+            vehicle_system_instance: 2,
+            vehicle_system: 3,
+            function: 130,
+            function_instance: 4,
+            ecu_instance: 5,
+            manufacturer_code: 0x123,
+            identity_number: 0x1_2345,
+        };
+        let raw = u64::from(name);
+        assert_eq!(raw, 0x9206_8225_2461_2345);
+        assert_eq!(Name::from(raw), name);
+    }
+}
@@ -0,0 +1,787 @@
+//! A J1939 node: address claiming, requests and transport protocols.
+//!
+//! Frames are handled whenever the node polls the bus, including while it
+//! sends, so address claim contention, requests and transport sessions
+//! from other nodes are served during a long transfer. Several messages can
+//! be received at once, one per source and destination.
+use super::{
+    is_pdu1, Id, Message, Name, ACKNOWLEDGEMENT, ADDRESS_CLAIMED, GLOBAL,
+    MAX_MESSAGE_LEN, NULL, REQUEST, TP_CM, TP_DT,
+};
+use bus::CanBus;
+use errors::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::thread;
+use std::time::{Duration, Instant};
+use CANFrame;
+
+/// Pause between polls of the bus while waiting.
+const POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+/// Time other nodes have to contest an address claim.
+const ADDRESS_CLAIM_TIME: Duration = Duration::from_millis(250);
+
+const PRIORITY_CONTROL: u8 = 6;
+const PRIORITY_TRANSPORT: u8 = 7;
+
+const RTS: u8 = 16;
+const CTS: u8 = 17;
+const END_OF_MESSAGE_ACK: u8 = 19;
+const BAM: u8 = 32;
+const ABORT: u8 = 255;
+
+const ABORT_TIMEOUT: u8 = 3;
+const ABORT_BAD_SEQUENCE: u8 = 7;
+
+const NACK: u8 = 1;
+
+/// Settings of a node. `Config::new` gives the J1939-21 timeouts, a 50 ms
+/// BAM packet interval and 16 packets per CTS.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub name: Name,
+    pub preferred_address: u8,
+    pub max_packets: u8, // Packets a sender may send per CTS
+    pub bam_interval: Duration, // Between sent BAM packets, 50 to 200 ms in J1939-21
+    pub t1: Duration,           // Time allowed between received data packets
+    pub t2: Duration,           // Time allowed for data after a CTS
+    pub t3: Duration, // Time allowed for a CTS or acknowledgement after sending
+    pub t4: Duration, // Time allowed for a CTS after a CTS holding the connection open
+}
+
+impl Config {
+    pub fn new(name: Name, preferred_address: u8) -> Config {
+        Config {
+            name,
+            preferred_address,
+            max_packets: 16,
+            bam_interval: Duration::from_millis(50),
+            t1: Duration::from_millis(750),
+            t2: Duration::from_millis(1250),
+            t3: Duration::from_millis(1250),
+            t4: Duration::from_millis(1050),
+        }
+    }
+}
+
+/// A message being received with the transport protocol.
+struct Reception {
+    id: Id, // Priority, source and destination of the announcement, PGN of the message
+    data: Vec<u8>,
+    size: usize,
+    packets: u8,
+    next: u8,       // Expected sequence number
+    window_end: u8, // Last sequence number of the current CTS
+    deadline: Instant,
+}
+
+impl Reception {
+    fn is_broadcast(&self) -> bool {
+        self.id.destination == GLOBAL
+    }
+}
+
+fn pgn_bytes(pgn: u32) -> [u8; 3] {
+    [pgn as u8, (pgn >> 8) as u8, (pgn >> 16) as u8]
+}
+
+fn pgn_from_bytes(data: &[u8]) -> u32 {
+    u32::from(data[0]) | u32::from(data[1]) << 8 | u32::from(data[2]) << 16
+}
+
+pub struct Node<B: CanBus> {
+    bus: B,
+    config: Config,
+    address: Option<u8>,
+    claimed_at: Instant,
+    claims: BTreeMap<u8, Name>,
+    responses: BTreeMap<u32, Vec<u8>>,
+    receptions: BTreeMap<(u8, u8), Reception>, // By source and destination
+    received: VecDeque<Message>,
+    control: Option<(u8, Vec<u8>)>, // Last CTS, acknowledgement or abort sent to us, with its source
+    sending: bool,                  // With the transport protocol
+    deferred: VecDeque<(u32, u8, Vec<u8>)>, // Responses to send after the transfer, with their PGN and destination
+}
+
+impl<B: CanBus> Node<B> {
+    /// A node without an address until `claim_address` is called. It
+    /// receives broadcasts meanwhile.
+    pub fn new(bus: B, config: Config) -> Node<B> {
+        Node {
+            bus,
+            config,
+            address: None,
+            claimed_at: Instant::now(),
+            claims: BTreeMap::new(),
+            responses: BTreeMap::new(),
+            receptions: BTreeMap::new(),
+            received: VecDeque::new(),
+            control: None,
+            sending: false,
+            deferred: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn into_inner(self) -> B {
+        self.bus
+    }
+
+    /// Claimed address, `None` before claiming or after losing the claim
+    /// without finding another address.
+    pub fn address(&self) -> Option<u8> {
+        self.address
+    }
+
+    /// Addresses claimed by other nodes, with their NAMEs.
+    pub fn claims(&self) -> &BTreeMap<u8, Name> {
+        &self.claims
+    }
+
+    /// Data sent in answer to requests for `pgn`. Requests for PGNs without
+    /// a response are refused with a NACK when sent to this node.
+    pub fn add_response(&mut self, pgn: u32, data: Vec<u8>) {
+        self.responses.insert(pgn, data);
+    }
+
+    /// Claim the preferred address and wait for other nodes to contest it.
+    /// Returns the address held afterwards.
+    pub fn claim_address(&mut self) -> Result<Option<u8>> {
+        self.address = Some(self.config.preferred_address);
+        self.send_claim()?;
+        while self.address.is_some()
+            && self.claimed_at.elapsed() < ADDRESS_CLAIM_TIME
+        {
+            self.poll()?;
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(self.address)
+    }
+
+    /// Send a message, with the transport protocol if it does not fit a
+    /// frame: BAM for broadcasts, RTS/CTS for a specific destination. The
+    /// destination of PDU2 PGNs is ignored. Requests answered with the
+    /// transport protocol meanwhile are answered once the message is sent.
+    pub fn send(
+        &mut self,
+        pgn: u32,
+        destination: u8,
+        priority: u8,
+        data: &[u8],
+    ) -> Result<()> {
+        let source = match self.address {
+            Some(address) => address,
+            None => {
+                bail!(ErrorKind::J1939Protocol("no address claimed".to_owned()))
+            }
+        };
+        if data.len() > MAX_MESSAGE_LEN {
+            bail!(ErrorKind::J1939Protocol(format!(
+                "message of {} bytes is too long",
+                data.len()
+            )));
+        }
+        let destination = if is_pdu1(pgn) { destination } else { GLOBAL };
+        let id = Id {
+            priority,
+            pgn,
+            source,
+            destination,
+        };
+        if data.len() <= 8 {
+            return self.transmit(id, data);
+        }
+        self.sending = true;
+        let sent = if destination == GLOBAL {
+            self.send_bam(id, data)
+        } else {
+            self.send_rts_cts(id, data)
+        };
+        self.sending = false;
+        let answered = self.send_deferred();
+        sent.and(answered)
+    }
+
+    fn send_deferred(&mut self) -> Result<()> {
+        while let Some((pgn, destination, data)) = self.deferred.pop_front() {
+            self.send(pgn, destination, PRIORITY_CONTROL, &data)?;
+        }
+        Ok(())
+    }
+
+    /// Request `pgn` from `destination`, or from every node with `GLOBAL`.
+    /// The answers arrive as received messages.
+    pub fn request(&mut self, pgn: u32, destination: u8) -> Result<()> {
+        self.send(REQUEST, destination, PRIORITY_CONTROL, &pgn_bytes(pgn))
+    }
+
+    /// Wait for the next message for this node or for all nodes, up to
+    /// `timeout`. Returns `None` on timeout.
+    pub fn receive(&mut self, timeout: Duration) -> Result<Option<Message>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(message) = self.received.pop_front() {
+                return Ok(Some(message));
+            }
+            self.poll()?;
+            if self.received.is_empty() {
+                if Instant::now() >= deadline {
+                    return Ok(None);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+
+    /// Handle every frame waiting on the bus and drop timed out receptions.
+    pub fn poll(&mut self) -> Result<()> {
+        while let Some(frame) = self.bus.receive()? {
+            if frame.is_extended() && !frame.is_rtr() {
+                self.handle_frame(
+                    Id::from(frame.id()),
+                    &frame.data()[..frame.len() as usize],
+                )?;
+            }
+        }
+        let now = Instant::now();
+        let expired: Vec<(u8, u8)> = self
+            .receptions
+            .iter()
+            .filter(|(_, r)| now > r.deadline)
+            .map(|(&key, _)| key)
+            .collect();
+        for key in expired {
+            let reception =
+                self.receptions.remove(&key).expect("expired reception");
+            if !reception.is_broadcast() {
+                self.abort(
+                    reception.id.source,
+                    reception.id.pgn,
+                    ABORT_TIMEOUT,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep handling received frames for `duration`.
+    fn pause(&mut self, duration: Duration) -> Result<()> {
+        let end = Instant::now() + duration;
+        loop {
+            self.poll()?;
+            let now = Instant::now();
+            if now >= end {
+                return Ok(());
+            }
+            thread::sleep(POLL_INTERVAL.min(end - now));
+        }
+    }
+
+    fn transmit(&self, id: Id, data: &[u8]) -> Result<()> {
+        self.bus
+            .transmit(&CANFrame::new_extended(u32::from(id), data, false)?)
+    }
+
+    /// Send Address Claimed, or Cannot Claim Address without an address.
+    fn send_claim(&mut self) -> Result<()> {
+        self.claimed_at = Instant::now();
+        let id = Id {
+            priority: PRIORITY_CONTROL,
+            pgn: ADDRESS_CLAIMED,
+            source: self.address.unwrap_or(NULL),
+            destination: GLOBAL,
+        };
+        self.transmit(id, &u64::from(self.config.name).to_le_bytes())
+    }
+
+    fn transmit_control(
+        &self,
+        destination: u8,
+        control: u8,
+        fields: [u8; 4],
+        pgn: u32,
+    ) -> Result<()> {
+        let id = Id {
+            priority: PRIORITY_TRANSPORT,
+            pgn: TP_CM,
+            source: self.address.unwrap_or(NULL),
+            destination,
+        };
+        let mut data = vec![control];
+        data.extend_from_slice(&fields);
+        data.extend_from_slice(&pgn_bytes(pgn));
+        self.transmit(id, &data)
+    }
+
+    /// Send packet `sequence`, counting from 1, of `data`.
+    fn transmit_packet(
+        &self,
+        destination: u8,
+        sequence: u8,
+        data: &[u8],
+    ) -> Result<()> {
+        let id = Id {
+            priority: PRIORITY_TRANSPORT,
+            pgn: TP_DT,
+            source: self.address.unwrap_or(NULL),
+            destination,
+        };
+        let mut packet = vec![sequence];
+        packet.extend(data.chunks(7).nth(sequence as usize - 1).unwrap_or(&[]));
+        packet.resize(8, 0xFF);
+        self.transmit(id, &packet)
+    }
+
+    fn abort(&self, destination: u8, pgn: u32, reason: u8) -> Result<()> {
+        self.transmit_control(
+            destination,
+            ABORT,
+            [reason, 0xFF, 0xFF, 0xFF],
+            pgn,
+        )
+    }
+
+    fn send_bam(&mut self, id: Id, data: &[u8]) -> Result<()> {
+        let packets = data.len().div_ceil(7) as u8;
+        let size = (data.len() as u16).to_le_bytes();
+        self.transmit_control(
+            GLOBAL,
+            BAM,
+            [size[0], size[1], packets, 0xFF],
+            id.pgn,
+        )?;
+        for sequence in 1..=packets {
+            self.pause(self.config.bam_interval)?;
+            self.transmit_packet(GLOBAL, sequence, data)?;
+        }
+        Ok(())
+    }
+
+    fn send_rts_cts(&mut self, id: Id, data: &[u8]) -> Result<()> {
+        let packets = data.len().div_ceil(7) as u8;
+        let size = (data.len() as u16).to_le_bytes();
+        self.control = None;
+        self.transmit_control(
+            id.destination,
+            RTS,
+            [size[0], size[1], packets, 0xFF],
+            id.pgn,
+        )?;
+
+        let (mut timer, mut deadline) = ("T3", Instant::now() + self.config.t3);
+        loop {
+            self.poll()?;
+            let control = match self.control.take() {
+                Some((source, control))
+                    if source == id.destination
+                        && pgn_from_bytes(&control[5..]) == id.pgn =>
+                {
+                    control
+                }
+                Some(_) => continue,
+                None if Instant::now() > deadline => {
+                    self.abort(id.destination, id.pgn, ABORT_TIMEOUT)?;
+                    bail!(ErrorKind::J1939Timeout(timer.to_owned()));
+                }
+                None => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+            match control[0] {
+                CTS if control[1] == 0 => {
+                    timer = "T4";
+                    deadline = Instant::now() + self.config.t4;
+                }
+                CTS if control[2] == 0 => {}
+                CTS => {
+                    let last = (u16::from(control[2]) + u16::from(control[1])
+                        - 1)
+                    .min(u16::from(packets));
+                    for sequence in control[2]..=last as u8 {
+                        self.transmit_packet(id.destination, sequence, data)?;
+                    }
+                    timer = "T3";
+                    deadline = Instant::now() + self.config.t3;
+                }
+                END_OF_MESSAGE_ACK => return Ok(()),
+                ABORT => bail!(ErrorKind::J1939Protocol(format!(
+                    "transfer aborted with reason {}",
+                    control[1]
+                ))),
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_frame(&mut self, id: Id, data: &[u8]) -> Result<()> {
+        let for_us =
+            id.destination == GLOBAL || Some(id.destination) == self.address;
+        match id.pgn {
+            ADDRESS_CLAIMED if data.len() == 8 => {
+                let mut name = [0u8; 8];
+                name.copy_from_slice(data);
+                self.handle_claim(
+                    id.source,
+                    Name::from(u64::from_le_bytes(name)),
+                )
+            }
+            REQUEST if for_us && data.len() >= 3 => {
+                self.handle_request(id, pgn_from_bytes(data))
+            }
+            TP_CM if for_us && data.len() == 8 => self.handle_control(id, data),
+            TP_DT if for_us && !data.is_empty() => self.handle_packet(id, data),
+            ADDRESS_CLAIMED | REQUEST | TP_CM | TP_DT => Ok(()),
+            _ => {
+                if for_us {
+                    self.received.push_back(Message {
+                        id,
+                        data: data.to_vec(),
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn handle_claim(&mut self, source: u8, name: Name) -> Result<()> {
+        if source == NULL {
+            return Ok(());
+        }
+        self.claims.retain(|_, claimed| *claimed != name);
+        self.claims.insert(source, name);
+        if Some(source) != self.address {
+            return Ok(());
+        }
+        if u64::from(self.config.name) >= u64::from(name) {
+            // Lost the address: take a free one of the dynamic range 128 to 247 if allowed, else give up
+            self.address = None;
+            if self.config.name.arbitrary_address_capable {
+                self.address = (128..=247)
+                    .find(|address| !self.claims.contains_key(address));
+            }
+        }
+        self.send_claim()
+    }
+
+    fn handle_request(&mut self, id: Id, pgn: u32) -> Result<()> {
+        if pgn == ADDRESS_CLAIMED {
+            return self.send_claim();
+        }
+        let address = match self.address {
+            Some(address) => address,
+            None => return Ok(()),
+        };
+        if let Some(data) = self.responses.get(&pgn).cloned() {
+            let destination = if id.destination == GLOBAL {
+                GLOBAL
+            } else {
+                id.source
+            };
+            if self.sending && data.len() > 8 {
+                // A transfer cannot start while another is in progress
+                self.deferred.push_back((pgn, destination, data));
+                return Ok(());
+            }
+            return self.send(pgn, destination, PRIORITY_CONTROL, &data);
+        }
+        if id.destination == address {
+            let mut nack = vec![NACK, 0xFF, 0xFF, 0xFF, id.source];
+            nack.extend_from_slice(&pgn_bytes(pgn));
+            let id = Id {
+                priority: PRIORITY_CONTROL,
+                pgn: ACKNOWLEDGEMENT,
+                source: address,
+                destination: GLOBAL,
+            };
+            return self.transmit(id, &nack);
+        }
+        Ok(())
+    }
+
+    fn handle_control(&mut self, id: Id, data: &[u8]) -> Result<()> {
+        let key = (id.source, id.destination);
+        let pgn = pgn_from_bytes(&data[5..]);
+        match data[0] {
+            BAM | RTS => {
+                let size = usize::from(u16::from_le_bytes([data[1], data[2]]));
+                let packets = data[3];
+                let broadcast = data[0] == BAM;
+                let valid = size <= MAX_MESSAGE_LEN
+                    && size.div_ceil(7) == packets as usize;
+                if !valid || broadcast != (id.destination == GLOBAL) {
+                    return Ok(());
+                }
+                let (window_end, deadline) = if broadcast {
+                    (packets, Instant::now() + self.config.t1)
+                } else {
+                    let window =
+                        packets.min(data[4]).min(self.config.max_packets);
+                    self.transmit_control(
+                        id.source,
+                        CTS,
+                        [window, 1, 0xFF, 0xFF],
+                        pgn,
+                    )?;
+                    (window, Instant::now() + self.config.t2)
+                };
+                let reception = Reception {
+                    id: Id {
+                        priority: id.priority,
+                        pgn,
+                        source: id.source,
+                        destination: id.destination,
+                    },
+                    data: Vec::with_capacity(size),
+                    size,
+                    packets,
+                    next: 1,
+                    window_end,
+                    deadline,
+                };
+                self.receptions.insert(key, reception);
+            }
+            CTS | END_OF_MESSAGE_ACK => {
+                self.control = Some((id.source, data.to_vec()))
+            }
+            ABORT => {
+                self.receptions.remove(&key);
+                self.control = Some((id.source, data.to_vec()));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_packet(&mut self, id: Id, data: &[u8]) -> Result<()> {
+        let key = (id.source, id.destination);
+        let mut reception = match self.receptions.remove(&key) {
+            Some(reception) => reception,
+            None => return Ok(()),
+        };
+        if data[0] != reception.next {
+            if !reception.is_broadcast() {
+                self.abort(id.source, reception.id.pgn, ABORT_BAD_SEQUENCE)?;
+            }
+            return Ok(());
+        }
+        let take = (reception.size - reception.data.len()).min(data.len() - 1);
+        reception.data.extend_from_slice(&data[1..=take]);
+        if reception.data.len() == reception.size {
+            if !reception.is_broadcast() {
+                let size = (reception.size as u16).to_le_bytes();
+                let fields = [size[0], size[1], reception.packets, 0xFF];
+                self.transmit_control(
+                    id.source,
+                    END_OF_MESSAGE_ACK,
+                    fields,
+                    reception.id.pgn,
+                )?;
+            }
+            self.received.push_back(Message {
+                id: reception.id,
+                data: reception.data,
+            });
+            return Ok(());
+        }
+
+        if !reception.is_broadcast() && reception.next == reception.window_end {
+            let window = (reception.packets - reception.next)
+                .min(self.config.max_packets);
+            let fields = [window, reception.next + 1, 0xFF, 0xFF];
+            self.transmit_control(id.source, CTS, fields, reception.id.pgn)?;
+            reception.window_end += window;
+            reception.deadline = Instant::now() + self.config.t2;
+        } else {
+            reception.deadline = Instant::now() + self.config.t1;
+        }
+        reception.next += 1;
+        self.receptions.insert(key, reception);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::VirtualBus;
+    use j1939::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn name(identity_number: u32, arbitrary_address_capable: bool) -> Name {
+        Name {
+            arbitrary_address_capable,
+            identity_number,
+            ..Name::default()
+        }
+    }
+
+    #[test]
+    fn address_claim_contention() {
+        let bus = VirtualBus::new();
+        let mut winner =
+            Node::new(bus.connect(), Config::new(name(1, false), 0x80));
+        let mut loser =
+            Node::new(bus.connect(), Config::new(name(2, true), 0x80));
+        assert_eq!(winner.claim_address().unwrap(), Some(0x80));
+        let winner = thread::spawn(move || {
+            winner.receive(Duration::from_millis(500)).unwrap();
+            winner
+        });
+        // The loser moves to the first free dynamic address
+        assert_eq!(loser.claim_address().unwrap(), Some(0x81));
+        let winner = winner.join().unwrap();
+        assert_eq!(winner.address(), Some(0x80));
+        assert_eq!(winner.claims().get(&0x81), Some(&name(2, true)));
+        assert_eq!(loser.claims().get(&0x80), Some(&name(1, false)));
+    }
+
+    #[test]
+    fn transport_protocols_and_requests() {
+        let bus = VirtualBus::new();
+        let mut config = Config::new(name(1, false), 0x00);
+        config.max_packets = 4;
+        let mut engine = Node::new(bus.connect(), config);
+        let mut config = Config::new(name(2, false), 0xF9);
+        config.bam_interval = Duration::from_millis(1);
+        let mut tool = Node::new(bus.connect(), config);
+
+        let software: Vec<u8> = (0..40u8).collect();
+        engine.add_response(0xFEDA, software.clone());
+        let engine = thread::spawn(move || {
+            engine.claim_address().unwrap();
+            let mut received = Vec::new();
+            while let Some(message) =
+                engine.receive(Duration::from_secs(1)).unwrap()
+            {
+                received.push(message);
+            }
+            received
+        });
+        assert_eq!(tool.claim_address().unwrap(), Some(0xF9));
+        thread::sleep(Duration::from_millis(300));
+
+        // Answered with BAM, as the request is global
+        tool.request(0xFEDA, GLOBAL).unwrap();
+        let message = tool.receive(Duration::from_secs(2)).unwrap().unwrap();
+        assert_eq!(
+            message.id,
+            Id {
+                priority: 7,
+                pgn: 0xFEDA,
+                source: 0x00,
+                destination: GLOBAL
+            }
+        );
+        assert_eq!(message.data, software);
+        // Refused when the engine has no response
+        tool.request(0xFEE5, 0x00).unwrap();
+        let message = tool.receive(Duration::from_secs(1)).unwrap().unwrap();
+        assert_eq!(message.id.pgn, ACKNOWLEDGEMENT);
+        assert_eq!(
+            message.data,
+            vec![1, 0xFF, 0xFF, 0xFF, 0xF9, 0xE5, 0xFE, 0x00]
+        );
+
+        let long: Vec<u8> = (0..100u8).collect();
+        tool.send(0xEF00, 0x00, 6, &long).unwrap();
+        tool.send(0xFF12, 0x00, 6, &long[..20]).unwrap();
+        tool.send(0xFF13, GLOBAL, 6, &[1, 2, 3]).unwrap();
+        let received = engine.join().unwrap();
+        assert_eq!(received.len(), 3);
+        assert_eq!(
+            (received[0].id.pgn, received[0].id.destination),
+            (0xEF00, 0x00)
+        );
+        assert_eq!(received[0].data, long);
+        assert_eq!(
+            (received[1].id.pgn, received[1].id.destination),
+            (0xFF12, GLOBAL)
+        );
+        assert_eq!(received[1].data, &long[..20]);
+        assert_eq!(received[2].data, vec![1, 2, 3]);
+    }
+
+    /// Next frame of `bus` with the transport protocol.
+    fn transport_frame(bus: &VirtualBus, pgn: u32) -> Vec<u8> {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            match bus.receive().unwrap() {
+                Some(frame) if Id::from(frame.id()).pgn == pgn => {
+                    return frame.data()[..frame.len() as usize].to_vec()
+                }
+                Some(_) => {}
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        }
+        panic!("no transport frame")
+    }
+
+    #[test]
+    fn request_during_a_transfer() {
+        let bus = VirtualBus::new();
+        let tool = bus.connect();
+        let mut engine =
+            Node::new(bus.connect(), Config::new(name(1, false), 0x00));
+        engine.address = Some(0x00);
+        let software: Vec<u8> = (0..40u8).collect();
+        engine.add_response(0xFEDA, software.clone());
+        let long: Vec<u8> = (0..20u8).collect();
+        let sent = long.clone();
+        let engine = thread::spawn(move || engine.send(0xEF00, 0xF9, 6, &sent));
+
+        let control = |control: u8, fields: [u8; 4], pgn: u32| {
+            let id = Id {
+                priority: 7,
+                pgn: TP_CM,
+                source: 0xF9,
+                destination: 0x00,
+            };
+            let mut data = vec![control];
+            data.extend_from_slice(&fields);
+            data.extend_from_slice(&pgn_bytes(pgn));
+            tool.transmit(
+                &CANFrame::new_extended(u32::from(id), &data, false).unwrap(),
+            )
+            .unwrap();
+        };
+        assert_eq!(transport_frame(&tool, TP_CM)[0], RTS);
+        // The engine is asked for a long message before the transfer goes on
+        let id = Id {
+            priority: 6,
+            pgn: REQUEST,
+            source: 0xF9,
+            destination: 0x00,
+        };
+        tool.transmit(
+            &CANFrame::new_extended(u32::from(id), &pgn_bytes(0xFEDA), false)
+                .unwrap(),
+        )
+        .unwrap();
+        control(CTS, [3, 1, 0xFF, 0xFF], 0xEF00);
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.extend_from_slice(&transport_frame(&tool, TP_DT)[1..]);
+        }
+        assert_eq!(&received[..20], &long[..]);
+        control(END_OF_MESSAGE_ACK, [20, 0, 3, 0xFF], 0xEF00);
+
+        // Broadcast, as the PGN is PDU2
+        let bam = transport_frame(&tool, TP_CM);
+        assert_eq!((bam[0], pgn_from_bytes(&bam[5..])), (BAM, 0xFEDA));
+        let mut received = Vec::new();
+        for _ in 0..6 {
+            received.extend_from_slice(&transport_frame(&tool, TP_DT)[1..]);
+        }
+        assert_eq!(&received[..40], &software[..]);
+        engine.join().unwrap().unwrap();
+    }
+}
@@ -1,22 +1,49 @@
-//! PCAN Basic (No FD support)
+//! PCAN Basic, with CAN FD support
 //!
+//! Besides the channel API, the crate reads and writes trace files, loads
+//! DBC databases and speaks ISO-TP, UDS, OBD-II, J1939 and CANopen.
+#![recursion_limit = "256"]
 extern crate pcan_basic_sys;
 #[macro_use]
 extern crate error_chain;
+extern crate flate2;
+extern crate roxmltree;
+#[cfg(feature = "derive")]
+extern crate pcan_basic_derive;
+#[cfg(feature = "key-library")]
+extern crate libloading;
 
 use pcan_basic_sys as pcan;
+use std::ffi::CString;
+use std::fmt;
 pub mod types;
 pub mod errors;
+pub mod trace;
+pub mod dbc;
+pub mod message;
+pub mod bus;
+pub mod isotp;
+pub mod uds;
+pub mod obd;
+pub mod j1939;
+pub mod canopen;
+mod text;
+#[cfg(test)]
+mod testing;
 pub use errors::*;
 pub use types::*;
+pub use message::CanMessage;
+#[cfg(feature = "derive")]
+pub use pcan_basic_derive::CanMessage;
 
 /// A Handle for a PCAN Device.
 #[derive(Debug)]
 pub struct PCANDevice {
     fd: Handle,
+    fd_mode: bool, // Opened with `open_fd`
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct CANFrame(pub pcan::TPCANMsg);
 
 impl CANFrame {
@@ -40,8 +67,15 @@ impl CANFrame {
             },
         );
 
-        frame.0.DATA.clone_from_slice(data);
+        frame.0.DATA[..data.len()].clone_from_slice(data);
+
+        Ok(frame)
+    }
 
+    /// Create a frame with a 29-bit identifier.
+    pub fn new_extended(id: u32, data: &[u8], rtr: bool) -> Result<CANFrame> {
+        let mut frame = CANFrame::new(id, data, rtr)?;
+        frame.0.MSGTYPE |= u8::from(MessageType::Extended);
         Ok(frame)
     }
 
@@ -50,6 +84,16 @@ impl CANFrame {
         self.0.ID
     }
 
+    #[inline(always)]
+    pub fn is_extended(&self) -> bool {
+        self.0.MSGTYPE & u8::from(MessageType::Extended) != 0
+    }
+
+    #[inline(always)]
+    pub fn is_rtr(&self) -> bool {
+        self.0.MSGTYPE & u8::from(MessageType::Rtr) != 0
+    }
+
     #[inline(always)]
     pub fn set_id(&mut self, id: &u32) {
         self.0.ID = *id;
@@ -81,12 +125,158 @@ impl CANFrame {
     }
 }
 
+/// Convert a CAN FD DLC code (0-15) into a payload length in bytes.
+pub fn fd_dlc_to_len(dlc: u8) -> usize {
+    match dlc {
+        0..=8 => dlc as usize,
+        9 => 12,
+        10 => 16,
+        11 => 20,
+        12 => 24,
+        13 => 32,
+        14 => 48,
+        _ => 64,
+    }
+}
+
+/// Smallest CAN FD DLC code able to carry `len` bytes, if any.
+pub fn fd_len_to_dlc(len: usize) -> Option<u8> {
+    match len {
+        0..=8 => Some(len as u8),
+        9..=12 => Some(9),
+        13..=16 => Some(10),
+        17..=20 => Some(11),
+        21..=24 => Some(12),
+        25..=32 => Some(13),
+        33..=48 => Some(14),
+        49..=64 => Some(15),
+        _ => None,
+    }
+}
+
+/// A CAN FD frame carrying up to 64 data bytes.
+#[derive(Clone, Copy)]
+pub struct CANFrameFd(pub pcan::TPCANMsgFD);
+
+impl CANFrameFd {
+    /// Create an FD frame. Payloads that do not match a DLC length exactly
+    /// are padded with zeros up to the next valid length.
+    pub fn new(id: u32, data: &[u8], extended: bool, brs: bool) -> Result<CANFrameFd> {
+        let dlc = match fd_len_to_dlc(data.len()) {
+            Some(dlc) => dlc,
+            None => bail!(ErrorKind::CANFrame),
+        };
+
+        let mut msg_type = u8::from(MessageType::Fd);
+        if extended {
+            msg_type |= u8::from(MessageType::Extended);
+        }
+        if brs {
+            msg_type |= u8::from(MessageType::Brs);
+        }
+
+        let mut frame = CANFrameFd(
+            pcan::TPCANMsgFD {
+                ID: id,
+                MSGTYPE: msg_type,
+                DLC: dlc,
+                DATA: [0; 64],
+            },
+        );
+
+        frame.0.DATA[..data.len()].clone_from_slice(data);
+
+        Ok(frame)
+    }
+
+    #[inline(always)]
+    pub fn id(&self) -> u32 {
+        self.0.ID
+    }
+
+    #[inline(always)]
+    pub fn set_id(&mut self, id: &u32) {
+        self.0.ID = *id;
+    }
+
+    #[inline(always)]
+    pub fn is_extended(&self) -> bool {
+        self.0.MSGTYPE & u8::from(MessageType::Extended) != 0
+    }
+
+    /// Whether the frame uses the FD format, rather than being a classic
+    /// frame read from an FD channel.
+    #[inline(always)]
+    pub fn is_fd(&self) -> bool {
+        self.0.MSGTYPE & u8::from(MessageType::Fd) != 0
+    }
+
+    #[inline(always)]
+    pub fn is_brs(&self) -> bool {
+        self.0.MSGTYPE & u8::from(MessageType::Brs) != 0
+    }
+
+    #[inline(always)]
+    pub fn is_esi(&self) -> bool {
+        self.0.MSGTYPE & u8::from(MessageType::Esi) != 0
+    }
+
+    #[inline(always)]
+    pub fn dlc(&self) -> u8 {
+        self.0.DLC
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        fd_dlc_to_len(self.0.DLC)
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.0.DLC == 0
+    }
+
+    #[inline(always)]
+    pub fn data(&self) -> &[u8] {
+        &self.0.DATA[..fd_dlc_to_len(self.0.DLC)]
+    }
+
+    #[inline(always)]
+    pub fn mut_data(&mut self) -> &mut [u8] {
+        let len = fd_dlc_to_len(self.0.DLC);
+        &mut self.0.DATA[..len]
+    }
+}
+
+impl From<CANFrame> for CANFrameFd {
+    /// A classic frame in FD form, as `CAN_ReadFD` returns them.
+    fn from(frame: CANFrame) -> CANFrameFd {
+        let mut data = [0; 64];
+        data[..8].copy_from_slice(&frame.0.DATA);
+        CANFrameFd(pcan::TPCANMsgFD {
+            ID: frame.0.ID,
+            MSGTYPE: frame.0.MSGTYPE,
+            DLC: frame.0.LEN,
+            DATA: data,
+        })
+    }
+}
 
+impl fmt::Debug for CANFrameFd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CANFrameFd")
+            .field("id", &self.0.ID)
+            .field("msg_type", &self.0.MSGTYPE)
+            .field("dlc", &self.0.DLC)
+            .field("data", &self.data())
+            .finish()
+    }
+}
 
 impl PCANDevice {
     /// Open a PCAN Device
     pub fn open(handle: &Handle, baudrate: &BaudRate) -> Result<PCANDevice> {
-        let device = PCANDevice { fd: *handle };
+        let device = PCANDevice { fd: *handle, fd_mode: false };
         let handle_value = device.fd.to_value().expect("Invalid handle");
 
         let err = unsafe {
@@ -98,6 +288,32 @@ impl PCANDevice {
         Ok(device)
     }
 
+    /// Open a PCAN Device in CAN FD mode. `bitrate` is a PCAN-Basic FD
+    /// bitrate string, such as
+    /// `f_clock_mhz=80, nom_brp=2, nom_tseg1=63, nom_tseg2=16, nom_sjw=16, data_brp=2, data_tseg1=15, data_tseg2=4, data_sjw=4`.
+    /// Frames must then be read and written with the `_fd` methods.
+    pub fn open_fd(handle: &Handle, bitrate: &str) -> Result<PCANDevice> {
+        let bitrate = match CString::new(bitrate) {
+            Ok(bitrate) => bitrate,
+            Err(_) => bail!(ErrorKind::PCAN(PCANStatus::InvalidParameterValue)),
+        };
+        let device = PCANDevice { fd: *handle, fd_mode: true };
+        let handle_value = device.fd.to_value().expect("Invalid handle");
+
+        let err = unsafe {
+            pcan::CAN_InitializeFD(handle_value, bitrate.as_ptr() as *mut _)
+        };
+
+        pcan_fn_to_result(err)?;
+
+        Ok(device)
+    }
+
+    /// Whether the device was opened with `open_fd`.
+    pub fn is_fd(&self) -> bool {
+        self.fd_mode
+    }
+
     pub fn read_frame(&self) -> Result<(CANFrame, pcan::TPCANTimestamp)> {
         let mut frame: pcan::TPCANMsg = pcan::TPCANMsg {
             ID: 0,
@@ -136,6 +352,41 @@ impl PCANDevice {
         pcan_fn_to_result(err)
     }
 
+    /// Read a frame from a device opened in FD mode, with its timestamp in
+    /// microseconds. Classic frames come without the FD flag.
+    pub fn read_frame_fd(&self) -> Result<(CANFrameFd, pcan::TPCANTimestampFD)> {
+        let mut frame: pcan::TPCANMsgFD = pcan::TPCANMsgFD {
+            ID: 0,
+            MSGTYPE: 0,
+            DLC: 0,
+            DATA: [0; 64],
+        };
+        let mut timestamp: pcan::TPCANTimestampFD = 0;
+
+        let err = unsafe {
+            pcan::CAN_ReadFD(
+                self.fd.to_value().unwrap(),
+                &mut frame as *mut pcan::TPCANMsgFD,
+                &mut timestamp as *mut pcan::TPCANTimestampFD,
+            )
+        };
+
+        pcan_fn_to_result(err)?;
+
+        Ok((CANFrameFd(frame), timestamp))
+    }
+
+    pub fn write_frame_fd(&self, frame: &mut CANFrameFd) -> Result<()> {
+        let err = unsafe {
+            pcan::CAN_WriteFD(
+                self.fd.to_value().unwrap(),
+                &mut (frame.0) as *mut pcan::TPCANMsgFD,
+            )
+        };
+
+        pcan_fn_to_result(err)
+    }
+
     pub fn close(&mut self) -> Result<()> {
         let err = unsafe {
             pcan::CAN_Uninitialize(self.fd.to_value().unwrap() as u16)
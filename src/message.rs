@@ -0,0 +1,42 @@
+//! Messages with a fixed payload layout, such as those implemented with
+//! `#[derive(CanMessage)]` from the `pcan-basic-derive` crate (re-exported
+//! here with the `derive` feature).
+use errors::*;
+use CANFrame;
+
+pub trait CanMessage: Sized {
+    const ID: u32;
+    const EXTENDED: bool;
+    const SIZE: usize; // Payload length, at most 8
+
+    /// Pack the fields into a payload of `SIZE` bytes.
+    fn encode_payload(&self, data: &mut [u8]) -> Result<()>;
+
+    /// Unpack the fields from a payload of at least `SIZE` bytes.
+    fn decode_payload(data: &[u8]) -> Self;
+
+    /// Build a frame ready for `PCANDevice::write_frame`.
+    fn encode(&self) -> Result<CANFrame> {
+        let mut data = [0u8; 8];
+        let data = &mut data[..Self::SIZE.min(8)];
+        self.encode_payload(data)?;
+        if Self::EXTENDED {
+            CANFrame::new_extended(Self::ID, data, false)
+        } else {
+            CANFrame::new(Self::ID, data, false)
+        }
+    }
+
+    /// Unpack a received frame, checking its identifier and length.
+    fn decode(frame: &CANFrame) -> Result<Self> {
+        let len = frame.len() as usize;
+        if frame.id() != Self::ID
+            || frame.is_extended() != Self::EXTENDED
+            || frame.is_rtr()
+            || len < Self::SIZE
+        {
+            bail!(ErrorKind::UnexpectedFrame(frame.id()));
+        }
+        Ok(Self::decode_payload(&frame.data()[..len]))
+    }
+}
@@ -0,0 +1,841 @@
+//! OBD-II (SAE J1979) over ISO-TP (ISO 15765-4).
+//!
+//! Requests are sent once on the functional identifier and every ECU
+//! answering before the timeout is heard, so results are keyed by the
+//! identifier each ECU responds on. Each responding ECU gets its own ISO-TP
+//! channel, which sends the flow control of multi-frame answers, such as
+//! the VIN, on the ECU's physical request identifier. Negative responses
+//! leave an ECU out of the results, except "response pending", which
+//! extends the wait.
+use bus::CanBus;
+use errors::*;
+use isotp::{Config, IsoTp};
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::{Duration, Instant};
+use uds::{Dtc, NEGATIVE_RESPONSE, POSITIVE_RESPONSE_OFFSET};
+use CANFrameFd;
+
+pub const FUNCTIONAL_ID: u32 = 0x7DF;
+pub const FUNCTIONAL_ID_EXTENDED: u32 = 0x18DB_33F1;
+
+pub const CURRENT_DATA: u8 = 0x01;
+pub const FREEZE_FRAME: u8 = 0x02;
+pub const STORED_DTCS: u8 = 0x03;
+pub const CLEAR_DTCS: u8 = 0x04;
+pub const PENDING_DTCS: u8 = 0x07;
+pub const VEHICLE_INFORMATION: u8 = 0x09;
+pub const PERMANENT_DTCS: u8 = 0x0A;
+
+const VIN: u8 = 0x02;
+const CALIBRATION_IDS: u8 = 0x04;
+const MONITOR_STATUS: u8 = 0x01;
+
+/// Wait for responses: the 50 ms P2 of J1979 with a margin.
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Wait after a "response pending".
+const PENDING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Fill byte of requests and flow controls.
+const PADDING: u8 = 0x55;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A mode 01 or 02 parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PidValue {
+    pub pid: u8,
+    pub name: &'static str, // Empty for PIDs without a definition
+    pub value: Option<f64>,
+    pub unit: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// Standard mode 01 PID with a single value.
+pub struct PidDefinition {
+    pub pid: u8,
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub length: usize,
+    decode: fn(&[u8]) -> f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonitorStatus {
+    pub mil: bool, // Malfunction indicator lamp on
+    pub dtc_count: u8,
+}
+
+fn a(d: &[u8]) -> f64 {
+    f64::from(d[0])
+}
+
+fn ab(d: &[u8]) -> f64 {
+    f64::from(u16::from_be_bytes([d[0], d[1]]))
+}
+
+fn percent(d: &[u8]) -> f64 {
+    a(d) * 100.0 / 255.0
+}
+
+fn temperature(d: &[u8]) -> f64 {
+    a(d) - 40.0
+}
+
+fn fuel_trim(d: &[u8]) -> f64 {
+    (a(d) - 128.0) * 100.0 / 128.0
+}
+
+static PIDS: [PidDefinition; 44] = [
+    PidDefinition {
+        pid: 0x04,
+        name: "Calculated engine load",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x05,
+        name: "Engine coolant temperature",
+        unit: "°C",
+        length: 1,
+        decode: temperature,
+    },
+    PidDefinition {
+        pid: 0x06,
+        name: "Short term fuel trim bank 1",
+        unit: "%",
+        length: 1,
+        decode: fuel_trim,
+    },
+    PidDefinition {
+        pid: 0x07,
+        name: "Long term fuel trim bank 1",
+        unit: "%",
+        length: 1,
+        decode: fuel_trim,
+    },
+    PidDefinition {
+        pid: 0x08,
+        name: "Short term fuel trim bank 2",
+        unit: "%",
+        length: 1,
+        decode: fuel_trim,
+    },
+    PidDefinition {
+        pid: 0x09,
+        name: "Long term fuel trim bank 2",
+        unit: "%",
+        length: 1,
+        decode: fuel_trim,
+    },
+    PidDefinition {
+        pid: 0x0A,
+        name: "Fuel pressure",
+        unit: "kPa",
+        length: 1,
+        decode: |d| a(d) * 3.0,
+    },
+    PidDefinition {
+        pid: 0x0B,
+        name: "Intake manifold absolute pressure",
+        unit: "kPa",
+        length: 1,
+        decode: a,
+    },
+    PidDefinition {
+        pid: 0x0C,
+        name: "Engine speed",
+        unit: "rpm",
+        length: 2,
+        decode: |d| ab(d) / 4.0,
+    },
+    PidDefinition {
+        pid: 0x0D,
+        name: "Vehicle speed",
+        unit: "km/h",
+        length: 1,
+        decode: a,
+    },
+    PidDefinition {
+        pid: 0x0E,
+        name: "Timing advance",
+        unit: "°",
+        length: 1,
+        decode: |d| a(d) / 2.0 - 64.0,
+    },
+    PidDefinition {
+        pid: 0x0F,
+        name: "Intake air temperature",
+        unit: "°C",
+        length: 1,
+        decode: temperature,
+    },
+    PidDefinition {
+        pid: 0x10,
+        name: "Mass air flow rate",
+        unit: "g/s",
+        length: 2,
+        decode: |d| ab(d) / 100.0,
+    },
+    PidDefinition {
+        pid: 0x11,
+        name: "Throttle position",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x1F,
+        name: "Run time since engine start",
+        unit: "s",
+        length: 2,
+        decode: ab,
+    },
+    PidDefinition {
+        pid: 0x21,
+        name: "Distance traveled with MIL on",
+        unit: "km",
+        length: 2,
+        decode: ab,
+    },
+    PidDefinition {
+        pid: 0x22,
+        name: "Fuel rail pressure",
+        unit: "kPa",
+        length: 2,
+        decode: |d| ab(d) * 0.079,
+    },
+    PidDefinition {
+        pid: 0x23,
+        name: "Fuel rail gauge pressure",
+        unit: "kPa",
+        length: 2,
+        decode: |d| ab(d) * 10.0,
+    },
+    PidDefinition {
+        pid: 0x2C,
+        name: "Commanded EGR",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x2D,
+        name: "EGR error",
+        unit: "%",
+        length: 1,
+        decode: fuel_trim,
+    },
+    PidDefinition {
+        pid: 0x2E,
+        name: "Commanded evaporative purge",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x2F,
+        name: "Fuel tank level input",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x30,
+        name: "Warm-ups since codes cleared",
+        unit: "",
+        length: 1,
+        decode: a,
+    },
+    PidDefinition {
+        pid: 0x31,
+        name: "Distance traveled since codes cleared",
+        unit: "km",
+        length: 2,
+        decode: ab,
+    },
+    PidDefinition {
+        pid: 0x33,
+        name: "Absolute barometric pressure",
+        unit: "kPa",
+        length: 1,
+        decode: a,
+    },
+    PidDefinition {
+        pid: 0x42,
+        name: "Control module voltage",
+        unit: "V",
+        length: 2,
+        decode: |d| ab(d) / 1000.0,
+    },
+    PidDefinition {
+        pid: 0x43,
+        name: "Absolute load value",
+        unit: "%",
+        length: 2,
+        decode: |d| ab(d) * 100.0 / 255.0,
+    },
+    PidDefinition {
+        pid: 0x44,
+        name: "Commanded air-fuel equivalence ratio",
+        unit: "",
+        length: 2,
+        decode: |d| ab(d) / 32768.0,
+    },
+    PidDefinition {
+        pid: 0x45,
+        name: "Relative throttle position",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x46,
+        name: "Ambient air temperature",
+        unit: "°C",
+        length: 1,
+        decode: temperature,
+    },
+    PidDefinition {
+        pid: 0x47,
+        name: "Absolute throttle position B",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x48,
+        name: "Absolute throttle position C",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x49,
+        name: "Accelerator pedal position D",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x4A,
+        name: "Accelerator pedal position E",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x4B,
+        name: "Accelerator pedal position F",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x4C,
+        name: "Commanded throttle actuator",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x4D,
+        name: "Time run with MIL on",
+        unit: "min",
+        length: 2,
+        decode: ab,
+    },
+    PidDefinition {
+        pid: 0x4E,
+        name: "Time since trouble codes cleared",
+        unit: "min",
+        length: 2,
+        decode: ab,
+    },
+    PidDefinition {
+        pid: 0x51,
+        name: "Fuel type",
+        unit: "",
+        length: 1,
+        decode: a,
+    },
+    PidDefinition {
+        pid: 0x52,
+        name: "Ethanol fuel",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x5A,
+        name: "Relative accelerator pedal position",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x5B,
+        name: "Hybrid battery pack remaining life",
+        unit: "%",
+        length: 1,
+        decode: percent,
+    },
+    PidDefinition {
+        pid: 0x5C,
+        name: "Engine oil temperature",
+        unit: "°C",
+        length: 1,
+        decode: temperature,
+    },
+    PidDefinition {
+        pid: 0x5E,
+        name: "Engine fuel rate",
+        unit: "L/h",
+        length: 2,
+        decode: |d| ab(d) / 20.0,
+    },
+];
+
+pub fn pid_definition(pid: u8) -> Option<&'static PidDefinition> {
+    PIDS.iter().find(|d| d.pid == pid)
+}
+
+/// Decode the data of a mode 01 or 02 PID. PIDs without a definition, or
+/// with too little data, keep only their raw data.
+pub fn decode_pid(pid: u8, data: &[u8]) -> PidValue {
+    let definition = pid_definition(pid);
+    PidValue {
+        pid,
+        name: definition.map_or("", |d| d.name),
+        value: definition
+            .filter(|d| data.len() >= d.length)
+            .map(|d| (d.decode)(data)),
+        unit: definition.map_or("", |d| d.unit),
+        data: data.to_vec(),
+    }
+}
+
+/// Request identifier of the ECU answering on `response_id`.
+fn physical_request_id(response_id: u32, extended: bool) -> Option<u32> {
+    if extended {
+        if response_id & 0x1FFF_FF00 == 0x18DA_F100 {
+            Some(0x18DA_00F1 | (response_id & 0xFF) << 8)
+        } else {
+            None
+        }
+    } else if (0x7E8..=0x7EF).contains(&response_id) {
+        Some(response_id - 8)
+    } else {
+        None
+    }
+}
+
+/// Text of a vehicle information record, without padding.
+fn ascii(data: &[u8]) -> String {
+    data.iter()
+        .filter(|&&b| b != 0)
+        .map(|&b| b as char)
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+pub struct Obd<B: CanBus> {
+    bus: B,
+    extended: bool,
+    timeout: Duration,
+}
+
+impl<B: CanBus> Obd<B> {
+    /// A client using 29-bit identifiers if `extended` is set and 11-bit
+    /// ones otherwise.
+    pub fn new(bus: B, extended: bool) -> Obd<B> {
+        Obd {
+            bus,
+            extended,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn into_inner(self) -> B {
+        self.bus
+    }
+
+    /// Time to wait for responses after a request.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn config(&self, tx_id: u32, rx_id: u32) -> Config {
+        let mut config = Config::new(tx_id, rx_id);
+        config.extended_id = self.extended;
+        config.padding = Some(PADDING);
+        config
+    }
+
+    /// Send a functional request and collect the positive responses, after
+    /// the service identifier, by response identifier.
+    pub fn request(&self, data: &[u8]) -> Result<BTreeMap<u32, Vec<u8>>> {
+        if data.is_empty() || data.len() > 7 {
+            bail!(ErrorKind::InvalidRequest(
+                "functional requests must fit a single frame".to_owned()
+            ));
+        }
+        let functional_id = if self.extended {
+            FUNCTIONAL_ID_EXTENDED
+        } else {
+            FUNCTIONAL_ID
+        };
+        IsoTp::new(&self.bus, self.config(functional_id, functional_id))
+            .send(data)?;
+
+        let service = data[0];
+        let mut channels = BTreeMap::new();
+        let mut responses = BTreeMap::new();
+        let mut deadline = Instant::now() + self.timeout;
+        loop {
+            while let Some(frame) = self.bus.receive()? {
+                let id = frame.id();
+                let tx_id = match physical_request_id(id, self.extended) {
+                    Some(tx_id) if frame.is_extended() == self.extended => {
+                        tx_id
+                    }
+                    _ => continue,
+                };
+                let channel = channels.entry(id).or_insert_with(|| {
+                    IsoTp::new(&self.bus, self.config(tx_id, id))
+                });
+                channel.feed(&CANFrameFd::from(frame))?;
+                while let Some(message) = channel.take_received() {
+                    match message.first() {
+                        Some(&NEGATIVE_RESPONSE)
+                            if message[1..].starts_with(&[service, 0x78]) =>
+                        {
+                            deadline =
+                                deadline.max(Instant::now() + PENDING_TIMEOUT);
+                        }
+                        Some(&sid)
+                            if sid
+                                == service
+                                    .wrapping_add(POSITIVE_RESPONSE_OFFSET) =>
+                        {
+                            responses.insert(id, message[1..].to_vec());
+                        }
+                        _ => {}
+                    }
+                }
+                // Let answers that are still arriving complete
+                if channel.is_receiving() {
+                    deadline =
+                        deadline.max(Instant::now() + channel.config().n_cr);
+                }
+            }
+            if Instant::now() >= deadline {
+                return Ok(responses);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Responses to a request whose answers echo `echo` first, with the
+    /// echo removed.
+    fn request_echoed(
+        &self,
+        data: &[u8],
+        echo: &[u8],
+    ) -> Result<BTreeMap<u32, Vec<u8>>> {
+        let responses = self.request(data)?;
+        Ok(responses
+            .into_iter()
+            .filter(|(_, response)| response.starts_with(echo))
+            .map(|(ecu, response)| (ecu, response[echo.len()..].to_vec()))
+            .collect())
+    }
+
+    /// PIDs each ECU supports in mode 01, 02 (freeze frame 0) or 09.
+    pub fn supported_pids(&self, mode: u8) -> Result<BTreeMap<u32, Vec<u8>>> {
+        let mut supported: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+        let mut base = 0u8;
+        loop {
+            let mut request = vec![mode, base];
+            if mode == FREEZE_FRAME {
+                request.push(0);
+            }
+            let echo = &request[1..];
+            let mut more = false;
+            for (ecu, bits) in self.request_echoed(&request, echo)? {
+                if bits.len() < 4 {
+                    continue;
+                }
+                let bits =
+                    u32::from_be_bytes([bits[0], bits[1], bits[2], bits[3]]);
+                let pids = supported.entry(ecu).or_default();
+                pids.extend(
+                    (0..32u16)
+                        .filter(|i| bits >> (31 - i) & 1 != 0)
+                        .map(|i| u16::from(base) + i + 1)
+                        .filter(|&pid| pid <= 0xFF)
+                        .map(|pid| pid as u8),
+                );
+                more |= bits & 1 != 0;
+            }
+            if !more || base == 0xE0 {
+                return Ok(supported);
+            }
+            base += 0x20;
+        }
+    }
+
+    /// Current value of a mode 01 PID.
+    pub fn current_data(&self, pid: u8) -> Result<BTreeMap<u32, PidValue>> {
+        let responses = self.request_echoed(&[CURRENT_DATA, pid], &[pid])?;
+        Ok(responses
+            .into_iter()
+            .map(|(ecu, data)| (ecu, decode_pid(pid, &data)))
+            .collect())
+    }
+
+    /// Value of a PID when freeze frame `frame` was stored.
+    pub fn freeze_frame(
+        &self,
+        pid: u8,
+        frame: u8,
+    ) -> Result<BTreeMap<u32, PidValue>> {
+        let responses =
+            self.request_echoed(&[FREEZE_FRAME, pid, frame], &[pid, frame])?;
+        Ok(responses
+            .into_iter()
+            .map(|(ecu, data)| (ecu, decode_pid(pid, &data)))
+            .collect())
+    }
+
+    /// MIL state and number of stored DTCs, from PID 01.
+    pub fn monitor_status(&self) -> Result<BTreeMap<u32, MonitorStatus>> {
+        let responses = self.request_echoed(
+            &[CURRENT_DATA, MONITOR_STATUS],
+            &[MONITOR_STATUS],
+        )?;
+        Ok(responses
+            .into_iter()
+            .filter_map(|(ecu, data)| {
+                let a = *data.first()?;
+                Some((
+                    ecu,
+                    MonitorStatus {
+                        mil: a & 0x80 != 0,
+                        dtc_count: a & 0x7F,
+                    },
+                ))
+            })
+            .collect())
+    }
+
+    fn dtcs(&self, mode: u8) -> Result<BTreeMap<u32, Vec<Dtc>>> {
+        let responses = self.request(&[mode])?;
+        Ok(responses
+            .into_iter()
+            .map(|(ecu, data)| {
+                // A count precedes the 2 byte codes on CAN
+                let codes = data
+                    .get(1..)
+                    .unwrap_or(&[])
+                    .chunks(2)
+                    .filter(|c| c.len() == 2 && c != &[0, 0]);
+                (
+                    ecu,
+                    codes
+                        .map(|c| {
+                            Dtc(u32::from(c[0]) << 16 | u32::from(c[1]) << 8)
+                        })
+                        .collect(),
+                )
+            })
+            .collect())
+    }
+
+    /// Confirmed DTCs (mode 03).
+    pub fn stored_dtcs(&self) -> Result<BTreeMap<u32, Vec<Dtc>>> {
+        self.dtcs(STORED_DTCS)
+    }
+
+    /// DTCs detected during the current or last driving cycle (mode 07).
+    pub fn pending_dtcs(&self) -> Result<BTreeMap<u32, Vec<Dtc>>> {
+        self.dtcs(PENDING_DTCS)
+    }
+
+    /// DTCs that clearing cannot erase (mode 0A).
+    pub fn permanent_dtcs(&self) -> Result<BTreeMap<u32, Vec<Dtc>>> {
+        self.dtcs(PERMANENT_DTCS)
+    }
+
+    /// Clear DTCs and freeze frames (mode 04). Returns the ECUs that
+    /// confirmed.
+    pub fn clear_dtcs(&self) -> Result<Vec<u32>> {
+        Ok(self.request(&[CLEAR_DTCS])?.into_keys().collect())
+    }
+
+    pub fn vin(&self) -> Result<BTreeMap<u32, String>> {
+        let responses =
+            self.request_echoed(&[VEHICLE_INFORMATION, VIN], &[VIN])?;
+        Ok(responses
+            .into_iter()
+            .map(|(ecu, data)| (ecu, ascii(data.get(1..).unwrap_or(&[]))))
+            .collect())
+    }
+
+    /// Calibration identifiers, 16 characters each.
+    pub fn calibration_ids(&self) -> Result<BTreeMap<u32, Vec<String>>> {
+        let responses = self.request_echoed(
+            &[VEHICLE_INFORMATION, CALIBRATION_IDS],
+            &[CALIBRATION_IDS],
+        )?;
+        Ok(responses
+            .into_iter()
+            .map(|(ecu, data)| {
+                (
+                    ecu,
+                    data.get(1..)
+                        .unwrap_or(&[])
+                        .chunks(16)
+                        .map(ascii)
+                        .collect(),
+                )
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::VirtualBus;
+    use isotp::{Config, IsoTp};
+    use std::thread;
+    use std::time::Duration;
+
+    /// An ECU hearing functional requests and answering with `respond`.
+    fn ecu(
+        bus: &VirtualBus,
+        response_id: u32,
+        respond: fn(&[u8]) -> Option<Vec<u8>>,
+    ) -> thread::JoinHandle<()> {
+        let mut functional =
+            IsoTp::new(bus.connect(), Config::new(response_id, FUNCTIONAL_ID));
+        let mut physical = IsoTp::new(
+            bus.connect(),
+            Config::new(response_id, response_id - 8),
+        );
+        thread::spawn(move || {
+            while let Some(request) =
+                functional.receive(Duration::from_millis(500)).unwrap()
+            {
+                if let Some(response) = respond(&request) {
+                    physical.send(&response).unwrap();
+                }
+            }
+        })
+    }
+
+    fn engine(request: &[u8]) -> Option<Vec<u8>> {
+        match request {
+            [0x01, 0x00] => Some(vec![0x41, 0x00, 0x18, 0x18, 0x00, 0x01]),
+            [0x01, 0x20] => Some(vec![0x41, 0x20, 0x00, 0x00, 0x40, 0x00]),
+            [0x01, 0x01] => Some(vec![0x41, 0x01, 0x82, 0x07, 0xE5, 0x00]),
+            [0x01, 0x0C] => Some(vec![0x41, 0x0C, 0x1A, 0xF8]),
+            [0x02, 0x05, 0x00] => Some(vec![0x42, 0x05, 0x00, 0x7B]),
+            [0x03] => Some(vec![0x43, 0x02, 0x01, 0x43, 0xC1, 0x00]),
+            [0x09, 0x02] => {
+                let mut response = vec![0x49, 0x02, 0x01];
+                response.extend_from_slice(b"1HGCM82633A004352");
+                Some(response)
+            }
+            _ => Some(vec![0x7F, request[0], 0x12]),
+        }
+    }
+
+    fn transmission(request: &[u8]) -> Option<Vec<u8>> {
+        match request {
+            [0x01, 0x00] => Some(vec![0x41, 0x00, 0x00, 0x18, 0x00, 0x00]),
+            [0x01, 0x0C] => Some(vec![0x41, 0x0C, 0x1B, 0x00]),
+            [0x03] => Some(vec![0x43, 0x00]),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn two_ecus() {
+        let bus = VirtualBus::new();
+        let ecus =
+            vec![ecu(&bus, 0x7E8, engine), ecu(&bus, 0x7E9, transmission)];
+        let obd = Obd::new(bus, false);
+
+        let supported = obd.supported_pids(CURRENT_DATA).unwrap();
+        assert_eq!(supported[&0x7E8], vec![0x04, 0x05, 0x0C, 0x0D, 0x20, 0x32]);
+        assert_eq!(supported[&0x7E9], vec![0x0C, 0x0D]);
+
+        let speed = obd.current_data(0x0C).unwrap();
+        assert_eq!(
+            (speed[&0x7E8].value, speed[&0x7E8].unit),
+            (Some(1726.0), "rpm")
+        );
+        assert_eq!(speed[&0x7E9].value, Some(1728.0));
+        assert_eq!(
+            obd.freeze_frame(0x05, 0).unwrap()[&0x7E8].value,
+            Some(83.0)
+        );
+        assert_eq!(
+            obd.monitor_status().unwrap()[&0x7E8],
+            MonitorStatus {
+                mil: true,
+                dtc_count: 2
+            }
+        );
+
+        let dtcs = obd.stored_dtcs().unwrap();
+        let codes: Vec<String> =
+            dtcs[&0x7E8].iter().map(|d| d.j2012()).collect();
+        assert_eq!(codes, vec!["P0143", "U0100"]);
+        assert!(dtcs[&0x7E9].is_empty());
+
+        // Multi-frame answer from one ECU, negative response from none
+        let vin = obd.vin().unwrap();
+        assert_eq!(vin.len(), 1);
+        assert_eq!(vin[&0x7E8], "1HGCM82633A004352");
+        drop(obd);
+        for ecu in ecus {
+            ecu.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn all_pids_supported() {
+        let bus = VirtualBus::new();
+        let ecu = ecu(&bus, 0x7E8, |request| match *request {
+            [0x01, base] => Some(vec![0x41, base, 0xFF, 0xFF, 0xFF, 0xFF]),
+            _ => None,
+        });
+        let obd = Obd::new(bus, false);
+
+        // The last range has bit 0 set too, yet there is no PID 0x100
+        let supported = obd.supported_pids(CURRENT_DATA).unwrap();
+        assert_eq!(supported[&0x7E8], (0x01..=0xFF).collect::<Vec<u8>>());
+        drop(obd);
+        ecu.join().unwrap();
+    }
+
+    #[test]
+    fn identifiers_and_pids() {
+        assert_eq!(physical_request_id(0x7E8, false), Some(0x7E0));
+        assert_eq!(physical_request_id(0x7F0, false), None);
+        assert_eq!(physical_request_id(0x18DA_F110, true), Some(0x18DA_10F1));
+        assert_eq!(decode_pid(0x05, &[0x5A]).value, Some(50.0));
+        assert_eq!(decode_pid(0x0C, &[0x1A]).value, None);
+        assert_eq!(decode_pid(0xA6, &[1, 2, 3, 4]).name, "");
+    }
+}
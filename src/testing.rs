@@ -0,0 +1,144 @@
+//! Scripted peers shared by the tests of the protocol layers.
+use bus::{CanBus, VirtualBus};
+use canopen::{AccessType, DataType, Object};
+use isotp::{Config, IsoTp};
+use std::thread;
+use std::time::{Duration, Instant};
+use uds::Client;
+use CANFrame;
+
+/// Time a peer waits for each request before failing the test.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The messages a scripted peer exchanges.
+pub trait Transport: Send + 'static {
+    /// Next request, panicking if none comes in time.
+    fn receive_request(&mut self) -> Vec<u8>;
+
+    fn send_response(&mut self, response: &[u8]);
+}
+
+/// Answer each expected request with its responses, in a thread.
+pub fn peer<T: Transport>(
+    mut transport: T,
+    script: Vec<(&[u8], Vec<&[u8]>)>,
+) -> thread::JoinHandle<()> {
+    let script: Vec<(Vec<u8>, Vec<Vec<u8>>)> = script
+        .into_iter()
+        .map(|(request, responses)| {
+            (
+                request.to_vec(),
+                responses.into_iter().map(<[u8]>::to_vec).collect(),
+            )
+        })
+        .collect();
+    thread::spawn(move || {
+        for (request, responses) in script {
+            assert_eq!(transport.receive_request(), request);
+            for response in responses {
+                transport.send_response(&response);
+            }
+        }
+    })
+}
+
+/// SDO server of a CANopen node.
+pub struct SdoServer {
+    pub bus: VirtualBus,
+    pub node: u8,
+}
+
+impl Transport for SdoServer {
+    fn receive_request(&mut self) -> Vec<u8> {
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        loop {
+            match self.bus.receive().unwrap() {
+                Some(frame) if frame.id() == 0x600 + u32::from(self.node) => {
+                    return frame.data()[..frame.len() as usize].to_vec();
+                }
+                Some(_) => {}
+                None if Instant::now() < deadline => {
+                    thread::sleep(Duration::from_micros(100))
+                }
+                None => panic!("no SDO request for node {}", self.node),
+            }
+        }
+    }
+
+    fn send_response(&mut self, response: &[u8]) {
+        let id = 0x580 + u32::from(self.node);
+        self.bus
+            .transmit(&CANFrame::new(id, response, false).unwrap())
+            .unwrap();
+    }
+}
+
+/// Answer each expected SDO request of `node` with its responses.
+pub fn sdo_server(
+    bus: VirtualBus,
+    node: u8,
+    script: Vec<([u8; 8], Vec<[u8; 8]>)>,
+) -> thread::JoinHandle<()> {
+    let script = script
+        .iter()
+        .map(|(request, responses)| {
+            (
+                &request[..],
+                responses.iter().map(|response| &response[..]).collect(),
+            )
+        })
+        .collect();
+    peer(SdoServer { bus, node }, script)
+}
+
+/// PDO mappable dictionary object without a parent or values.
+pub fn object(
+    index: u16,
+    subindex: u8,
+    name: &str,
+    data_type: DataType,
+    access: AccessType,
+) -> Object {
+    Object {
+        index,
+        subindex,
+        name: name.to_owned(),
+        parent: None,
+        data_type,
+        access,
+        default_value: String::new(),
+        parameter_value: String::new(),
+        pdo_mappable: true,
+    }
+}
+
+/// UDS server on 0x7E8/0x7E0. A response pending is followed by a pause
+/// longer than P2.
+pub struct UdsEcu(pub IsoTp<VirtualBus>);
+
+impl UdsEcu {
+    pub fn new(bus: VirtualBus) -> UdsEcu {
+        UdsEcu(IsoTp::new(bus, Config::new(0x7E8, 0x7E0)))
+    }
+}
+
+impl Transport for UdsEcu {
+    fn receive_request(&mut self) -> Vec<u8> {
+        self.0
+            .receive(REQUEST_TIMEOUT)
+            .unwrap()
+            .expect("no UDS request")
+    }
+
+    fn send_response(&mut self, response: &[u8]) {
+        self.0.send(response).unwrap();
+        if response.len() == 3 && response[0] == 0x7F && response[2] == 0x78 {
+            thread::sleep(Duration::from_millis(150));
+        }
+    }
+}
+
+/// UDS client of a `UdsEcu`.
+pub fn uds_client(bus: VirtualBus) -> Client<VirtualBus> {
+    Client::new(IsoTp::new(bus, Config::new(0x7E0, 0x7E8)))
+}
@@ -0,0 +1,39 @@
+//! Decoding of the text files written by Windows tools.
+
+/// Characters of Windows-1252 bytes 0x80 to 0x9F. The five unassigned
+/// bytes keep their C1 control code points.
+const WINDOWS_1252_C1: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}',
+    '\u{2020}', '\u{2021}', '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}',
+    '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}', '\u{90}', '\u{2018}', '\u{2019}',
+    '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}', '\u{2DC}',
+    '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}',
+    '\u{178}',
+];
+
+/// Decode a file as UTF-8, or as Windows-1252 if it is not valid UTF-8.
+pub fn decode(bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => e
+            .into_bytes()
+            .iter()
+            .map(|&b| match b {
+                0x80..=0x9F => WINDOWS_1252_C1[(b - 0x80) as usize],
+                _ => b as char,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_and_windows_1252() {
+        assert_eq!(decode("Grüße €".as_bytes().to_vec()), "Grüße €");
+        let bytes = vec![0x80, b' ', 0x93, b'x', 0x94, b' ', 0xFC, 0x81];
+        assert_eq!(decode(bytes), "\u{20AC} \u{201C}x\u{201D} \u{FC}\u{81}");
+    }
+}
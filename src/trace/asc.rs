@@ -0,0 +1,637 @@
+//! Vector ASC (ASCII logging) trace files.
+//!
+//! Classic CAN lines (`Rx`/`Tx`, `d`/`r`, `x` suffix for extended ids),
+//! `CANFD` lines and `ErrorFrame` lines are understood. Other events found
+//! in CANalyzer logs (statistics, triggers, system variables...) are
+//! skipped by the reader.
+use errors::*;
+use std::io::{BufRead, Write};
+use trace::{
+    new_fd_frame, new_frame, timestamp_from_micros, Direction, Event, Record,
+};
+
+/// Number base used for identifiers and data bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Base {
+    Hex,
+    Dec,
+}
+
+/// Reference point of the timestamps in the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timestamps {
+    Absolute, // Measured from the start of the measurement
+    Relative, // Measured from the previous event
+}
+
+/// Settings found at the top of an ASC file.
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub date: Option<String>,
+    pub base: Base,
+    pub timestamps: Timestamps,
+}
+
+impl Default for Header {
+    fn default() -> Header {
+        Header {
+            date: None,
+            base: Base::Hex,
+            timestamps: Timestamps::Absolute,
+        }
+    }
+}
+
+/// Streaming ASC reader yielding one `Record` per frame line.
+pub struct Reader<R> {
+    inner: R,
+    header: Header,
+    pending: Option<String>,
+    line_number: usize,
+    last_micros: u64,
+}
+
+impl<R: BufRead> Reader<R> {
+    /// Create a reader, consuming the header lines of the file.
+    pub fn new(inner: R) -> Result<Reader<R>> {
+        let mut reader = Reader {
+            inner,
+            header: Header::default(),
+            pending: None,
+            line_number: 0,
+            last_micros: 0,
+        };
+
+        while let Some(line) = reader.next_line()? {
+            let trimmed = line.trim().to_owned();
+            if let Some(date) = trimmed.strip_prefix("date ") {
+                reader.header.date = Some(date.trim().to_owned());
+            } else if trimmed.starts_with("base ") {
+                reader.parse_base_line(&trimmed)?;
+            } else if !(trimmed.is_empty()
+                || trimmed.starts_with("//")
+                || trimmed.ends_with("internal events logged")
+                || trimmed.starts_with("Begin Triggerblock"))
+            {
+                reader.pending = Some(line);
+                break;
+            }
+        }
+
+        Ok(reader)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    fn next_line(&mut self) -> Result<Option<String>> {
+        if let Some(line) = self.pending.take() {
+            return Ok(Some(line));
+        }
+
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line_number += 1;
+        Ok(Some(line))
+    }
+
+    fn malformed(&self, reason: &str) -> Error {
+        ErrorKind::TraceFormat(format!("line {}: {}", self.line_number, reason))
+            .into()
+    }
+
+    fn parse_base_line(&mut self, line: &str) -> Result<()> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        self.header.base = match tokens.get(1) {
+            Some(&"hex") => Base::Hex,
+            Some(&"dec") => Base::Dec,
+            _ => return Err(self.malformed("unknown number base")),
+        };
+        self.header.timestamps = match tokens.get(3) {
+            Some(&"absolute") | None => Timestamps::Absolute,
+            Some(&"relative") => Timestamps::Relative,
+            _ => return Err(self.malformed("unknown timestamp mode")),
+        };
+        Ok(())
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<Option<Record>> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() < 2 {
+            return Ok(None);
+        }
+
+        let micros = match parse_seconds(tokens[0]) {
+            Some(micros) => micros,
+            None => return Ok(None),
+        };
+        let micros = match self.header.timestamps {
+            Timestamps::Absolute => micros,
+            Timestamps::Relative => self.last_micros + micros,
+        };
+        self.last_micros = micros;
+
+        let parsed = if tokens[1] == "CANFD" {
+            self.parse_fd(&tokens[2..])?
+        } else if let Ok(channel) = tokens[1].parse::<u8>() {
+            if tokens.get(2) == Some(&"ErrorFrame") {
+                Some((channel, Direction::Rx, Event::ErrorFrame))
+            } else {
+                self.parse_classic(&tokens[2..])?
+                    .map(|(direction, event)| (channel, direction, event))
+            }
+        } else {
+            None
+        };
+
+        Ok(parsed.map(|(channel, direction, event)| Record {
+            timestamp: timestamp_from_micros(micros),
+            channel,
+            direction,
+            event,
+        }))
+    }
+
+    /// Direction of a frame line. Transmit requests (`TxRq`) and unknown
+    /// tokens give `None`, and the line is skipped.
+    fn parse_direction(token: Option<&&str>) -> Option<Direction> {
+        match token {
+            Some(&"Rx") | Some(&"RX") => Some(Direction::Rx),
+            Some(&"Tx") | Some(&"TX") => Some(Direction::Tx),
+            _ => None,
+        }
+    }
+
+    fn parse_bytes(&self, tokens: &[&str], count: usize) -> Result<Vec<u8>> {
+        if tokens.len() < count {
+            return Err(self.malformed("missing data bytes"));
+        }
+        tokens[..count]
+            .iter()
+            .map(|token| match parse_number(token, self.header.base) {
+                Some(byte) if byte <= 0xFF => Ok(byte as u8),
+                _ => Err(self.malformed("invalid data byte")),
+            })
+            .collect()
+    }
+
+    /// `<id>[x] <Rx|Tx> d <dlc> <data...>` or `<id>[x] <Rx|Tx> r [dlc]`
+    fn parse_classic(
+        &self,
+        tokens: &[&str],
+    ) -> Result<Option<(Direction, Event)>> {
+        let (id, extended) =
+            match tokens.first().and_then(|t| parse_id(t, self.header.base)) {
+                Some(id) => id,
+                None => return Ok(None),
+            };
+        let direction = match Self::parse_direction(tokens.get(1)) {
+            Some(direction) => direction,
+            None => return Ok(None),
+        };
+        let dlc = match tokens.get(3) {
+            Some(token) => match parse_number(token, self.header.base) {
+                Some(dlc) if dlc <= 0xF => dlc as usize,
+                _ => return Err(self.malformed("invalid DLC")),
+            },
+            None => 0,
+        };
+
+        let frame = match tokens.get(2) {
+            Some(&"d") => {
+                let data = self
+                    .parse_bytes(tokens.get(4..).unwrap_or(&[]), dlc.min(8))?;
+                new_frame(id, &data, extended, false)?
+            }
+            Some(&"r") => {
+                let mut frame = new_frame(id, &[], extended, true)?;
+                frame.0.LEN = dlc.min(8) as u8;
+                frame
+            }
+            _ => return Err(self.malformed("expected d or r")),
+        };
+
+        Ok(Some((direction, Event::Frame(frame))))
+    }
+
+    /// `<ch> <Rx|Tx> <id>[x] [name] <brs> <esi> <dlc> <len> <data...> <duration> <bits> <flags> ...`
+    /// or `<ch> <Rx|Tx> ErrorFrame ...`
+    fn parse_fd(
+        &self,
+        tokens: &[&str],
+    ) -> Result<Option<(u8, Direction, Event)>> {
+        let channel = match tokens.first().and_then(|t| t.parse::<u8>().ok()) {
+            Some(channel) => channel,
+            None => return Err(self.malformed("invalid channel")),
+        };
+        let direction = match Self::parse_direction(tokens.get(1)) {
+            Some(direction) => direction,
+            None => return Ok(None),
+        };
+        if tokens.get(2) == Some(&"ErrorFrame") {
+            return Ok(Some((channel, direction, Event::ErrorFrame)));
+        }
+        let (id, extended) =
+            match tokens.get(2).and_then(|t| parse_id(t, self.header.base)) {
+                Some(id) => id,
+                None => return Err(self.malformed("invalid identifier")),
+            };
+
+        // The symbolic message name is optional
+        let mut i = 3;
+        if tokens
+            .get(i)
+            .is_some_and(|t| !t.chars().all(|c| c.is_ascii_digit()))
+        {
+            i += 1;
+        }
+        if tokens.len() < i + 4 {
+            return Err(self.malformed("truncated CANFD line"));
+        }
+
+        let brs = tokens[i] == "1";
+        let esi = tokens[i + 1] == "1";
+        let dlc = match u8::from_str_radix(tokens[i + 2], 16) {
+            Ok(dlc) if dlc <= 0xF => dlc,
+            _ => return Err(self.malformed("invalid DLC")),
+        };
+        let len = match tokens[i + 3].parse::<usize>() {
+            Ok(len) if len <= 64 => len,
+            _ => return Err(self.malformed("invalid data length")),
+        };
+        let data = self.parse_bytes(&tokens[i + 4..], len)?;
+
+        // Bit 12 of the flags (EDL) tells FD frames from classic frames logged
+        // in the CANFD format
+        let flags = tokens
+            .get(i + 4 + len + 2)
+            .and_then(|t| u32::from_str_radix(t, 16).ok());
+        let fd = flags.is_none_or(|flags| flags & 0x1000 != 0);
+
+        let event = if fd {
+            Event::FdFrame(new_fd_frame(id, &data, extended, brs, esi)?)
+        } else if len == 0 && dlc > 0 {
+            let mut frame = new_frame(id, &[], extended, true)?;
+            frame.0.LEN = dlc.min(8);
+            Event::Frame(frame)
+        } else {
+            Event::Frame(new_frame(id, &data, extended, false)?)
+        };
+
+        Ok(Some((channel, direction, event)))
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        loop {
+            let line = match self.next_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+
+            match self.parse_line(&line) {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Streaming ASC writer.
+pub struct Writer<W: Write> {
+    inner: W,
+    header: Header,
+    last_micros: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a writer and emit the file header.
+    pub fn new(mut inner: W, header: Header) -> Result<Writer<W>> {
+        if let Some(ref date) = header.date {
+            writeln!(inner, "date {}", date)?;
+        }
+        writeln!(
+            inner,
+            "base {}  timestamps {}",
+            match header.base {
+                Base::Hex => "hex",
+                Base::Dec => "dec",
+            },
+            match header.timestamps {
+                Timestamps::Absolute => "absolute",
+                Timestamps::Relative => "relative",
+            }
+        )?;
+        writeln!(inner, "no internal events logged")?;
+        match header.date {
+            Some(ref date) => writeln!(inner, "Begin Triggerblock {}", date)?,
+            None => writeln!(inner, "Begin Triggerblock")?,
+        }
+
+        Ok(Writer {
+            inner,
+            header,
+            last_micros: 0,
+        })
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<()> {
+        let micros = record.micros();
+        let stamp = match self.header.timestamps {
+            Timestamps::Absolute => micros,
+            Timestamps::Relative => micros.saturating_sub(self.last_micros),
+        };
+        self.last_micros = micros;
+        let stamp = format!("{}.{:06}", stamp / 1_000_000, stamp % 1_000_000);
+        let direction = match record.direction {
+            Direction::Rx => "Rx",
+            Direction::Tx => "Tx",
+        };
+
+        match record.event {
+            Event::Frame(ref frame) => {
+                let id = self.format_id(frame.id(), frame.is_extended());
+                if frame.is_rtr() {
+                    writeln!(
+                        self.inner,
+                        "{:>11} {:<2} {:<15} {:<4} r {:x}",
+                        stamp,
+                        record.channel,
+                        id,
+                        direction,
+                        frame.len()
+                    )?;
+                } else {
+                    let data = &frame.data()[..frame.len().min(8) as usize];
+                    writeln!(
+                        self.inner,
+                        "{:>11} {:<2} {:<15} {:<4} d {:x} {}",
+                        stamp,
+                        record.channel,
+                        id,
+                        direction,
+                        data.len(),
+                        self.format_bytes(data)
+                    )?;
+                }
+            }
+            Event::FdFrame(ref frame) => {
+                // EDL, BRS and ESI; frames read from an FD channel may be
+                // classic frames
+                let mut flags = 0;
+                if frame.is_fd() {
+                    flags |= 0x1000;
+                }
+                if frame.is_brs() {
+                    flags |= 0x2000;
+                }
+                if frame.is_esi() {
+                    flags |= 0x4000;
+                }
+                writeln!(
+                    self.inner,
+                    "{:>11} CANFD {:>3} {:<4} {:>8} {} {} {:x} {:>2} {} {:>8} {:>4} {:>8x} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                    stamp,
+                    record.channel,
+                    direction,
+                    self.format_id(frame.id(), frame.is_extended()),
+                    frame.is_brs() as u8,
+                    frame.is_esi() as u8,
+                    frame.dlc(),
+                    frame.len(),
+                    self.format_bytes(frame.data()),
+                    0,
+                    0,
+                    flags,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0
+                )?;
+            }
+            Event::ErrorFrame => {
+                writeln!(
+                    self.inner,
+                    "{:>11} {:<2} ErrorFrame",
+                    stamp, record.channel
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Close the trigger block and hand back the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        writeln!(self.inner, "End TriggerBlock")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn format_id(&self, id: u32, extended: bool) -> String {
+        let suffix = if extended { "x" } else { "" };
+        match self.header.base {
+            Base::Hex => format!("{:X}{}", id, suffix),
+            Base::Dec => format!("{}{}", id, suffix),
+        }
+    }
+
+    fn format_bytes(&self, data: &[u8]) -> String {
+        let bytes: Vec<String> = data
+            .iter()
+            .map(|byte| match self.header.base {
+                Base::Hex => format!("{:02X}", byte),
+                Base::Dec => format!("{}", byte),
+            })
+            .collect();
+        bytes.join(" ")
+    }
+}
+
+fn parse_number(token: &str, base: Base) -> Option<u32> {
+    match base {
+        Base::Hex => u32::from_str_radix(token, 16).ok(),
+        Base::Dec => token.parse().ok(),
+    }
+}
+
+/// Parse an identifier with an optional `x` suffix marking extended frames.
+fn parse_id(token: &str, base: Base) -> Option<(u32, bool)> {
+    if token.ends_with('x') || token.ends_with('X') {
+        parse_number(&token[..token.len() - 1], base).map(|id| (id, true))
+    } else {
+        parse_number(token, base).map(|id| (id, false))
+    }
+}
+
+/// Parse `<seconds>.<fraction>` into microseconds without going through floats.
+fn parse_seconds(token: &str) -> Option<u64> {
+    let mut parts = token.splitn(2, '.');
+    let seconds = parts.next()?.parse::<u64>().ok()?;
+    let fraction = parts.next().unwrap_or("0");
+    if fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits: String =
+        fraction.chars().chain("000000".chars()).take(6).collect();
+    Some(seconds * 1_000_000 + digits.parse::<u64>().ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use trace::timestamp_from_micros;
+    use {CANFrame, CANFrameFd};
+
+    const SAMPLE: &str = "date Mon Oct 19 10:15:02.123 am 2026
+base hex  timestamps absolute
+internal events logged
+// version 9.0.0
+Begin Triggerblock Mon Oct 19 10:15:02.123 am 2026
+   0.000000 Start of measurement
+   0.001234 1  123             Rx   d 8 01 02 03 04 05 06 07 08  Length = 228000 BitCount = 118 ID = 291
+   0.002000 2  1ABCDEF0x       Tx   d 2 AA BB
+   0.003000 1  7FF             Rx   r 4
+   0.004000 1  ErrorFrame
+   0.005000 1  Statistic: D 0 R 0 XD 0 XR 0 E 0 O 0 B 0.00%
+   0.006000 CANFD   1 Rx        456  EngineData                     1 0 9 12 00 01 02 03 04 05 06 07 08 09 0A 0B   130000  223    03000 1b4ff 0 0 0 0
+End TriggerBlock
+";
+
+    #[test]
+    fn read_sample() {
+        let mut reader = Reader::new(Cursor::new(SAMPLE)).unwrap();
+        assert_eq!(reader.header().base, Base::Hex);
+        assert!(reader.header().date.is_some());
+        let records: Vec<Record> =
+            reader.by_ref().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 5);
+
+        assert_eq!(records[0].micros(), 1234);
+        match records[1].event {
+            Event::Frame(ref frame) => {
+                assert!(frame.is_extended());
+                assert_eq!(frame.id(), 0x1ABCDEF0);
+                assert_eq!(&frame.data()[..2], &[0xAA, 0xBB]);
+            }
+            _ => panic!("expected a classic frame"),
+        }
+        assert_eq!(records[1].direction, Direction::Tx);
+        assert_eq!(records[1].channel, 2);
+        match records[2].event {
+            Event::Frame(ref frame) => {
+                assert!(frame.is_rtr() && frame.len() == 4)
+            }
+            _ => panic!("expected a remote frame"),
+        }
+        match records[3].event {
+            Event::ErrorFrame => {}
+            _ => panic!("expected an error frame"),
+        }
+        match records[4].event {
+            Event::FdFrame(ref frame) => {
+                assert!(frame.is_brs());
+                assert_eq!(frame.id(), 0x456);
+                assert_eq!(frame.len(), 12);
+                assert_eq!(frame.data()[11], 0x0B);
+            }
+            _ => panic!("expected an FD frame"),
+        }
+    }
+
+    #[test]
+    fn fd_error_frames_and_unknown_directions() {
+        let text = "base hex  timestamps absolute
+Begin Triggerblock
+   0.001000 CANFD   1 Rx ErrorFrame                                    \
+0 0 0 0 0 0 0 0 0
+   0.002000 1  123             Foo  d 1 00
+   0.003000 1  124             Rx   d 1 01
+End TriggerBlock
+";
+        let records: Vec<Record> = Reader::new(Cursor::new(text))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        match records[0].event {
+            Event::ErrorFrame => assert_eq!(records[0].channel, 1),
+            _ => panic!("expected an error frame"),
+        }
+        assert_eq!(records[1].id(), Some(0x124));
+    }
+
+    #[test]
+    fn classic_frames_from_fd_channels() {
+        let mut frame = CANFrameFd::new(0x300, &[1, 2], false, false).unwrap();
+        frame.0.MSGTYPE = 0;
+        let record = Record {
+            timestamp: timestamp_from_micros(0),
+            channel: 1,
+            direction: Direction::Rx,
+            event: Event::FdFrame(frame),
+        };
+        let mut writer = Writer::new(Vec::new(), Header::default()).unwrap();
+        writer.write_record(&record).unwrap();
+        let text = writer.finish().unwrap();
+
+        let mut reader = Reader::new(Cursor::new(text)).unwrap();
+        match reader.next().unwrap().unwrap().event {
+            Event::Frame(ref frame) => {
+                assert_eq!(&frame.data()[..2], &[1, 2])
+            }
+            _ => panic!("expected a classic frame"),
+        }
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let header = Header {
+            date: None,
+            base: Base::Dec,
+            timestamps: Timestamps::Relative,
+        };
+        let frames = [
+            Event::Frame(CANFrame::new(0x100, &[1, 2, 3], false).unwrap()),
+            Event::Frame(
+                CANFrame::new_extended(0x18DAF110, &[0xFF], false).unwrap(),
+            ),
+            Event::FdFrame(
+                CANFrameFd::new(0x200, &[7; 20], false, true).unwrap(),
+            ),
+            Event::ErrorFrame,
+        ];
+
+        let mut writer = Writer::new(Vec::new(), header).unwrap();
+        for (i, event) in frames.iter().enumerate() {
+            let record = Record {
+                timestamp: timestamp_from_micros(1_000_000 + i as u64 * 1500),
+                channel: 1,
+                direction: Direction::Tx,
+                event: *event,
+            };
+            writer.write_record(&record).unwrap();
+        }
+        let text = writer.finish().unwrap();
+
+        let reader = Reader::new(Cursor::new(text)).unwrap();
+        let records: Vec<Record> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[3].micros(), 1_004_500);
+        assert_eq!(records[1].id(), Some(0x18DAF110));
+        match records[2].event {
+            Event::FdFrame(ref frame) => assert_eq!(frame.data(), &[7; 20][..]),
+            _ => panic!("expected an FD frame"),
+        }
+    }
+}
@@ -0,0 +1,794 @@
+//! Vector BLF (binary logging format) trace files.
+//!
+//! A BLF file is a `LOGG` file header followed by `LOBJ` objects, most of
+//! them `LOG_CONTAINER`s holding zlib-compressed runs of further objects.
+//! Objects may straddle container boundaries, so the reader keeps a small
+//! window of uncompressed data and never holds more than one container in
+//! memory. `CAN_MESSAGE`, `CAN_MESSAGE2`, `CAN_FD_MESSAGE`,
+//! `CAN_FD_MESSAGE_64` and `CAN_ERROR_EXT` objects are turned into records,
+//! anything else is skipped.
+use errors::*;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use trace::bytes::{
+    le_u16, le_u32, le_u64, put_u16, put_u32, put_u64, read_full, skip,
+};
+use trace::{
+    new_fd_frame, new_frame, timestamp_from_micros, Direction, Event, Record,
+};
+
+const FILE_SIGNATURE: &[u8] = b"LOGG";
+const OBJECT_SIGNATURE: &[u8] = b"LOBJ";
+const FILE_HEADER_SIZE: usize = 144;
+const OBJECT_HEADER_BASE_SIZE: usize = 16;
+const OBJECT_HEADER_V1_SIZE: usize = 32;
+const CONTAINER_HEADER_SIZE: usize = 16;
+const MAX_CONTAINER_SIZE: usize = 128 * 1024;
+/// Largest object or uncompressed container read into memory. Longer top
+/// level objects other than containers are skipped.
+const MAX_OBJECT_SIZE: usize = 0x10_0000;
+
+const CAN_MESSAGE: u32 = 1;
+const LOG_CONTAINER: u32 = 10;
+const CAN_ERROR_EXT: u32 = 73;
+const CAN_MESSAGE2: u32 = 86;
+const CAN_FD_MESSAGE: u32 = 100;
+const CAN_FD_MESSAGE_64: u32 = 101;
+
+const NO_COMPRESSION: u16 = 0;
+const ZLIB_DEFLATE: u16 = 2;
+
+const TIME_TEN_MICS: u32 = 1;
+const TIME_ONE_NANS: u32 = 2;
+
+const CAN_MSG_EXT: u32 = 0x8000_0000;
+const DIR_TX: u8 = 0x01;
+const REMOTE_FLAG: u8 = 0x80;
+const FD_EDL: u8 = 0x01;
+const FD_BRS: u8 = 0x02;
+const FD_ESI: u8 = 0x04;
+const FD64_REMOTE: u32 = 0x0010;
+const FD64_EDL: u32 = 0x1000;
+const FD64_BRS: u32 = 0x2000;
+const FD64_ESI: u32 = 0x4000;
+
+/// Information from the BLF file header.
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub start_time: Option<SystemTime>,
+    pub stop_time: Option<SystemTime>,
+    pub object_count: u32,
+    pub file_size: u64,
+    pub uncompressed_size: u64,
+}
+
+/// Streaming BLF reader.
+pub struct Reader<R> {
+    inner: R,
+    header: Header,
+    buffer: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> Reader<R> {
+    /// Create a reader, consuming the file header.
+    pub fn new(mut inner: R) -> Result<Reader<R>> {
+        let mut fixed = [0u8; 72];
+        inner.read_exact(&mut fixed)?;
+        if &fixed[0..4] != FILE_SIGNATURE {
+            bail!(ErrorKind::TraceFormat("missing LOGG signature".to_owned()));
+        }
+
+        let header_size = le_u32(&fixed, 4) as usize;
+        if header_size < fixed.len() {
+            bail!(ErrorKind::TraceFormat("file header too short".to_owned()));
+        }
+        skip(&mut inner, (header_size - fixed.len()) as u64)?;
+
+        let header = Header {
+            start_time: read_systemtime(&fixed[40..56]),
+            stop_time: read_systemtime(&fixed[56..72]),
+            object_count: le_u32(&fixed, 32),
+            file_size: le_u64(&fixed, 16),
+            uncompressed_size: le_u64(&fixed, 24),
+        };
+
+        Ok(Reader {
+            inner,
+            header,
+            buffer: Vec::new(),
+            pos: 0,
+            eof: false,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Pull the next top level object from the file into the buffer.
+    /// Returns `false` once the file is exhausted.
+    fn fill(&mut self) -> Result<bool> {
+        let mut base = [0u8; OBJECT_HEADER_BASE_SIZE];
+        match read_full(&mut self.inner, &mut base)? {
+            0 => return Ok(false),
+            n if n < base.len() => return Ok(false),
+            _ => {}
+        }
+        if &base[0..4] != OBJECT_SIGNATURE {
+            bail!(ErrorKind::TraceFormat("missing LOBJ signature".to_owned()));
+        }
+
+        let object_size = le_u32(&base, 8) as usize;
+        let object_type = le_u32(&base, 12);
+        if object_size < base.len() {
+            bail!(ErrorKind::TraceFormat(
+                "object smaller than its header".to_owned()
+            ));
+        }
+        // Objects are followed by `size % 4` bytes of padding
+        let padding = (object_size % 4) as u64;
+        if object_size > MAX_OBJECT_SIZE {
+            if object_type == LOG_CONTAINER {
+                bail!(ErrorKind::TraceFormat(format!(
+                    "object of {} bytes is too long",
+                    object_size
+                )));
+            }
+            skip(&mut self.inner, (object_size - base.len()) as u64)?;
+            skip(&mut self.inner, padding).ok();
+            return Ok(true);
+        }
+        let mut body = vec![0u8; object_size - base.len()];
+        self.inner.read_exact(&mut body)?;
+        skip(&mut self.inner, padding).ok();
+
+        self.buffer.drain(..self.pos);
+        self.pos = 0;
+
+        if object_type == LOG_CONTAINER {
+            if body.len() < CONTAINER_HEADER_SIZE {
+                bail!(ErrorKind::TraceFormat("truncated container".to_owned()));
+            }
+            let method = le_u16(&body, 0);
+            let uncompressed = le_u32(&body, 8) as usize;
+            let data = &body[CONTAINER_HEADER_SIZE..];
+            match method {
+                NO_COMPRESSION => self.buffer.extend_from_slice(data),
+                ZLIB_DEFLATE => {
+                    if uncompressed > MAX_OBJECT_SIZE {
+                        bail!(ErrorKind::TraceFormat(format!(
+                            "container of {} uncompressed bytes is too long",
+                            uncompressed
+                        )));
+                    }
+                    let read = ZlibDecoder::new(data)
+                        .take(uncompressed as u64)
+                        .read_to_end(&mut self.buffer)?;
+                    if read != uncompressed {
+                        bail!(ErrorKind::TraceFormat(
+                            "truncated compressed container".to_owned()
+                        ));
+                    }
+                }
+                _ => {
+                    bail!(ErrorKind::TraceFormat(format!(
+                        "unknown container compression {}",
+                        method
+                    ),))
+                }
+            }
+        } else {
+            self.buffer.extend_from_slice(&base);
+            self.buffer.extend_from_slice(&body);
+        }
+
+        Ok(true)
+    }
+
+    /// Parse the next object held in the buffer, if it is complete.
+    fn next_object(&mut self) -> Result<Option<Option<Record>>> {
+        // Skip padding and any garbage up to the next object signature
+        match find(&self.buffer[self.pos..], OBJECT_SIGNATURE) {
+            Some(offset) => self.pos += offset,
+            None => {
+                self.pos = self
+                    .buffer
+                    .len()
+                    .saturating_sub(OBJECT_SIGNATURE.len() - 1)
+                    .max(self.pos);
+                return Ok(None);
+            }
+        }
+
+        let available = &self.buffer[self.pos..];
+        if available.len() < OBJECT_HEADER_BASE_SIZE {
+            return Ok(None);
+        }
+        let header_size = le_u16(available, 4) as usize;
+        let header_version = le_u16(available, 6);
+        let object_size = le_u32(available, 8) as usize;
+        let object_type = le_u32(available, 12);
+        if object_size < header_size
+            || header_size < OBJECT_HEADER_V1_SIZE
+            || object_size > MAX_OBJECT_SIZE
+        {
+            // Not a sensible object, resynchronise on the next signature
+            self.pos += OBJECT_SIGNATURE.len();
+            return Ok(Some(None));
+        }
+        if available.len() < object_size {
+            return Ok(None);
+        }
+
+        // Version 1 and 2 headers both keep the timestamp at the same offset
+        let flags = le_u32(available, 16);
+        let timestamp = if header_version <= 2 {
+            le_u64(available, 24)
+        } else {
+            0
+        };
+        let micros = match flags {
+            TIME_TEN_MICS => timestamp * 10,
+            TIME_ONE_NANS => timestamp / 1000,
+            _ => timestamp,
+        };
+        let body = &available[header_size..object_size];
+        let parsed = parse_object(object_type, body)?;
+        self.pos += object_size;
+
+        Ok(Some(parsed.map(|(channel, direction, event)| Record {
+            timestamp: timestamp_from_micros(micros),
+            channel,
+            direction,
+            event,
+        })))
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        loop {
+            match self.next_object() {
+                Ok(Some(Some(record))) => return Some(Ok(record)),
+                Ok(Some(None)) => continue,
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+
+            if self.eof {
+                return None;
+            }
+            match self.fill() {
+                Ok(true) => {}
+                Ok(false) => self.eof = true,
+                Err(e) => {
+                    self.eof = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+fn parse_object(
+    object_type: u32,
+    body: &[u8],
+) -> Result<Option<(u8, Direction, Event)>> {
+    let truncated = || -> Error {
+        ErrorKind::TraceFormat(format!(
+            "truncated object of type {}",
+            object_type
+        ))
+        .into()
+    };
+
+    match object_type {
+        CAN_MESSAGE | CAN_MESSAGE2 => {
+            if body.len() < 16 {
+                return Err(truncated());
+            }
+            let channel = le_u16(body, 0) as u8;
+            let flags = body[2];
+            let dlc = body[3];
+            let raw_id = le_u32(body, 4);
+            let extended = raw_id & CAN_MSG_EXT != 0;
+            let id = raw_id & !CAN_MSG_EXT;
+
+            let frame = if flags & REMOTE_FLAG != 0 {
+                let mut frame = new_frame(id, &[], extended, true)?;
+                frame.0.LEN = dlc.min(8);
+                frame
+            } else {
+                new_frame(
+                    id,
+                    &body[8..8 + dlc.min(8) as usize],
+                    extended,
+                    false,
+                )?
+            };
+
+            Ok(Some((
+                channel,
+                direction(flags & DIR_TX != 0),
+                Event::Frame(frame),
+            )))
+        }
+        CAN_FD_MESSAGE => {
+            if body.len() < 84 {
+                return Err(truncated());
+            }
+            let channel = le_u16(body, 0) as u8;
+            let flags = body[2];
+            let dlc = body[3];
+            let raw_id = le_u32(body, 4);
+            let fd_flags = body[13];
+            let valid_bytes = (body[14] as usize).min(64);
+            let extended = raw_id & CAN_MSG_EXT != 0;
+            let id = raw_id & !CAN_MSG_EXT;
+            let data = &body[20..20 + valid_bytes];
+
+            let event = if fd_flags & FD_EDL != 0 {
+                Event::FdFrame(new_fd_frame(
+                    id,
+                    data,
+                    extended,
+                    fd_flags & FD_BRS != 0,
+                    fd_flags & FD_ESI != 0,
+                )?)
+            } else if flags & REMOTE_FLAG != 0 {
+                let mut frame = new_frame(id, &[], extended, true)?;
+                frame.0.LEN = dlc.min(8);
+                Event::Frame(frame)
+            } else {
+                Event::Frame(new_frame(
+                    id,
+                    &data[..data.len().min(8)],
+                    extended,
+                    false,
+                )?)
+            };
+
+            Ok(Some((channel, direction(flags & DIR_TX != 0), event)))
+        }
+        CAN_FD_MESSAGE_64 => {
+            if body.len() < 40 {
+                return Err(truncated());
+            }
+            let channel = body[0];
+            let dlc = body[1];
+            let valid_bytes = (body[2] as usize).min(64);
+            let raw_id = le_u32(body, 4);
+            let flags = le_u32(body, 12);
+            let tx = body[34] != 0;
+            let extended = raw_id & CAN_MSG_EXT != 0;
+            let id = raw_id & !CAN_MSG_EXT;
+            if body.len() < 40 + valid_bytes {
+                return Err(truncated());
+            }
+            let data = &body[40..40 + valid_bytes];
+
+            let event = if flags & FD64_EDL != 0 {
+                Event::FdFrame(new_fd_frame(
+                    id,
+                    data,
+                    extended,
+                    flags & FD64_BRS != 0,
+                    flags & FD64_ESI != 0,
+                )?)
+            } else if flags & FD64_REMOTE != 0 {
+                let mut frame = new_frame(id, &[], extended, true)?;
+                frame.0.LEN = dlc.min(8);
+                Event::Frame(frame)
+            } else {
+                Event::Frame(new_frame(
+                    id,
+                    &data[..data.len().min(8)],
+                    extended,
+                    false,
+                )?)
+            };
+
+            Ok(Some((channel, direction(tx), event)))
+        }
+        CAN_ERROR_EXT => {
+            if body.len() < 2 {
+                return Err(truncated());
+            }
+            Ok(Some((
+                le_u16(body, 0) as u8,
+                Direction::Rx,
+                Event::ErrorFrame,
+            )))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Streaming BLF writer.
+///
+/// Objects are collected into containers of up to 128 KiB which are
+/// compressed and written out as they fill up. The file header is
+/// rewritten by `finish` once the object count and sizes are known, which
+/// is why the underlying writer must be seekable.
+pub struct Writer<W: Write + Seek> {
+    inner: W,
+    start_time: SystemTime,
+    buffer: Vec<u8>,
+    object_count: u32,
+    uncompressed_size: u64,
+    last_micros: u64,
+    message2: bool,
+}
+
+impl<W: Write + Seek> Writer<W> {
+    /// Create a writer for a measurement started at `start_time`.
+    pub fn new(mut inner: W, start_time: SystemTime) -> Result<Writer<W>> {
+        inner.write_all(&[0u8; FILE_HEADER_SIZE])?;
+
+        Ok(Writer {
+            inner,
+            start_time,
+            buffer: Vec::with_capacity(MAX_CONTAINER_SIZE),
+            object_count: 0,
+            uncompressed_size: FILE_HEADER_SIZE as u64,
+            last_micros: 0,
+            message2: false,
+        })
+    }
+
+    /// Store classic frames as `CAN_MESSAGE2` instead of `CAN_MESSAGE`.
+    pub fn set_can_message2(&mut self, enable: bool) {
+        self.message2 = enable;
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<()> {
+        let mut body = Vec::with_capacity(84);
+        let tx = record.direction == Direction::Tx;
+        let channel = record.channel as u16;
+
+        let object_type = match record.event {
+            Event::Frame(ref frame) => {
+                let mut flags = if tx { DIR_TX } else { 0 };
+                if frame.is_rtr() {
+                    flags |= REMOTE_FLAG;
+                }
+                put_u16(&mut body, channel);
+                body.push(flags);
+                body.push(frame.len());
+                put_u32(&mut body, raw_id(frame.id(), frame.is_extended()));
+                body.extend_from_slice(&frame.data()[..8]);
+                if self.message2 {
+                    body.extend_from_slice(&[0u8; 8]);
+                    CAN_MESSAGE2
+                } else {
+                    CAN_MESSAGE
+                }
+            }
+            Event::FdFrame(ref frame) => {
+                let mut fd_flags = FD_EDL;
+                if frame.is_brs() {
+                    fd_flags |= FD_BRS;
+                }
+                if frame.is_esi() {
+                    fd_flags |= FD_ESI;
+                }
+                put_u16(&mut body, channel);
+                body.push(if tx { DIR_TX } else { 0 });
+                body.push(frame.dlc());
+                put_u32(&mut body, raw_id(frame.id(), frame.is_extended()));
+                put_u32(&mut body, 0); // frame length in ns
+                body.push(0); // bit count
+                body.push(fd_flags);
+                body.push(frame.len() as u8);
+                body.extend_from_slice(&[0u8; 5]);
+                body.extend_from_slice(&frame.0.DATA);
+                CAN_FD_MESSAGE
+            }
+            Event::ErrorFrame => {
+                put_u16(&mut body, channel);
+                body.extend_from_slice(&[0u8; 30]);
+                CAN_ERROR_EXT
+            }
+        };
+
+        let micros = record.micros();
+        self.last_micros = self.last_micros.max(micros);
+
+        let object_size = OBJECT_HEADER_V1_SIZE + body.len();
+        self.buffer.extend_from_slice(OBJECT_SIGNATURE);
+        put_u16(&mut self.buffer, OBJECT_HEADER_V1_SIZE as u16);
+        put_u16(&mut self.buffer, 1);
+        put_u32(&mut self.buffer, object_size as u32);
+        put_u32(&mut self.buffer, object_type);
+        put_u32(&mut self.buffer, TIME_ONE_NANS);
+        put_u16(&mut self.buffer, 0); // client index
+        put_u16(&mut self.buffer, 0); // object version
+        put_u64(&mut self.buffer, micros * 1000);
+        self.buffer.extend_from_slice(&body);
+        self.buffer.extend(padding(object_size));
+        self.object_count += 1;
+
+        if self.buffer.len() >= MAX_CONTAINER_SIZE {
+            self.flush_container()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write out any buffered objects and the final file header, then hand
+    /// back the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.flush_container()?;
+
+        let file_size = self.inner.stream_position()?;
+        let stop_time =
+            self.start_time + Duration::from_micros(self.last_micros);
+
+        let mut header = Vec::with_capacity(FILE_HEADER_SIZE);
+        header.extend_from_slice(FILE_SIGNATURE);
+        put_u32(&mut header, FILE_HEADER_SIZE as u32);
+        // Application id and version, followed by the BL API version
+        header.extend_from_slice(&[5, 0, 0, 0, 2, 6, 8, 1]);
+        put_u64(&mut header, file_size);
+        put_u64(&mut header, self.uncompressed_size);
+        put_u32(&mut header, self.object_count);
+        put_u32(&mut header, 0);
+        write_systemtime(&mut header, self.start_time);
+        write_systemtime(&mut header, stop_time);
+        header.resize(FILE_HEADER_SIZE, 0);
+
+        self.inner.seek(SeekFrom::Start(0))?;
+        self.inner.write_all(&header)?;
+        self.inner.seek(SeekFrom::Start(file_size))?;
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+
+    fn flush_container(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.buffer)?;
+        let compressed = encoder.finish()?;
+
+        let object_size =
+            OBJECT_HEADER_BASE_SIZE + CONTAINER_HEADER_SIZE + compressed.len();
+        let mut container = Vec::with_capacity(object_size + 3);
+        container.extend_from_slice(OBJECT_SIGNATURE);
+        put_u16(&mut container, OBJECT_HEADER_BASE_SIZE as u16);
+        put_u16(&mut container, 1);
+        put_u32(&mut container, object_size as u32);
+        put_u32(&mut container, LOG_CONTAINER);
+        put_u16(&mut container, ZLIB_DEFLATE);
+        container.extend_from_slice(&[0u8; 6]);
+        put_u32(&mut container, self.buffer.len() as u32);
+        container.extend_from_slice(&[0u8; 4]);
+        container.extend_from_slice(&compressed);
+        container.extend(padding(object_size));
+        self.inner.write_all(&container)?;
+
+        self.uncompressed_size += (OBJECT_HEADER_BASE_SIZE
+            + CONTAINER_HEADER_SIZE
+            + self.buffer.len()) as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+fn direction(tx: bool) -> Direction {
+    if tx {
+        Direction::Tx
+    } else {
+        Direction::Rx
+    }
+}
+
+fn raw_id(id: u32, extended: bool) -> u32 {
+    if extended {
+        id | CAN_MSG_EXT
+    } else {
+        id
+    }
+}
+
+fn padding(object_size: usize) -> ::std::iter::RepeatN<u8> {
+    ::std::iter::repeat_n(0, object_size % 4)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Decode a Windows `SYSTEMTIME` (year, month, day of week, day, hour,
+/// minute, second, milliseconds) into a `SystemTime`.
+fn read_systemtime(buf: &[u8]) -> Option<SystemTime> {
+    let field = |i: usize| le_u16(buf, i * 2) as i64;
+    let (year, month, day) = (field(0), field(1), field(3));
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    let millis = ((days * 24 + field(4)) * 60 + field(5)) * 60_000
+        + field(6) * 1000
+        + field(7);
+    Some(UNIX_EPOCH + Duration::from_millis(millis as u64))
+}
+
+fn write_systemtime(buf: &mut Vec<u8>, time: SystemTime) {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64 * 1000 + d.subsec_millis() as i64)
+        .unwrap_or(0);
+    let days = millis / 86_400_000;
+    let (year, month, day) = civil_from_days(days);
+    let in_day = millis % 86_400_000;
+
+    for &value in &[
+        year,
+        month,
+        (days + 4) % 7, // 1970-01-01 was a Thursday
+        day,
+        in_day / 3_600_000,
+        in_day / 60_000 % 60,
+        in_day / 1000 % 60,
+        in_day % 1000,
+    ] {
+        put_u16(buf, value as u16);
+    }
+}
+
+// Conversions between days since 1970-01-01 and the proleptic Gregorian
+// calendar, after Howard Hinnant's `chrono`-compatible date algorithms.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5
+        + day
+        - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use trace::timestamp_from_micros;
+    use {CANFrame, CANFrameFd};
+
+    #[test]
+    fn write_and_read_back() {
+        let start = UNIX_EPOCH + Duration::from_millis(1_792_404_902_123);
+        let mut writer = Writer::new(Cursor::new(Vec::new()), start).unwrap();
+        // Enough records to span several containers
+        for i in 0..20_000u32 {
+            let event = match i % 4 {
+                0 => Event::Frame(
+                    CANFrame::new(i & 0x7FF, &[i as u8, 1, 2], false).unwrap(),
+                ),
+                1 => {
+                    Event::Frame(CANFrame::new_extended(i, &[], true).unwrap())
+                }
+                2 => Event::FdFrame(
+                    CANFrameFd::new(0x123, &[i as u8; 48], false, true)
+                        .unwrap(),
+                ),
+                _ => Event::ErrorFrame,
+            };
+            let record = Record {
+                timestamp: timestamp_from_micros(i as u64 * 250),
+                channel: 1 + (i % 2) as u8,
+                direction: if i.is_multiple_of(3) {
+                    Direction::Tx
+                } else {
+                    Direction::Rx
+                },
+                event,
+            };
+            writer.write_record(&record).unwrap();
+        }
+        let file = writer.finish().unwrap().into_inner();
+
+        let reader = Reader::new(Cursor::new(file)).unwrap();
+        assert_eq!(reader.header().object_count, 20_000);
+        assert_eq!(reader.header().start_time, Some(start));
+
+        let mut count = 0;
+        for (i, record) in reader.enumerate() {
+            let record = record.unwrap();
+            let i = i as u32;
+            assert_eq!(record.micros(), i as u64 * 250);
+            assert_eq!(record.channel, 1 + (i % 2) as u8);
+            match record.event {
+                Event::Frame(ref frame) if i.is_multiple_of(4) => {
+                    assert_eq!(frame.id(), i & 0x7FF);
+                    assert_eq!(&frame.data()[..3], &[i as u8, 1, 2]);
+                    assert_eq!(
+                        record.direction == Direction::Tx,
+                        i.is_multiple_of(3)
+                    );
+                }
+                Event::Frame(ref frame) if i % 4 == 1 => {
+                    assert!(frame.is_extended() && frame.is_rtr());
+                }
+                Event::FdFrame(ref frame) => {
+                    assert!(frame.is_brs());
+                    assert_eq!(frame.data(), &[i as u8; 48][..]);
+                }
+                Event::ErrorFrame => assert_eq!(i % 4, 3),
+                _ => panic!("unexpected event for record {}", i),
+            }
+            count += 1;
+        }
+        assert_eq!(count, 20_000);
+    }
+
+    #[test]
+    fn oversized_objects() {
+        let mut writer =
+            Writer::new(Cursor::new(Vec::new()), UNIX_EPOCH).unwrap();
+        for i in 0..10u32 {
+            let frame = CANFrame::new(i, &[i as u8], false).unwrap();
+            let record = Record {
+                timestamp: timestamp_from_micros(i as u64),
+                channel: 1,
+                direction: Direction::Rx,
+                event: Event::Frame(frame),
+            };
+            writer.write_record(&record).unwrap();
+        }
+        let file = writer.finish().unwrap().into_inner();
+        let read = |file: &[u8]| -> Result<Vec<Record>> {
+            Reader::new(Cursor::new(file))?.collect()
+        };
+        let patch = |offset: usize, value: u32| {
+            let mut file = file.clone();
+            file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            file
+        };
+        assert_eq!(read(&file).unwrap().len(), 10);
+
+        // The first container follows the file header
+        let object_size = FILE_HEADER_SIZE + 8;
+        let uncompressed_size = FILE_HEADER_SIZE + OBJECT_HEADER_BASE_SIZE + 8;
+        assert!(read(&patch(object_size, 0xFFFF_FFF0)).is_err());
+        assert!(read(&patch(uncompressed_size, u32::MAX)).is_err());
+        let inflated = le_u32(&file, uncompressed_size);
+        assert!(read(&patch(uncompressed_size, inflated + 1)).is_err());
+    }
+
+    #[test]
+    fn calendar_round_trip() {
+        for &days in &[0, 365, 10_957, 20_745, 47_482] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+    }
+}
@@ -0,0 +1,66 @@
+//! Byte order helpers shared by the binary trace formats.
+use errors::*;
+use std::io::{self, Read};
+
+pub fn le_u16(buf: &[u8], offset: usize) -> u16 {
+    buf[offset] as u16 | (buf[offset + 1] as u16) << 8
+}
+
+pub fn le_u32(buf: &[u8], offset: usize) -> u32 {
+    le_u16(buf, offset) as u32 | (le_u16(buf, offset + 2) as u32) << 16
+}
+
+pub fn le_u64(buf: &[u8], offset: usize) -> u64 {
+    le_u32(buf, offset) as u64 | (le_u32(buf, offset + 4) as u64) << 32
+}
+
+pub fn be_u16(buf: &[u8], offset: usize) -> u16 {
+    (buf[offset] as u16) << 8 | buf[offset + 1] as u16
+}
+
+pub fn be_u32(buf: &[u8], offset: usize) -> u32 {
+    (be_u16(buf, offset) as u32) << 16 | be_u16(buf, offset + 2) as u32
+}
+
+pub fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+}
+
+pub fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    put_u16(buf, value as u16);
+    put_u16(buf, (value >> 16) as u16);
+}
+
+pub fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    put_u32(buf, value as u32);
+    put_u32(buf, (value >> 32) as u32);
+}
+
+pub fn put_be_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&[
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ]);
+}
+
+/// Read as many bytes as possible, returning the count (short only at EOF).
+pub fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+pub fn skip<R: Read>(reader: &mut R, count: u64) -> Result<()> {
+    let skipped = io::copy(&mut reader.take(count), &mut io::sink())?;
+    if skipped < count {
+        bail!(ErrorKind::TraceFormat("unexpected end of file".to_owned()));
+    }
+    Ok(())
+}
@@ -0,0 +1,371 @@
+//! Log files written by `candump -l` from Linux can-utils.
+//!
+//! Each line reads `(seconds.micros) interface frame`, where the frame is
+//! `id#data` for classic frames, `id#R[len]` for remote frames and
+//! `id##<flags>data` for CAN FD frames. Identifiers with eight digits are
+//! extended, and error frames carry the SocketCAN `CAN_ERR_FLAG` in the id.
+//! Newer versions of candump may add an `R`/`T` direction column.
+//!
+//! Timestamps are absolute. The reader returns microseconds since the Unix
+//! epoch, and the writer adds the record timestamps to a start time.
+//! Interface `canN` maps to channel N + 1.
+use errors::*;
+use std::io::{BufRead, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use trace::{
+    new_fd_frame, new_frame, timestamp_from_micros, Direction, Event, Record,
+};
+
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+const CANFD_BRS: u8 = 0x01;
+const CANFD_ESI: u8 = 0x02;
+
+/// Streaming candump log reader.
+pub struct Reader<R> {
+    inner: R,
+    interfaces: Vec<String>,
+    line_number: usize,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(inner: R) -> Reader<R> {
+        Reader {
+            inner,
+            interfaces: Vec::new(),
+            line_number: 0,
+        }
+    }
+
+    fn malformed(&self, reason: &str) -> Error {
+        ErrorKind::TraceFormat(format!("line {}: {}", self.line_number, reason))
+            .into()
+    }
+
+    /// Channel of an interface: names ending in a number N (`can0`,
+    /// `vcan1`...) map to N + 1, other names are numbered in order of
+    /// appearance.
+    fn channel(&mut self, interface: &str) -> u8 {
+        let digits = interface.len()
+            - interface
+                .trim_end_matches(|c: char| c.is_ascii_digit())
+                .len();
+        if digits > 0 {
+            if let Ok(index) =
+                interface[interface.len() - digits..].parse::<u8>()
+            {
+                return index.saturating_add(1);
+            }
+        }
+
+        match self.interfaces.iter().position(|name| name == interface) {
+            Some(index) => index as u8 + 1,
+            None => {
+                self.interfaces.push(interface.to_owned());
+                self.interfaces.len() as u8
+            }
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<Option<Record>> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+        if tokens.len() < 3 {
+            return Err(self.malformed("too few columns"));
+        }
+
+        let micros = self.parse_timestamp(tokens[0])?;
+        let channel = self.channel(tokens[1]);
+        let direction = match tokens.get(3) {
+            Some(&"T") => Direction::Tx,
+            _ => Direction::Rx,
+        };
+        let event = self.parse_frame(tokens[2])?;
+
+        Ok(Some(Record {
+            timestamp: timestamp_from_micros(micros),
+            channel,
+            direction,
+            event,
+        }))
+    }
+
+    fn parse_timestamp(&self, token: &str) -> Result<u64> {
+        let inner = token
+            .strip_prefix('(')
+            .and_then(|t| t.strip_suffix(')'))
+            .ok_or_else(|| self.malformed("invalid timestamp"))?;
+        let mut parts = inner.splitn(2, '.');
+        let seconds = parts
+            .next()
+            .unwrap_or("")
+            .parse::<u64>()
+            .map_err(|_| self.malformed("invalid timestamp"))?;
+        let fraction = parts.next().unwrap_or("0");
+        if !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return Err(self.malformed("invalid timestamp"));
+        }
+        let digits: String =
+            fraction.chars().chain("000000".chars()).take(6).collect();
+        Ok(seconds * 1_000_000 + digits.parse::<u64>().unwrap_or(0))
+    }
+
+    fn parse_frame(&self, token: &str) -> Result<Event> {
+        let separator = token
+            .find('#')
+            .ok_or_else(|| self.malformed("missing '#'"))?;
+        let id_text = &token[..separator];
+        let id = u32::from_str_radix(id_text, 16)
+            .map_err(|_| self.malformed("invalid id"))?;
+        let extended = id_text.len() > 3;
+        let rest = &token[separator + 1..];
+
+        if extended && id & CAN_ERR_FLAG != 0 {
+            return Ok(Event::ErrorFrame);
+        }
+
+        if let Some(fd) = rest.strip_prefix('#') {
+            let mut chars = fd.chars();
+            let flags = chars
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.malformed("missing CAN FD flags"))?
+                as u8;
+            let data = self.parse_data(chars.as_str())?;
+            if data.len() > 64 {
+                return Err(self.malformed("too many data bytes"));
+            }
+            let frame = new_fd_frame(
+                id,
+                &data,
+                extended,
+                flags & CANFD_BRS != 0,
+                flags & CANFD_ESI != 0,
+            )?;
+            return Ok(Event::FdFrame(frame));
+        }
+
+        if let Some(len) = rest.strip_prefix('R') {
+            let mut frame = new_frame(id, &[], extended, true)?;
+            if !len.is_empty() {
+                let len = len.parse::<u8>().map_err(|_| {
+                    self.malformed("invalid remote frame length")
+                })?;
+                frame.0.LEN = len.min(8);
+            }
+            return Ok(Event::Frame(frame));
+        }
+
+        let data = self.parse_data(rest)?;
+        if data.len() > 8 {
+            return Err(self.malformed("too many data bytes"));
+        }
+        Ok(Event::Frame(new_frame(id, &data, extended, false)?))
+    }
+
+    /// Hex payload, optionally with `.` separators between bytes.
+    fn parse_data(&self, text: &str) -> Result<Vec<u8>> {
+        let digits: Vec<u8> = text.bytes().filter(|&b| b != b'.').collect();
+        if !digits.len().is_multiple_of(2) {
+            return Err(self.malformed("odd number of hex digits"));
+        }
+        digits
+            .chunks(2)
+            .map(|pair| {
+                ::std::str::from_utf8(pair)
+                    .ok()
+                    .and_then(|s| u8::from_str_radix(s, 16).ok())
+                    .ok_or_else(|| self.malformed("invalid data byte"))
+            })
+            .collect()
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        loop {
+            let mut line = String::new();
+            match self.inner.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) => self.line_number += 1,
+                Err(e) => return Some(Err(e.into())),
+            }
+
+            match self.parse_line(&line) {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Streaming candump log writer.
+pub struct Writer<W: Write> {
+    inner: W,
+    start_micros: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a writer. Record timestamps are offset by `start_time` to
+    /// produce the absolute times of the log.
+    pub fn new(inner: W, start_time: SystemTime) -> Writer<W> {
+        let start_micros = start_time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() * 1_000_000 + d.subsec_micros() as u64)
+            .unwrap_or(0);
+
+        Writer {
+            inner,
+            start_micros,
+        }
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<()> {
+        let micros = self.start_micros + record.micros();
+        let frame = match record.event {
+            Event::Frame(ref frame) if frame.is_rtr() => {
+                format!(
+                    "{}#R{}",
+                    format_id(frame.id(), frame.is_extended()),
+                    frame.len()
+                )
+            }
+            Event::Frame(ref frame) => {
+                let data = &frame.data()[..frame.len().min(8) as usize];
+                format!(
+                    "{}#{}",
+                    format_id(frame.id(), frame.is_extended()),
+                    format_bytes(data)
+                )
+            }
+            Event::FdFrame(ref frame) => {
+                let mut flags = 0;
+                if frame.is_brs() {
+                    flags |= CANFD_BRS;
+                }
+                if frame.is_esi() {
+                    flags |= CANFD_ESI;
+                }
+                format!(
+                    "{}##{:X}{}",
+                    format_id(frame.id(), frame.is_extended()),
+                    flags,
+                    format_bytes(frame.data())
+                )
+            }
+            Event::ErrorFrame => {
+                format!("{:08X}#0000000000000000", CAN_ERR_FLAG)
+            }
+        };
+
+        writeln!(
+            self.inner,
+            "({}.{:06}) can{} {}",
+            micros / 1_000_000,
+            micros % 1_000_000,
+            record.channel.saturating_sub(1),
+            frame
+        )?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+fn format_id(id: u32, extended: bool) -> String {
+    if extended {
+        format!("{:08X}", id)
+    } else {
+        format!("{:03X}", id)
+    }
+}
+
+fn format_bytes(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::Duration;
+    use {CANFrame, CANFrameFd};
+
+    #[test]
+    fn read_log() {
+        let text = "(1436509052.249713) vcan0 044#2A366C2BBA
+(1436509052.449847) vcan1 0F6E48AB#R
+(1436509052.650004) can0 123##1AABBCCDDEEFF0011223344
+(1436509052.850124) can0 20000004#0000000000000000
+(1436509053.000000) can0 7DF#02.01.0D T
+";
+        let records: Vec<Record> =
+            Reader::new(Cursor::new(text)).map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[0].micros(), 1_436_509_052_249_713);
+        assert_eq!(records[1].channel, 2);
+        assert_eq!(records[1].id(), Some(0x0F6E48AB));
+        match records[2].event {
+            Event::FdFrame(ref frame) => {
+                assert!(frame.is_brs() && frame.len() == 12)
+            }
+            _ => panic!("expected an FD frame"),
+        }
+        assert!(matches!(records[3].event, Event::ErrorFrame));
+        assert_eq!(records[4].direction, Direction::Tx);
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let mut writer = Writer::new(
+            Vec::new(),
+            UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+        );
+        let events = [
+            Event::Frame(
+                CANFrame::new(0x7E8, &[0x06, 0x50, 0x03], false).unwrap(),
+            ),
+            Event::Frame(
+                CANFrame::new_extended(0x18DAF100, &[0; 3], true).unwrap(),
+            ),
+            Event::FdFrame(
+                CANFrameFd::new(0x100, &[0x55; 24], false, false).unwrap(),
+            ),
+            Event::ErrorFrame,
+        ];
+        for (i, event) in events.iter().enumerate() {
+            writer
+                .write_record(&Record {
+                    timestamp: timestamp_from_micros(1_500 * i as u64),
+                    channel: 2,
+                    direction: Direction::Rx,
+                    event: *event,
+                })
+                .unwrap();
+        }
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert!(text.starts_with("(1600000000.000000) can1 7E8#065003\n"));
+
+        let records: Vec<Record> =
+            Reader::new(Cursor::new(text)).map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[3].micros(), 1_600_000_000_004_500);
+        match records[1].event {
+            Event::Frame(ref frame) => assert!(
+                frame.is_rtr() && frame.is_extended() && frame.len() == 3
+            ),
+            _ => panic!("expected a remote frame"),
+        }
+        match records[2].event {
+            Event::FdFrame(ref frame) => assert_eq!(frame.len(), 24),
+            _ => panic!("expected an FD frame"),
+        }
+    }
+}
@@ -0,0 +1,1275 @@
+//! ASAM MDF 4 measurement files using the bus logging convention for CAN.
+//!
+//! The writer produces one unsorted data group with three channel groups,
+//! told apart by a one byte record id:
+//!
+//! * `CAN_DataFrame`: `Timestamp` plus the `CAN_DataFrame` structure with
+//!   `BusChannel`, `ID`, `IDE`, `DLC`, `DataLength`, `Dir`, `EDL`, `BRS`,
+//!   `ESI` and a fixed 64 byte `DataBytes` array.
+//! * `CAN_RemoteFrame`: `Timestamp`, `BusChannel`, `ID`, `IDE`, `DLC`, `Dir`.
+//! * `CAN_ErrorFrame`: `Timestamp`, `BusChannel`.
+//!
+//! Records are appended to a single `DT` block as they arrive and the file is
+//! finalised (block length and cycle counters) by `Writer::finish`.
+//!
+//! The reader locates these groups by name in any MDF 4 file, so files from
+//! other loggers using fixed length records are understood as well. Data may
+//! be stored in `DT` blocks directly or through `HL`/`DL` lists, including
+//! deflate compressed `DZ` blocks.
+use errors::*;
+use flate2::read::ZlibDecoder;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use trace::bytes::{le_u16, le_u32, le_u64, put_u16, put_u32, put_u64};
+use trace::{
+    new_fd_frame, new_frame, timestamp_from_micros, Direction, Event, Record,
+};
+
+const ID_BLOCK_SIZE: usize = 64;
+const BLOCK_HEADER_SIZE: usize = 24;
+/// Largest inflated `DZ` block read into memory.
+const MAX_INFLATED_SIZE: u64 = 0x400_0000;
+
+const CN_TYPE_FIXED: u8 = 0;
+const CN_TYPE_VLSD: u8 = 1;
+const CN_TYPE_MASTER: u8 = 2;
+const CN_TYPE_VIRTUAL_MASTER: u8 = 3;
+const CN_SYNC_TIME: u8 = 1;
+
+const DT_UINT_LE: u8 = 0;
+const DT_UINT_BE: u8 = 1;
+const DT_FLOAT_LE: u8 = 4;
+const DT_BYTE_ARRAY: u8 = 10;
+
+const CG_FLAG_VLSD: u16 = 0x01;
+const CG_FLAG_BUS_EVENT: u16 = 0x02;
+const CG_FLAG_PLAIN_BUS_EVENT: u16 = 0x04;
+
+const SI_TYPE_BUS: u8 = 2;
+const SI_BUS_CAN: u8 = 2;
+
+const CC_LINEAR: u8 = 1;
+
+const UNFINALISED_CYCLE_COUNTERS: u16 = 0x01;
+const UNFINALISED_DT_LENGTH: u16 = 0x04;
+
+const DATA_FRAME: u8 = 1;
+const REMOTE_FRAME: u8 = 2;
+const ERROR_FRAME: u8 = 3;
+
+const DATA_FRAME_SIZE: u32 = 80;
+const REMOTE_FRAME_SIZE: u32 = 16;
+const ERROR_FRAME_SIZE: u32 = 9;
+
+/// Streaming MDF 4 writer.
+pub struct Writer<W: Write + Seek> {
+    inner: W,
+    data_block: u64,
+    cycle_counts: [(u64, u64); 3], // (channel group offset, records written)
+}
+
+impl<W: Write + Seek> Writer<W> {
+    /// Create a writer for a measurement started at `start_time`. Record
+    /// timestamps are stored relative to it.
+    pub fn new(mut inner: W, start_time: SystemTime) -> Result<Writer<W>> {
+        let start_ns = start_time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64)
+            .unwrap_or(0);
+
+        let mut layout = Layout::new();
+        layout.bytes.extend_from_slice(&id_block(true));
+
+        let mut hd_data = Vec::with_capacity(32);
+        put_u64(&mut hd_data, start_ns);
+        put_u16(&mut hd_data, 0); // time zone offset
+        put_u16(&mut hd_data, 0); // daylight saving offset
+        hd_data.extend_from_slice(&[0, 0, 0, 0]); // time flags, time class, flags, reserved
+        put_u64(&mut hd_data, 0); // start angle
+        put_u64(&mut hd_data, 0); // start distance
+        let hd = layout.add(b"HD", 6, &hd_data);
+
+        let comment = layout.text(
+            b"MD",
+            &format!(
+                "<FHcomment><TX>CAN bus logging</TX><tool_id>pcan-basic</tool_id>\
+                 <tool_vendor>pcan-basic</tool_vendor><tool_version>{}</tool_version></FHcomment>",
+                env!("CARGO_PKG_VERSION")
+            ),
+        );
+        let mut fh_data = Vec::with_capacity(16);
+        put_u64(&mut fh_data, start_ns);
+        put_u16(&mut fh_data, 0);
+        put_u16(&mut fh_data, 0);
+        fh_data.extend_from_slice(&[0, 0, 0, 0]);
+        let fh = layout.add(b"FH", 2, &fh_data);
+        layout.link(fh, 1, comment);
+        layout.link(hd, 1, fh);
+
+        let mut dg_data = vec![0u8; 8];
+        dg_data[0] = 1; // record id size
+        let dg = layout.add(b"DG", 4, &dg_data);
+        layout.link(hd, 0, dg);
+
+        let data_frame = layout.channel_group(
+            DATA_FRAME,
+            "CAN_DataFrame",
+            DATA_FRAME_SIZE,
+            &[
+                ("BusChannel", DT_UINT_LE, 8, 0, 8),
+                ("ID", DT_UINT_LE, 9, 0, 29),
+                ("IDE", DT_UINT_LE, 12, 7, 1),
+                ("DLC", DT_UINT_LE, 13, 0, 4),
+                ("DataLength", DT_UINT_LE, 14, 0, 7),
+                ("Dir", DT_UINT_LE, 15, 0, 1),
+                ("EDL", DT_UINT_LE, 15, 1, 1),
+                ("BRS", DT_UINT_LE, 15, 2, 1),
+                ("ESI", DT_UINT_LE, 15, 3, 1),
+                ("DataBytes", DT_BYTE_ARRAY, 16, 0, 64 * 8),
+            ],
+        );
+        let remote_frame = layout.channel_group(
+            REMOTE_FRAME,
+            "CAN_RemoteFrame",
+            REMOTE_FRAME_SIZE,
+            &[
+                ("BusChannel", DT_UINT_LE, 8, 0, 8),
+                ("ID", DT_UINT_LE, 9, 0, 29),
+                ("IDE", DT_UINT_LE, 12, 7, 1),
+                ("DLC", DT_UINT_LE, 13, 0, 4),
+                ("Dir", DT_UINT_LE, 14, 0, 1),
+            ],
+        );
+        let error_frame = layout.channel_group(
+            ERROR_FRAME,
+            "CAN_ErrorFrame",
+            ERROR_FRAME_SIZE,
+            &[("BusChannel", DT_UINT_LE, 8, 0, 8)],
+        );
+        layout.link(dg, 1, data_frame);
+        layout.link(data_frame, 0, remote_frame);
+        layout.link(remote_frame, 0, error_frame);
+
+        // The data block goes last so records can simply be appended
+        layout.align();
+        let data_block = layout.bytes.len() as u64;
+        layout.link(dg, 2, data_block);
+        layout.bytes.extend_from_slice(&block_header(
+            b"DT",
+            BLOCK_HEADER_SIZE as u64,
+            0,
+        ));
+
+        inner.write_all(&layout.bytes)?;
+
+        Ok(Writer {
+            inner,
+            data_block,
+            cycle_counts: [
+                (data_frame, 0),
+                (remote_frame, 0),
+                (error_frame, 0),
+            ],
+        })
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<()> {
+        let seconds = record.micros() as f64 / 1e6;
+        let tx = (record.direction == Direction::Tx) as u8;
+        let mut buf = Vec::with_capacity(1 + DATA_FRAME_SIZE as usize);
+
+        let group = match record.event {
+            Event::Frame(ref frame) if frame.is_rtr() => {
+                buf.push(REMOTE_FRAME);
+                put_u64(&mut buf, seconds.to_bits());
+                buf.push(record.channel);
+                put_u32(
+                    &mut buf,
+                    frame.id() | (frame.is_extended() as u32) << 31,
+                );
+                buf.push(frame.len());
+                buf.push(tx);
+                buf.push(0);
+                1
+            }
+            Event::Frame(ref frame) => {
+                let len = frame.len().min(8) as usize;
+                buf.push(DATA_FRAME);
+                put_u64(&mut buf, seconds.to_bits());
+                buf.push(record.channel);
+                put_u32(
+                    &mut buf,
+                    frame.id() | (frame.is_extended() as u32) << 31,
+                );
+                buf.push(frame.len());
+                buf.push(len as u8);
+                buf.push(tx);
+                buf.extend_from_slice(&frame.data()[..len]);
+                buf.resize(1 + DATA_FRAME_SIZE as usize, 0);
+                0
+            }
+            Event::FdFrame(ref frame) => {
+                let flags = tx
+                    | 0x02
+                    | (frame.is_brs() as u8) << 2
+                    | (frame.is_esi() as u8) << 3;
+                buf.push(DATA_FRAME);
+                put_u64(&mut buf, seconds.to_bits());
+                buf.push(record.channel);
+                put_u32(
+                    &mut buf,
+                    frame.id() | (frame.is_extended() as u32) << 31,
+                );
+                buf.push(frame.dlc());
+                buf.push(frame.len() as u8);
+                buf.push(flags);
+                buf.extend_from_slice(&frame.0.DATA);
+                0
+            }
+            Event::ErrorFrame => {
+                buf.push(ERROR_FRAME);
+                put_u64(&mut buf, seconds.to_bits());
+                buf.push(record.channel);
+                2
+            }
+        };
+
+        self.inner.write_all(&buf)?;
+        self.cycle_counts[group].1 += 1;
+        Ok(())
+    }
+
+    /// Patch the data block length and cycle counters, mark the file as
+    /// finalised and hand back the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        let end = self.inner.stream_position()?;
+
+        self.inner.seek(SeekFrom::Start(self.data_block + 8))?;
+        self.inner
+            .write_all(&(end - self.data_block).to_le_bytes())?;
+
+        for &(group, count) in &self.cycle_counts {
+            // cg_cycle_count follows the six links and the record id
+            self.inner.seek(SeekFrom::Start(
+                group + BLOCK_HEADER_SIZE as u64 + 6 * 8 + 8,
+            ))?;
+            self.inner.write_all(&count.to_le_bytes())?;
+        }
+
+        self.inner.seek(SeekFrom::Start(0))?;
+        self.inner.write_all(&id_block(false))?;
+        self.inner.seek(SeekFrom::Start(end))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Metadata blocks laid out in memory before being written in one go.
+struct Layout {
+    bytes: Vec<u8>,
+}
+
+impl Layout {
+    fn new() -> Layout {
+        Layout { bytes: Vec::new() }
+    }
+
+    fn align(&mut self) {
+        let aligned = (self.bytes.len() + 7) & !7;
+        self.bytes.resize(aligned, 0);
+    }
+
+    /// Append a block with `link_count` zeroed links, returning its offset.
+    fn add(&mut self, id: &[u8], link_count: usize, data: &[u8]) -> u64 {
+        self.align();
+        let offset = self.bytes.len() as u64;
+        let length = BLOCK_HEADER_SIZE + link_count * 8 + data.len();
+        self.bytes.extend_from_slice(&block_header(
+            id,
+            length as u64,
+            link_count as u64,
+        ));
+        self.bytes.resize(self.bytes.len() + link_count * 8, 0);
+        self.bytes.extend_from_slice(data);
+        offset
+    }
+
+    fn link(&mut self, block: u64, index: usize, target: u64) {
+        let at = block as usize + BLOCK_HEADER_SIZE + index * 8;
+        self.bytes[at..at + 8].copy_from_slice(&target.to_le_bytes());
+    }
+
+    /// Append a `TX` or `MD` block holding a zero terminated string.
+    fn text(&mut self, id: &[u8], text: &str) -> u64 {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        data.resize((data.len() + 7) & !7, 0);
+        self.add(id, 0, &data)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn channel(
+        &mut self,
+        name: &str,
+        cn_type: u8,
+        sync_type: u8,
+        data_type: u8,
+        byte_offset: u32,
+        bit_offset: u8,
+        bit_count: u32,
+    ) -> u64 {
+        let name = self.text(b"TX", name);
+        let mut data = Vec::with_capacity(72);
+        data.extend_from_slice(&[cn_type, sync_type, data_type, bit_offset]);
+        put_u32(&mut data, byte_offset);
+        put_u32(&mut data, bit_count);
+        put_u32(&mut data, 0); // flags
+        put_u32(&mut data, 0); // invalidation bit position
+        data.extend_from_slice(&[0xFF, 0]); // precision, reserved
+        put_u16(&mut data, 0); // attachment count
+        data.resize(72, 0); // value range and limits
+        let channel = self.add(b"CN", 8, &data);
+        self.link(channel, 2, name);
+        channel
+    }
+
+    /// Append a bus logging channel group made of a `Timestamp` master channel
+    /// and a structure channel named after the group holding `signals`.
+    fn channel_group(
+        &mut self,
+        record_id: u8,
+        name: &str,
+        size: u32,
+        signals: &[(&str, u8, u32, u8, u32)],
+    ) -> u64 {
+        let acq_name = self.text(b"TX", name);
+        let source_name = self.text(b"TX", "CAN");
+        let source =
+            self.add(b"SI", 3, &[SI_TYPE_BUS, SI_BUS_CAN, 0, 0, 0, 0, 0, 0]);
+        self.link(source, 0, source_name);
+
+        let mut cg_data = Vec::with_capacity(32);
+        put_u64(&mut cg_data, record_id as u64);
+        put_u64(&mut cg_data, 0); // cycle count, patched when finishing
+        put_u16(&mut cg_data, CG_FLAG_BUS_EVENT | CG_FLAG_PLAIN_BUS_EVENT);
+        put_u16(&mut cg_data, b'.' as u16);
+        put_u32(&mut cg_data, 0);
+        put_u32(&mut cg_data, size);
+        put_u32(&mut cg_data, 0); // invalidation bytes
+        let group = self.add(b"CG", 6, &cg_data);
+        self.link(group, 2, acq_name);
+        self.link(group, 3, source);
+
+        let timestamp = self.channel(
+            "Timestamp",
+            CN_TYPE_MASTER,
+            CN_SYNC_TIME,
+            DT_FLOAT_LE,
+            0,
+            0,
+            64,
+        );
+        let unit = self.text(b"TX", "s");
+        self.link(timestamp, 6, unit);
+        self.link(group, 1, timestamp);
+
+        let structure = self.channel(
+            name,
+            CN_TYPE_FIXED,
+            0,
+            DT_BYTE_ARRAY,
+            8,
+            0,
+            (size - 8) * 8,
+        );
+        self.link(timestamp, 0, structure);
+
+        let mut previous = None;
+        for &(signal, data_type, byte_offset, bit_offset, bit_count) in signals
+        {
+            let full_name = format!("{}.{}", name, signal);
+            let channel = self.channel(
+                &full_name,
+                CN_TYPE_FIXED,
+                0,
+                data_type,
+                byte_offset,
+                bit_offset,
+                bit_count,
+            );
+            match previous {
+                Some(previous) => self.link(previous, 0, channel),
+                None => self.link(structure, 1, channel),
+            }
+            previous = Some(channel);
+        }
+
+        group
+    }
+}
+
+fn block_header(id: &[u8], length: u64, link_count: u64) -> Vec<u8> {
+    let mut header = Vec::with_capacity(BLOCK_HEADER_SIZE);
+    header.extend_from_slice(b"##");
+    header.extend_from_slice(id);
+    header.extend_from_slice(&[0u8; 4]);
+    put_u64(&mut header, length);
+    put_u64(&mut header, link_count);
+    header
+}
+
+fn id_block(unfinalised: bool) -> Vec<u8> {
+    let mut block = Vec::with_capacity(ID_BLOCK_SIZE);
+    block.extend_from_slice(if unfinalised {
+        b"UnFinMF "
+    } else {
+        b"MDF     "
+    });
+    block.extend_from_slice(b"4.10    ");
+    block.extend_from_slice(b"pcanbsc ");
+    block.extend_from_slice(&[0u8; 4]);
+    put_u16(&mut block, 410);
+    block.extend_from_slice(&[0u8; 30]);
+    put_u16(
+        &mut block,
+        if unfinalised {
+            UNFINALISED_CYCLE_COUNTERS | UNFINALISED_DT_LENGTH
+        } else {
+            0
+        },
+    );
+    put_u16(&mut block, 0);
+    block
+}
+
+/// Location of a value within a record.
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    data_type: u8,
+    byte_offset: usize,
+    bit_offset: u8,
+    bit_count: u32,
+    factor: f64,
+    offset: f64,
+}
+
+impl Field {
+    fn raw(&self, record: &[u8]) -> u64 {
+        let bytes = (self.bit_offset as u32 + self.bit_count).div_ceil(8).min(8)
+            as usize;
+        let end = (self.byte_offset + bytes).min(record.len());
+        let start = self.byte_offset.min(end);
+        let mut value = 0u64;
+        if self.data_type == DT_UINT_BE {
+            for &byte in &record[start..end] {
+                value = value << 8 | byte as u64;
+            }
+        } else {
+            for (i, &byte) in record[start..end].iter().enumerate() {
+                value |= (byte as u64) << (8 * i);
+            }
+        }
+        value >>= self.bit_offset;
+        if self.bit_count < 64 {
+            value &= (1u64 << self.bit_count) - 1;
+        }
+        value
+    }
+
+    fn float(&self, record: &[u8]) -> f64 {
+        let raw = self.raw(record);
+        let value = match (self.data_type, self.bit_count) {
+            (DT_FLOAT_LE, 32) => f32::from_bits(raw as u32) as f64,
+            (DT_FLOAT_LE, 64) => f64::from_bits(raw),
+            _ => raw as f64,
+        };
+        self.offset + self.factor * value
+    }
+
+    fn bytes<'a>(&self, record: &'a [u8]) -> &'a [u8] {
+        let end =
+            (self.byte_offset + self.bit_count as usize / 8).min(record.len());
+        &record[self.byte_offset.min(end)..end]
+    }
+}
+
+/// Raw value of a field, 0 if the group does not have it.
+fn raw(field: Option<Field>, record: &[u8]) -> u64 {
+    field.map_or(0, |field| field.raw(record))
+}
+
+/// Fields of the CAN bus logging groups, found by the last part of their
+/// channel name.
+#[derive(Debug, Clone, Copy, Default)]
+struct Fields {
+    bus_channel: Option<Field>,
+    dir: Option<Field>,
+    id: Option<Field>,
+    ide: Option<Field>,
+    dlc: Option<Field>,
+    data_length: Option<Field>,
+    data_bytes: Option<Field>,
+    edl: Option<Field>,
+    brs: Option<Field>,
+    esi: Option<Field>,
+}
+
+impl Fields {
+    /// Keep the first field of each name.
+    fn insert(&mut self, name: &str, field: Field) {
+        let slot = match name {
+            "BusChannel" => &mut self.bus_channel,
+            "Dir" => &mut self.dir,
+            "ID" => &mut self.id,
+            "IDE" => &mut self.ide,
+            "DLC" => &mut self.dlc,
+            "DataLength" => &mut self.data_length,
+            "DataBytes" => &mut self.data_bytes,
+            "EDL" => &mut self.edl,
+            "BRS" => &mut self.brs,
+            "ESI" => &mut self.esi,
+            _ => return,
+        };
+        slot.get_or_insert(field);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    DataFrame,
+    RemoteFrame,
+    ErrorFrame,
+    Other,
+}
+
+/// Decoding information for one channel group.
+#[derive(Debug, Clone)]
+struct Group {
+    record_id: u64,
+    size: usize,
+    vlsd: bool,
+    kind: Kind,
+    time: Option<Field>,
+    fields: Fields,
+}
+
+impl Group {
+    fn decode(&self, record: &[u8]) -> Result<Option<Record>> {
+        let fields = &self.fields;
+        let seconds = self.time.map_or(0.0, |time| time.float(record));
+        let micros = (seconds.max(0.0) * 1e6).round() as u64;
+        let channel = raw(fields.bus_channel, record) as u8;
+        let direction = if raw(fields.dir, record) != 0 {
+            Direction::Tx
+        } else {
+            Direction::Rx
+        };
+        let id = raw(fields.id, record) as u32 & 0x1FFF_FFFF;
+        let extended = raw(fields.ide, record) != 0;
+        let dlc = raw(fields.dlc, record) as u8;
+
+        let event = match self.kind {
+            Kind::DataFrame => {
+                let data = fields
+                    .data_bytes
+                    .map_or(&[][..], |field| field.bytes(record));
+                let fd = raw(fields.edl, record) != 0;
+                let len = match fields.data_length {
+                    Some(field) => field.raw(record) as usize,
+                    None if fd => ::fd_dlc_to_len(dlc),
+                    None => dlc.min(8) as usize,
+                };
+                let data = &data[..len.min(data.len())];
+                if fd {
+                    let (brs, esi) = (
+                        raw(fields.brs, record) != 0,
+                        raw(fields.esi, record) != 0,
+                    );
+                    Event::FdFrame(new_fd_frame(id, data, extended, brs, esi)?)
+                } else {
+                    Event::Frame(new_frame(
+                        id,
+                        &data[..data.len().min(8)],
+                        extended,
+                        false,
+                    )?)
+                }
+            }
+            Kind::RemoteFrame => {
+                let mut frame = new_frame(id, &[], extended, true)?;
+                frame.0.LEN = dlc.min(8);
+                Event::Frame(frame)
+            }
+            Kind::ErrorFrame => Event::ErrorFrame,
+            Kind::Other => return Ok(None),
+        };
+
+        Ok(Some(Record {
+            timestamp: timestamp_from_micros(micros),
+            channel,
+            direction,
+            event,
+        }))
+    }
+}
+
+/// A stretch of record data inside the file.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    offset: u64,
+    length: u64,
+    zip: Option<(u8, u32, u64)>, // zip type, parameter and inflated length
+}
+
+/// Data group whose records are being read.
+struct DataGroup {
+    record_id_size: usize,
+    groups: BTreeMap<u64, Group>, // By record id
+    segments: Vec<Segment>,
+}
+
+impl DataGroup {
+    fn group(&self, record_id: u64) -> Option<&Group> {
+        // Without record ids the data group has a single channel group
+        if self.record_id_size == 0 {
+            self.groups.values().next()
+        } else {
+            self.groups.get(&record_id)
+        }
+    }
+}
+
+/// Streaming MDF 4 reader yielding the records of CAN bus logging groups.
+pub struct Reader<R> {
+    inner: R,
+    file_size: u64,
+    start_time: SystemTime,
+    unfinalised_flags: u16,
+    data_groups: Vec<DataGroup>,
+    current: usize,
+    segment: usize,
+    buffer: Vec<u8>,
+    pos: usize,
+    remaining: u64,
+}
+
+impl<R: Read + Seek> Reader<R> {
+    /// Create a reader, loading the file structure (but not the data).
+    pub fn new(mut inner: R) -> Result<Reader<R>> {
+        let mut id = [0u8; ID_BLOCK_SIZE];
+        inner.read_exact(&mut id)?;
+        if &id[0..3] != b"MDF" && &id[0..8] != b"UnFinMF " {
+            bail!(ErrorKind::TraceFormat("not an MDF file".to_owned()));
+        }
+        if le_u16(&id, 28) < 400 {
+            bail!(ErrorKind::TraceFormat(
+                "only MDF 4 files are supported".to_owned()
+            ));
+        }
+
+        let file_size = inner.seek(SeekFrom::End(0))?;
+        let mut reader = Reader {
+            inner,
+            file_size,
+            start_time: UNIX_EPOCH,
+            unfinalised_flags: if &id[0..8] == b"UnFinMF " {
+                le_u16(&id, 60)
+            } else {
+                0
+            },
+            data_groups: Vec::new(),
+            current: 0,
+            segment: 0,
+            buffer: Vec::new(),
+            pos: 0,
+            remaining: 0,
+        };
+
+        let (hd_links, hd_data) =
+            reader.block(ID_BLOCK_SIZE as u64, b"HD", 1, 8)?;
+        reader.start_time =
+            UNIX_EPOCH + Duration::from_nanos(le_u64(&hd_data, 0));
+
+        let mut dg = hd_links[0];
+        while dg != 0 {
+            let (dg_links, dg_data) = reader.block(dg, b"DG", 3, 1)?;
+            let record_id_size = dg_data[0] as usize;
+            if ![0, 1, 2, 4, 8].contains(&record_id_size) {
+                bail!(ErrorKind::TraceFormat(format!(
+                    "invalid record id size {}",
+                    record_id_size
+                )));
+            }
+            let mut groups = BTreeMap::new();
+            let mut cg = dg_links[1];
+            while cg != 0 {
+                let (cg_links, group) = reader.channel_group(cg)?;
+                groups.entry(group.record_id).or_insert(group);
+                cg = cg_links[0];
+            }
+
+            if groups.values().any(|group| group.kind != Kind::Other) {
+                let mut segments = Vec::new();
+                reader.segments(dg_links[2], &mut segments)?;
+                reader.data_groups.push(DataGroup {
+                    record_id_size,
+                    groups,
+                    segments,
+                });
+            }
+            dg = dg_links[0];
+        }
+
+        // A logger that did not finish writing leaves the length of its last
+        // data block open, which then runs to the end of the file
+        if reader.unfinalised_flags & UNFINALISED_DT_LENGTH != 0 {
+            let last = reader
+                .data_groups
+                .iter_mut()
+                .flat_map(|group| group.segments.iter_mut())
+                .filter(|segment| segment.zip.is_none())
+                .max_by_key(|segment| segment.offset);
+            if let Some(segment) = last {
+                segment.length = file_size.saturating_sub(segment.offset);
+            }
+        }
+
+        Ok(reader)
+    }
+
+    /// Absolute time the measurement started, record timestamps are
+    /// relative to it.
+    pub fn start_time(&self) -> SystemTime {
+        self.start_time
+    }
+
+    /// Read a block, returning its links and data section, which must hold
+    /// at least `links` links and `data` bytes.
+    fn block(
+        &mut self,
+        offset: u64,
+        expected: &[u8],
+        links: usize,
+        data: usize,
+    ) -> Result<(Vec<u64>, Vec<u8>)> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; BLOCK_HEADER_SIZE];
+        self.inner.read_exact(&mut header)?;
+        if &header[0..2] != b"##" || &header[2..4] != expected {
+            bail!(ErrorKind::TraceFormat(format!(
+                "expected ##{} block at offset {}",
+                String::from_utf8_lossy(expected),
+                offset
+            )));
+        }
+        let length = le_u64(&header, 8);
+        let link_count = le_u64(&header, 16);
+        let links_end = link_count
+            .checked_mul(8)
+            .and_then(|size| size.checked_add(BLOCK_HEADER_SIZE as u64));
+        let fits = match links_end {
+            Some(end) => {
+                end <= length && length <= self.file_size.saturating_sub(offset)
+            }
+            None => false,
+        };
+        if !fits {
+            bail!(ErrorKind::TraceFormat(format!(
+                "corrupt block at offset {}",
+                offset
+            )));
+        }
+        let (length, link_count) = (length as usize, link_count as usize);
+        if link_count < links
+            || length - BLOCK_HEADER_SIZE - link_count * 8 < data
+        {
+            bail!(ErrorKind::TraceFormat(format!(
+                "truncated ##{} block at offset {}",
+                String::from_utf8_lossy(expected),
+                offset
+            )));
+        }
+
+        let mut body = vec![0u8; length - BLOCK_HEADER_SIZE];
+        self.inner.read_exact(&mut body)?;
+        let links = (0..link_count).map(|i| le_u64(&body, i * 8)).collect();
+        let data = body.split_off(link_count * 8);
+        Ok((links, data))
+    }
+
+    /// Read the string of a `TX` or `MD` block.
+    fn text(&mut self, offset: u64) -> Result<String> {
+        if offset == 0 {
+            return Ok(String::new());
+        }
+        self.inner.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; BLOCK_HEADER_SIZE];
+        self.inner.read_exact(&mut header)?;
+        let id = &header[2..4];
+        let (_, data) =
+            self.block(offset, if id == b"MD" { b"MD" } else { b"TX" }, 0, 0)?;
+        let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        Ok(String::from_utf8_lossy(&data[..end]).into_owned())
+    }
+
+    fn channel_group(&mut self, offset: u64) -> Result<(Vec<u64>, Group)> {
+        let (links, data) = self.block(offset, b"CG", 3, 32)?;
+        let flags = le_u16(&data, 16);
+        let mut group = Group {
+            record_id: le_u64(&data, 0),
+            size: le_u32(&data, 24) as usize + le_u32(&data, 28) as usize,
+            vlsd: flags & CG_FLAG_VLSD != 0,
+            kind: Kind::Other,
+            time: None,
+            fields: Fields::default(),
+        };
+        if group.vlsd {
+            return Ok((links, group));
+        }
+
+        let acq_name = self.text(links[2])?;
+        let mut names = vec![acq_name];
+        let mut pending = vec![links[1]];
+        while let Some(cn) = pending.pop() {
+            if cn == 0 {
+                continue;
+            }
+            let (cn_links, cn_data) = self.block(cn, b"CN", 5, 12)?;
+            if cn_data[3] > 7 {
+                bail!(ErrorKind::TraceFormat(format!(
+                    "invalid bit offset in channel at offset {}",
+                    cn
+                )));
+            }
+            pending.push(cn_links[0]);
+            pending.push(cn_links[1]);
+
+            let name = self.text(cn_links[2])?;
+            let (factor, offset) = self.linear_conversion(cn_links[4])?;
+            let field = Field {
+                data_type: cn_data[2],
+                byte_offset: le_u32(&cn_data, 4) as usize,
+                bit_offset: cn_data[3],
+                bit_count: le_u32(&cn_data, 8),
+                factor,
+                offset,
+            };
+            let cn_type = cn_data[0];
+            if (cn_type == CN_TYPE_MASTER || cn_type == CN_TYPE_VIRTUAL_MASTER)
+                && cn_data[1] == CN_SYNC_TIME
+            {
+                group.time = Some(field);
+            } else if cn_type == CN_TYPE_VLSD && name.ends_with("DataBytes") {
+                bail!(ErrorKind::TraceFormat(
+                    "variable length DataBytes are not supported".to_owned()
+                ));
+            } else {
+                group
+                    .fields
+                    .insert(name.rsplit('.').next().unwrap_or(""), field);
+                names.push(name);
+            }
+        }
+
+        group.kind = if names.iter().any(|n| n == "CAN_DataFrame") {
+            Kind::DataFrame
+        } else if names.iter().any(|n| n == "CAN_RemoteFrame") {
+            Kind::RemoteFrame
+        } else if names.iter().any(|n| n == "CAN_ErrorFrame") {
+            Kind::ErrorFrame
+        } else {
+            Kind::Other
+        };
+
+        Ok((links, group))
+    }
+
+    /// Factor and offset of a linear conversion, identity otherwise.
+    fn linear_conversion(&mut self, offset: u64) -> Result<(f64, f64)> {
+        if offset == 0 {
+            return Ok((1.0, 0.0));
+        }
+        let (_, data) = self.block(offset, b"CC", 0, 1)?;
+        if data[0] == CC_LINEAR && data.len() >= 40 {
+            Ok((
+                f64::from_bits(le_u64(&data, 32)),
+                f64::from_bits(le_u64(&data, 24)),
+            ))
+        } else {
+            Ok((1.0, 0.0))
+        }
+    }
+
+    /// Collect the data segments reachable from a data link.
+    fn segments(
+        &mut self,
+        offset: u64,
+        segments: &mut Vec<Segment>,
+    ) -> Result<()> {
+        if offset == 0 {
+            return Ok(());
+        }
+        self.inner.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; BLOCK_HEADER_SIZE];
+        self.inner.read_exact(&mut header)?;
+        let length = le_u64(&header, 8);
+
+        match &header[0..4] {
+            b"##DT" => {
+                // The length of the last block of an unfinished file is
+                // fixed up once all data groups are known
+                let unfinished =
+                    self.unfinalised_flags & UNFINALISED_DT_LENGTH != 0;
+                let end = offset.saturating_add(length).min(self.file_size);
+                if length < BLOCK_HEADER_SIZE as u64
+                    || (end - offset < length && !unfinished)
+                {
+                    bail!(ErrorKind::TraceFormat(format!(
+                        "corrupt data block at offset {}",
+                        offset
+                    )));
+                }
+                let start = offset + BLOCK_HEADER_SIZE as u64;
+                segments.push(Segment {
+                    offset: start,
+                    length: end.saturating_sub(start),
+                    zip: None,
+                });
+            }
+            b"##DZ" => {
+                let mut dz = [0u8; 24];
+                self.inner.read_exact(&mut dz)?;
+                if &dz[0..2] != b"DT" {
+                    return Ok(());
+                }
+                let start = offset + BLOCK_HEADER_SIZE as u64 + 24;
+                let (inflated, length) = (le_u64(&dz, 8), le_u64(&dz, 16));
+                if length > self.file_size.saturating_sub(start)
+                    || inflated > MAX_INFLATED_SIZE
+                {
+                    bail!(ErrorKind::TraceFormat(format!(
+                        "corrupt compressed block at offset {}",
+                        offset
+                    )));
+                }
+                segments.push(Segment {
+                    offset: start,
+                    length,
+                    zip: Some((dz[2], le_u32(&dz, 4), inflated)),
+                });
+            }
+            b"##HL" => {
+                let (links, _) = self.block(offset, b"HL", 1, 0)?;
+                self.segments(links[0], segments)?;
+            }
+            b"##DL" => {
+                let mut next = offset;
+                while next != 0 {
+                    let (links, _) = self.block(next, b"DL", 1, 0)?;
+                    for &data in &links[1..] {
+                        self.segments(data, segments)?;
+                    }
+                    next = links[0];
+                }
+            }
+            _ => {
+                bail!(ErrorKind::TraceFormat(format!(
+                    "unsupported data block at offset {}",
+                    offset
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Load the next segment of the current data group into the buffer.
+    fn load_segment(&mut self) -> Result<bool> {
+        let segment =
+            match self.data_groups[self.current].segments.get(self.segment) {
+                Some(segment) => *segment,
+                None => return Ok(false),
+            };
+        self.segment += 1;
+        self.inner.seek(SeekFrom::Start(segment.offset))?;
+        self.buffer.drain(..self.pos);
+        self.pos = 0;
+
+        match segment.zip {
+            None => {
+                self.remaining = segment.length;
+                self.refill()?;
+            }
+            Some((zip_type, parameter, inflated)) => {
+                let mut compressed =
+                    Vec::with_capacity(segment.length as usize);
+                (&mut self.inner)
+                    .take(segment.length)
+                    .read_to_end(&mut compressed)?;
+                let mut data = Vec::new();
+                ZlibDecoder::new(&compressed[..])
+                    .take(inflated)
+                    .read_to_end(&mut data)?;
+                if data.len() as u64 != inflated {
+                    bail!(ErrorKind::TraceFormat(
+                        "truncated compressed block".to_owned()
+                    ));
+                }
+                if zip_type == 1 {
+                    data = untranspose(&data, parameter as usize);
+                }
+                self.buffer.extend_from_slice(&data);
+                self.remaining = 0;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Top up the buffer from an uncompressed segment.
+    fn refill(&mut self) -> io::Result<()> {
+        const CHUNK: u64 = 64 * 1024;
+        if self.remaining == 0 {
+            return Ok(());
+        }
+        let chunk = self.remaining.min(CHUNK);
+        self.buffer.drain(..self.pos);
+        self.pos = 0;
+        let start = self.buffer.len();
+        self.buffer.resize(start + chunk as usize, 0);
+        self.inner.read_exact(&mut self.buffer[start..])?;
+        self.remaining -= chunk;
+        Ok(())
+    }
+
+    /// Make sure `count` bytes are buffered, pulling in further segments.
+    fn ensure(&mut self, count: usize) -> Result<bool> {
+        while self.buffer.len() - self.pos < count {
+            if self.remaining > 0 {
+                self.refill()?;
+            } else if !self.load_segment()? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn next_record(&mut self) -> Result<Option<Option<Record>>> {
+        while self.current < self.data_groups.len() {
+            let id_size = self.data_groups[self.current].record_id_size;
+            if !self.ensure(id_size.max(1))? {
+                self.current += 1;
+                self.segment = 0;
+                self.buffer.clear();
+                self.pos = 0;
+                self.remaining = 0;
+                continue;
+            }
+
+            let record_id = match id_size {
+                0 => 0,
+                1 => self.buffer[self.pos] as u64,
+                2 => le_u16(&self.buffer, self.pos) as u64,
+                4 => le_u32(&self.buffer, self.pos) as u64,
+                _ => le_u64(&self.buffer, self.pos),
+            };
+            let (size, vlsd) =
+                match self.data_groups[self.current].group(record_id) {
+                    Some(group) => (group.size, group.vlsd),
+                    None => bail!(ErrorKind::TraceFormat(format!(
+                        "unknown record id {}",
+                        record_id
+                    ))),
+                };
+
+            if vlsd {
+                if !self.ensure(id_size + 4)? {
+                    bail!(ErrorKind::TraceFormat(
+                        "truncated record".to_owned()
+                    ));
+                }
+                let length = le_u32(&self.buffer, self.pos + id_size) as usize;
+                if !self.ensure(id_size + 4 + length)? {
+                    bail!(ErrorKind::TraceFormat(
+                        "truncated record".to_owned()
+                    ));
+                }
+                self.pos += id_size + 4 + length;
+                return Ok(Some(None));
+            }
+
+            if !self.ensure(id_size + size)? {
+                bail!(ErrorKind::TraceFormat("truncated record".to_owned()));
+            }
+            let start = self.pos + id_size;
+            let group = self.data_groups[self.current]
+                .group(record_id)
+                .expect("group of the record");
+            let record = group.decode(&self.buffer[start..start + size])?;
+            self.pos = start + size;
+            return Ok(Some(record));
+        }
+        Ok(None)
+    }
+}
+
+impl<R: Read + Seek> Iterator for Reader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        loop {
+            match self.next_record() {
+                Ok(Some(Some(record))) => return Some(Ok(record)),
+                Ok(Some(None)) => continue,
+                Ok(None) => return None,
+                Err(e) => {
+                    // Stop after an error rather than decoding garbage
+                    self.current = self.data_groups.len();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Undo the byte transposition of a `DZ` block with `columns` byte columns.
+fn untranspose(data: &[u8], columns: usize) -> Vec<u8> {
+    if columns == 0 {
+        return data.to_vec();
+    }
+    let rows = data.len() / columns;
+    let mut out = data.to_vec();
+    for row in 0..rows {
+        for column in 0..columns {
+            out[row * columns + column] = data[column * rows + row];
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use {CANFrame, CANFrameFd};
+
+    #[test]
+    fn write_and_read_back() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_760_000_000);
+        let events = [
+            Event::Frame(
+                CANFrame::new(0x7DF, &[0x02, 0x01, 0x0C], false).unwrap(),
+            ),
+            Event::Frame(
+                CANFrame::new_extended(
+                    0x18FEF100,
+                    &[1, 2, 3, 4, 5, 6, 7, 8],
+                    false,
+                )
+                .unwrap(),
+            ),
+            Event::Frame(CANFrame::new(0x321, &[], true).unwrap()),
+            Event::FdFrame(
+                CANFrameFd::new(0x456, &[0xA5; 64], false, true).unwrap(),
+            ),
+            Event::ErrorFrame,
+        ];
+
+        let mut writer = Writer::new(Cursor::new(Vec::new()), start).unwrap();
+        for (i, event) in events.iter().enumerate() {
+            writer
+                .write_record(&Record {
+                    timestamp: timestamp_from_micros(10_000 * i as u64 + 7),
+                    channel: 2,
+                    direction: if i == 1 {
+                        Direction::Tx
+                    } else {
+                        Direction::Rx
+                    },
+                    event: *event,
+                })
+                .unwrap();
+        }
+        let file = writer.finish().unwrap().into_inner();
+        assert_eq!(&file[0..8], b"MDF     ");
+
+        // A file from a writer that was never finished is still readable
+        let mut unfinished = file.clone();
+        unfinished[..ID_BLOCK_SIZE].copy_from_slice(&id_block(true));
+        let reader = Reader::new(Cursor::new(unfinished)).unwrap();
+        assert_eq!(reader.count(), 5);
+
+        let reader = Reader::new(Cursor::new(file)).unwrap();
+        assert_eq!(reader.start_time(), start);
+        let records: Vec<Record> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 5);
+
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(record.micros(), 10_000 * i as u64 + 7);
+            assert_eq!(record.channel, 2);
+        }
+        assert_eq!(records[1].direction, Direction::Tx);
+        match records[1].event {
+            Event::Frame(ref frame) => {
+                assert!(frame.is_extended());
+                assert_eq!(frame.id(), 0x18FEF100);
+                assert_eq!(frame.data(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+            }
+            _ => panic!("expected a classic frame"),
+        }
+        match records[2].event {
+            Event::Frame(ref frame) => assert!(frame.is_rtr()),
+            _ => panic!("expected a remote frame"),
+        }
+        match records[3].event {
+            Event::FdFrame(ref frame) => {
+                assert_eq!(frame.data(), &[0xA5; 64][..])
+            }
+            _ => panic!("expected an FD frame"),
+        }
+        match records[4].event {
+            Event::ErrorFrame => {}
+            _ => panic!("expected an error frame"),
+        }
+    }
+
+    #[test]
+    fn truncated_and_corrupt_files() {
+        let mut writer =
+            Writer::new(Cursor::new(Vec::new()), UNIX_EPOCH).unwrap();
+        for i in 0..3 {
+            let frame = CANFrame::new(0x100 + i, &[i as u8; 8], false).unwrap();
+            writer
+                .write_record(&Record {
+                    timestamp: timestamp_from_micros(i as u64),
+                    channel: 1,
+                    direction: Direction::Rx,
+                    event: Event::Frame(frame),
+                })
+                .unwrap();
+        }
+        let file = writer.finish().unwrap().into_inner();
+
+        // Every cut either fails cleanly or loses records, never panics
+        for length in 0..file.len() {
+            let records = Reader::new(Cursor::new(&file[..length]))
+                .map(|reader| reader.filter_map(|r| r.ok()).count());
+            assert!(records.map_or(true, |count| count < 3), "{}", length);
+        }
+
+        // Header link counts beyond the block length or missing links
+        let link_count = ID_BLOCK_SIZE + 16;
+        for &count in &[u64::MAX, 0] {
+            let mut corrupt = file.clone();
+            corrupt[link_count..link_count + 8]
+                .copy_from_slice(&count.to_le_bytes());
+            assert!(Reader::new(Cursor::new(corrupt)).is_err());
+        }
+    }
+
+    #[test]
+    fn untranspose_columns() {
+        // Two records of three bytes stored column by column plus a tail
+        let data = [1, 4, 2, 5, 3, 6, 9];
+        assert_eq!(untranspose(&data, 3), vec![1, 2, 3, 4, 5, 6, 9]);
+    }
+}
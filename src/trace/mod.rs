@@ -0,0 +1,130 @@
+//! Reading and writing CAN trace files produced by other tools.
+//!
+//! Every format is streamed record by record: readers implement `Iterator`
+//! over `Result<Record>` and writers accept one `Record` at a time, so the
+//! size of a trace is never limited by available memory.
+use errors::*;
+use pcan_basic_sys as pcan;
+use {fd_dlc_to_len, fd_len_to_dlc, CANFrame, CANFrameFd, MessageType};
+
+pub mod asc;
+pub mod blf;
+mod bytes;
+pub mod candump;
+pub mod mdf;
+pub mod pcap;
+pub mod trc;
+
+/// Direction of a frame as seen by the logging node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+/// The bus event stored in a trace record.
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    Frame(CANFrame),
+    FdFrame(CANFrameFd),
+    ErrorFrame,
+}
+
+/// A single timestamped entry of a trace file.
+#[derive(Debug, Clone, Copy)]
+pub struct Record {
+    pub timestamp: pcan::TPCANTimestamp,
+    pub channel: u8, // 1-based bus channel, as used by most logging tools
+    pub direction: Direction,
+    pub event: Event,
+}
+
+impl Record {
+    /// Wrap a frame returned by `PCANDevice::read_frame`.
+    pub fn rx(
+        frame: CANFrame,
+        timestamp: pcan::TPCANTimestamp,
+        channel: u8,
+    ) -> Record {
+        Record {
+            timestamp,
+            channel,
+            direction: Direction::Rx,
+            event: Event::Frame(frame),
+        }
+    }
+
+    /// Timestamp of the record in microseconds.
+    pub fn micros(&self) -> u64 {
+        timestamp_to_micros(&self.timestamp)
+    }
+
+    /// Identifier of the frame, if the record holds one.
+    pub fn id(&self) -> Option<u32> {
+        match self.event {
+            Event::Frame(ref frame) => Some(frame.id()),
+            Event::FdFrame(ref frame) => Some(frame.id()),
+            Event::ErrorFrame => None,
+        }
+    }
+}
+
+/// Flatten a PCAN timestamp into microseconds.
+pub fn timestamp_to_micros(ts: &pcan::TPCANTimestamp) -> u64 {
+    let millis = ((ts.millis_overflow as u64) << 32) + ts.millis as u64;
+    millis * 1000 + ts.micros as u64
+}
+
+/// Split a microsecond count into a PCAN timestamp.
+pub fn timestamp_from_micros(micros: u64) -> pcan::TPCANTimestamp {
+    let millis = micros / 1000;
+    pcan::TPCANTimestamp {
+        millis: millis as u32,
+        millis_overflow: (millis >> 32) as u16,
+        micros: (micros % 1000) as u16,
+    }
+}
+
+fn new_frame(
+    id: u32,
+    data: &[u8],
+    extended: bool,
+    rtr: bool,
+) -> Result<CANFrame> {
+    if extended {
+        CANFrame::new_extended(id, data, rtr)
+    } else {
+        CANFrame::new(id, data, rtr)
+    }
+}
+
+/// Build an FD frame from a payload that may not be DLC aligned, as some
+/// formats store the number of valid bytes rather than the DLC.
+fn new_fd_frame(
+    id: u32,
+    data: &[u8],
+    extended: bool,
+    brs: bool,
+    esi: bool,
+) -> Result<CANFrameFd> {
+    let len = fd_len_to_dlc(data.len()).map(fd_dlc_to_len).unwrap_or(64);
+    let mut padded = [0u8; 64];
+    padded[..data.len()].copy_from_slice(data);
+    let mut frame = CANFrameFd::new(id, &padded[..len], extended, brs)?;
+    if esi {
+        frame.0.MSGTYPE |= u8::from(MessageType::Esi);
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_round_trip() {
+        for &us in &[0, 999, 1000, 1_234_567, 0x1_0000_0000 * 1000 + 42] {
+            assert_eq!(timestamp_to_micros(&timestamp_from_micros(us)), us);
+        }
+    }
+}
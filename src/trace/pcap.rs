@@ -0,0 +1,759 @@
+//! pcap and pcapng captures using the `LINKTYPE_CAN_SOCKETCAN` link type,
+//! as understood by Wireshark's CAN dissectors.
+//!
+//! Each packet is a SocketCAN `can_frame`/`canfd_frame`: a big-endian
+//! identifier word carrying the EFF/RTR/ERR flags, the payload length, the
+//! FD flags, two reserved bytes and the payload. The reader accepts both
+//! byte orders and microsecond or nanosecond resolution. pcapng captures
+//! get one interface per bus channel, and the packet direction is kept in
+//! the `epb_flags` option.
+//!
+//! Packet timestamps are absolute, so the writer adds the record timestamps
+//! to a start time and the reader returns microseconds since the Unix epoch.
+use errors::*;
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use trace::bytes::{
+    be_u16, be_u32, le_u16, le_u32, put_be_u32, put_u16, put_u32, read_full,
+    skip,
+};
+use trace::{
+    new_fd_frame, new_frame, timestamp_from_micros, Direction, Event, Record,
+};
+
+/// `LINKTYPE_CAN_SOCKETCAN`
+pub const LINKTYPE_CAN_SOCKETCAN: u32 = 227;
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const SIMPLE_PACKET_BLOCK: u32 = 3;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+
+const OPT_END: u16 = 0;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+const EPB_INBOUND: u32 = 1;
+const EPB_OUTBOUND: u32 = 2;
+
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+const CAN_RTR_FLAG: u32 = 0x4000_0000;
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+const CANFD_BRS: u8 = 0x01;
+const CANFD_ESI: u8 = 0x02;
+const CANFD_FDF: u8 = 0x04;
+const CAN_MTU: usize = 16;
+const CANFD_MTU: usize = 72;
+/// Largest pcapng block read into memory; others are skipped.
+const MAX_BLOCK_LENGTH: usize = 0x1_0000;
+
+/// Capture file flavour.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Pcap,
+    PcapNg,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    link_type: u32,
+    ticks_per_second: u64,
+    channel: u8,
+    /// Longest packet in the capture, 0 if unlimited
+    snap_length: usize,
+}
+
+impl Interface {
+    /// Check the captured length of a packet before reading it.
+    fn check_captured(&self, captured: usize) -> Result<()> {
+        if captured > CANFD_MTU
+            || (self.snap_length != 0 && captured > self.snap_length)
+        {
+            bail!(ErrorKind::TraceFormat(format!(
+                "packet of {} bytes is too long",
+                captured
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Streaming pcap/pcapng reader, detecting the flavour from the file magic.
+pub struct Reader<R> {
+    inner: R,
+    format: Format,
+    big_endian: bool,
+    interfaces: Vec<Interface>,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut inner: R) -> Result<Reader<R>> {
+        let mut magic = [0u8; 4];
+        inner.read_exact(&mut magic)?;
+
+        if le_u32(&magic, 0) == SECTION_HEADER_BLOCK {
+            let mut reader = Reader {
+                inner,
+                format: Format::PcapNg,
+                big_endian: false,
+                interfaces: Vec::new(),
+            };
+            let mut fixed = [0u8; 8];
+            reader.inner.read_exact(&mut fixed)?;
+            reader.section_header(&fixed)?;
+            return Ok(reader);
+        }
+
+        let (big_endian, ticks_per_second) =
+            match (le_u32(&magic, 0), be_u32(&magic, 0)) {
+                (PCAP_MAGIC_MICROS, _) => (false, 1_000_000),
+                (PCAP_MAGIC_NANOS, _) => (false, 1_000_000_000),
+                (_, PCAP_MAGIC_MICROS) => (true, 1_000_000),
+                (_, PCAP_MAGIC_NANOS) => (true, 1_000_000_000),
+                _ => bail!(ErrorKind::TraceFormat(
+                    "not a pcap or pcapng file".to_owned()
+                )),
+            };
+
+        let mut header = [0u8; 20];
+        inner.read_exact(&mut header)?;
+        let mut reader = Reader {
+            inner,
+            format: Format::Pcap,
+            big_endian,
+            interfaces: Vec::new(),
+        };
+        let link_type = reader.u32_at(&header, 16) & 0x0FFF_FFFF;
+        if link_type != LINKTYPE_CAN_SOCKETCAN {
+            bail!(ErrorKind::TraceFormat(format!(
+                "unsupported link type {}",
+                link_type
+            )));
+        }
+        reader.interfaces.push(Interface {
+            link_type,
+            ticks_per_second,
+            channel: 1,
+            snap_length: reader.u32_at(&header, 12) as usize,
+        });
+
+        Ok(reader)
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    fn u16_at(&self, buf: &[u8], offset: usize) -> u16 {
+        if self.big_endian {
+            be_u16(buf, offset)
+        } else {
+            le_u16(buf, offset)
+        }
+    }
+
+    fn u32_at(&self, buf: &[u8], offset: usize) -> u32 {
+        if self.big_endian {
+            be_u32(buf, offset)
+        } else {
+            le_u32(buf, offset)
+        }
+    }
+
+    /// Handle a section header block given its length and byte order magic,
+    /// skipping the rest of the block.
+    fn section_header(&mut self, fixed: &[u8]) -> Result<()> {
+        self.big_endian = match (le_u32(fixed, 4), be_u32(fixed, 4)) {
+            (BYTE_ORDER_MAGIC, _) => false,
+            (_, BYTE_ORDER_MAGIC) => true,
+            _ => bail!(ErrorKind::TraceFormat(
+                "bad pcapng byte order magic".to_owned()
+            )),
+        };
+        let length = self.u32_at(fixed, 0) as u64;
+        if length < 28 {
+            bail!(ErrorKind::TraceFormat(
+                "section header block too short".to_owned()
+            ));
+        }
+        // Interfaces are numbered per section
+        self.interfaces.clear();
+        skip(&mut self.inner, length - 12)
+    }
+
+    fn next_pcap(&mut self) -> Result<Option<Record>> {
+        let mut header = [0u8; 16];
+        if read_full(&mut self.inner, &mut header)? < header.len() {
+            return Ok(None);
+        }
+        let seconds = self.u32_at(&header, 0) as u64;
+        let fraction = self.u32_at(&header, 4) as u64;
+        let captured = self.u32_at(&header, 8) as usize;
+        let interface = self.interfaces[0];
+        interface.check_captured(captured)?;
+        let mut data = vec![0u8; captured];
+        self.inner.read_exact(&mut data)?;
+
+        let micros = seconds * 1_000_000
+            + fraction * 1_000_000 / interface.ticks_per_second;
+        decode_packet(&data, micros, interface.channel, Direction::Rx)
+    }
+
+    fn next_pcapng(&mut self) -> Result<Option<Option<Record>>> {
+        let mut header = [0u8; 8];
+        if read_full(&mut self.inner, &mut header)? < header.len() {
+            return Ok(None);
+        }
+        let block_type = self.u32_at(&header, 0);
+        if block_type == SECTION_HEADER_BLOCK {
+            // The new section may use another byte order
+            let mut fixed = [0u8; 8];
+            fixed[..4].copy_from_slice(&header[4..]);
+            self.inner.read_exact(&mut fixed[4..])?;
+            self.section_header(&fixed)?;
+            return Ok(Some(None));
+        }
+
+        let length = self.u32_at(&header, 4) as usize;
+        if length < 12 || !length.is_multiple_of(4) {
+            bail!(ErrorKind::TraceFormat(format!(
+                "bad pcapng block length {}",
+                length
+            )));
+        }
+        let known = [
+            INTERFACE_DESCRIPTION_BLOCK,
+            ENHANCED_PACKET_BLOCK,
+            SIMPLE_PACKET_BLOCK,
+        ];
+        if !known.contains(&block_type) {
+            skip(&mut self.inner, length as u64 - 8)?;
+            return Ok(Some(None));
+        }
+        if length > MAX_BLOCK_LENGTH {
+            bail!(ErrorKind::TraceFormat(format!(
+                "pcapng block of {} bytes is too long",
+                length
+            )));
+        }
+        let mut body = vec![0u8; length - 8];
+        self.inner.read_exact(&mut body)?;
+        let body = &body[..length - 12];
+
+        match block_type {
+            INTERFACE_DESCRIPTION_BLOCK => {
+                if body.len() < 8 {
+                    bail!(ErrorKind::TraceFormat(
+                        "truncated interface block".to_owned()
+                    ));
+                }
+                let mut interface = Interface {
+                    link_type: self.u16_at(body, 0) as u32,
+                    ticks_per_second: 1_000_000,
+                    channel: (self.interfaces.len() + 1).min(u8::MAX as usize)
+                        as u8,
+                    snap_length: self.u32_at(body, 4) as usize,
+                };
+                for (code, value) in self.options(&body[8..]) {
+                    if code == IF_TSRESOL && !value.is_empty() {
+                        let exponent = (value[0] & 0x7F) as u32;
+                        let base: u64 =
+                            if value[0] & 0x80 != 0 { 2 } else { 10 };
+                        interface.ticks_per_second =
+                            base.checked_pow(exponent).unwrap_or(u64::MAX);
+                    }
+                    // Interfaces named after SocketCAN devices keep their channel
+                    if code == IF_NAME {
+                        let name = String::from_utf8_lossy(value);
+                        if let Some(Ok(index)) = name
+                            .trim_end_matches('\0')
+                            .strip_prefix("can")
+                            .map(str::parse::<u8>)
+                        {
+                            interface.channel = index.saturating_add(1);
+                        }
+                    }
+                }
+                self.interfaces.push(interface);
+                Ok(Some(None))
+            }
+            ENHANCED_PACKET_BLOCK => {
+                if body.len() < 20 {
+                    bail!(ErrorKind::TraceFormat(
+                        "truncated packet block".to_owned()
+                    ));
+                }
+                let interface_id = self.u32_at(body, 0) as usize;
+                let ticks = (self.u32_at(body, 4) as u64) << 32
+                    | self.u32_at(body, 8) as u64;
+                let captured = self.u32_at(body, 12) as usize;
+                let interface = match self.interfaces.get(interface_id) {
+                    Some(interface) => *interface,
+                    None => bail!(ErrorKind::TraceFormat(format!(
+                        "unknown interface {}",
+                        interface_id
+                    ))),
+                };
+                if interface.link_type != LINKTYPE_CAN_SOCKETCAN {
+                    return Ok(Some(None));
+                }
+                interface.check_captured(captured)?;
+                let padded = (captured + 3) & !3;
+                if body.len() < 20 + padded {
+                    bail!(ErrorKind::TraceFormat(
+                        "truncated packet block".to_owned()
+                    ));
+                }
+
+                let mut direction = Direction::Rx;
+                for (code, value) in self.options(&body[20 + padded..]) {
+                    if code == EPB_FLAGS
+                        && value.len() >= 4
+                        && self.u32_at(value, 0) & 0x3 == EPB_OUTBOUND
+                    {
+                        direction = Direction::Tx;
+                    }
+                }
+
+                let micros = (ticks as u128 * 1_000_000
+                    / interface.ticks_per_second as u128)
+                    as u64;
+                decode_packet(
+                    &body[20..20 + captured],
+                    micros,
+                    interface.channel,
+                    direction,
+                )
+                .map(Some)
+            }
+            SIMPLE_PACKET_BLOCK => {
+                // Simple packets have no timestamp and always belong to the
+                // first interface
+                let interface = match self.interfaces.first() {
+                    Some(interface)
+                        if interface.link_type == LINKTYPE_CAN_SOCKETCAN =>
+                    {
+                        *interface
+                    }
+                    _ => return Ok(Some(None)),
+                };
+                if body.len() < 4 {
+                    return Ok(Some(None));
+                }
+                let captured =
+                    (self.u32_at(body, 0) as usize).min(body.len() - 4);
+                interface.check_captured(captured)?;
+                decode_packet(
+                    &body[4..4 + captured],
+                    0,
+                    interface.channel,
+                    Direction::Rx,
+                )
+                .map(Some)
+            }
+            _ => Ok(Some(None)),
+        }
+    }
+
+    /// Split a pcapng option list into `(code, value)` pairs.
+    fn options<'a>(&self, mut buf: &'a [u8]) -> Vec<(u16, &'a [u8])> {
+        let mut options = Vec::new();
+        while buf.len() >= 4 {
+            let code = self.u16_at(buf, 0);
+            let length = self.u16_at(buf, 2) as usize;
+            if code == OPT_END || buf.len() < 4 + length {
+                break;
+            }
+            options.push((code, &buf[4..4 + length]));
+            let padded = (4 + length + 3) & !3;
+            buf = &buf[padded.min(buf.len())..];
+        }
+        options
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        loop {
+            let next = match self.format {
+                Format::Pcap => self.next_pcap().map(|record| record.map(Some)),
+                Format::PcapNg => self.next_pcapng(),
+            };
+            match next {
+                Ok(Some(Some(record))) => return Some(Ok(record)),
+                Ok(Some(None)) => continue,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Turn a SocketCAN packet into a record.
+fn decode_packet(
+    data: &[u8],
+    micros: u64,
+    channel: u8,
+    direction: Direction,
+) -> Result<Option<Record>> {
+    if data.len() < 8 {
+        bail!(ErrorKind::TraceFormat(
+            "SocketCAN packet shorter than its header".to_owned()
+        ));
+    }
+    let raw_id = be_u32(data, 0);
+    let len = data[4] as usize;
+    let fd_flags = data[5];
+    let payload = &data[8..(8 + len).min(data.len())];
+    let id = raw_id & CAN_EFF_MASK;
+    let extended = raw_id & CAN_EFF_FLAG != 0;
+
+    let event = if raw_id & CAN_ERR_FLAG != 0 {
+        Event::ErrorFrame
+    } else if fd_flags & CANFD_FDF != 0 || data.len() == CANFD_MTU {
+        if payload.len() > 64 {
+            bail!(ErrorKind::TraceFormat(
+                "CAN FD payload longer than 64 bytes".to_owned()
+            ));
+        }
+        Event::FdFrame(new_fd_frame(
+            id,
+            payload,
+            extended,
+            fd_flags & CANFD_BRS != 0,
+            fd_flags & CANFD_ESI != 0,
+        )?)
+    } else if raw_id & CAN_RTR_FLAG != 0 {
+        let mut frame = new_frame(id, &[], extended, true)?;
+        frame.0.LEN = len.min(8) as u8;
+        Event::Frame(frame)
+    } else {
+        Event::Frame(new_frame(
+            id,
+            &payload[..payload.len().min(8)],
+            extended,
+            false,
+        )?)
+    };
+
+    Ok(Some(Record {
+        timestamp: timestamp_from_micros(micros),
+        channel,
+        direction,
+        event,
+    }))
+}
+
+/// Turn a record into a SocketCAN packet.
+fn encode_packet(event: &Event) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(CANFD_MTU);
+    match *event {
+        Event::Frame(ref frame) => {
+            let mut raw_id = frame.id();
+            if frame.is_extended() {
+                raw_id |= CAN_EFF_FLAG;
+            }
+            if frame.is_rtr() {
+                raw_id |= CAN_RTR_FLAG;
+            }
+            put_be_u32(&mut packet, raw_id);
+            packet.extend_from_slice(&[frame.len().min(8), 0, 0, 0]);
+            packet.extend_from_slice(&frame.data()[..8]);
+            if frame.is_rtr() {
+                // Remote frames carry no payload on the wire
+                for byte in &mut packet[8..] {
+                    *byte = 0;
+                }
+            }
+        }
+        Event::FdFrame(ref frame) => {
+            let mut raw_id = frame.id();
+            if frame.is_extended() {
+                raw_id |= CAN_EFF_FLAG;
+            }
+            let mut fd_flags = CANFD_FDF;
+            if frame.is_brs() {
+                fd_flags |= CANFD_BRS;
+            }
+            if frame.is_esi() {
+                fd_flags |= CANFD_ESI;
+            }
+            put_be_u32(&mut packet, raw_id);
+            packet.extend_from_slice(&[frame.len() as u8, fd_flags, 0, 0]);
+            packet.extend_from_slice(&frame.0.DATA);
+        }
+        Event::ErrorFrame => {
+            put_be_u32(&mut packet, CAN_ERR_FLAG);
+            packet.extend_from_slice(&[8, 0, 0, 0]);
+            packet.extend_from_slice(&[0u8; 8]);
+        }
+    }
+    debug_assert!(packet.len() == CAN_MTU || packet.len() == CANFD_MTU);
+    packet
+}
+
+/// Streaming pcap/pcapng writer.
+pub struct Writer<W: Write> {
+    inner: W,
+    format: Format,
+    start_micros: u64,
+    channels: Vec<u8>,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a writer. Record timestamps are offset by `start_time` to
+    /// produce the absolute packet times.
+    pub fn new(
+        mut inner: W,
+        format: Format,
+        start_time: SystemTime,
+    ) -> Result<Writer<W>> {
+        let start_micros = start_time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() * 1_000_000 + d.subsec_micros() as u64)
+            .unwrap_or(0);
+
+        let mut header = Vec::with_capacity(28);
+        match format {
+            Format::Pcap => {
+                put_u32(&mut header, PCAP_MAGIC_MICROS);
+                put_u16(&mut header, 2);
+                put_u16(&mut header, 4);
+                put_u32(&mut header, 0); // GMT offset
+                put_u32(&mut header, 0); // timestamp accuracy
+                put_u32(&mut header, 65_535);
+                put_u32(&mut header, LINKTYPE_CAN_SOCKETCAN);
+            }
+            Format::PcapNg => {
+                put_u32(&mut header, SECTION_HEADER_BLOCK);
+                put_u32(&mut header, 28);
+                put_u32(&mut header, BYTE_ORDER_MAGIC);
+                put_u16(&mut header, 1);
+                put_u16(&mut header, 0);
+                put_u32(&mut header, 0xFFFF_FFFF); // section length is unknown
+                put_u32(&mut header, 0xFFFF_FFFF);
+                put_u32(&mut header, 28);
+            }
+        }
+        inner.write_all(&header)?;
+
+        Ok(Writer {
+            inner,
+            format,
+            start_micros,
+            channels: Vec::new(),
+        })
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<()> {
+        let packet = encode_packet(&record.event);
+        let micros = self.start_micros + record.micros();
+        let mut block = Vec::with_capacity(packet.len() + 48);
+
+        match self.format {
+            Format::Pcap => {
+                put_u32(&mut block, (micros / 1_000_000) as u32);
+                put_u32(&mut block, (micros % 1_000_000) as u32);
+                put_u32(&mut block, packet.len() as u32);
+                put_u32(&mut block, packet.len() as u32);
+                block.extend_from_slice(&packet);
+            }
+            Format::PcapNg => {
+                let interface_id = self.interface(record.channel)?;
+                let flags = match record.direction {
+                    Direction::Rx => EPB_INBOUND,
+                    Direction::Tx => EPB_OUTBOUND,
+                };
+                // Packets are always 16 or 72 bytes so no padding is needed
+                let length = 32 + packet.len() + 12;
+                put_u32(&mut block, ENHANCED_PACKET_BLOCK);
+                put_u32(&mut block, length as u32);
+                put_u32(&mut block, interface_id);
+                put_u32(&mut block, (micros >> 32) as u32);
+                put_u32(&mut block, micros as u32);
+                put_u32(&mut block, packet.len() as u32);
+                put_u32(&mut block, packet.len() as u32);
+                block.extend_from_slice(&packet);
+                put_u16(&mut block, EPB_FLAGS);
+                put_u16(&mut block, 4);
+                put_u32(&mut block, flags);
+                put_u32(&mut block, OPT_END as u32);
+                put_u32(&mut block, length as u32);
+            }
+        }
+
+        self.inner.write_all(&block)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Index of the interface of `channel`, describing it on first use.
+    fn interface(&mut self, channel: u8) -> Result<u32> {
+        if let Some(index) = self.channels.iter().position(|&c| c == channel) {
+            return Ok(index as u32);
+        }
+
+        let name = format!("can{}", channel.saturating_sub(1));
+        let padded_name = (name.len() + 3) & !3;
+        let length = 20 + 4 + padded_name + 4;
+        let mut block = Vec::with_capacity(length);
+        put_u32(&mut block, INTERFACE_DESCRIPTION_BLOCK);
+        put_u32(&mut block, length as u32);
+        put_u16(&mut block, LINKTYPE_CAN_SOCKETCAN as u16);
+        put_u16(&mut block, 0);
+        put_u32(&mut block, 0); // no snap length limit
+        put_u16(&mut block, IF_NAME);
+        put_u16(&mut block, name.len() as u16);
+        block.extend_from_slice(name.as_bytes());
+        block.resize(block.len() + padded_name - name.len(), 0);
+        put_u32(&mut block, OPT_END as u32);
+        put_u32(&mut block, length as u32);
+        self.inner.write_all(&block)?;
+
+        self.channels.push(channel);
+        Ok(self.channels.len() as u32 - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::Duration;
+    use {CANFrame, CANFrameFd};
+
+    fn records() -> Vec<Record> {
+        let events = [
+            Event::Frame(
+                CANFrame::new(0x7E8, &[0x02, 0x50, 0x01], false).unwrap(),
+            ),
+            Event::Frame(
+                CANFrame::new_extended(0x18DB33F1, &[], true).unwrap(),
+            ),
+            Event::FdFrame(
+                CANFrameFd::new(0x123, &[0x55; 32], true, true).unwrap(),
+            ),
+            Event::ErrorFrame,
+        ];
+        events
+            .iter()
+            .enumerate()
+            .map(|(i, event)| Record {
+                timestamp: timestamp_from_micros(1_500 * i as u64),
+                channel: 2 - (i % 2) as u8,
+                direction: if i == 0 { Direction::Tx } else { Direction::Rx },
+                event: *event,
+            })
+            .collect()
+    }
+
+    fn round_trip(format: Format) -> Vec<Record> {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut writer = Writer::new(Vec::new(), format, start).unwrap();
+        for record in records() {
+            writer.write_record(&record).unwrap();
+        }
+        let file = writer.finish().unwrap();
+
+        let reader = Reader::new(Cursor::new(file)).unwrap();
+        assert_eq!(reader.format(), format);
+        reader.map(|r| r.unwrap()).collect()
+    }
+
+    fn check_events(read: &[Record]) {
+        assert_eq!(read.len(), 4);
+        assert_eq!(read[3].micros() - read[0].micros(), 4_500);
+        assert_eq!(read[0].micros(), 1_700_000_000_000_000);
+        match read[0].event {
+            Event::Frame(ref frame) => {
+                assert_eq!(&frame.data()[..3], &[0x02, 0x50, 0x01])
+            }
+            _ => panic!("expected a classic frame"),
+        }
+        match read[1].event {
+            Event::Frame(ref frame) => {
+                assert!(frame.is_extended() && frame.is_rtr())
+            }
+            _ => panic!("expected a remote frame"),
+        }
+        match read[2].event {
+            Event::FdFrame(ref frame) => {
+                assert!(frame.is_extended() && frame.is_brs());
+                assert_eq!(frame.data(), &[0x55; 32][..]);
+            }
+            _ => panic!("expected an FD frame"),
+        }
+        match read[3].event {
+            Event::ErrorFrame => {}
+            _ => panic!("expected an error frame"),
+        }
+    }
+
+    #[test]
+    fn pcap_round_trip() {
+        check_events(&round_trip(Format::Pcap));
+    }
+
+    #[test]
+    fn pcapng_round_trip() {
+        let read = round_trip(Format::PcapNg);
+        check_events(&read);
+        assert_eq!(read[0].direction, Direction::Tx);
+        assert_eq!(read[1].direction, Direction::Rx);
+        assert_eq!(read[0].channel, 2);
+        assert_eq!(read[1].channel, 1);
+    }
+
+    #[test]
+    fn oversized_packets() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut file = Writer::new(Vec::new(), Format::Pcap, start)
+            .unwrap()
+            .finish()
+            .unwrap();
+        // A packet header claiming 4 GiB of data
+        file.extend_from_slice(&[
+            0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0xFF,
+        ]);
+        let mut reader = Reader::new(Cursor::new(file)).unwrap();
+        assert!(reader.next().unwrap().is_err());
+
+        let mut writer =
+            Writer::new(Vec::new(), Format::PcapNg, start).unwrap();
+        writer.write_record(&records()[0]).unwrap();
+        let mut file = writer.finish().unwrap();
+        let packet = file.len() - 60;
+        file[packet + 20] = 0x80; // captured length
+        let mut reader = Reader::new(Cursor::new(file.clone())).unwrap();
+        assert!(reader.next().unwrap().is_err());
+
+        // Unknown blocks are skipped whatever their size, others are limited
+        file.truncate(packet);
+        let mut block = vec![0u8; 0x2_0000];
+        block[..4].copy_from_slice(&0x0000_0004u32.to_le_bytes());
+        block[4..8].copy_from_slice(&0x2_0000u32.to_le_bytes());
+        file.extend_from_slice(&block);
+        assert!(Reader::new(Cursor::new(file.clone()))
+            .unwrap()
+            .next()
+            .is_none());
+        file[packet] = 6;
+        assert!(Reader::new(Cursor::new(file))
+            .unwrap()
+            .next()
+            .unwrap()
+            .is_err());
+    }
+}
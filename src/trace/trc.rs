@@ -0,0 +1,550 @@
+//! PEAK `.trc` trace files as written by PCAN-View and the PCAN-Basic
+//! tracer.
+//!
+//! The reader understands file versions 1.0 to 1.3 and 2.0/2.1 (honouring
+//! the `$COLUMNS` layout of 2.x files); the writer produces version 2.1.
+//! Time offsets are stored in milliseconds from `$STARTTIME`, an OLE
+//! automation date (days since 1899-12-30).
+use errors::*;
+use fd_dlc_to_len;
+use std::io::{BufRead, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use trace::{
+    new_fd_frame, new_frame, timestamp_from_micros, Direction, Event, Record,
+};
+
+/// Days between the OLE automation epoch and the Unix epoch.
+const OLE_UNIX_EPOCH_DAYS: f64 = 25_569.0;
+
+/// Trace file format version.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Version {
+    V1_0,
+    V1_1,
+    V1_2,
+    V1_3,
+    V2_0,
+    V2_1,
+}
+
+/// Streaming TRC reader.
+pub struct Reader<R> {
+    inner: R,
+    version: Version,
+    start_time: Option<SystemTime>,
+    columns: Vec<char>,
+    pending: Option<String>,
+    line_number: usize,
+}
+
+impl<R: BufRead> Reader<R> {
+    /// Create a reader, consuming the header comments of the file.
+    pub fn new(inner: R) -> Result<Reader<R>> {
+        let mut reader = Reader {
+            inner,
+            version: Version::V1_0,
+            start_time: None,
+            columns: Vec::new(),
+            pending: None,
+            line_number: 0,
+        };
+
+        while let Some(line) = reader.next_line()? {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if !trimmed.starts_with(';') {
+                reader.pending = Some(line);
+                break;
+            }
+
+            let setting = trimmed.trim_start_matches(';').trim();
+            if let Some(version) = setting.strip_prefix("$FILEVERSION=") {
+                reader.version = match version.trim() {
+                    "1.1" => Version::V1_1,
+                    "1.2" => Version::V1_2,
+                    "1.3" => Version::V1_3,
+                    "2.0" => Version::V2_0,
+                    "2.1" => Version::V2_1,
+                    _ => Version::V1_0,
+                };
+            } else if let Some(days) = setting.strip_prefix("$STARTTIME=") {
+                reader.start_time =
+                    days.trim().parse::<f64>().ok().and_then(ole_to_systemtime);
+            } else if let Some(columns) = setting.strip_prefix("$COLUMNS=") {
+                reader.columns = columns
+                    .split(',')
+                    .filter_map(|c| c.trim().chars().next())
+                    .collect();
+            }
+        }
+
+        if reader.columns.is_empty() {
+            reader.columns = match reader.version {
+                Version::V2_1 => "NOTBIdRLD".chars().collect(),
+                _ => "NOTIdlD".chars().collect(),
+            };
+        }
+
+        Ok(reader)
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Absolute time of the first time offset, if the file records it.
+    pub fn start_time(&self) -> Option<SystemTime> {
+        self.start_time
+    }
+
+    fn next_line(&mut self) -> Result<Option<String>> {
+        if let Some(line) = self.pending.take() {
+            return Ok(Some(line));
+        }
+
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line_number += 1;
+        Ok(Some(line))
+    }
+
+    fn malformed(&self, reason: &str) -> Error {
+        ErrorKind::TraceFormat(format!("line {}: {}", self.line_number, reason))
+            .into()
+    }
+
+    fn parse_line(&self, line: &str) -> Result<Option<Record>> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() || tokens[0].starts_with(';') {
+            return Ok(None);
+        }
+        if self.version >= Version::V2_0 {
+            self.parse_v2(&tokens)
+        } else {
+            self.parse_v1(&tokens)
+        }
+    }
+
+    /// `N) O [B] [T] I [-] l D...`
+    fn parse_v1(&self, tokens: &[&str]) -> Result<Option<Record>> {
+        let (bus, kind, id, dlc, data) = match self.version {
+            Version::V1_0 => (None, "Rx", 2, 3, 4),
+            Version::V1_1 => {
+                (None, tokens.get(2).cloned().unwrap_or(""), 3, 4, 5)
+            }
+            Version::V1_2 => {
+                (Some(2), tokens.get(3).cloned().unwrap_or(""), 4, 5, 6)
+            }
+            _ => (Some(2), tokens.get(3).cloned().unwrap_or(""), 4, 6, 7),
+        };
+        if tokens.len() < dlc + 1 {
+            return Err(self.malformed("too few columns"));
+        }
+
+        let micros = self.parse_offset(tokens[1])?;
+        let channel = match bus {
+            Some(bus) => tokens[bus]
+                .parse::<u8>()
+                .map_err(|_| self.malformed("invalid bus"))?,
+            None => 1,
+        };
+        let direction = match kind {
+            "Rx" => Direction::Rx,
+            "Tx" => Direction::Tx,
+            "Error" => {
+                return Ok(Some(self.record(
+                    micros,
+                    channel,
+                    Direction::Rx,
+                    Event::ErrorFrame,
+                )))
+            }
+            _ => return Ok(None), // warnings and other status lines
+        };
+
+        let (id, extended) = self.parse_id(tokens[id])?;
+        let dlc = tokens[dlc]
+            .parse::<usize>()
+            .map_err(|_| self.malformed("invalid DLC"))?;
+        let event = if tokens.get(data) == Some(&"RTR") {
+            let mut frame = new_frame(id, &[], extended, true)?;
+            frame.0.LEN = dlc.min(8) as u8;
+            Event::Frame(frame)
+        } else {
+            let data = self.parse_data(&tokens[data..], dlc.min(8))?;
+            Event::Frame(new_frame(id, &data, extended, false)?)
+        };
+
+        Ok(Some(self.record(micros, channel, direction, event)))
+    }
+
+    /// Columns as listed by `$COLUMNS`, data bytes always come last.
+    fn parse_v2(&self, tokens: &[&str]) -> Result<Option<Record>> {
+        let column = |name: char| {
+            self.columns
+                .iter()
+                .position(|&c| c == name)
+                .and_then(|i| tokens.get(i).cloned())
+        };
+
+        let micros = self.parse_offset(
+            column('O').ok_or_else(|| self.malformed("missing time offset"))?,
+        )?;
+        let channel = match column('B') {
+            Some(bus) if bus != "-" => bus
+                .parse::<u8>()
+                .map_err(|_| self.malformed("invalid bus"))?,
+            _ => 1,
+        };
+        let direction = match column('d') {
+            Some("Tx") => Direction::Tx,
+            _ => Direction::Rx,
+        };
+        let kind = column('T').unwrap_or("DT");
+        if kind == "ER" {
+            return Ok(Some(self.record(
+                micros,
+                channel,
+                direction,
+                Event::ErrorFrame,
+            )));
+        }
+        if !["DT", "FD", "FB", "FE", "BI", "RR"].contains(&kind) {
+            return Ok(None);
+        }
+
+        let (id, extended) = self.parse_id(
+            column('I').ok_or_else(|| self.malformed("missing id"))?,
+        )?;
+        let fd = kind != "DT" && kind != "RR";
+        let len = match (column('L'), column('l')) {
+            (Some(len), _) => len
+                .parse::<usize>()
+                .map_err(|_| self.malformed("invalid data length"))?,
+            (None, Some(dlc)) => {
+                let dlc = dlc
+                    .parse::<u8>()
+                    .map_err(|_| self.malformed("invalid DLC"))?;
+                if fd {
+                    fd_dlc_to_len(dlc)
+                } else {
+                    dlc.min(8) as usize
+                }
+            }
+            (None, None) => return Err(self.malformed("missing data length")),
+        };
+
+        let event = if kind == "RR" {
+            let mut frame = new_frame(id, &[], extended, true)?;
+            frame.0.LEN = len.min(8) as u8;
+            Event::Frame(frame)
+        } else {
+            let first = self
+                .columns
+                .iter()
+                .position(|&c| c == 'D')
+                .unwrap_or(tokens.len());
+            let data = self.parse_data(
+                tokens.get(first..).unwrap_or(&[]),
+                if fd { len.min(64) } else { len.min(8) },
+            )?;
+            if fd {
+                let brs = kind == "FB" || kind == "BI";
+                let esi = kind == "FE" || kind == "BI";
+                Event::FdFrame(new_fd_frame(id, &data, extended, brs, esi)?)
+            } else {
+                Event::Frame(new_frame(id, &data, extended, false)?)
+            }
+        };
+
+        Ok(Some(self.record(micros, channel, direction, event)))
+    }
+
+    fn record(
+        &self,
+        micros: u64,
+        channel: u8,
+        direction: Direction,
+        event: Event,
+    ) -> Record {
+        Record {
+            timestamp: timestamp_from_micros(micros),
+            channel,
+            direction,
+            event,
+        }
+    }
+
+    /// Parse a millisecond offset such as `1059.900` into microseconds.
+    fn parse_offset(&self, token: &str) -> Result<u64> {
+        let mut parts = token.splitn(2, '.');
+        let millis = parts
+            .next()
+            .unwrap_or("")
+            .parse::<u64>()
+            .map_err(|_| self.malformed("invalid time offset"))?;
+        let fraction = parts.next().unwrap_or("0");
+        if !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return Err(self.malformed("invalid time offset"));
+        }
+        let digits: String =
+            fraction.chars().chain("000".chars()).take(3).collect();
+        Ok(millis * 1000 + digits.parse::<u64>().unwrap_or(0))
+    }
+
+    /// Identifiers longer than four hex digits are extended.
+    fn parse_id(&self, token: &str) -> Result<(u32, bool)> {
+        let id = u32::from_str_radix(token, 16)
+            .map_err(|_| self.malformed("invalid id"))?;
+        Ok((id, token.len() > 4))
+    }
+
+    fn parse_data(&self, tokens: &[&str], count: usize) -> Result<Vec<u8>> {
+        if tokens.len() < count {
+            return Err(self.malformed("missing data bytes"));
+        }
+        tokens[..count]
+            .iter()
+            .map(|token| {
+                u8::from_str_radix(token, 16)
+                    .map_err(|_| self.malformed("invalid data byte"))
+            })
+            .collect()
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        loop {
+            let line = match self.next_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+
+            match self.parse_line(&line) {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Streaming writer producing version 2.1 files.
+pub struct Writer<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a writer for a measurement started at `start_time` and emit
+    /// the file header.
+    pub fn new(mut inner: W, start_time: SystemTime) -> Result<Writer<W>> {
+        writeln!(inner, ";$FILEVERSION=2.1")?;
+        writeln!(inner, ";$STARTTIME={:.10}", systemtime_to_ole(start_time))?;
+        writeln!(inner, ";$COLUMNS=N,O,T,B,I,d,R,L,D")?;
+        writeln!(inner, ";")?;
+        writeln!(
+            inner,
+            ";   Generated by pcan-basic {}",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(inner, ";")?;
+        writeln!(inner, ";   Message   Time    Type    ID     Rx/Tx")?;
+        writeln!(inner, ";   Number    Offset  |  Bus  [hex]  |  Reserved")?;
+        writeln!(
+            inner,
+            ";   |         [ms]    |  |    |      |  |  Data Length"
+        )?;
+        writeln!(
+            inner,
+            ";   |         |       |  |    |      |  |  |    Data [hex] ..."
+        )?;
+        writeln!(inner, ";   |         |       |  |    |      |  |  |    |")?;
+        writeln!(inner, ";---+-- ------+------ +- +- --+----- +- +- +--- +- -- -- -- -- -- -- --")?;
+
+        Ok(Writer { inner, count: 0 })
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<()> {
+        self.count += 1;
+        let micros = record.micros();
+        let offset = format!("{}.{:03}", micros / 1000, micros % 1000);
+        let direction = match record.direction {
+            Direction::Rx => "Rx",
+            Direction::Tx => "Tx",
+        };
+
+        let (kind, id, len, data) = match record.event {
+            Event::Frame(ref frame) if frame.is_rtr() => (
+                "RR",
+                format_id(frame.id(), frame.is_extended()),
+                frame.len() as usize,
+                String::new(),
+            ),
+            Event::Frame(ref frame) => {
+                let data = &frame.data()[..frame.len().min(8) as usize];
+                (
+                    "DT",
+                    format_id(frame.id(), frame.is_extended()),
+                    data.len(),
+                    format_bytes(data),
+                )
+            }
+            Event::FdFrame(ref frame) => {
+                let kind = match (frame.is_brs(), frame.is_esi()) {
+                    (false, false) => "FD",
+                    (true, false) => "FB",
+                    (false, true) => "FE",
+                    (true, true) => "BI",
+                };
+                (
+                    kind,
+                    format_id(frame.id(), frame.is_extended()),
+                    frame.len(),
+                    format_bytes(frame.data()),
+                )
+            }
+            Event::ErrorFrame => ("ER", "-".to_owned(), 0, String::new()),
+        };
+
+        let line = format!(
+            "{:>7} {:>13} {:>2} {:>2} {:>8} {:>2} -  {:<4} {}",
+            self.count, offset, kind, record.channel, id, direction, len, data
+        );
+        writeln!(self.inner, "{}", line.trim_end())?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+fn format_id(id: u32, extended: bool) -> String {
+    if extended {
+        format!("{:08X}", id)
+    } else {
+        format!("{:04X}", id)
+    }
+}
+
+fn format_bytes(data: &[u8]) -> String {
+    let bytes: Vec<String> =
+        data.iter().map(|byte| format!("{:02X}", byte)).collect();
+    bytes.join(" ")
+}
+
+fn ole_to_systemtime(days: f64) -> Option<SystemTime> {
+    let seconds = (days - OLE_UNIX_EPOCH_DAYS) * 86_400.0;
+    if seconds.is_finite() && seconds >= 0.0 {
+        Some(UNIX_EPOCH + Duration::from_micros((seconds * 1e6).round() as u64))
+    } else {
+        None
+    }
+}
+
+fn systemtime_to_ole(time: SystemTime) -> f64 {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as f64 + d.subsec_micros() as f64 / 1e6)
+        .unwrap_or(0.0);
+    OLE_UNIX_EPOCH_DAYS + seconds / 86_400.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use {CANFrame, CANFrameFd};
+
+    #[test]
+    fn read_version_1_1() {
+        let text = ";$FILEVERSION=1.1
+;$STARTTIME=43000.5
+;---+--   ----+----  --+--  ----+---  +  -+ -- -- -- -- -- -- --
+     1)      1841.0  Rx         0001  8  00 11 22 33 44 55 66 77
+     2)      1842.3  Tx     18FF0001  2  AA BB
+     3)      1850.0  Rx         0100  4  RTR
+     4)      1851.0  Warng  FFFFFFFF  4  00 00 00 08  BUSHEAVY
+";
+        let reader = Reader::new(Cursor::new(text)).unwrap();
+        assert_eq!(reader.version(), Version::V1_1);
+        assert!(reader.start_time().is_some());
+        let records: Vec<Record> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].micros(), 1_842_300);
+        assert_eq!(records[1].direction, Direction::Tx);
+        assert_eq!(records[1].id(), Some(0x18FF0001));
+        match records[2].event {
+            Event::Frame(ref frame) => {
+                assert!(frame.is_rtr() && frame.len() == 4)
+            }
+            _ => panic!("expected a remote frame"),
+        }
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut writer = Writer::new(Vec::new(), start).unwrap();
+        let events = [
+            Event::Frame(
+                CANFrame::new(0x7E0, &[0x02, 0x10, 0x03], false).unwrap(),
+            ),
+            Event::Frame(
+                CANFrame::new_extended(0x18DA00F1, &[], true).unwrap(),
+            ),
+            Event::FdFrame(
+                CANFrameFd::new(0x7E0, &[0x11; 12], false, true).unwrap(),
+            ),
+            Event::ErrorFrame,
+        ];
+        for (i, event) in events.iter().enumerate() {
+            writer
+                .write_record(&Record {
+                    timestamp: timestamp_from_micros(123_456 * i as u64),
+                    channel: 3,
+                    direction: Direction::Tx,
+                    event: *event,
+                })
+                .unwrap();
+        }
+        let text = writer.finish().unwrap();
+
+        let reader = Reader::new(Cursor::new(text)).unwrap();
+        assert_eq!(reader.version(), Version::V2_1);
+        let drift = reader
+            .start_time()
+            .unwrap()
+            .duration_since(start)
+            .unwrap_or_default();
+        assert!(drift < Duration::from_millis(1));
+        let records: Vec<Record> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[3].micros(), 370_368);
+        assert!(records
+            .iter()
+            .all(|r| r.channel == 3 && r.direction == Direction::Tx));
+        match records[2].event {
+            Event::FdFrame(ref frame) => {
+                assert!(frame.is_brs() && frame.data() == &[0x11; 12][..])
+            }
+            _ => panic!("expected an FD frame"),
+        }
+        match records[1].event {
+            Event::Frame(ref frame) => {
+                assert!(frame.is_rtr() && frame.is_extended())
+            }
+            _ => panic!("expected a remote frame"),
+        }
+    }
+}
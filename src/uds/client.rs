@@ -0,0 +1,498 @@
+//! Diagnostic client (tester side) of UDS.
+//!
+//! Requests wait P2 for a response. A "response pending" negative response
+//! extends the wait to P2* and may be repeated. Both timeouts are taken
+//! from the server's diagnostic session control response. Responses to
+//! other services received while waiting are dropped.
+//!
+//! The server falls back to the default session when it hears nothing for
+//! a while. With a tester present interval set, `keep_alive` sends a
+//! suppressed tester present when the interval has passed since the last
+//! request; call it regularly while idle.
+use super::*;
+use bus::CanBus;
+use errors::*;
+use isotp::IsoTp;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// P2 and P2* of a server until its session control response says
+/// otherwise.
+const DEFAULT_P2: Duration = Duration::from_millis(50);
+const DEFAULT_P2_STAR: Duration = Duration::from_millis(5000);
+
+/// Added to the server's timeouts for the delay of the network.
+const TIMING_MARGIN: Duration = Duration::from_millis(50);
+
+pub struct Client<B: CanBus> {
+    transport: IsoTp<B>,
+    p2: Duration,
+    p2_star: Duration,
+    tester_present_interval: Option<Duration>,
+    security_policy: SecurityPolicy,
+    last_request: Instant,
+}
+
+impl<B: CanBus> Client<B> {
+    pub fn new(transport: IsoTp<B>) -> Client<B> {
+        Client {
+            transport,
+            p2: DEFAULT_P2 + TIMING_MARGIN,
+            p2_star: DEFAULT_P2_STAR + TIMING_MARGIN,
+            tester_present_interval: None,
+            security_policy: SecurityPolicy::default(),
+            last_request: Instant::now(),
+        }
+    }
+
+    pub fn transport(&self) -> &IsoTp<B> {
+        &self.transport
+    }
+
+    pub fn into_inner(self) -> IsoTp<B> {
+        self.transport
+    }
+
+    /// Client side P2 and P2* timeouts.
+    pub fn timing(&self) -> (Duration, Duration) {
+        (self.p2, self.p2_star)
+    }
+
+    pub fn set_timing(&mut self, p2: Duration, p2_star: Duration) {
+        self.p2 = p2;
+        self.p2_star = p2_star;
+    }
+
+    pub fn set_tester_present_interval(&mut self, interval: Option<Duration>) {
+        self.tester_present_interval = interval;
+    }
+
+    pub fn set_security_policy(&mut self, policy: SecurityPolicy) {
+        self.security_policy = policy;
+    }
+
+    /// Send a request and wait for its positive response. Returns the
+    /// response after the service identifier.
+    pub fn request(&mut self, service: u8, data: &[u8]) -> Result<Vec<u8>> {
+        self.send(service, data)?;
+        let mut deadline = Instant::now() + self.p2;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let response = match self.transport.receive(timeout)? {
+                Some(response) => response,
+                None => bail!(ErrorKind::DiagnosticTimeout(service)),
+            };
+            match response.first() {
+                Some(&NEGATIVE_RESPONSE)
+                    if response.len() >= 3 && response[1] == service =>
+                {
+                    let code = NegativeResponseCode::from(response[2]);
+                    if code != NegativeResponseCode::ResponsePending {
+                        bail!(ErrorKind::NegativeResponse(service, code));
+                    }
+                    deadline = Instant::now() + self.p2_star;
+                }
+                Some(&sid)
+                    if sid
+                        == service.wrapping_add(POSITIVE_RESPONSE_OFFSET) =>
+                {
+                    return Ok(response[1..].to_vec());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Send a request with the suppress positive response bit set in its
+    /// sub-function (the first byte of `data`), without waiting.
+    pub fn request_suppressed(
+        &mut self,
+        service: u8,
+        data: &[u8],
+    ) -> Result<()> {
+        let mut data = data.to_vec();
+        if let Some(sub_function) = data.first_mut() {
+            *sub_function |= SUPPRESS_POSITIVE_RESPONSE;
+        }
+        self.send(service, &data)
+    }
+
+    fn send(&mut self, service: u8, data: &[u8]) -> Result<()> {
+        let mut request = Vec::with_capacity(data.len() + 1);
+        request.push(service);
+        request.extend_from_slice(data);
+        self.transport.send(&request)?;
+        self.last_request = Instant::now();
+        Ok(())
+    }
+
+    /// Switch session and adopt the server's P2 and P2*.
+    pub fn diagnostic_session_control(
+        &mut self,
+        session: Session,
+    ) -> Result<SessionTiming> {
+        let response =
+            self.request(DIAGNOSTIC_SESSION_CONTROL, &[session.into()])?;
+        expect(&response, &[session.into()], 5)?;
+        let timing = SessionTiming {
+            p2_max: u16::from_be_bytes([response[1], response[2]]),
+            p2_star_max: u16::from_be_bytes([response[3], response[4]]),
+        };
+        self.p2 =
+            Duration::from_millis(u64::from(timing.p2_max)) + TIMING_MARGIN;
+        self.p2_star =
+            Duration::from_millis(u64::from(timing.p2_star_max) * 10)
+                + TIMING_MARGIN;
+        Ok(timing)
+    }
+
+    /// Reset the server. Returns the power down time in seconds when
+    /// enabling rapid power shut down.
+    pub fn ecu_reset(&mut self, reset: ResetType) -> Result<Option<u8>> {
+        let response = self.request(ECU_RESET, &[reset.into()])?;
+        expect(&response, &[reset.into()], 1)?;
+        Ok(match reset {
+            ResetType::EnableRapidPowerShutDown => response.get(1).cloned(),
+            _ => None,
+        })
+    }
+
+    /// Request the seed of an (odd) security access level.
+    pub fn request_seed(&mut self, level: u8) -> Result<Vec<u8>> {
+        check_level(level)?;
+        let response = self.request(SECURITY_ACCESS, &[level])?;
+        expect(&response, &[level], 1)?;
+        Ok(response[1..].to_vec())
+    }
+
+    /// Send the key for the seed of `level`.
+    pub fn send_key(&mut self, level: u8, key: &[u8]) -> Result<()> {
+        check_level(level)?;
+        let mut data = vec![level + 1];
+        data.extend_from_slice(key);
+        let response = self.request(SECURITY_ACCESS, &data)?;
+        expect(&response, &[level + 1], 1)
+    }
+
+    /// Unlock a security access level with the key computed from its seed.
+    /// A seed of zeros means the level is already unlocked. Rejected keys
+    /// and lockouts are retried as the security policy allows.
+    pub fn unlock<S: SeedKey + ?Sized>(
+        &mut self,
+        level: u8,
+        seed_key: &S,
+    ) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            let result = self.request_seed(level).and_then(|seed| {
+                if seed.iter().all(|&b| b == 0) {
+                    return Ok(());
+                }
+                let key = seed_key.compute_key(level, &seed)?;
+                self.send_key(level, &key)
+            });
+            let error = match result {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+            let wait = match *error.kind() {
+                ErrorKind::NegativeResponse(
+                    SECURITY_ACCESS,
+                    NegativeResponseCode::InvalidKey,
+                ) => false,
+                ErrorKind::NegativeResponse(
+                    SECURITY_ACCESS,
+                    NegativeResponseCode::ExceededNumberOfAttempts,
+                )
+                | ErrorKind::NegativeResponse(
+                    SECURITY_ACCESS,
+                    NegativeResponseCode::RequiredTimeDelayNotExpired,
+                ) => true,
+                _ => return Err(error),
+            };
+            if attempt >= self.security_policy.attempts {
+                return Err(error);
+            }
+            attempt += 1;
+            if wait {
+                thread::sleep(self.security_policy.delay);
+            }
+        }
+    }
+
+    pub fn read_data_by_identifier(
+        &mut self,
+        identifier: u16,
+    ) -> Result<Vec<u8>> {
+        let did = identifier.to_be_bytes();
+        let response = self.request(READ_DATA_BY_IDENTIFIER, &did)?;
+        expect(&response, &did, 2)?;
+        Ok(response[2..].to_vec())
+    }
+
+    pub fn write_data_by_identifier(
+        &mut self,
+        identifier: u16,
+        data: &[u8],
+    ) -> Result<()> {
+        let did = identifier.to_be_bytes();
+        let mut request = did.to_vec();
+        request.extend_from_slice(data);
+        let response = self.request(WRITE_DATA_BY_IDENTIFIER, &request)?;
+        expect(&response, &did, 2)
+    }
+
+    /// Start, stop or get the results of a routine. Returns the routine
+    /// status record.
+    pub fn routine_control(
+        &mut self,
+        control: RoutineControl,
+        routine: u16,
+        options: &[u8],
+    ) -> Result<Vec<u8>> {
+        let mut request = vec![control.into()];
+        request.extend_from_slice(&routine.to_be_bytes());
+        request.extend_from_slice(options);
+        let response = self.request(ROUTINE_CONTROL, &request)?;
+        expect(&response, &request[..3], 3)?;
+        Ok(response[3..].to_vec())
+    }
+
+    /// Any read DTC information report. Returns the response after the
+    /// report type.
+    pub fn read_dtc_information(
+        &mut self,
+        report_type: u8,
+        parameters: &[u8],
+    ) -> Result<Vec<u8>> {
+        let mut request = vec![report_type];
+        request.extend_from_slice(parameters);
+        let response = self.request(READ_DTC_INFORMATION, &request)?;
+        expect(&response, &[report_type], 1)?;
+        Ok(response[1..].to_vec())
+    }
+
+    /// Clear the DTCs of a group, 0xFFFFFF for all.
+    pub fn clear_diagnostic_information(&mut self, group: u32) -> Result<()> {
+        let group = group.to_be_bytes();
+        self.request(CLEAR_DIAGNOSTIC_INFORMATION, &group[1..])
+            .map(|_| ())
+    }
+
+    /// Send a tester present without asking for a response.
+    pub fn tester_present(&mut self) -> Result<()> {
+        self.request_suppressed(TESTER_PRESENT, &[0x00])
+    }
+
+    /// Send a tester present if the tester present interval has passed
+    /// since the last request.
+    pub fn keep_alive(&mut self) -> Result<()> {
+        match self.tester_present_interval {
+            Some(interval) if self.last_request.elapsed() >= interval => {
+                self.tester_present()
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Seeds are requested with the odd levels 0x01 to 0x7D, keys sent with
+/// the level after.
+fn check_level(level: u8) -> Result<()> {
+    if level.is_multiple_of(2) || level > 0x7D {
+        bail!(ErrorKind::InvalidRequest(format!(
+            "0x{:02X} is not a security access level",
+            level
+        )));
+    }
+    Ok(())
+}
+
+/// Check that a response echoes the request and is long enough.
+pub fn expect(response: &[u8], echo: &[u8], min_len: usize) -> Result<()> {
+    if response.len() < min_len.max(echo.len()) {
+        bail!(ErrorKind::InvalidResponse(format!(
+            "{} bytes is too short",
+            response.len() + 1
+        )));
+    }
+    if !response.starts_with(echo) {
+        bail!(ErrorKind::InvalidResponse(format!(
+            "expected {:02X?}, got {:02X?}",
+            echo,
+            &response[..echo.len()]
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::{CanBus, VirtualBus};
+    use std::time::Duration;
+    use testing::{peer, uds_client as client, UdsEcu};
+
+    #[test]
+    fn session_timing_and_response_pending() {
+        let bus = VirtualBus::new();
+        let server = peer(
+            UdsEcu::new(bus.connect()),
+            vec![
+                (&[0x10, 0x03], vec![&[0x50, 0x03, 0x00, 0x19, 0x01, 0xF4]]),
+                (
+                    &[0x22, 0xF1, 0x90],
+                    vec![
+                        &[0x7F, 0x22, 0x78],
+                        &[0x62, 0xF1, 0x90, b'V', b'I', b'N'],
+                    ],
+                ),
+                (
+                    &[0x31, 0x01, 0xFF, 0x00, 0x01],
+                    vec![&[0x71, 0x01, 0xFF, 0x00, 0x00]],
+                ),
+            ],
+        );
+        let mut client = client(bus);
+
+        let timing = client
+            .diagnostic_session_control(Session::Extended)
+            .unwrap();
+        assert_eq!(
+            timing,
+            SessionTiming {
+                p2_max: 25,
+                p2_star_max: 500
+            }
+        );
+        assert_eq!(
+            client.timing(),
+            (Duration::from_millis(75), Duration::from_millis(5050))
+        );
+        assert_eq!(
+            client.read_data_by_identifier(0xF190).unwrap(),
+            b"VIN".to_vec()
+        );
+        assert_eq!(
+            client
+                .routine_control(RoutineControl::Start, 0xFF00, &[0x01])
+                .unwrap(),
+            vec![0x00]
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn negative_responses_security_access_and_dtcs() {
+        let bus = VirtualBus::new();
+        let server = peer(
+            UdsEcu::new(bus.connect()),
+            vec![
+                (&[0x22, 0x12, 0x34], vec![&[0x7F, 0x22, 0x31]]),
+                (&[0x27, 0x01], vec![&[0x67, 0x01, 0x12, 0x34]]),
+                (&[0x27, 0x02, 0xED, 0xCB], vec![&[0x67, 0x02]]),
+                (
+                    &[0x19, 0x02, 0x09],
+                    vec![&[
+                        0x59, 0x02, 0xFF, 0x12, 0x34, 0x56, 0x09, 0xC0, 0x01,
+                        0x00, 0x08,
+                    ]],
+                ),
+            ],
+        );
+        let mut client = client(bus);
+
+        match *client.read_data_by_identifier(0x1234).unwrap_err().kind() {
+            ErrorKind::NegativeResponse(
+                0x22,
+                NegativeResponseCode::RequestOutOfRange,
+            ) => {}
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        client.unlock(0x01, &XorKey { mask: vec![0xFF] }).unwrap();
+        let dtcs = client.read_dtcs_by_status(0x09).unwrap();
+        assert_eq!(dtcs.availability_mask, 0xFF);
+        assert_eq!(
+            dtcs.dtcs,
+            vec![
+                DtcRecord {
+                    code: Dtc(0x123456),
+                    status: DtcStatus(0x09)
+                },
+                DtcRecord {
+                    code: Dtc(0xC00100),
+                    status: DtcStatus(0x08)
+                },
+            ]
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn unlock_after_invalid_key_and_lockout() {
+        let bus = VirtualBus::new();
+        let server = peer(
+            UdsEcu::new(bus.connect()),
+            vec![
+                (&[0x27, 0x03], vec![&[0x67, 0x03, 0x01, 0x02]]),
+                (&[0x27, 0x04, 0x02, 0x04], vec![&[0x7F, 0x27, 0x35]]),
+                (&[0x27, 0x03], vec![&[0x7F, 0x27, 0x37]]),
+                (&[0x27, 0x03], vec![&[0x67, 0x03, 0x10, 0x20]]),
+                (&[0x27, 0x04, 0x20, 0x40], vec![&[0x67, 0x04]]),
+            ],
+        );
+        let mut client = client(bus);
+        let double =
+            |seed: &[u8]| seed.iter().map(|b| b << 1).collect::<Vec<u8>>();
+
+        match *client.unlock(0x03, &double).unwrap_err().kind() {
+            ErrorKind::NegativeResponse(
+                0x27,
+                NegativeResponseCode::InvalidKey,
+            ) => {}
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        client.set_security_policy(SecurityPolicy {
+            attempts: 2,
+            delay: Duration::from_millis(10),
+        });
+        client.unlock(0x03, &double).unwrap();
+        server.join().unwrap();
+
+        // Even levels send keys, and 0x7F would send its key with 0x80
+        for &level in &[0x02, 0x7F, 0xFF] {
+            match *client.unlock(level, &double).unwrap_err().kind() {
+                ErrorKind::InvalidRequest(_) => {}
+                ref kind => panic!("unexpected error {:?}", kind),
+            }
+            assert!(client.send_key(level, &[0]).is_err());
+        }
+    }
+
+    #[test]
+    fn timeout_and_tester_present() {
+        let bus = VirtualBus::new();
+        let monitor = bus.connect();
+        let mut client = client(bus);
+        client.set_timing(Duration::from_millis(10), Duration::from_millis(10));
+
+        match *client.ecu_reset(ResetType::Soft).unwrap_err().kind() {
+            ErrorKind::DiagnosticTimeout(0x11) => {}
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert_eq!(
+            monitor.receive().unwrap().unwrap().data()[..3],
+            [0x02, 0x11, 0x03]
+        );
+
+        client.set_tester_present_interval(Some(Duration::from_secs(60)));
+        client.keep_alive().unwrap();
+        assert!(monitor.receive().unwrap().is_none());
+        client.set_tester_present_interval(Some(Duration::from_millis(0)));
+        client.keep_alive().unwrap();
+        assert_eq!(
+            monitor.receive().unwrap().unwrap().data()[..3],
+            [0x02, 0x3E, 0x80]
+        );
+    }
+}
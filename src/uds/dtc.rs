@@ -0,0 +1,614 @@
+//! Diagnostic trouble codes read with ReadDTCInformation.
+//!
+//! Snapshot and extended data records carry no lengths, so decoding them
+//! needs a description of their data identifiers and record numbers. One
+//! unknown identifier or record can still be decoded when it ends the
+//! response, as it then holds the rest. Descriptions are read from a
+//! text file with three sections:
+//!
+//! ```text
+//! # Comment
+//! [dtc]
+//! P0123-00 = Throttle position sensor circuit high
+//! P0130 = O2 sensor circuit (any failure type)
+//! [snapshot]
+//! 0x0100 = 2 EngineSpeed scale=0.25 unit=rpm
+//! 0xF190 = 17 VIN
+//! [extended]
+//! 0x01 = 1 OccurrenceCounter
+//! ```
+//!
+//! Data entries give a length in bytes and a name, optionally followed by
+//! a scale, offset and unit. Data of up to 8 bytes is also decoded as an
+//! unsigned big endian number, scaled and offset.
+use super::client::expect;
+use super::*;
+use bus::CanBus;
+use errors::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+const REPORT_NUMBER_BY_STATUS_MASK: u8 = 0x01;
+const REPORT_BY_STATUS_MASK: u8 = 0x02;
+const REPORT_SNAPSHOT_BY_DTC_NUMBER: u8 = 0x04;
+const REPORT_EXTENDED_DATA_BY_DTC_NUMBER: u8 = 0x06;
+const REPORT_SUPPORTED: u8 = 0x0A;
+
+/// A 3 byte DTC, shown as its SAE J2012 code and failure type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Dtc(pub u32);
+
+impl Dtc {
+    /// The J2012 code without the failure type, such as `P0123`.
+    pub fn j2012(&self) -> String {
+        let system = ['P', 'C', 'B', 'U'][(self.0 >> 22 & 0x3) as usize];
+        format!("{}{:04X}", system, self.0 >> 8 & 0x3FFF)
+    }
+
+    pub fn failure_type(&self) -> u8 {
+        self.0 as u8
+    }
+}
+
+impl fmt::Display for Dtc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{:02X}", self.j2012(), self.failure_type())
+    }
+}
+
+/// Parses `P0123-45`, or `P0123` with a failure type of 0.
+impl FromStr for Dtc {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Dtc> {
+        let invalid = || {
+            Error::from(ErrorKind::DescriptionFormat(format!(
+                "invalid DTC '{}'",
+                text
+            )))
+        };
+        let (code, failure_type) = match text.split_once('-') {
+            Some((code, failure_type)) => (
+                code,
+                u8::from_str_radix(failure_type, 16).map_err(|_| invalid())?,
+            ),
+            None => (text, 0),
+        };
+        let system = match code.chars().next().map(|c| c.to_ascii_uppercase()) {
+            Some('P') => 0,
+            Some('C') => 1,
+            Some('B') => 2,
+            Some('U') => 3,
+            _ => return Err(invalid()),
+        };
+        let digits = &code[1..];
+        let number = match u32::from_str_radix(digits, 16) {
+            Ok(number) if digits.len() == 4 && number <= 0x3FFF => number,
+            _ => return Err(invalid()),
+        };
+        Ok(Dtc(system << 22 | number << 8 | u32::from(failure_type)))
+    }
+}
+
+/// ISO 14229 DTC status bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DtcStatus(pub u8);
+
+impl DtcStatus {
+    pub const TEST_FAILED: u8 = 0x01;
+    pub const TEST_FAILED_THIS_OPERATION_CYCLE: u8 = 0x02;
+    pub const PENDING: u8 = 0x04;
+    pub const CONFIRMED: u8 = 0x08;
+    pub const TEST_NOT_COMPLETED_SINCE_LAST_CLEAR: u8 = 0x10;
+    pub const TEST_FAILED_SINCE_LAST_CLEAR: u8 = 0x20;
+    pub const TEST_NOT_COMPLETED_THIS_OPERATION_CYCLE: u8 = 0x40;
+    pub const WARNING_INDICATOR_REQUESTED: u8 = 0x80;
+
+    const NAMES: [&'static str; 8] = [
+        "testFailed",
+        "testFailedThisOperationCycle",
+        "pendingDTC",
+        "confirmedDTC",
+        "testNotCompletedSinceLastClear",
+        "testFailedSinceLastClear",
+        "testNotCompletedThisOperationCycle",
+        "warningIndicatorRequested",
+    ];
+
+    pub fn is_set(&self, bit: u8) -> bool {
+        self.0 & bit != 0
+    }
+
+    pub fn test_failed(&self) -> bool {
+        self.is_set(DtcStatus::TEST_FAILED)
+    }
+
+    pub fn pending(&self) -> bool {
+        self.is_set(DtcStatus::PENDING)
+    }
+
+    pub fn confirmed(&self) -> bool {
+        self.is_set(DtcStatus::CONFIRMED)
+    }
+
+    pub fn warning_indicator_requested(&self) -> bool {
+        self.is_set(DtcStatus::WARNING_INDICATOR_REQUESTED)
+    }
+
+    /// Names of the set bits, as ISO 14229 writes them.
+    pub fn names(&self) -> Vec<&'static str> {
+        (0..8)
+            .filter(|i| self.0 >> i & 1 != 0)
+            .map(|i| DtcStatus::NAMES[i])
+            .collect()
+    }
+}
+
+impl fmt::Display for DtcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:02X} [{}]", self.0, self.names().join(", "))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DtcRecord {
+    pub code: Dtc,
+    pub status: DtcStatus,
+}
+
+/// DTCs reported by read DTC information.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtcList {
+    pub availability_mask: u8, // Status bits the server supports
+    pub dtcs: Vec<DtcRecord>,
+}
+
+/// Answer to `reportNumberOfDTCByStatusMask`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DtcCount {
+    pub availability_mask: u8,
+    pub format: u8, // DTC format identifier, 0x01 for ISO 14229-1
+    pub count: u16,
+}
+
+/// Data of a snapshot identifier or an extended data record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataRecord {
+    pub identifier: u16, // Data identifier, or extended data record number
+    pub name: Option<String>,
+    pub data: Vec<u8>,
+    pub value: Option<f64>, // Scaled number, for data of up to 8 bytes
+    pub unit: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub number: u8,
+    pub data: Vec<DataRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DtcSnapshots {
+    pub dtc: DtcRecord,
+    pub snapshots: Vec<Snapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DtcExtendedData {
+    pub dtc: DtcRecord,
+    pub records: Vec<DataRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataDescription {
+    pub length: usize,
+    pub name: String,
+    pub scale: f64,
+    pub offset: f64,
+    pub unit: String,
+}
+
+/// Texts of DTCs and layouts of their snapshot and extended data.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DtcDescriptions {
+    pub dtcs: HashMap<Dtc, String>,
+    pub codes: HashMap<String, String>, // By J2012 code, for any failure type
+    pub snapshot: HashMap<u16, DataDescription>,
+    pub extended: HashMap<u8, DataDescription>,
+}
+
+impl DtcDescriptions {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<DtcDescriptions> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Text of a DTC, or of its code with any failure type.
+    pub fn describe(&self, dtc: Dtc) -> Option<&str> {
+        self.dtcs
+            .get(&dtc)
+            .or_else(|| self.codes.get(&dtc.j2012()))
+            .map(|s| s.as_str())
+    }
+
+    fn record(
+        &self,
+        identifier: u16,
+        description: Option<&DataDescription>,
+        data: &[u8],
+    ) -> DataRecord {
+        let value = match description {
+            Some(d) if data.len() <= 8 => {
+                let raw =
+                    data.iter().fold(0u64, |a, &b| (a << 8) | u64::from(b));
+                Some(raw as f64 * d.scale + d.offset)
+            }
+            None if data.len() <= 8 => {
+                Some(data.iter().fold(0u64, |a, &b| (a << 8) | u64::from(b))
+                    as f64)
+            }
+            _ => None,
+        };
+        DataRecord {
+            identifier,
+            name: description.map(|d| d.name.clone()),
+            data: data.to_vec(),
+            value,
+            unit: description.map(|d| d.unit.clone()).unwrap_or_default(),
+        }
+    }
+}
+
+impl FromStr for DtcDescriptions {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<DtcDescriptions> {
+        let mut descriptions = DtcDescriptions::default();
+        let mut section = String::new();
+        for (number, line) in text.lines().enumerate() {
+            let error = |reason: &str| {
+                Error::from(ErrorKind::DescriptionFormat(format!(
+                    "line {}: {}",
+                    number + 1,
+                    reason
+                )))
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) =
+                line.strip_prefix('[').and_then(|l| l.strip_suffix(']'))
+            {
+                section = name.trim().to_ascii_lowercase();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected 'key = value'"))?;
+            let (key, value) = (key.trim(), value.trim());
+            match section.as_str() {
+                "dtc" if key.contains('-') => {
+                    let dtc = key.parse().map_err(|_| {
+                        error(&format!("invalid DTC '{}'", key))
+                    })?;
+                    descriptions.dtcs.insert(dtc, value.to_owned());
+                }
+                "dtc" => {
+                    let dtc: Dtc = key.parse().map_err(|_| {
+                        error(&format!("invalid DTC '{}'", key))
+                    })?;
+                    descriptions.codes.insert(dtc.j2012(), value.to_owned());
+                }
+                "snapshot" => {
+                    let identifier = integer(key)
+                        .filter(|&i| i <= 0xFFFF)
+                        .ok_or_else(|| error("invalid identifier"))?;
+                    let data =
+                        data_description(value).map_err(|e| error(&e))?;
+                    descriptions.snapshot.insert(identifier as u16, data);
+                }
+                "extended" => {
+                    let record = integer(key)
+                        .filter(|&i| i <= 0xFF)
+                        .ok_or_else(|| error("invalid record number"))?;
+                    let data =
+                        data_description(value).map_err(|e| error(&e))?;
+                    descriptions.extended.insert(record as u8, data);
+                }
+                _ => {
+                    return Err(error(
+                        "entry outside the [dtc], [snapshot] and [extended] \
+                         sections",
+                    ))
+                }
+            }
+        }
+        Ok(descriptions)
+    }
+}
+
+fn integer(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// `<length> <name> [scale=<f64>] [offset=<f64>] [unit=<text>]`
+fn data_description(
+    text: &str,
+) -> ::std::result::Result<DataDescription, String> {
+    let mut words = text.split_whitespace();
+    let length = words
+        .next()
+        .and_then(|w| w.parse().ok())
+        .filter(|&l| l > 0)
+        .ok_or("invalid length")?;
+    let name = words.next().ok_or("missing name")?.to_owned();
+    let mut description = DataDescription {
+        length,
+        name,
+        scale: 1.0,
+        offset: 0.0,
+        unit: String::new(),
+    };
+    for word in words {
+        match word.split_once('=') {
+            Some(("scale", v)) => {
+                description.scale =
+                    v.parse().map_err(|_| format!("invalid scale '{}'", v))?
+            }
+            Some(("offset", v)) => {
+                description.offset =
+                    v.parse().map_err(|_| format!("invalid offset '{}'", v))?
+            }
+            Some(("unit", v)) => description.unit = v.to_owned(),
+            _ => return Err(format!("unexpected '{}'", word)),
+        }
+    }
+    Ok(description)
+}
+
+impl<B: CanBus> Client<B> {
+    /// Number of DTCs with any of the `status_mask` bits set.
+    pub fn read_dtc_count(&mut self, status_mask: u8) -> Result<DtcCount> {
+        let response = self.read_dtc_information(
+            REPORT_NUMBER_BY_STATUS_MASK,
+            &[status_mask],
+        )?;
+        if response.len() < 4 {
+            bail!(ErrorKind::InvalidResponse("DTC count too short".to_owned()));
+        }
+        Ok(DtcCount {
+            availability_mask: response[0],
+            format: response[1],
+            count: u16::from_be_bytes([response[2], response[3]]),
+        })
+    }
+
+    /// DTCs with any of the `status_mask` bits set.
+    pub fn read_dtcs_by_status(&mut self, status_mask: u8) -> Result<DtcList> {
+        let response =
+            self.read_dtc_information(REPORT_BY_STATUS_MASK, &[status_mask])?;
+        dtc_list(&response)
+    }
+
+    /// Every DTC the server supports, whatever its status.
+    pub fn read_supported_dtcs(&mut self) -> Result<DtcList> {
+        let response = self.read_dtc_information(REPORT_SUPPORTED, &[])?;
+        dtc_list(&response)
+    }
+
+    /// Snapshot records of a DTC, 0xFF for all of them.
+    pub fn read_dtc_snapshots(
+        &mut self,
+        dtc: Dtc,
+        record: u8,
+        descriptions: &DtcDescriptions,
+    ) -> Result<DtcSnapshots> {
+        let request = dtc_request(dtc, record);
+        let response =
+            self.read_dtc_information(REPORT_SNAPSHOT_BY_DTC_NUMBER, &request)?;
+        expect(&response, &request[..3], 4)?;
+        decode_snapshots(&response, descriptions)
+    }
+
+    /// Extended data records of a DTC, 0xFF for all of them.
+    pub fn read_dtc_extended_data(
+        &mut self,
+        dtc: Dtc,
+        record: u8,
+        descriptions: &DtcDescriptions,
+    ) -> Result<DtcExtendedData> {
+        let request = dtc_request(dtc, record);
+        let response = self.read_dtc_information(
+            REPORT_EXTENDED_DATA_BY_DTC_NUMBER,
+            &request,
+        )?;
+        expect(&response, &request[..3], 4)?;
+        decode_extended_data(&response, descriptions)
+    }
+}
+
+fn dtc_request(dtc: Dtc, record: u8) -> Vec<u8> {
+    let mut request = dtc.0.to_be_bytes()[1..].to_vec();
+    request.push(record);
+    request
+}
+
+fn dtc_record(bytes: &[u8]) -> DtcRecord {
+    DtcRecord {
+        code: Dtc(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]])),
+        status: DtcStatus(bytes[3]),
+    }
+}
+
+fn dtc_list(response: &[u8]) -> Result<DtcList> {
+    if response.is_empty() || !(response.len() - 1).is_multiple_of(4) {
+        bail!(ErrorKind::InvalidResponse(format!(
+            "DTC list of {} bytes",
+            response.len()
+        )));
+    }
+    Ok(DtcList {
+        availability_mask: response[0],
+        dtcs: response[1..].chunks(4).map(dtc_record).collect(),
+    })
+}
+
+/// Take `length` bytes, or the rest if `length` is unknown.
+fn take<'a>(
+    data: &mut &'a [u8],
+    length: Option<usize>,
+    what: &str,
+) -> Result<&'a [u8]> {
+    let length = length.unwrap_or(data.len());
+    if length > data.len() {
+        bail!(ErrorKind::InvalidResponse(format!("{} truncated", what)));
+    }
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+    Ok(taken)
+}
+
+/// Decode the DTC, status and records of a `reportDTCSnapshotRecordByDTCNumber` response.
+fn decode_snapshots(
+    response: &[u8],
+    descriptions: &DtcDescriptions,
+) -> Result<DtcSnapshots> {
+    let mut snapshots = Vec::new();
+    let mut rest = &response[4..];
+    while rest.len() >= 2 {
+        let (number, count) = (rest[0], rest[1]);
+        rest = &rest[2..];
+        let mut data = Vec::new();
+        for _ in 0..count {
+            let identifier = take(&mut rest, Some(2), "snapshot identifier")?;
+            let identifier = u16::from_be_bytes([identifier[0], identifier[1]]);
+            let description = descriptions.snapshot.get(&identifier);
+            let bytes = take(
+                &mut rest,
+                description.map(|d| d.length),
+                "snapshot data",
+            )?;
+            data.push(descriptions.record(identifier, description, bytes));
+        }
+        snapshots.push(Snapshot { number, data });
+    }
+    if !rest.is_empty() {
+        bail!(ErrorKind::InvalidResponse(
+            "trailing snapshot bytes".to_owned()
+        ));
+    }
+    Ok(DtcSnapshots {
+        dtc: dtc_record(response),
+        snapshots,
+    })
+}
+
+/// Decode the DTC, status and records of a `reportDTCExtDataRecordByDTCNumber` response.
+fn decode_extended_data(
+    response: &[u8],
+    descriptions: &DtcDescriptions,
+) -> Result<DtcExtendedData> {
+    let mut records = Vec::new();
+    let mut rest = &response[4..];
+    while let Some(&number) = rest.first() {
+        rest = &rest[1..];
+        let description = descriptions.extended.get(&number);
+        let bytes =
+            take(&mut rest, description.map(|d| d.length), "extended data")?;
+        records.push(descriptions.record(
+            u16::from(number),
+            description,
+            bytes,
+        ));
+    }
+    Ok(DtcExtendedData {
+        dtc: dtc_record(response),
+        records,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTIONS: &str = "
+# Test descriptions
+[dtc]
+P0123-00 = Throttle position sensor circuit high
+U0100 = Lost communication with ECM
+[snapshot]
+0x0100 = 2 EngineSpeed scale=0.25 unit=rpm
+0x0101 = 1 CoolantTemp offset=-40 unit=degC
+[extended]
+0x01 = 1 OccurrenceCounter
+";
+
+    #[test]
+    fn codes_and_status() {
+        assert_eq!(Dtc(0x012300).to_string(), "P0123-00");
+        assert_eq!(Dtc(0xC10087).to_string(), "U0100-87");
+        assert_eq!(Dtc(0x9A3456).j2012(), "B1A34");
+        assert_eq!("U0100-87".parse::<Dtc>().unwrap(), Dtc(0xC10087));
+        assert_eq!("c0035".parse::<Dtc>().unwrap(), Dtc(0x403500));
+        assert!("X0100".parse::<Dtc>().is_err());
+
+        let status = DtcStatus(0x09);
+        assert!(
+            status.test_failed() && status.confirmed() && !status.pending()
+        );
+        assert_eq!(status.to_string(), "0x09 [testFailed, confirmedDTC]");
+    }
+
+    #[test]
+    fn descriptions_and_records() {
+        let descriptions: DtcDescriptions = DESCRIPTIONS.parse().unwrap();
+        assert_eq!(
+            descriptions.describe(Dtc(0x012300)),
+            Some("Throttle position sensor circuit high")
+        );
+        assert_eq!(descriptions.describe(Dtc(0x012301)), None);
+        assert_eq!(
+            descriptions.describe(Dtc(0xC10087)),
+            Some("Lost communication with ECM")
+        );
+
+        // Two snapshots, the last one with an undescribed identifier
+        let response = [
+            0x01, 0x23, 0x00, 0x09, 0x01, 0x02, 0x01, 0x00, 0x0F, 0xA0, 0x01,
+            0x01, 0x82, 0x02, 0x01, 0xF1, 0x90, 0x41, 0x42,
+        ];
+        let snapshots = decode_snapshots(&response, &descriptions).unwrap();
+        assert_eq!(snapshots.dtc.status, DtcStatus(0x09));
+        assert_eq!(snapshots.snapshots.len(), 2);
+        let first = &snapshots.snapshots[0].data;
+        assert_eq!(first[0].name.as_deref(), Some("EngineSpeed"));
+        assert_eq!(
+            (first[0].value, first[0].unit.as_str()),
+            (Some(1000.0), "rpm")
+        );
+        assert_eq!(first[1].value, Some(90.0));
+        assert_eq!(snapshots.snapshots[1].data[0].data, vec![0x41, 0x42]);
+
+        let response = [0x01, 0x23, 0x00, 0x09, 0x01, 0x05, 0x02, 0x00, 0x10];
+        let extended = decode_extended_data(&response, &descriptions).unwrap();
+        assert_eq!(extended.records[0].value, Some(5.0));
+        assert_eq!(
+            (
+                extended.records[1].identifier,
+                extended.records[1].name.as_ref()
+            ),
+            (2, None)
+        );
+        assert_eq!(extended.records[1].data, vec![0x00, 0x10]);
+
+        assert!("[snapshot]\n0x0100 = two Speed\n"
+            .parse::<DtcDescriptions>()
+            .is_err());
+    }
+}
@@ -0,0 +1,442 @@
+//! Download of memory images to a server.
+//!
+//! Each segment of the image is optionally erased by a routine, then sent
+//! with RequestDownload, TransferData blocks of the length the server
+//! negotiates and RequestTransferExit. A check routine may run once every
+//! segment is written.
+//!
+//! A block that times out is sent again with the same block sequence
+//! counter, which the server acknowledges without writing twice. A
+//! `Flasher` keeps its position when it fails, so running it again resumes
+//! from the failed block, or from the start of its segment if the server
+//! aborted the download.
+use super::*;
+use bus::CanBus;
+use errors::*;
+use std::time::Duration;
+
+/// Transferred bytes, reported after every block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub segment: usize,
+    pub segments: usize,
+    pub transferred: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FlashOptions {
+    pub data_format: u8, // Compression and encryption methods, 0x00 for none
+    pub address_format: u8, // Bytes of the memory size (high nibble) and address (low nibble)
+    pub erase_routine: Option<u16>, // Started before each segment with the address format, address and size
+    pub check_routine: Option<u16>, // Started without options once every segment is written
+    pub max_block_length: Option<usize>, // Cap on the block length the server allows
+    pub attempts: u32,                   // Tries of each block before giving up
+    pub erase_timeout: Duration,         // P2* while erasing
+}
+
+impl Default for FlashOptions {
+    fn default() -> FlashOptions {
+        FlashOptions {
+            data_format: 0x00,
+            address_format: 0x44,
+            erase_routine: None,
+            check_routine: None,
+            max_block_length: None,
+            attempts: 3,
+            erase_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl<B: CanBus> Client<B> {
+    /// Announce a download. Returns the longest TransferData request the
+    /// server takes, service identifier and block sequence counter included.
+    pub fn request_download(
+        &mut self,
+        data_format: u8,
+        address_format: u8,
+        address: u32,
+        size: u32,
+    ) -> Result<usize> {
+        let mut request = vec![data_format, address_format];
+        request.extend(memory_bytes(address_format, address, size)?);
+        let response = self.request(REQUEST_DOWNLOAD, &request)?;
+        let length_bytes =
+            response.first().map_or(0, |&format| (format >> 4) as usize);
+        if length_bytes == 0
+            || length_bytes > 8
+            || response.len() < length_bytes + 1
+        {
+            bail!(ErrorKind::InvalidResponse(
+                "missing max block length".to_owned()
+            ));
+        }
+        Ok(response[1..=length_bytes]
+            .iter()
+            .fold(0usize, |a, &b| (a << 8) | b as usize))
+    }
+
+    pub fn transfer_data(
+        &mut self,
+        sequence: u8,
+        data: &[u8],
+    ) -> Result<Vec<u8>> {
+        let mut request = vec![sequence];
+        request.extend_from_slice(data);
+        let response = self.request(TRANSFER_DATA, &request)?;
+        if response.first() != Some(&sequence) {
+            bail!(ErrorKind::InvalidResponse(format!(
+                "block {} acknowledged as {:02X?}",
+                sequence,
+                response.first()
+            )));
+        }
+        Ok(response[1..].to_vec())
+    }
+
+    pub fn request_transfer_exit(
+        &mut self,
+        parameters: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.request(REQUEST_TRANSFER_EXIT, parameters)
+    }
+}
+
+/// Memory address and size in the lengths of an address and length format
+/// identifier.
+fn memory_bytes(
+    address_format: u8,
+    address: u32,
+    size: u32,
+) -> Result<Vec<u8>> {
+    let size_len = (address_format >> 4) as usize;
+    let address_len = (address_format & 0x0F) as usize;
+    let fits = |value: u32, len: usize| {
+        (1..=4).contains(&len) && (len == 4 || value >> (len * 8) == 0)
+    };
+    if !fits(address, address_len) || !fits(size, size_len) {
+        bail!(ErrorKind::InvalidRequest(format!(
+            "address 0x{:X} or size {} does not fit format 0x{:02X}",
+            address, size, address_format
+        )));
+    }
+    let mut bytes = address.to_be_bytes()[4 - address_len..].to_vec();
+    bytes.extend_from_slice(&size.to_be_bytes()[4 - size_len..]);
+    Ok(bytes)
+}
+
+/// Writes an image, segment after segment.
+pub struct Flasher<'a> {
+    image: &'a Image,
+    options: FlashOptions,
+    segment: usize,
+    offset: usize, // Bytes of the segment acknowledged by the server
+    sequence: u8,
+    block_length: Option<usize>, // Data bytes per block while a download is active
+    checked: bool,
+}
+
+impl<'a> Flasher<'a> {
+    pub fn new(image: &'a Image, options: FlashOptions) -> Flasher<'a> {
+        Flasher {
+            image,
+            options,
+            segment: 0,
+            offset: 0,
+            sequence: 1,
+            block_length: None,
+            checked: false,
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        let done: usize = self.image.segments[..self.segment]
+            .iter()
+            .map(|s| s.data.len())
+            .sum();
+        Progress {
+            segment: self.segment,
+            segments: self.image.segments.len(),
+            transferred: done + self.offset,
+            total: self.image.len(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.segment == self.image.segments.len() && self.checked
+    }
+
+    /// Write what is left of the image, calling `progress` after each
+    /// block. On failure the flasher can be run again to resume.
+    pub fn run<B, F>(
+        &mut self,
+        client: &mut Client<B>,
+        mut progress: F,
+    ) -> Result<()>
+    where
+        B: CanBus,
+        F: FnMut(&Progress),
+    {
+        while let Some(segment) = self.image.segments.get(self.segment) {
+            let block_length = match self.block_length {
+                Some(length) => length,
+                None => self.start_segment(client, segment)?,
+            };
+            while self.offset < segment.data.len() {
+                let end = segment.data.len().min(self.offset + block_length);
+                self.send_block(client, &segment.data[self.offset..end])?;
+                self.offset = end;
+                self.sequence = self.sequence.wrapping_add(1);
+                progress(&self.progress());
+            }
+            client.request_transfer_exit(&[])?;
+            self.segment += 1;
+            self.offset = 0;
+            self.block_length = None;
+        }
+        if !self.checked {
+            if let Some(routine) = self.options.check_routine {
+                client.routine_control(RoutineControl::Start, routine, &[])?;
+            }
+            self.checked = true;
+        }
+        Ok(())
+    }
+
+    /// Erase a segment and request its download. Returns the data bytes
+    /// per block.
+    fn start_segment<B: CanBus>(
+        &mut self,
+        client: &mut Client<B>,
+        segment: &Segment,
+    ) -> Result<usize> {
+        let size = segment.data.len() as u32;
+        if let Some(routine) = self.options.erase_routine {
+            let mut options = vec![self.options.address_format];
+            options.extend(memory_bytes(
+                self.options.address_format,
+                segment.address,
+                size,
+            )?);
+            let (p2, p2_star) = client.timing();
+            client.set_timing(p2, self.options.erase_timeout);
+            let erased = client.routine_control(
+                RoutineControl::Start,
+                routine,
+                &options,
+            );
+            client.set_timing(p2, p2_star);
+            erased?;
+        }
+        let (data_format, address_format) =
+            (self.options.data_format, self.options.address_format);
+        let max = client.request_download(
+            data_format,
+            address_format,
+            segment.address,
+            size,
+        )?;
+        let max = self
+            .options
+            .max_block_length
+            .map_or(max, |cap| cap.min(max));
+        if max <= 2 {
+            bail!(ErrorKind::InvalidResponse(format!(
+                "max block length {} leaves no room for data",
+                max
+            )));
+        }
+        self.offset = 0;
+        self.sequence = 1;
+        self.block_length = Some(max - 2);
+        Ok(max - 2)
+    }
+
+    /// Send a block, again after a timeout, until the server acknowledges
+    /// it. A negative response other than "busy" ends the download.
+    fn send_block<B: CanBus>(
+        &mut self,
+        client: &mut Client<B>,
+        data: &[u8],
+    ) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            let error = match client.transfer_data(self.sequence, data) {
+                Ok(_) => return Ok(()),
+                Err(error) => error,
+            };
+            let retry = match *error.kind() {
+                ErrorKind::DiagnosticTimeout(_) => true,
+                ErrorKind::NegativeResponse(
+                    _,
+                    NegativeResponseCode::BusyRepeatRequest,
+                ) => true,
+                ErrorKind::NegativeResponse(..) => {
+                    self.block_length = None;
+                    self.offset = 0;
+                    false
+                }
+                _ => false,
+            };
+            if !retry || attempt >= self.options.attempts {
+                return Err(error);
+            }
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::VirtualBus;
+    use isotp::{Config, IsoTp};
+    use std::cell::Cell;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Server writing downloads to memory. It ignores the first request for
+    /// block 2 and rejects the first download after `reject_block` blocks.
+    fn server(
+        bus: VirtualBus,
+        reject_block: u8,
+    ) -> thread::JoinHandle<Vec<(u32, Vec<u8>)>> {
+        thread::spawn(move || {
+            let mut isotp = IsoTp::new(bus, Config::new(0x7E8, 0x7E0));
+            let mut memory: Vec<(u32, Vec<u8>)> = Vec::new();
+            let mut last_sequence = 0u8;
+            let (mut ignored, mut rejected) = (false, false);
+            while let Some(request) =
+                isotp.receive(Duration::from_millis(500)).unwrap()
+            {
+                let response = match request[0] {
+                    ROUTINE_CONTROL => {
+                        vec![0x71, request[1], request[2], request[3], 0x00]
+                    }
+                    REQUEST_DOWNLOAD => {
+                        let address = u32::from_be_bytes([
+                            request[3], request[4], request[5], request[6],
+                        ]);
+                        memory.push((address, Vec::new()));
+                        last_sequence = 0;
+                        vec![0x74, 0x20, 0x00, 0x08]
+                    }
+                    TRANSFER_DATA if request[1] == 2 && !ignored => {
+                        ignored = true;
+                        continue;
+                    }
+                    TRANSFER_DATA
+                        if request[1] == reject_block && !rejected =>
+                    {
+                        rejected = true;
+                        vec![NEGATIVE_RESPONSE, TRANSFER_DATA, 0x72]
+                    }
+                    TRANSFER_DATA => {
+                        if request[1] != last_sequence {
+                            memory
+                                .last_mut()
+                                .unwrap()
+                                .1
+                                .extend_from_slice(&request[2..]);
+                            last_sequence = request[1];
+                        }
+                        vec![0x76, request[1]]
+                    }
+                    REQUEST_TRANSFER_EXIT => vec![0x77],
+                    _ => vec![NEGATIVE_RESPONSE, request[0], 0x11],
+                };
+                isotp.send(&response).unwrap();
+            }
+            memory
+        })
+    }
+
+    fn image() -> Image {
+        Image {
+            segments: vec![
+                Segment {
+                    address: 0x0800_0000,
+                    data: (0..20).collect(),
+                },
+                Segment {
+                    address: 0x0801_0000,
+                    data: vec![0xAA; 3],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn flash_with_retried_block() {
+        let bus = VirtualBus::new();
+        let server = server(bus.connect(), 0);
+        let mut client =
+            Client::new(IsoTp::new(bus, Config::new(0x7E0, 0x7E8)));
+        client
+            .set_timing(Duration::from_millis(50), Duration::from_millis(500));
+
+        let image = image();
+        let options = FlashOptions {
+            erase_routine: Some(0xFF00),
+            check_routine: Some(0xFF01),
+            ..FlashOptions::default()
+        };
+        let mut flasher = Flasher::new(&image, options);
+        let mut reports = Vec::new();
+        flasher
+            .run(&mut client, |p| reports.push(p.transferred))
+            .unwrap();
+        assert!(flasher.is_finished());
+        // Blocks of 6 data bytes
+        assert_eq!(reports, vec![6, 12, 18, 20, 23]);
+
+        drop(client);
+        let memory = server.join().unwrap();
+        assert_eq!(
+            memory,
+            vec![
+                (0x0800_0000, (0..20).collect()),
+                (0x0801_0000, vec![0xAA; 3])
+            ]
+        );
+    }
+
+    #[test]
+    fn resume_after_aborted_download() {
+        let bus = VirtualBus::new();
+        let server = server(bus.connect(), 3);
+        let mut client =
+            Client::new(IsoTp::new(bus, Config::new(0x7E0, 0x7E8)));
+        client
+            .set_timing(Duration::from_millis(50), Duration::from_millis(500));
+
+        let image = image();
+        let mut flasher = Flasher::new(&image, FlashOptions::default());
+        let calls = Cell::new(0);
+        match *flasher
+            .run(&mut client, |_| calls.set(calls.get() + 1))
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::NegativeResponse(
+                TRANSFER_DATA,
+                NegativeResponseCode::GeneralProgrammingFailure,
+            ) => {}
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert_eq!((calls.get(), flasher.progress().transferred), (2, 0));
+
+        flasher.run(&mut client, |_| ()).unwrap();
+        drop(client);
+        let memory = server.join().unwrap();
+        assert_eq!(memory.len(), 3);
+        assert_eq!(
+            &memory[1..],
+            &[
+                (0x0800_0000, (0..20).collect()),
+                (0x0801_0000, vec![0xAA; 3])
+            ]
+        );
+    }
+}
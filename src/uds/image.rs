@@ -0,0 +1,247 @@
+//! Memory images for flashing, read from Intel HEX and Motorola S-record
+//! files.
+//!
+//! Records are gathered into segments of contiguous memory sorted by
+//! address. Overlapping records are an error. Start addresses and S-record
+//! headers are ignored.
+use errors::*;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+}
+
+impl Image {
+    /// Load an image, as Intel HEX or S-record depending on its extension
+    /// or, failing that, its first character.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Image> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hex") | Some("ihex") | Some("ihx") => Image::from_ihex(&text),
+            Some("s19") | Some("s28") | Some("s37") | Some("srec")
+            | Some("mot") => Image::from_srec(&text),
+            _ if text.trim_start().starts_with('S') => Image::from_srec(&text),
+            _ => Image::from_ihex(&text),
+        }
+    }
+
+    pub fn from_ihex(text: &str) -> Result<Image> {
+        let mut records = Vec::new();
+        let mut base = 0u32;
+        for (number, line) in lines(text) {
+            let bytes = line
+                .strip_prefix(':')
+                .ok_or_else(|| error(number, "missing ':'"))
+                .and_then(|hex| record_bytes(number, hex, 0x00))?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(error(number, "wrong record length"));
+            }
+            let address = u32::from(u16::from_be_bytes([bytes[1], bytes[2]]));
+            let data = &bytes[4..bytes.len() - 1];
+            match bytes[3] {
+                0x00 => {
+                    records.push((base.wrapping_add(address), data.to_vec()))
+                }
+                0x01 => break,
+                0x02 if data.len() == 2 => {
+                    base =
+                        u32::from(u16::from_be_bytes([data[0], data[1]])) << 4
+                }
+                0x04 if data.len() == 2 => {
+                    base =
+                        u32::from(u16::from_be_bytes([data[0], data[1]])) << 16
+                }
+                0x03 | 0x05 => {}
+                kind => {
+                    return Err(error(
+                        number,
+                        &format!("invalid record type {:02X}", kind),
+                    ))
+                }
+            }
+        }
+        Image::from_records(records)
+    }
+
+    pub fn from_srec(text: &str) -> Result<Image> {
+        let mut records = Vec::new();
+        for (number, line) in lines(text) {
+            let kind =
+                match line.strip_prefix('S').and_then(|l| l.chars().next()) {
+                    Some(kind) => kind,
+                    None => return Err(error(number, "missing 'S'")),
+                };
+            let bytes =
+                record_bytes(number, &line[1 + kind.len_utf8()..], 0xFF)?;
+            if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+                return Err(error(number, "wrong record length"));
+            }
+            let address_len = match kind {
+                '1' => 2,
+                '2' => 3,
+                '3' => 4,
+                '0' | '5' | '6' | '7' | '8' | '9' => continue,
+                kind => {
+                    return Err(error(
+                        number,
+                        &format!("invalid record type S{}", kind),
+                    ))
+                }
+            };
+            if bytes.len() < address_len + 2 {
+                return Err(error(number, "wrong record length"));
+            }
+            let address = bytes[1..=address_len]
+                .iter()
+                .fold(0u32, |a, &b| (a << 8) | u32::from(b));
+            records.push((
+                address,
+                bytes[address_len + 1..bytes.len() - 1].to_vec(),
+            ));
+        }
+        Image::from_records(records)
+    }
+
+    fn from_records(mut records: Vec<(u32, Vec<u8>)>) -> Result<Image> {
+        records.sort_by_key(|&(address, _)| address);
+        let mut segments: Vec<Segment> = Vec::new();
+        for (address, data) in
+            records.into_iter().filter(|(_, data)| !data.is_empty())
+        {
+            if let Some(last) = segments.last_mut() {
+                let end = u64::from(last.address) + last.data.len() as u64;
+                if u64::from(address) < end {
+                    bail!(ErrorKind::ImageFormat(format!(
+                        "data overlaps at 0x{:08X}",
+                        address
+                    )));
+                }
+                if u64::from(address) == end {
+                    last.data.extend(data);
+                    continue;
+                }
+            }
+            segments.push(Segment { address, data });
+        }
+        Ok(Image { segments })
+    }
+
+    /// Number of data bytes in all segments.
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|s| s.data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn error(line: usize, reason: &str) -> Error {
+    ErrorKind::ImageFormat(format!("line {}: {}", line, reason)).into()
+}
+
+/// Non-blank lines with their numbers.
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .map(str::trim)
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+/// Bytes of a hexadecimal record, checked to sum to `checksum_sum` with
+/// the checksum included: 0x00 for Intel HEX, 0xFF for S-records.
+fn record_bytes(line: usize, hex: &str, checksum_sum: u8) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(error(line, "invalid hexadecimal record"));
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<::std::result::Result<Vec<u8>, _>>()
+        .map_err(|_| error(line, "invalid hexadecimal digit"))?;
+    let sum = bytes.iter().fold(0u8, |a, &b| a.wrapping_add(b));
+    if sum != checksum_sum {
+        return Err(error(line, "checksum mismatch"));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intel_hex() {
+        let text = "\
+:020000040800F2
+:0400000001020304F2
+:0400040005060708DE
+:02001000AABB89
+:00000001FF
+";
+        let image = Image::from_ihex(text).unwrap();
+        assert_eq!(
+            image.segments,
+            vec![
+                Segment {
+                    address: 0x0800_0000,
+                    data: vec![1, 2, 3, 4, 5, 6, 7, 8]
+                },
+                Segment {
+                    address: 0x0800_0010,
+                    data: vec![0xAA, 0xBB]
+                },
+            ]
+        );
+        assert_eq!(image.len(), 10);
+
+        let error = Image::from_ihex(":0400000001020304F3\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Malformed firmware image: line 1: checksum mismatch"
+        );
+    }
+
+    #[test]
+    fn s_records() {
+        let text = "\
+S00600004844521B
+S3090800000001020304E4
+S3090800000405060708D0
+S1050100AABB94
+S70508000000F2
+";
+        let image = Image::from_srec(text).unwrap();
+        assert_eq!(
+            image.segments,
+            vec![
+                Segment {
+                    address: 0x0100,
+                    data: vec![0xAA, 0xBB]
+                },
+                Segment {
+                    address: 0x0800_0000,
+                    data: vec![1, 2, 3, 4, 5, 6, 7, 8]
+                },
+            ]
+        );
+        assert!(Image::from_srec(
+            "S3090800000001020304E4\nS307080000020102EB\n"
+        )
+        .is_err());
+    }
+}
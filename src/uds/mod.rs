@@ -0,0 +1,290 @@
+//! Unified Diagnostic Services (ISO 14229) over ISO-TP.
+use std::fmt;
+
+mod client;
+mod dtc;
+mod flash;
+mod image;
+mod security;
+mod server;
+
+pub use self::client::Client;
+pub use self::dtc::{
+    DataDescription, DataRecord, Dtc, DtcCount, DtcDescriptions,
+    DtcExtendedData, DtcList, DtcRecord, DtcSnapshots, DtcStatus, Snapshot,
+};
+pub use self::flash::{FlashOptions, Flasher, Progress};
+pub use self::image::{Image, Segment};
+#[cfg(feature = "key-library")]
+pub use self::security::KeyLibrary;
+pub use self::security::{SecurityPolicy, SeedKey, TableKey, XorKey};
+pub use self::server::{DataIdentifier, RoutineHandler, Server};
+
+pub const DIAGNOSTIC_SESSION_CONTROL: u8 = 0x10;
+pub const ECU_RESET: u8 = 0x11;
+pub const CLEAR_DIAGNOSTIC_INFORMATION: u8 = 0x14;
+pub const READ_DTC_INFORMATION: u8 = 0x19;
+pub const READ_DATA_BY_IDENTIFIER: u8 = 0x22;
+pub const SECURITY_ACCESS: u8 = 0x27;
+pub const WRITE_DATA_BY_IDENTIFIER: u8 = 0x2E;
+pub const ROUTINE_CONTROL: u8 = 0x31;
+pub const REQUEST_DOWNLOAD: u8 = 0x34;
+pub const TRANSFER_DATA: u8 = 0x36;
+pub const REQUEST_TRANSFER_EXIT: u8 = 0x37;
+pub const TESTER_PRESENT: u8 = 0x3E;
+pub const NEGATIVE_RESPONSE: u8 = 0x7F;
+
+/// Added to a service identifier in its positive response.
+pub const POSITIVE_RESPONSE_OFFSET: u8 = 0x40;
+
+/// Set in a sub-function byte to ask the server not to respond.
+pub const SUPPRESS_POSITIVE_RESPONSE: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Session {
+    Default,
+    Programming,
+    Extended,
+    SafetySystem,
+    Other(u8),
+}
+
+impl From<u8> for Session {
+    fn from(x: u8) -> Session {
+        match x {
+            0x01 => Session::Default,
+            0x02 => Session::Programming,
+            0x03 => Session::Extended,
+            0x04 => Session::SafetySystem,
+            x => Session::Other(x),
+        }
+    }
+}
+
+impl From<Session> for u8 {
+    fn from(x: Session) -> u8 {
+        match x {
+            Session::Default => 0x01,
+            Session::Programming => 0x02,
+            Session::Extended => 0x03,
+            Session::SafetySystem => 0x04,
+            Session::Other(x) => x,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetType {
+    Hard,
+    KeyOffOn,
+    Soft,
+    EnableRapidPowerShutDown,
+    DisableRapidPowerShutDown,
+    Other(u8),
+}
+
+impl From<u8> for ResetType {
+    fn from(x: u8) -> ResetType {
+        match x {
+            0x01 => ResetType::Hard,
+            0x02 => ResetType::KeyOffOn,
+            0x03 => ResetType::Soft,
+            0x04 => ResetType::EnableRapidPowerShutDown,
+            0x05 => ResetType::DisableRapidPowerShutDown,
+            x => ResetType::Other(x),
+        }
+    }
+}
+
+impl From<ResetType> for u8 {
+    fn from(x: ResetType) -> u8 {
+        match x {
+            ResetType::Hard => 0x01,
+            ResetType::KeyOffOn => 0x02,
+            ResetType::Soft => 0x03,
+            ResetType::EnableRapidPowerShutDown => 0x04,
+            ResetType::DisableRapidPowerShutDown => 0x05,
+            ResetType::Other(x) => x,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutineControl {
+    Start,
+    Stop,
+    RequestResults,
+}
+
+impl From<RoutineControl> for u8 {
+    fn from(x: RoutineControl) -> u8 {
+        match x {
+            RoutineControl::Start => 0x01,
+            RoutineControl::Stop => 0x02,
+            RoutineControl::RequestResults => 0x03,
+        }
+    }
+}
+
+/// Server timing reported by a diagnostic session control response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionTiming {
+    pub p2_max: u16,      // Milliseconds
+    pub p2_star_max: u16, // Tens of milliseconds
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NegativeResponseCode {
+    GeneralReject,
+    ServiceNotSupported,
+    SubFunctionNotSupported,
+    IncorrectMessageLength,
+    ResponseTooLong,
+    BusyRepeatRequest,
+    ConditionsNotCorrect,
+    RequestSequenceError,
+    NoResponseFromSubnetComponent,
+    FailurePreventsExecution,
+    RequestOutOfRange,
+    SecurityAccessDenied,
+    InvalidKey,
+    ExceededNumberOfAttempts,
+    RequiredTimeDelayNotExpired,
+    UploadDownloadNotAccepted,
+    TransferDataSuspended,
+    GeneralProgrammingFailure,
+    WrongBlockSequenceCounter,
+    ResponsePending,
+    SubFunctionNotSupportedInActiveSession,
+    ServiceNotSupportedInActiveSession,
+    Other(u8), // Including the vehicle condition codes 0x81-0xFE
+}
+
+impl From<u8> for NegativeResponseCode {
+    fn from(x: u8) -> NegativeResponseCode {
+        match x {
+            0x10 => NegativeResponseCode::GeneralReject,
+            0x11 => NegativeResponseCode::ServiceNotSupported,
+            0x12 => NegativeResponseCode::SubFunctionNotSupported,
+            0x13 => NegativeResponseCode::IncorrectMessageLength,
+            0x14 => NegativeResponseCode::ResponseTooLong,
+            0x21 => NegativeResponseCode::BusyRepeatRequest,
+            0x22 => NegativeResponseCode::ConditionsNotCorrect,
+            0x24 => NegativeResponseCode::RequestSequenceError,
+            0x25 => NegativeResponseCode::NoResponseFromSubnetComponent,
+            0x26 => NegativeResponseCode::FailurePreventsExecution,
+            0x31 => NegativeResponseCode::RequestOutOfRange,
+            0x33 => NegativeResponseCode::SecurityAccessDenied,
+            0x35 => NegativeResponseCode::InvalidKey,
+            0x36 => NegativeResponseCode::ExceededNumberOfAttempts,
+            0x37 => NegativeResponseCode::RequiredTimeDelayNotExpired,
+            0x70 => NegativeResponseCode::UploadDownloadNotAccepted,
+            0x71 => NegativeResponseCode::TransferDataSuspended,
+            0x72 => NegativeResponseCode::GeneralProgrammingFailure,
+            0x73 => NegativeResponseCode::WrongBlockSequenceCounter,
+            0x78 => NegativeResponseCode::ResponsePending,
+            0x7E => {
+                NegativeResponseCode::SubFunctionNotSupportedInActiveSession
+            }
+            0x7F => NegativeResponseCode::ServiceNotSupportedInActiveSession,
+            x => NegativeResponseCode::Other(x),
+        }
+    }
+}
+
+impl From<NegativeResponseCode> for u8 {
+    fn from(x: NegativeResponseCode) -> u8 {
+        match x {
+            NegativeResponseCode::GeneralReject => 0x10,
+            NegativeResponseCode::ServiceNotSupported => 0x11,
+            NegativeResponseCode::SubFunctionNotSupported => 0x12,
+            NegativeResponseCode::IncorrectMessageLength => 0x13,
+            NegativeResponseCode::ResponseTooLong => 0x14,
+            NegativeResponseCode::BusyRepeatRequest => 0x21,
+            NegativeResponseCode::ConditionsNotCorrect => 0x22,
+            NegativeResponseCode::RequestSequenceError => 0x24,
+            NegativeResponseCode::NoResponseFromSubnetComponent => 0x25,
+            NegativeResponseCode::FailurePreventsExecution => 0x26,
+            NegativeResponseCode::RequestOutOfRange => 0x31,
+            NegativeResponseCode::SecurityAccessDenied => 0x33,
+            NegativeResponseCode::InvalidKey => 0x35,
+            NegativeResponseCode::ExceededNumberOfAttempts => 0x36,
+            NegativeResponseCode::RequiredTimeDelayNotExpired => 0x37,
+            NegativeResponseCode::UploadDownloadNotAccepted => 0x70,
+            NegativeResponseCode::TransferDataSuspended => 0x71,
+            NegativeResponseCode::GeneralProgrammingFailure => 0x72,
+            NegativeResponseCode::WrongBlockSequenceCounter => 0x73,
+            NegativeResponseCode::ResponsePending => 0x78,
+            NegativeResponseCode::SubFunctionNotSupportedInActiveSession => {
+                0x7E
+            }
+            NegativeResponseCode::ServiceNotSupportedInActiveSession => 0x7F,
+            NegativeResponseCode::Other(x) => x,
+        }
+    }
+}
+
+impl fmt::Display for NegativeResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            NegativeResponseCode::GeneralReject => "general reject",
+            NegativeResponseCode::ServiceNotSupported => {
+                "service not supported"
+            }
+            NegativeResponseCode::SubFunctionNotSupported => {
+                "sub-function not supported"
+            }
+            NegativeResponseCode::IncorrectMessageLength => {
+                "incorrect message length or invalid format"
+            }
+            NegativeResponseCode::ResponseTooLong => "response too long",
+            NegativeResponseCode::BusyRepeatRequest => "busy, repeat request",
+            NegativeResponseCode::ConditionsNotCorrect => {
+                "conditions not correct"
+            }
+            NegativeResponseCode::RequestSequenceError => {
+                "request sequence error"
+            }
+            NegativeResponseCode::NoResponseFromSubnetComponent => {
+                "no response from subnet component"
+            }
+            NegativeResponseCode::FailurePreventsExecution => {
+                "failure prevents execution of requested action"
+            }
+            NegativeResponseCode::RequestOutOfRange => "request out of range",
+            NegativeResponseCode::SecurityAccessDenied => {
+                "security access denied"
+            }
+            NegativeResponseCode::InvalidKey => "invalid key",
+            NegativeResponseCode::ExceededNumberOfAttempts => {
+                "exceeded number of attempts"
+            }
+            NegativeResponseCode::RequiredTimeDelayNotExpired => {
+                "required time delay not expired"
+            }
+            NegativeResponseCode::UploadDownloadNotAccepted => {
+                "upload/download not accepted"
+            }
+            NegativeResponseCode::TransferDataSuspended => {
+                "transfer data suspended"
+            }
+            NegativeResponseCode::GeneralProgrammingFailure => {
+                "general programming failure"
+            }
+            NegativeResponseCode::WrongBlockSequenceCounter => {
+                "wrong block sequence counter"
+            }
+            NegativeResponseCode::ResponsePending => "response pending",
+            NegativeResponseCode::SubFunctionNotSupportedInActiveSession => {
+                "sub-function not supported in active session"
+            }
+            NegativeResponseCode::ServiceNotSupportedInActiveSession => {
+                "service not supported in active session"
+            }
+            NegativeResponseCode::Other(x) => {
+                return write!(f, "negative response 0x{:02X}", x)
+            }
+        };
+        write!(f, "{} (0x{:02X})", name, u8::from(*self))
+    }
+}
@@ -0,0 +1,178 @@
+//! Seed to key algorithms for security access.
+//!
+//! `Client::unlock` takes any `SeedKey`: the XOR and table algorithms
+//! here, a closure, or, with the `key-library` feature, a shared library
+//! exporting Vector's `GenerateKeyEx`.
+use errors::*;
+use std::time::Duration;
+
+pub trait SeedKey {
+    /// Key answering `seed` for the (odd) security access `level`.
+    fn compute_key(&self, level: u8, seed: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl<F: Fn(&[u8]) -> Vec<u8>> SeedKey for F {
+    fn compute_key(&self, _level: u8, seed: &[u8]) -> Result<Vec<u8>> {
+        Ok(self(seed))
+    }
+}
+
+/// XOR of each seed byte with a mask, repeated as needed.
+#[derive(Debug, Clone)]
+pub struct XorKey {
+    pub mask: Vec<u8>,
+}
+
+impl SeedKey for XorKey {
+    fn compute_key(&self, _level: u8, seed: &[u8]) -> Result<Vec<u8>> {
+        if self.mask.is_empty() {
+            bail!(ErrorKind::KeyGeneration("empty XOR mask".to_owned()));
+        }
+        Ok(seed
+            .iter()
+            .zip(self.mask.iter().cycle())
+            .map(|(s, m)| s ^ m)
+            .collect())
+    }
+}
+
+/// Substitution of each seed byte through a 256 entry table.
+#[derive(Clone)]
+pub struct TableKey {
+    pub table: [u8; 256],
+}
+
+impl SeedKey for TableKey {
+    fn compute_key(&self, _level: u8, seed: &[u8]) -> Result<Vec<u8>> {
+        Ok(seed.iter().map(|&s| self.table[s as usize]).collect())
+    }
+}
+
+/// How `Client::unlock` copes with rejected keys and lockouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityPolicy {
+    pub attempts: u32, // Seed and key exchanges tried before giving up
+    pub delay: Duration, // Wait after "exceeded number of attempts" or "required time delay not expired"
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> SecurityPolicy {
+        SecurityPolicy {
+            attempts: 1,
+            delay: Duration::from_secs(10),
+        }
+    }
+}
+
+#[cfg(feature = "key-library")]
+pub use self::library::KeyLibrary;
+
+#[cfg(feature = "key-library")]
+mod library {
+    use super::SeedKey;
+    use errors::*;
+    use libloading::{Library, Symbol};
+    use std::ffi::CString;
+    use std::os::raw::{c_char, c_int, c_uchar, c_uint};
+    use std::path::Path;
+
+    type GenerateKeyEx = unsafe extern "C" fn(
+        seed: *const c_uchar,
+        seed_size: c_uint,
+        level: c_uint,
+        variant: *const c_char,
+        key: *mut c_uchar,
+        max_key_size: c_uint,
+        key_size: *mut c_uint,
+    ) -> c_int;
+
+    /// Longest key the library may write.
+    const MAX_KEY_SIZE: usize = 256;
+
+    /// A seed to key library following Vector's `GenerateKeyEx` convention.
+    pub struct KeyLibrary {
+        library: Library,
+        variant: CString,
+    }
+
+    impl KeyLibrary {
+        /// Load a library. It must export `GenerateKeyEx`.
+        pub fn open<P: AsRef<Path>>(path: P) -> Result<KeyLibrary> {
+            let library =
+                unsafe { Library::new(path.as_ref()) }.map_err(error)?;
+            unsafe { library.get::<GenerateKeyEx>(b"GenerateKeyEx\0") }
+                .map_err(error)?;
+            Ok(KeyLibrary {
+                library,
+                variant: CString::default(),
+            })
+        }
+
+        /// ECU variant passed to the library, empty by default.
+        pub fn set_variant(&mut self, variant: &str) -> Result<()> {
+            self.variant = CString::new(variant).map_err(error)?;
+            Ok(())
+        }
+    }
+
+    fn error<E: ::std::fmt::Display>(e: E) -> Error {
+        ErrorKind::KeyGeneration(e.to_string()).into()
+    }
+
+    impl SeedKey for KeyLibrary {
+        fn compute_key(&self, level: u8, seed: &[u8]) -> Result<Vec<u8>> {
+            let mut key = vec![0u8; MAX_KEY_SIZE];
+            let mut key_size: c_uint = 0;
+            let result = unsafe {
+                let generate: Symbol<GenerateKeyEx> =
+                    self.library.get(b"GenerateKeyEx\0").map_err(error)?;
+                generate(
+                    seed.as_ptr(),
+                    seed.len() as c_uint,
+                    c_uint::from(level),
+                    self.variant.as_ptr(),
+                    key.as_mut_ptr(),
+                    key.len() as c_uint,
+                    &mut key_size,
+                )
+            };
+            let reason = match result {
+                0 => {
+                    key.truncate((key_size as usize).min(MAX_KEY_SIZE));
+                    return Ok(key);
+                }
+                1 => "key buffer too small",
+                2 => "invalid security level",
+                3 => "invalid variant",
+                _ => "unspecified error",
+            };
+            Err(error(format!("GenerateKeyEx: {}", reason)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_algorithms() {
+        let xor = XorKey {
+            mask: vec![0xFF, 0x00],
+        };
+        assert_eq!(
+            xor.compute_key(1, &[0x12, 0x34, 0x56]).unwrap(),
+            vec![0xED, 0x34, 0xA9]
+        );
+
+        let mut table = [0u8; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            *entry = (i as u8).rotate_left(3);
+        }
+        let table = TableKey { table };
+        assert_eq!(
+            table.compute_key(1, &[0x01, 0x80]).unwrap(),
+            vec![0x08, 0x04]
+        );
+    }
+}
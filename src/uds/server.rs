@@ -0,0 +1,651 @@
+//! Diagnostic server (ECU side) of UDS, to simulate ECUs in tests.
+//!
+//! The server answers session control, ECU reset, security access, read
+//! and write data by identifier, routine control, read DTC information by
+//! status mask, clear DTCs and tester present. Data identifiers and
+//! routines may require a security level, which is lost on a session
+//! change, a reset or when the session times out after S3 without
+//! requests.
+//!
+//! Responses to a service can be delayed, with "response pending"
+//! negative responses sent meanwhile, and negative responses can be
+//! injected for the next requests of a service. As ISO 14229 requires, a
+//! delayed positive response is sent even if the request suppressed it.
+use super::*;
+use bus::CanBus;
+use errors::*;
+use isotp::IsoTp;
+use std::collections::{HashMap, VecDeque};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time without requests after which a non-default session ends.
+const S3_SERVER: Duration = Duration::from_secs(5);
+
+type Response = ::std::result::Result<Vec<u8>, NegativeResponseCode>;
+
+/// Handler of a routine: takes the control type and options, returns the
+/// routine status record or a negative response code.
+pub type RoutineHandler =
+    Box<dyn FnMut(RoutineControl, &[u8]) -> Response + Send>;
+
+pub struct DataIdentifier {
+    pub data: Vec<u8>,
+    pub writable: bool, // Writes must keep the length of the data
+    pub security_level: Option<u8>, // Level that must be unlocked to read or write
+}
+
+struct Routine {
+    handler: RoutineHandler,
+    security_level: Option<u8>,
+}
+
+struct SecurityLevel {
+    seed: Vec<u8>,
+    seed_key: Box<dyn SeedKey + Send>,
+}
+
+pub struct Server<B: CanBus> {
+    transport: IsoTp<B>,
+    sessions: HashMap<u8, SessionTiming>,
+    identifiers: HashMap<u16, DataIdentifier>,
+    routines: HashMap<u16, Routine>,
+    levels: HashMap<u8, SecurityLevel>,
+    dtcs: Vec<DtcRecord>,
+    delays: HashMap<u8, Duration>,
+    injected: VecDeque<(u8, NegativeResponseCode)>,
+    max_attempts: u32,
+    lockout: Duration,
+    // State
+    session: u8,
+    unlocked: Option<u8>,
+    seed_sent: Option<u8>,
+    failed_attempts: u32,
+    locked_until: Option<Instant>,
+    last_request: Instant,
+}
+
+impl<B: CanBus> Server<B> {
+    /// A server with only the default session, using P2 50 ms and P2*
+    /// 5 s.
+    pub fn new(transport: IsoTp<B>) -> Server<B> {
+        let mut sessions = HashMap::new();
+        sessions.insert(
+            Session::Default.into(),
+            SessionTiming {
+                p2_max: 50,
+                p2_star_max: 500,
+            },
+        );
+        Server {
+            transport,
+            sessions,
+            identifiers: HashMap::new(),
+            routines: HashMap::new(),
+            levels: HashMap::new(),
+            dtcs: Vec::new(),
+            delays: HashMap::new(),
+            injected: VecDeque::new(),
+            max_attempts: 3,
+            lockout: Duration::from_secs(10),
+            session: Session::Default.into(),
+            unlocked: None,
+            seed_sent: None,
+            failed_attempts: 0,
+            locked_until: None,
+            last_request: Instant::now(),
+        }
+    }
+
+    pub fn transport(&self) -> &IsoTp<B> {
+        &self.transport
+    }
+
+    pub fn into_inner(self) -> IsoTp<B> {
+        self.transport
+    }
+
+    pub fn add_session(&mut self, session: Session, timing: SessionTiming) {
+        self.sessions.insert(session.into(), timing);
+    }
+
+    pub fn add_identifier(&mut self, identifier: u16, did: DataIdentifier) {
+        self.identifiers.insert(identifier, did);
+    }
+
+    /// Current data of an identifier, as last written.
+    pub fn identifier(&self, identifier: u16) -> Option<&DataIdentifier> {
+        self.identifiers.get(&identifier)
+    }
+
+    pub fn add_routine(
+        &mut self,
+        routine: u16,
+        security_level: Option<u8>,
+        handler: RoutineHandler,
+    ) {
+        self.routines.insert(
+            routine,
+            Routine {
+                handler,
+                security_level,
+            },
+        );
+    }
+
+    /// Accept an (odd) security level, answering requests with `seed` and
+    /// expecting the key `seed_key` computes for it.
+    pub fn add_security_level<S: SeedKey + Send + 'static>(
+        &mut self,
+        level: u8,
+        seed: Vec<u8>,
+        seed_key: S,
+    ) {
+        self.levels.insert(
+            level,
+            SecurityLevel {
+                seed,
+                seed_key: Box::new(seed_key),
+            },
+        );
+    }
+
+    /// Invalid keys accepted before locking security access for `lockout`.
+    pub fn set_lockout(&mut self, max_attempts: u32, lockout: Duration) {
+        self.max_attempts = max_attempts;
+        self.lockout = lockout;
+    }
+
+    pub fn set_dtcs(&mut self, dtcs: Vec<DtcRecord>) {
+        self.dtcs = dtcs;
+    }
+
+    /// Answer a service only after `delay`, sending "response pending"
+    /// meanwhile.
+    pub fn set_response_delay(&mut self, service: u8, delay: Duration) {
+        self.delays.insert(service, delay);
+    }
+
+    /// Answer the next request of a service with a negative response.
+    pub fn inject_negative_response(
+        &mut self,
+        service: u8,
+        code: NegativeResponseCode,
+    ) {
+        self.injected.push_back((service, code));
+    }
+
+    pub fn session(&self) -> Session {
+        Session::from(self.session)
+    }
+
+    /// Handle the next request to arrive within `timeout`. Returns whether
+    /// there was one.
+    pub fn serve(&mut self, timeout: Duration) -> Result<bool> {
+        let request = match self.transport.receive(timeout)? {
+            Some(request) => request,
+            None => return Ok(false),
+        };
+        let service = match request.first() {
+            Some(&service) => service,
+            None => return Ok(true),
+        };
+        if self.last_request.elapsed() > S3_SERVER {
+            self.reset_session(Session::Default.into());
+        }
+        self.last_request = Instant::now();
+
+        let injected = self.injected.iter().position(|&(s, _)| s == service);
+        let response = match injected.and_then(|i| self.injected.remove(i)) {
+            Some((_, code)) => Err(code),
+            None => self.handle(service, &request[1..]),
+        };
+        let suppressed =
+            response.is_ok() && suppresses_response(service, &request[1..]);
+        let delay = self.delays.get(&service).cloned();
+        if let Some(delay) = delay {
+            self.respond_pending(service, delay)?;
+        }
+        // Once response pending went out the final response is due anyway
+        let response = match response {
+            Ok(_) if suppressed && delay.is_none() => return Ok(true),
+            Ok(response) => response,
+            Err(code) => vec![NEGATIVE_RESPONSE, service, code.into()],
+        };
+        self.transport.send(&response).map(|_| true)
+    }
+
+    /// Handle requests until none arrives for `idle`.
+    pub fn run(&mut self, idle: Duration) -> Result<()> {
+        while self.serve(idle)? {}
+        Ok(())
+    }
+
+    /// Send "response pending" every half P2* until `delay` has passed.
+    fn respond_pending(&mut self, service: u8, delay: Duration) -> Result<()> {
+        let end = Instant::now() + delay;
+        let period =
+            Duration::from_millis(u64::from(self.timing().p2_star_max) * 5);
+        while Instant::now() < end {
+            self.transport.send(&[
+                NEGATIVE_RESPONSE,
+                service,
+                NegativeResponseCode::ResponsePending.into(),
+            ])?;
+            thread::sleep(
+                period.min(end.saturating_duration_since(Instant::now())),
+            );
+        }
+        Ok(())
+    }
+
+    fn timing(&self) -> SessionTiming {
+        self.sessions[&self.session]
+    }
+
+    fn reset_session(&mut self, session: u8) {
+        self.session = session;
+        self.unlocked = None;
+        self.seed_sent = None;
+    }
+
+    fn check_security(
+        &self,
+        level: Option<u8>,
+    ) -> ::std::result::Result<(), NegativeResponseCode> {
+        match level {
+            Some(level) if self.unlocked != Some(level) => {
+                Err(NegativeResponseCode::SecurityAccessDenied)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Positive response, even if suppressed, or the negative response code.
+    fn handle(&mut self, service: u8, data: &[u8]) -> Response {
+        let sub_function =
+            data.first().map(|&b| b & !SUPPRESS_POSITIVE_RESPONSE);
+        let mut response = vec![service.wrapping_add(POSITIVE_RESPONSE_OFFSET)];
+        match (service, sub_function) {
+            (DIAGNOSTIC_SESSION_CONTROL, Some(session)) if data.len() == 1 => {
+                let timing = *self
+                    .sessions
+                    .get(&session)
+                    .ok_or(NegativeResponseCode::SubFunctionNotSupported)?;
+                self.reset_session(session);
+                response.push(session);
+                response.extend_from_slice(&timing.p2_max.to_be_bytes());
+                response.extend_from_slice(&timing.p2_star_max.to_be_bytes());
+            }
+            (ECU_RESET, Some(reset)) if data.len() == 1 => {
+                if !matches!(
+                    ResetType::from(reset),
+                    ResetType::Hard | ResetType::KeyOffOn | ResetType::Soft
+                ) {
+                    return Err(NegativeResponseCode::SubFunctionNotSupported);
+                }
+                self.reset_session(Session::Default.into());
+                response.push(reset);
+            }
+            (SECURITY_ACCESS, Some(level)) => {
+                response.push(level);
+                response.extend(self.security_access(level, &data[1..])?);
+                return Ok(response);
+            }
+            (READ_DATA_BY_IDENTIFIER, _)
+                if !data.is_empty() && data.len().is_multiple_of(2) =>
+            {
+                for identifier in data.chunks(2) {
+                    let did = self
+                        .identifiers
+                        .get(&u16::from_be_bytes([
+                            identifier[0],
+                            identifier[1],
+                        ]))
+                        .ok_or(NegativeResponseCode::RequestOutOfRange)?;
+                    self.check_security(did.security_level)?;
+                    response.extend_from_slice(identifier);
+                    response.extend_from_slice(&did.data);
+                }
+                return Ok(response);
+            }
+            (WRITE_DATA_BY_IDENTIFIER, _) if data.len() > 2 => {
+                let identifier = u16::from_be_bytes([data[0], data[1]]);
+                let security_level = match self.identifiers.get(&identifier) {
+                    Some(did) if did.writable => did.security_level,
+                    _ => return Err(NegativeResponseCode::RequestOutOfRange),
+                };
+                self.check_security(security_level)?;
+                let did = self.identifiers.get_mut(&identifier).unwrap();
+                if did.data.len() != data.len() - 2 {
+                    return Err(NegativeResponseCode::IncorrectMessageLength);
+                }
+                did.data = data[2..].to_vec();
+                response.extend_from_slice(&data[..2]);
+                return Ok(response);
+            }
+            (ROUTINE_CONTROL, Some(control)) if data.len() >= 3 => {
+                let control = match control {
+                    0x01 => RoutineControl::Start,
+                    0x02 => RoutineControl::Stop,
+                    0x03 => RoutineControl::RequestResults,
+                    _ => {
+                        return Err(
+                            NegativeResponseCode::SubFunctionNotSupported,
+                        )
+                    }
+                };
+                let security_level = self
+                    .routines
+                    .get(&u16::from_be_bytes([data[1], data[2]]))
+                    .ok_or(NegativeResponseCode::RequestOutOfRange)?
+                    .security_level;
+                self.check_security(security_level)?;
+                let routine = self
+                    .routines
+                    .get_mut(&u16::from_be_bytes([data[1], data[2]]))
+                    .unwrap();
+                let status = (routine.handler)(control, &data[3..])?;
+                response.push(control.into());
+                response.extend_from_slice(&data[1..3]);
+                response.extend(status);
+            }
+            (READ_DTC_INFORMATION, Some(report))
+                if data.len() == 2 && (report == 0x01 || report == 0x02) =>
+            {
+                let mask = data[1];
+                let matching =
+                    self.dtcs.iter().filter(|d| d.status.0 & mask != 0);
+                response.extend_from_slice(&[report, 0xFF]);
+                if report == 0x01 {
+                    response.push(0x01);
+                    response.extend_from_slice(
+                        &(matching.count() as u16).to_be_bytes(),
+                    );
+                } else {
+                    for dtc in matching {
+                        response
+                            .extend_from_slice(&dtc.code.0.to_be_bytes()[1..]);
+                        response.push(dtc.status.0);
+                    }
+                }
+                return Ok(response);
+            }
+            (READ_DTC_INFORMATION, Some(_)) => {
+                return Err(NegativeResponseCode::SubFunctionNotSupported)
+            }
+            (CLEAR_DIAGNOSTIC_INFORMATION, _) if data.len() == 3 => {
+                self.dtcs.clear();
+                return Ok(response);
+            }
+            (TESTER_PRESENT, Some(0x00)) if data.len() == 1 => {
+                response.push(0x00)
+            }
+            (TESTER_PRESENT, Some(_)) => {
+                return Err(NegativeResponseCode::SubFunctionNotSupported)
+            }
+            (DIAGNOSTIC_SESSION_CONTROL, _)
+            | (ECU_RESET, _)
+            | (SECURITY_ACCESS, _)
+            | (READ_DATA_BY_IDENTIFIER, _)
+            | (WRITE_DATA_BY_IDENTIFIER, _)
+            | (ROUTINE_CONTROL, _)
+            | (READ_DTC_INFORMATION, _)
+            | (CLEAR_DIAGNOSTIC_INFORMATION, _)
+            | (TESTER_PRESENT, _) => {
+                return Err(NegativeResponseCode::IncorrectMessageLength)
+            }
+            _ => return Err(NegativeResponseCode::ServiceNotSupported),
+        }
+        Ok(response)
+    }
+
+    /// Seed for odd levels, key check for even ones. Returns the response
+    /// after the level.
+    fn security_access(&mut self, level: u8, key: &[u8]) -> Response {
+        if level % 2 == 1 {
+            let seed = &self
+                .levels
+                .get(&level)
+                .ok_or(NegativeResponseCode::SubFunctionNotSupported)?
+                .seed;
+            if self
+                .locked_until
+                .is_some_and(|until| Instant::now() < until)
+            {
+                return Err(NegativeResponseCode::RequiredTimeDelayNotExpired);
+            }
+            if self.unlocked == Some(level) {
+                return Ok(vec![0; seed.len()]);
+            }
+            let seed = seed.clone();
+            self.seed_sent = Some(level);
+            return Ok(seed);
+        }
+
+        let seed_level = level.wrapping_sub(1);
+        let security = self
+            .levels
+            .get(&seed_level)
+            .ok_or(NegativeResponseCode::SubFunctionNotSupported)?;
+        if self.seed_sent.take() != Some(seed_level) {
+            return Err(NegativeResponseCode::RequestSequenceError);
+        }
+        if security
+            .seed_key
+            .compute_key(seed_level, &security.seed)
+            .ok()
+            .as_deref()
+            == Some(key)
+        {
+            self.unlocked = Some(seed_level);
+            self.failed_attempts = 0;
+            return Ok(Vec::new());
+        }
+        self.failed_attempts += 1;
+        if self.failed_attempts >= self.max_attempts {
+            self.failed_attempts = 0;
+            self.locked_until = Some(Instant::now() + self.lockout);
+            return Err(NegativeResponseCode::ExceededNumberOfAttempts);
+        }
+        Err(NegativeResponseCode::InvalidKey)
+    }
+}
+
+/// Whether a request to `service` asks for no positive response. Security
+/// access and DTC reports always answer.
+fn suppresses_response(service: u8, data: &[u8]) -> bool {
+    match service {
+        DIAGNOSTIC_SESSION_CONTROL
+        | ECU_RESET
+        | ROUTINE_CONTROL
+        | TESTER_PRESENT => data
+            .first()
+            .is_some_and(|&b| b & SUPPRESS_POSITIVE_RESPONSE != 0),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::VirtualBus;
+    use isotp::{Config, IsoTp};
+    use std::thread;
+    use std::time::Duration;
+    use testing::uds_client as client;
+
+    fn server(bus: VirtualBus) -> Server<VirtualBus> {
+        let mut server =
+            Server::new(IsoTp::new(bus, Config::new(0x7E8, 0x7E0)));
+        server.add_session(
+            Session::Extended,
+            SessionTiming {
+                p2_max: 25,
+                p2_star_max: 10,
+            },
+        );
+        server.add_security_level(
+            0x01,
+            vec![0x12, 0x34],
+            XorKey { mask: vec![0xFF] },
+        );
+        server.set_lockout(2, Duration::from_millis(50));
+        let vin = DataIdentifier {
+            data: b"VIN0123".to_vec(),
+            writable: false,
+            security_level: None,
+        };
+        server.add_identifier(0xF190, vin);
+        server.add_identifier(
+            0x0100,
+            DataIdentifier {
+                data: vec![0, 0],
+                writable: true,
+                security_level: Some(0x01),
+            },
+        );
+        server.add_routine(
+            0xFF00,
+            None,
+            Box::new(|control, options| match control {
+                RoutineControl::Start => {
+                    Ok(options.iter().rev().cloned().collect())
+                }
+                _ => Err(NegativeResponseCode::RequestSequenceError),
+            }),
+        );
+        server.set_dtcs(vec![DtcRecord {
+            code: Dtc(0x012300),
+            status: DtcStatus(0x09),
+        }]);
+        server
+    }
+
+    #[test]
+    fn diagnostic_sequence() {
+        let bus = VirtualBus::new();
+        let mut server = server(bus.connect());
+        server.set_response_delay(ROUTINE_CONTROL, Duration::from_millis(120));
+        let ecu = thread::spawn(move || {
+            server.run(Duration::from_millis(500)).unwrap();
+            server
+        });
+        let mut client = client(bus);
+
+        assert_eq!(
+            client.read_data_by_identifier(0xF190).unwrap(),
+            b"VIN0123".to_vec()
+        );
+        match *client
+            .write_data_by_identifier(0x0100, &[1, 2])
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::NegativeResponse(
+                WRITE_DATA_BY_IDENTIFIER,
+                NegativeResponseCode::SecurityAccessDenied,
+            ) => {}
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        client
+            .diagnostic_session_control(Session::Extended)
+            .unwrap();
+        client.unlock(0x01, &XorKey { mask: vec![0xFF] }).unwrap();
+        client.write_data_by_identifier(0x0100, &[1, 2]).unwrap();
+        assert_eq!(client.read_data_by_identifier(0x0100).unwrap(), vec![1, 2]);
+        // Answered after response pending, beyond P2
+        assert_eq!(
+            client
+                .routine_control(RoutineControl::Start, 0xFF00, &[1, 2])
+                .unwrap(),
+            vec![2, 1]
+        );
+        assert_eq!(client.read_dtcs_by_status(0x08).unwrap().dtcs.len(), 1);
+        client.tester_present().unwrap();
+        client.ecu_reset(ResetType::Hard).unwrap();
+
+        let server = ecu.join().unwrap();
+        assert_eq!(server.session(), Session::Default);
+        assert_eq!(server.identifier(0x0100).unwrap().data, vec![1, 2]);
+    }
+
+    #[test]
+    fn lockout_and_injected_responses() {
+        let bus = VirtualBus::new();
+        let mut server = server(bus.connect());
+        server.inject_negative_response(
+            READ_DATA_BY_IDENTIFIER,
+            NegativeResponseCode::BusyRepeatRequest,
+        );
+        let ecu = thread::spawn(move || {
+            server.run(Duration::from_millis(500)).unwrap()
+        });
+        let mut client = client(bus);
+
+        match *client.read_data_by_identifier(0xF190).unwrap_err().kind() {
+            ErrorKind::NegativeResponse(
+                _,
+                NegativeResponseCode::BusyRepeatRequest,
+            ) => {}
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(client.read_data_by_identifier(0xF190).is_ok());
+
+        let wrong = XorKey { mask: vec![0x00] };
+        for expected in &[
+            NegativeResponseCode::InvalidKey,
+            NegativeResponseCode::ExceededNumberOfAttempts,
+        ] {
+            match *client.unlock(0x01, &wrong).unwrap_err().kind() {
+                ErrorKind::NegativeResponse(SECURITY_ACCESS, code) => {
+                    assert_eq!(code, *expected)
+                }
+                ref kind => panic!("unexpected error {:?}", kind),
+            }
+        }
+        match *client.request_seed(0x01).unwrap_err().kind() {
+            ErrorKind::NegativeResponse(
+                _,
+                NegativeResponseCode::RequiredTimeDelayNotExpired,
+            ) => {}
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        // The client waits out the lockout before its second attempt
+        client.set_security_policy(SecurityPolicy {
+            attempts: 2,
+            delay: Duration::from_millis(60),
+        });
+        client.unlock(0x01, &XorKey { mask: vec![0xFF] }).unwrap();
+        drop(client);
+        ecu.join().unwrap();
+    }
+
+    #[test]
+    fn suppressed_response_after_delay() {
+        let bus = VirtualBus::new();
+        let mut server = server(bus.connect());
+        server.set_response_delay(TESTER_PRESENT, Duration::from_millis(60));
+        let ecu = thread::spawn(move || {
+            server.run(Duration::from_millis(300)).unwrap()
+        });
+        let mut tester = IsoTp::new(bus, Config::new(0x7E0, 0x7E8));
+
+        // Response pending, then the final response despite the bit
+        for request in &[0x80, 0x00] {
+            tester.send(&[TESTER_PRESENT, *request]).unwrap();
+            assert_eq!(
+                tester.receive(Duration::from_millis(150)).unwrap(),
+                Some(vec![0x7F, TESTER_PRESENT, 0x78])
+            );
+            assert_eq!(
+                tester.receive(Duration::from_millis(150)).unwrap(),
+                Some(vec![0x7E, 0x00])
+            );
+        }
+        // Nothing at all without a delay
+        tester.send(&[ECU_RESET, 0x81]).unwrap();
+        assert_eq!(tester.receive(Duration::from_millis(100)).unwrap(), None);
+        ecu.join().unwrap();
+    }
+}